@@ -0,0 +1,84 @@
+// frontend - throughput of the lexer in tokens/sec and of the parser in
+// ast nodes/sec on generated programs, to weigh changes to either, e.g.
+// interning identifiers or allocating nodes in an arena
+//
+//   cargo bench --bench frontend [-- <filter>]
+//
+// criterion keeps the results of the last run in target/criterion and
+// compares the next one with them
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use kaleidoscope::ast::{self, Item};
+use kaleidoscope::lexer::{Lexer, Token};
+use kaleidoscope::parser::Parser;
+use std::fmt::Write;
+use std::hint::black_box;
+use std::time::Duration;
+
+// definitions whose bodies are chains of `terms` operators, left nested
+// as deep as the chain is long, deeper than the parser allows by default
+fn operator_chains(definitions: usize, terms: usize) -> String {
+    let mut source = String::new();
+    for i in 0..definitions {
+        write!(source, "def chain{}(x y)", i).unwrap();
+        for j in 0..terms {
+            let op = ['+', '-', '*', '<'][j % 4];
+            write!(source, " x {} {}.5 {}", op, j, op).unwrap();
+        }
+        source.push_str(" y;\n");
+    }
+    source
+}
+
+// many one line functions, each calling the one before, and an extern
+fn small_functions(definitions: usize) -> String {
+    let mut source = String::from("extern sin(x);\ndef f0(x) sin(x);\n");
+    for i in 1..definitions {
+        writeln!(source, "def f{}(a b) f{}(a * b) + -b;", i, i - 1).unwrap();
+    }
+    source
+}
+
+fn tokens(source: &str) -> usize {
+    let mut lexer = Lexer::new(source.chars());
+    let mut count = 0;
+    while black_box(lexer.next_token()) != Token::Eof {
+        count += 1;
+    }
+    count
+}
+
+fn parse(source: &str) -> Vec<Item> {
+    let out = Parser::new(Lexer::new(source.chars())).parse_all();
+    assert!(out.diagnostics.is_empty());
+    out.items
+}
+
+// functions, externs and expressions
+fn nodes(items: &[Item]) -> usize {
+    let counts = ast::stats(items).nodes;
+    counts.functions + counts.externs + counts.exprs()
+}
+
+fn frontend(c: &mut Criterion) {
+    let inputs = [
+        ("operator chains", operator_chains(100, 1000)),
+        ("small functions", small_functions(50_000)),
+    ];
+    for (name, source) in &inputs {
+        let mut group = c.benchmark_group(*name);
+        // a run takes tens of milliseconds
+        group.sample_size(10);
+        group.measurement_time(Duration::from_secs(10));
+        group.throughput(Throughput::Elements(tokens(source) as u64));
+        group.bench_function("lex", |b| b.iter(|| tokens(black_box(source))));
+        group.throughput(Throughput::Elements(nodes(&parse(source)) as u64));
+        // the items are dropped after the clock stopped
+        group.bench_function("parse", |b| {
+            b.iter_with_large_drop(|| parse(black_box(source)))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, frontend);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+// parallel - wall clock of `klc --ir` on a program with many large
+// definitions, compiling the functions on one thread and on all of them
+//
+//   cargo bench --bench parallel [-- <definitions>]
+use std::fmt::Write as _;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+// `definitions` functions, each a sum of products calling the one before
+fn program(definitions: usize) -> String {
+    let mut source = String::from("extern sin(x);\ndef f0(x) sin(x);\n");
+    for i in 1..definitions {
+        write!(source, "def f{}(x) f{}(x)", i, i - 1).unwrap();
+        for j in 0..200 {
+            write!(source, " + x * {} - sin(x * {})", j, j + i).unwrap();
+        }
+        source.push_str(";\n");
+    }
+    source
+}
+
+// fastest of `RUNS` compilations of `source` with `jobs` threads, if any
+fn time(source: &str, jobs: Option<&str>) -> Duration {
+    let mut fastest = Duration::MAX;
+    for _ in 0..RUNS {
+        let mut klc = Command::new(env!("CARGO_BIN_EXE_klc"));
+        klc.arg("--ir");
+        if let Some(jobs) = jobs {
+            klc.args(["-j", jobs]);
+        }
+        let start = Instant::now();
+        let mut child = klc
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("klc runs");
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+        assert!(child.wait().unwrap().success());
+        fastest = fastest.min(start.elapsed());
+    }
+    fastest
+}
+
+fn main() {
+    let definitions = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(1000);
+    let source = program(definitions);
+    let one = time(&source, Some("1"));
+    let all = time(&source, None);
+    println!("{} definitions, fastest of {} runs", definitions, RUNS);
+    println!("  1 thread:    {:>8.1?}", one);
+    println!(
+        "  all threads: {:>8.1?} ({:.2}x)",
+        all,
+        one.as_secs_f64() / all.as_secs_f64()
+    );
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+use kaleidoscope::lexer::{Lexer, LexerConfig, ReadChars, Token};
+use libfuzzer_sys::fuzz_target;
+
+// any bytes lex to eof without a panic, the limits are small so the
+// errors for over long tokens are reached too
+fuzz_target!(|data: &[u8]| {
+    let config = LexerConfig {
+        max_identifier_len: 8,
+        max_number_len: 8,
+        max_comment_len: 8,
+        emit_comments: true,
+    };
+    let mut lexer = Lexer::with_config(ReadChars::new(data), config);
+    while lexer.next_token() != Token::Eof {}
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use kaleidoscope::lexer::Lexer;
+use kaleidoscope::parser::{Parser, ParserConfig};
+use libfuzzer_sys::fuzz_target;
+
+// any source parses without a panic, with and without recovery, the
+// depth limit is small so it is reached too
+fuzz_target!(|input: (bool, &str)| {
+    let (recover, source) = input;
+    let config = ParserConfig {
+        max_depth: 16,
+        recover,
+    };
+    let _ = Parser::with_config(Lexer::new(source.chars()), config).parse_program();
+});
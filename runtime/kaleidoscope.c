@@ -0,0 +1,303 @@
+// runtime of kaleidoscope programs, linked into executables by `klc build`
+// and loaded into the jit, which builds it with KLC_NO_MAIN
+//
+// every value is a double, so objects on the heap are passed around as
+// their address converted to a double, which is exact as addresses fit
+// the 53 bits of its mantissa
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+// generated, runs the top-level expressions of the program in order
+void __kaleidoscope_main(void);
+
+// print `x` and a newline, for `extern printd(x)`
+double printd(double x) {
+    printf("%f\n", x);
+    return 0;
+}
+
+// print `x` as a char, for `extern putchard(x)`
+double putchard(double x) {
+    putchar((char)x);
+    return 0;
+}
+
+// heap
+
+// header of every object, its elements follow
+struct object {
+    size_t len;
+};
+
+static void fail(const char *message, double x) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s %g\n", message, x);
+    exit(1);
+}
+
+// `size` bytes, zeroed, for generated code, never fails
+void *__kaleidoscope_alloc(size_t size) {
+    void *p = calloc(1, size);
+    if (!p) {
+        fail("out of memory allocating bytes:", (double)size);
+    }
+    return p;
+}
+
+static double handle(struct object *object) {
+    return (double)(uintptr_t)object;
+}
+
+static struct object *object(double handle) {
+    if (handle == 0) {
+        fail("not an object:", handle);
+    }
+    return (struct object *)(uintptr_t)handle;
+}
+
+static size_t length(double n) {
+    if (!(n >= 0 && n <= (double)(SIZE_MAX / 16))) {
+        fail("invalid length:", n);
+    }
+    return (size_t)n;
+}
+
+// element `i` of `object`, exits if out of bounds
+static size_t element(struct object *object, double i) {
+    if (!(i >= 0 && i < (double)object->len)) {
+        fail("index out of bounds:", i);
+    }
+    return (size_t)i;
+}
+
+// free an array or string, for `extern heapfree(x)`, 0 is ignored
+double heapfree(double x) {
+    if (x != 0) {
+        free(object(x));
+    }
+    return 0;
+}
+
+// arrays of `n` doubles, zeroed
+
+struct array {
+    struct object header;
+    double elements[];
+};
+
+double arraynew(double n) {
+    size_t len = length(n);
+    struct array *array = __kaleidoscope_alloc(sizeof(struct array) + len * sizeof(double));
+    array->header.len = len;
+    return handle(&array->header);
+}
+
+double arraylen(double a) {
+    return (double)object(a)->len;
+}
+
+double arrayget(double a, double i) {
+    struct array *array = (struct array *)object(a);
+    return array->elements[element(&array->header, i)];
+}
+
+// returns `x`
+double arrayset(double a, double i, double x) {
+    struct array *array = (struct array *)object(a);
+    array->elements[element(&array->header, i)] = x;
+    return x;
+}
+
+// strings of `n` bytes, zeroed, elements are char codes
+
+struct string {
+    struct object header;
+    char bytes[];
+};
+
+double stringnew(double n) {
+    size_t len = length(n);
+    struct string *string = __kaleidoscope_alloc(sizeof(struct string) + len);
+    string->header.len = len;
+    return handle(&string->header);
+}
+
+double stringlen(double s) {
+    return (double)object(s)->len;
+}
+
+double stringget(double s, double i) {
+    struct string *string = (struct string *)object(s);
+    return (unsigned char)string->bytes[element(&string->header, i)];
+}
+
+// returns `c`
+double stringset(double s, double i, double c) {
+    struct string *string = (struct string *)object(s);
+    string->bytes[element(&string->header, i)] = (char)c;
+    return c;
+}
+
+// new string of `a` followed by `b`
+double stringcat(double a, double b) {
+    struct string *first = (struct string *)object(a);
+    struct string *second = (struct string *)object(b);
+    double s = stringnew((double)(first->header.len + second->header.len));
+    struct string *string = (struct string *)object(s);
+    memcpy(string->bytes, first->bytes, first->header.len);
+    memcpy(string->bytes + first->header.len, second->bytes, second->header.len);
+    return s;
+}
+
+// print `s`, for `extern prints(s)`
+double prints(double s) {
+    struct string *string = (struct string *)object(s);
+    fwrite(string->bytes, 1, string->header.len, stdout);
+    return 0;
+}
+
+// profiling, for `klc --instrument=profile`, generated code counts calls
+// and their time per function, the report is printed at exit
+
+struct profile {
+    char *name;
+    size_t len;
+    uint64_t calls;
+    // calls in progress, recursive calls are timed by the outermost
+    unsigned depth;
+    double start;
+    double seconds;
+};
+
+static struct profile *profiles;
+static size_t profiles_len;
+
+static double now(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return ts.tv_sec + ts.tv_nsec * 1e-9;
+}
+
+static int by_seconds(const void *a, const void *b) {
+    double x = ((const struct profile *)a)->seconds;
+    double y = ((const struct profile *)b)->seconds;
+    return (x < y) - (x > y);
+}
+
+// at exit, also when the program failed, a destructor as atexit is not
+// resolved by the jit
+__attribute__((destructor)) static void profile_report(void) {
+    if (profiles == NULL) {
+        return;
+    }
+    fflush(stdout);
+    qsort(profiles, profiles_len, sizeof(struct profile), by_seconds);
+    fprintf(stderr, "%10s %12s  %s\n", "calls", "seconds", "function");
+    for (size_t i = 0; i < profiles_len; i++) {
+        struct profile *profile = &profiles[i];
+        if (profile->calls > 0) {
+            fprintf(stderr, "%10llu %12.6f  %.*s\n", (unsigned long long)profile->calls,
+                    profile->seconds, (int)profile->len, profile->name);
+        }
+    }
+}
+
+// append `c` to the name of function `id`, generated code names all
+// functions before the program runs
+void __kaleidoscope_profile_name(int32_t id, int32_t c) {
+    if ((size_t)id >= profiles_len) {
+        size_t len = (size_t)id + 1;
+        profiles = realloc(profiles, len * sizeof(struct profile));
+        if (!profiles) {
+            fail("out of memory allocating profiles:", (double)len);
+        }
+        memset(&profiles[profiles_len], 0, (len - profiles_len) * sizeof(struct profile));
+        profiles_len = len;
+    }
+    struct profile *profile = &profiles[id];
+    profile->name = realloc(profile->name, profile->len + 1);
+    if (!profile->name) {
+        fail("out of memory allocating profiles:", (double)id);
+    }
+    profile->name[profile->len++] = (char)c;
+}
+
+void __kaleidoscope_profile_enter(int32_t id) {
+    struct profile *profile = &profiles[id];
+    profile->calls++;
+    if (profile->depth++ == 0) {
+        profile->start = now();
+    }
+}
+
+void __kaleidoscope_profile_exit(int32_t id) {
+    struct profile *profile = &profiles[id];
+    if (--profile->depth == 0) {
+        profile->seconds += now() - profile->start;
+    }
+}
+
+// coverage, for `klc --instrument=coverage`, generated code counts how
+// often each region of the source ran, the counts are written at exit to
+// $KLC_COVERAGE or klc.coverage, a line `start end count` per region,
+// start and end as byte offsets, for `klc coverage`
+
+struct region {
+    int32_t start;
+    int32_t end;
+    uint64_t count;
+};
+
+static struct region *regions;
+static size_t regions_len;
+
+// at exit like the profile report
+__attribute__((destructor)) static void coverage_write(void) {
+    if (regions == NULL) {
+        return;
+    }
+    const char *path = getenv("KLC_COVERAGE");
+    if (path == NULL || *path == 0) {
+        path = "klc.coverage";
+    }
+    FILE *file = fopen(path, "w");
+    if (file == NULL) {
+        fprintf(stderr, "error: cannot write coverage to %s\n", path);
+        return;
+    }
+    for (size_t i = 0; i < regions_len; i++) {
+        fprintf(file, "%d %d %llu\n", regions[i].start, regions[i].end,
+                (unsigned long long)regions[i].count);
+    }
+    fclose(file);
+}
+
+// region `id` is the source from byte `start` up to `end`, generated code
+// declares all regions before the program runs
+void __kaleidoscope_coverage_region(int32_t id, int32_t start, int32_t end) {
+    if ((size_t)id >= regions_len) {
+        size_t len = (size_t)id + 1;
+        regions = realloc(regions, len * sizeof(struct region));
+        if (!regions) {
+            fail("out of memory allocating regions:", (double)len);
+        }
+        memset(&regions[regions_len], 0, (len - regions_len) * sizeof(struct region));
+        regions_len = len;
+    }
+    regions[id].start = start;
+    regions[id].end = end;
+}
+
+void __kaleidoscope_coverage_hit(int32_t id) {
+    regions[id].count++;
+}
+
+#ifndef KLC_NO_MAIN
+int main(void) {
+    __kaleidoscope_main();
+    return 0;
+}
+#endif
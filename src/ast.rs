@@ -0,0 +1,449 @@
+// ast - the syntax tree built by the parser, constructors of its nodes
+// and its export formats
+#[cfg(any(test, feature = "arbitrary"))]
+mod arbitrary;
+#[cfg(feature = "std")]
+mod dot;
+mod hash;
+#[cfg(feature = "std")]
+mod json;
+#[cfg(feature = "std")]
+mod sexpr;
+#[cfg(feature = "std")]
+mod stats;
+
+#[cfg(feature = "std")]
+pub use dot::{program_to_dot, to_dot};
+pub use hash::{hash_function, hash_item};
+#[cfg(feature = "ffi")]
+pub(crate) use json::write_str as write_json_str;
+#[cfg(feature = "std")]
+pub use json::{diagnostic_to_json, errors_to_json, program_to_json, to_json};
+#[cfg(feature = "std")]
+pub use sexpr::{
+    item_to_sexpr, parse_item_sexpr, parse_program_sexpr, parse_sexpr, program_to_sexpr, to_sexpr,
+    SexprError,
+};
+#[cfg(feature = "std")]
+pub use stats::{stats, FunctionStats, NodeCounts, Stats};
+
+use crate::lexer::Span;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// identity of a parsed node, unique within one parser
+// later passes key side tables by it instead of mutating the tree
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+    // id of nodes not created by the parser (built by hand or by a fold)
+    pub const DUMMY: NodeId = NodeId(u32::MAX);
+}
+
+// every node carries its id and the source span it was parsed from as
+// last fields
+// equality is structural, ids are left out of the comparison
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ExpressionAST {
+    // number - expression class for numeric literals
+    Number(f64, NodeId, Span),
+
+    // variable - expression class for referencing a variable
+    Variable(String, NodeId, Span),
+
+    // unary - expression class for prefix operator
+    Unary(char, Box<ExpressionAST>, NodeId, Span),
+
+    // binary - expression class for binary operator
+    Binary(char, Box<ExpressionAST>, Box<ExpressionAST>, NodeId, Span),
+
+    // call - expression class for function calls
+    Call(String, Vec<ExpressionAST>, NodeId, Span),
+
+    // error - hole left where a malformed expression was skipped
+    // (only with ParserConfig::recover)
+    Error(NodeId, Span),
+}
+
+// dropped without recursion, a chain of operators nests as deep as it is
+// long, the nodes with children of their own are taken out and dropped
+// from a stack
+impl Drop for ExpressionAST {
+    fn drop(&mut self) {
+        fn take_nested(expr: &mut ExpressionAST, stack: &mut Vec<ExpressionAST>) {
+            let mut i = 0;
+            while let Some(child) = expr.child_mut(i) {
+                if child.has_children() {
+                    stack.push(child.take());
+                }
+                i += 1;
+            }
+        }
+
+        let mut stack = Vec::new();
+        take_nested(self, &mut stack);
+        while let Some(mut expr) = stack.pop() {
+            take_nested(&mut expr, &mut stack);
+        }
+    }
+}
+
+// cloned without recursion like it is dropped, the nodes are rebuilt
+// bottom up on a stack
+impl Clone for ExpressionAST {
+    fn clone(&self) -> Self {
+        use ExpressionAST::*;
+        let mut built = Vec::new();
+        for step in self.walk() {
+            let Step::Leave(expr, _) = step else {
+                continue;
+            };
+            let node = match expr {
+                Number(num, id, span) => Number(*num, *id, *span),
+                Variable(name, id, span) => Variable(name.clone(), *id, *span),
+                Unary(op, _, id, span) => {
+                    let operand = built.pop().unwrap();
+                    Unary(*op, Box::new(operand), *id, *span)
+                }
+                Binary(op, _, _, id, span) => {
+                    let rhs = built.pop().unwrap();
+                    let lhs = built.pop().unwrap();
+                    Binary(*op, Box::new(lhs), Box::new(rhs), *id, *span)
+                }
+                Call(callee, args, id, span) => {
+                    let args = built.split_off(built.len() - args.len());
+                    Call(callee.clone(), args, *id, *span)
+                }
+                Error(id, span) => Error(*id, *span),
+            };
+            built.push(node);
+        }
+        built.pop().unwrap()
+    }
+}
+
+// compared node by node along `walk`, the walks stay in step as long as
+// the nodes entered so far have the same number of children
+impl PartialEq for ExpressionAST {
+    fn eq(&self, other: &Self) -> bool {
+        use ExpressionAST::*;
+        let node_eq = |a: &Self, b: &Self| match (a, b) {
+            (Number(a, _, sa), Number(b, _, sb)) => a == b && sa == sb,
+            (Variable(a, _, sa), Variable(b, _, sb)) => a == b && sa == sb,
+            (Unary(a, _, _, sa), Unary(b, _, _, sb)) => a == b && sa == sb,
+            (Binary(a, _, _, _, sa), Binary(b, _, _, _, sb)) => a == b && sa == sb,
+            (Call(a, aa, _, sa), Call(b, ba, _, sb)) => a == b && aa.len() == ba.len() && sa == sb,
+            (Error(_, sa), Error(_, sb)) => sa == sb,
+            _ => false,
+        };
+        self.walk().zip(other.walk()).all(|steps| match steps {
+            (Step::Enter(a, _), Step::Enter(b, _)) => node_eq(a, b),
+            (Step::Leave(..), Step::Leave(..)) => true,
+            _ => false,
+        })
+    }
+}
+
+// a step of `ExpressionAST::walk`, the node with its parent and its index
+// among the children of the parent, none for the root
+#[derive(Debug, Clone, Copy)]
+pub enum Step<'a> {
+    Enter(&'a ExpressionAST, Option<(&'a ExpressionAST, usize)>),
+    Leave(&'a ExpressionAST, Option<(&'a ExpressionAST, usize)>),
+}
+
+// the nodes of an expression depth first, each entered before and left
+// after its children, see `ExpressionAST::walk`
+pub struct Walk<'a>(Vec<Step<'a>>);
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = Step<'a>;
+
+    fn next(&mut self) -> Option<Step<'a>> {
+        let step = self.0.pop()?;
+        if let Step::Enter(expr, parent) = step {
+            self.0.push(Step::Leave(expr, parent));
+            let child = |i, child| Step::Enter(child, Some((expr, i)));
+            match expr {
+                ExpressionAST::Unary(_, operand, ..) => self.0.push(child(0, operand)),
+                ExpressionAST::Binary(_, lhs, rhs, ..) => {
+                    self.0.push(child(1, rhs));
+                    self.0.push(child(0, lhs));
+                }
+                ExpressionAST::Call(_, args, ..) => self
+                    .0
+                    .extend(args.iter().enumerate().rev().map(|(i, arg)| child(i, arg))),
+                _ => {}
+            }
+        }
+        Some(step)
+    }
+}
+
+impl ExpressionAST {
+    // nodes built outside the parser, with NodeId::DUMMY
+    pub fn number(value: f64, span: Span) -> Self {
+        ExpressionAST::Number(value, NodeId::DUMMY, span)
+    }
+
+    pub fn variable(name: impl Into<String>, span: Span) -> Self {
+        ExpressionAST::Variable(name.into(), NodeId::DUMMY, span)
+    }
+
+    pub fn unary(op: char, operand: ExpressionAST, span: Span) -> Self {
+        ExpressionAST::Unary(op, Box::new(operand), NodeId::DUMMY, span)
+    }
+
+    pub fn binary(op: char, lhs: ExpressionAST, rhs: ExpressionAST, span: Span) -> Self {
+        ExpressionAST::Binary(op, Box::new(lhs), Box::new(rhs), NodeId::DUMMY, span)
+    }
+
+    pub fn call(callee: impl Into<String>, args: Vec<ExpressionAST>, span: Span) -> Self {
+        ExpressionAST::Call(callee.into(), args, NodeId::DUMMY, span)
+    }
+
+    pub fn error(span: Span) -> Self {
+        ExpressionAST::Error(NodeId::DUMMY, span)
+    }
+
+    pub fn id(&self) -> NodeId {
+        match self {
+            ExpressionAST::Number(.., id, _)
+            | ExpressionAST::Variable(.., id, _)
+            | ExpressionAST::Unary(.., id, _)
+            | ExpressionAST::Binary(.., id, _)
+            | ExpressionAST::Call(.., id, _)
+            | ExpressionAST::Error(id, _) => *id,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            ExpressionAST::Number(.., span)
+            | ExpressionAST::Variable(.., span)
+            | ExpressionAST::Unary(.., span)
+            | ExpressionAST::Binary(.., span)
+            | ExpressionAST::Call(.., span)
+            | ExpressionAST::Error(.., span) => *span,
+        }
+    }
+
+    // the nodes depth first without recursion, a chain of operators nests
+    // as deep as it is long, walkers of the whole tree go through here
+    // rather than calling themselves on the children
+    pub fn walk(&self) -> Walk<'_> {
+        Walk(vec![Step::Enter(self, None)])
+    }
+
+    // the node, leaving an error hole in its place, e.g. to fold a child
+    // in place, nodes can't be moved out of as they have a `Drop`
+    pub fn take(&mut self) -> ExpressionAST {
+        core::mem::replace(self, ExpressionAST::error(Span::default()))
+    }
+
+    // child `i` in walking order
+    pub(crate) fn child_mut(&mut self, i: usize) -> Option<&mut ExpressionAST> {
+        match (self, i) {
+            (ExpressionAST::Unary(_, operand, ..), 0) => Some(operand),
+            (ExpressionAST::Binary(_, lhs, ..), 0) => Some(lhs),
+            (ExpressionAST::Binary(_, _, rhs, ..), 1) => Some(rhs),
+            (ExpressionAST::Call(_, args, ..), i) => args.get_mut(i),
+            _ => None,
+        }
+    }
+
+    fn has_children(&self) -> bool {
+        match self {
+            ExpressionAST::Unary(..) | ExpressionAST::Binary(..) => true,
+            ExpressionAST::Call(_, args, ..) => !args.is_empty(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn span_mut(&mut self) -> &mut Span {
+        match self {
+            ExpressionAST::Number(.., span)
+            | ExpressionAST::Variable(.., span)
+            | ExpressionAST::Unary(.., span)
+            | ExpressionAST::Binary(.., span)
+            | ExpressionAST::Call(.., span)
+            | ExpressionAST::Error(.., span) => span,
+        }
+    }
+}
+
+// PrototypeAST - represents the "prototype" for a function
+// captures - names, argument names and the doc comment ('##' lines)
+// preceding the 'def' or 'extern'
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrototypeAST(
+    pub(crate) String,
+    pub(crate) Vec<String>,
+    pub(crate) Option<String>,
+    pub(crate) NodeId,
+    pub(crate) Span,
+);
+
+impl PartialEq for PrototypeAST {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 && self.1 == other.1 && self.2 == other.2 && self.4 == other.4
+    }
+}
+
+impl PrototypeAST {
+    // prototype without a doc comment, with NodeId::DUMMY
+    pub fn new(name: impl Into<String>, params: Vec<String>, span: Span) -> Self {
+        PrototypeAST(name.into(), params, None, NodeId::DUMMY, span)
+    }
+
+    pub fn with_doc(mut self, doc: impl Into<String>) -> Self {
+        self.2 = Some(doc.into());
+        self
+    }
+
+    // empty for anonymous functions
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.1
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.2.as_deref()
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.3
+    }
+
+    pub fn span(&self) -> Span {
+        self.4
+    }
+}
+
+// FunctionAST - represent function definition
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionAST(
+    pub(crate) PrototypeAST,
+    pub(crate) ExpressionAST,
+    pub(crate) NodeId,
+    pub(crate) Span,
+);
+
+impl PartialEq for FunctionAST {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 && self.1 == other.1 && self.3 == other.3
+    }
+}
+
+impl FunctionAST {
+    // function with NodeId::DUMMY
+    pub fn new(proto: PrototypeAST, body: ExpressionAST, span: Span) -> Self {
+        FunctionAST(proto, body, NodeId::DUMMY, span)
+    }
+
+    // wrap a top-level expression into a function without name and args
+    pub fn anonymous(expr: ExpressionAST) -> Self {
+        let span = expr.span();
+        FunctionAST::new(PrototypeAST::new("", Vec::new(), span), expr, span)
+    }
+
+    pub fn proto(&self) -> &PrototypeAST {
+        &self.0
+    }
+
+    pub fn body(&self) -> &ExpressionAST {
+        &self.1
+    }
+
+    // e.g. for passes rewriting the body in place
+    pub fn body_mut(&mut self) -> &mut ExpressionAST {
+        &mut self.1
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.2
+    }
+
+    pub fn span(&self) -> Span {
+        self.3
+    }
+}
+
+// Item - top-level entry of a program
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Item {
+    Function(FunctionAST), // def
+    Extern(PrototypeAST),  // extern
+    Expr(ExpressionAST),   // top-level expression
+}
+
+impl Item {
+    pub fn span(&self) -> Span {
+        match self {
+            Item::Function(func) => func.3,
+            Item::Extern(proto) => proto.4,
+            Item::Expr(expr) => expr.span(),
+        }
+    }
+
+    // name of the function or extern, None for top-level expressions
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Item::Function(FunctionAST(proto, ..)) | Item::Extern(proto) if !proto.0.is_empty() => {
+                Some(&proto.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{item_to_sexpr, to_sexpr, ExpressionAST, FunctionAST, Item, NodeId, PrototypeAST};
+    use crate::lexer::Span;
+
+    #[test]
+    fn node_constructors() {
+        let span = Span::default();
+        let x = || ExpressionAST::variable("x", span);
+        let expr = ExpressionAST::binary(
+            '+',
+            ExpressionAST::unary('-', x(), span),
+            ExpressionAST::call("f", vec![x(), ExpressionAST::number(2.0, span)], span),
+            span,
+        );
+        assert_eq!(expr.id(), NodeId::DUMMY);
+        assert_eq!(
+            to_sexpr(&expr),
+            "(binary + (unary - (var x)) (call f (var x) (num 2)))"
+        );
+        assert_eq!(to_sexpr(&ExpressionAST::error(span)), "(error)");
+
+        let proto = PrototypeAST::new("g", vec!["x".into()], span).with_doc("negated");
+        let mut func = FunctionAST::new(proto, expr, span);
+        assert_eq!(func.proto().name(), "g");
+        assert_eq!(func.proto().params(), ["x"]);
+        assert_eq!(func.proto().doc(), Some("negated"));
+        *func.body_mut() = ExpressionAST::unary('-', func.body().clone(), span);
+        assert!(to_sexpr(func.body()).starts_with("(unary - (binary +"));
+        assert_eq!((func.id(), func.span()), (NodeId::DUMMY, span));
+
+        let func = Item::Function(func);
+        assert_eq!(func.name(), Some("g"));
+        assert!(item_to_sexpr(&func).starts_with("(def g (x) (unary -"));
+        assert_eq!(Item::Expr(x()).name(), None);
+    }
+}
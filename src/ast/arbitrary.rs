@@ -0,0 +1,160 @@
+use super::{ExpressionAST, FunctionAST, Item, NodeId, PrototypeAST};
+use crate::lexer::arbitrary::{identifier as name, number};
+use crate::lexer::Span;
+use crate::operator::OperatorTable;
+use ::arbitrary::{Arbitrary, Result, Unstructured};
+
+// random asts for fuzzing and property tests
+//
+// only trees the printer can write back as source are generated: names
+// are short identifiers that are never keywords, numbers are small and
+// finite, operators come from the default table and there are no error
+// holes, spans and node ids are defaults
+
+// nesting depth of generated expressions
+const MAX_DEPTH: usize = 8;
+
+fn operator(u: &mut Unstructured, is_op: impl Fn(char) -> bool) -> Result<char> {
+    let ops: Vec<char> = (0..128u8).map(char::from).filter(|&c| is_op(c)).collect();
+    Ok(*u.choose(&ops)?)
+}
+
+fn expr(u: &mut Unstructured, depth: usize) -> Result<ExpressionAST> {
+    let span = Span::default();
+    let ops = OperatorTable::default();
+
+    // leaves only once the depth or the input runs out
+    let kind = if depth == 0 || u.is_empty() {
+        u.int_in_range(0..=1)?
+    } else {
+        u.int_in_range(0..=4)?
+    };
+    Ok(match kind {
+        0 => ExpressionAST::number(number(u)?, span),
+        1 => ExpressionAST::variable(name(u)?, span),
+        2 => {
+            let op = operator(u, |c| ops.is_unary(c))?;
+            ExpressionAST::unary(op, expr(u, depth - 1)?, span)
+        }
+        3 => {
+            let op = operator(u, |c| ops.get(c).is_some())?;
+            let lhs = expr(u, depth - 1)?;
+            let rhs = expr(u, depth - 1)?;
+            ExpressionAST::binary(op, lhs, rhs, span)
+        }
+        _ => {
+            let args = (0..u.int_in_range(0..=3)?)
+                .map(|_| expr(u, depth - 1))
+                .collect::<Result<_>>()?;
+            ExpressionAST::call(name(u)?, args, span)
+        }
+    })
+}
+
+// one to two lines of words, lines that print back the same
+fn doc(u: &mut Unstructured) -> Result<Option<String>> {
+    if !u.arbitrary()? {
+        return Ok(None);
+    }
+    let lines = (0..u.int_in_range(1..=2)?)
+        .map(|_| {
+            let words = (0..u.int_in_range(1..=3)?)
+                .map(|_| name(u))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(words.join(" "))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(lines.join("\n")))
+}
+
+impl<'a> Arbitrary<'a> for ExpressionAST {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        expr(u, MAX_DEPTH)
+    }
+}
+
+impl<'a> Arbitrary<'a> for PrototypeAST {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let params = (0..u.int_in_range(0..=3)?)
+            .map(|_| name(u))
+            .collect::<Result<_>>()?;
+        Ok(PrototypeAST(
+            name(u)?,
+            params,
+            doc(u)?,
+            NodeId::DUMMY,
+            Span::default(),
+        ))
+    }
+}
+
+// always named, anonymous functions print as a top-level expression
+impl<'a> Arbitrary<'a> for FunctionAST {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(FunctionAST::new(
+            u.arbitrary()?,
+            u.arbitrary()?,
+            Span::default(),
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Item {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Item::Function(u.arbitrary()?),
+            1 => Item::Extern(u.arbitrary()?),
+            _ => Item::Expr(u.arbitrary()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST};
+    use crate::fold::{self, Fold};
+    use crate::fuzz::fuzz;
+    use crate::lexer::Span;
+    use crate::parser::parse_file;
+    use arbitrary::{Arbitrary, Unstructured};
+
+    // reset all spans, generated trees have none
+    struct ClearSpans;
+
+    impl Fold for ClearSpans {
+        fn fold_function(&mut self, func: FunctionAST) -> FunctionAST {
+            let mut func = fold::walk_function(self, func);
+            func.3 = Span::default();
+            func
+        }
+
+        fn fold_prototype(&mut self, mut proto: PrototypeAST) -> PrototypeAST {
+            proto.4 = Span::default();
+            proto
+        }
+
+        fn fold_expr(&mut self, mut expr: ExpressionAST) -> ExpressionAST {
+            *expr.span_mut() = Span::default();
+            expr
+        }
+    }
+
+    #[test]
+    fn fuzz_print_round_trip() {
+        fuzz(0x2545f4914f6cdd1d, 2000, |_, random| {
+            let bytes = random.bytes(256);
+            let item = Item::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+
+            // print, reparse and compare, spans and ids aside
+            let source = item.to_string();
+            let items = parse_file(&source).unwrap_or_else(|errors| {
+                panic!("{:?} printed as {:?}: {:?}", item, source, errors)
+            });
+            let items: Vec<_> = items
+                .into_iter()
+                .map(|item| ClearSpans.fold_item(item))
+                .collect();
+            assert_eq!(items, vec![item], "printed as {:?}", source);
+        });
+    }
+}
@@ -0,0 +1,145 @@
+use super::{ExpressionAST, Item, PrototypeAST, Step};
+use std::fmt::Write;
+
+// graphviz dot graph of an expression tree, one node per ast node with
+// operands in source order, e.g. `dot -Tsvg` renders it
+pub fn to_dot(expr: &ExpressionAST) -> String {
+    let mut out = String::from("digraph ast {\n    ordering=out;\n    node [shape=box];\n");
+    write_node(&mut out, expr, &mut 0);
+    out.push_str("}\n");
+    out
+}
+
+// dot graph of a whole program, a node per item in source order, with
+// the tree of its body below it
+pub fn program_to_dot(items: &[Item]) -> String {
+    let mut out = String::from("digraph ast {\n    ordering=out;\n    node [shape=box];\n");
+    let mut next = 0;
+    for item in items {
+        let node = next;
+        next += 1;
+        let (label, body) = match item {
+            Item::Function(func) if func.0 .0.is_empty() => {
+                ("top-level expression".into(), Some(&func.1))
+            }
+            Item::Function(func) => (format!("def {}", signature(&func.0)), Some(&func.1)),
+            Item::Extern(proto) => (format!("extern {}", signature(proto)), None),
+            Item::Expr(expr) => ("top-level expression".into(), Some(expr)),
+        };
+        writeln!(
+            out,
+            "    n{} [label=\"{}\", shape=note];",
+            node,
+            escape(&label)
+        )
+        .unwrap();
+        if let Some(body) = body {
+            let child = write_node(&mut out, body, &mut next);
+            writeln!(out, "    n{} -> n{};", node, child).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// `name(params)` of a prototype
+fn signature(proto: &PrototypeAST) -> String {
+    format!("{}({})", proto.0, proto.1.join(", "))
+}
+
+// write `expr` and the edges to its operands, returns its node number
+// the edge to a node follows the subtree below it
+fn write_node(out: &mut String, expr: &ExpressionAST, next: &mut usize) -> usize {
+    let root = *next;
+    // node numbers of the nodes entered and not left yet
+    let mut nodes = Vec::new();
+    for step in expr.walk() {
+        match step {
+            Step::Enter(expr, _) => {
+                let node = *next;
+                *next += 1;
+                nodes.push(node);
+
+                // operators are circles, leaves and calls the default boxes
+                let (label, style) = match expr {
+                    ExpressionAST::Number(num, ..) => (num.to_string(), ""),
+                    ExpressionAST::Variable(name, ..) => (name.clone(), ""),
+                    ExpressionAST::Unary(op, ..) | ExpressionAST::Binary(op, ..) => {
+                        (op.to_string(), ", shape=circle")
+                    }
+                    ExpressionAST::Call(callee, ..) => (format!("{}()", callee), ""),
+                    ExpressionAST::Error(..) => ("<error>".into(), ""),
+                };
+                writeln!(
+                    out,
+                    "    n{} [label=\"{}\"{}];",
+                    node,
+                    escape(&label),
+                    style
+                )
+                .unwrap();
+            }
+            Step::Leave(..) => {
+                let child = nodes.pop().unwrap();
+                if let Some(node) = nodes.last() {
+                    writeln!(out, "    n{} -> n{};", node, child).unwrap();
+                }
+            }
+        }
+    }
+    root
+}
+
+// quote `label` for a dot string
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{program_to_dot, to_dot};
+    use crate::parser::{parse_expr, parse_file};
+
+    #[test]
+    fn dot_graph() {
+        let expr = parse_expr("a + 2 * f(b)").unwrap();
+        assert_eq!(
+            to_dot(&expr),
+            "digraph ast {
+    ordering=out;
+    node [shape=box];
+    n0 [label=\"+\", shape=circle];
+    n1 [label=\"a\"];
+    n0 -> n1;
+    n2 [label=\"*\", shape=circle];
+    n3 [label=\"2\"];
+    n2 -> n3;
+    n4 [label=\"f()\"];
+    n5 [label=\"b\"];
+    n4 -> n5;
+    n2 -> n4;
+    n0 -> n2;
+}
+"
+        );
+
+        let items = parse_file("extern sin(x); def f(x) -x; 1").unwrap();
+        assert_eq!(
+            program_to_dot(&items),
+            "digraph ast {
+    ordering=out;
+    node [shape=box];
+    n0 [label=\"extern sin(x)\", shape=note];
+    n1 [label=\"def f(x)\", shape=note];
+    n2 [label=\"-\", shape=circle];
+    n3 [label=\"x\"];
+    n2 -> n3;
+    n1 -> n2;
+    n4 [label=\"top-level expression\", shape=note];
+    n5 [label=\"1\"];
+    n4 -> n5;
+}
+"
+        );
+    }
+}
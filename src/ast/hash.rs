@@ -0,0 +1,165 @@
+use super::{ExpressionAST, FunctionAST, Item, PrototypeAST, Step};
+
+// structural hash of the ast, equal for definitions that only differ in
+// where they are, e.g. moved by an edit above them or reformatted, so a
+// cache keyed by it survives edits that don't change what is defined
+//
+// only the structure counts, like in the s-expression form: spans, node
+// ids and doc comments are left out
+// the hash is FNV-1a over a prefix-free encoding of the nodes, the same
+// on every platform and from one build to the next, so it can be stored,
+// e.g. by a test runner remembering what passed
+
+pub fn hash_function(func: &FunctionAST) -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.function(func);
+    hasher.0
+}
+
+pub fn hash_item(item: &Item) -> u64 {
+    let mut hasher = Fnv::new();
+    match item {
+        Item::Function(func) => {
+            hasher.tag(b'd');
+            hasher.function(func);
+        }
+        Item::Extern(proto) => {
+            hasher.tag(b'e');
+            hasher.proto(proto);
+        }
+        Item::Expr(expr) => {
+            hasher.tag(b'x');
+            hasher.expr(expr);
+        }
+    }
+    hasher.0
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Fnv(Self::OFFSET)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn tag(&mut self, tag: u8) {
+        self.bytes(&[tag]);
+    }
+
+    fn len(&mut self, len: usize) {
+        self.bytes(&(len as u64).to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    fn char(&mut self, c: char) {
+        self.bytes(&(c as u32).to_le_bytes());
+    }
+
+    fn function(&mut self, func: &FunctionAST) {
+        self.proto(&func.0);
+        self.expr(&func.1);
+    }
+
+    fn proto(&mut self, proto: &PrototypeAST) {
+        self.str(&proto.0);
+        self.len(proto.1.len());
+        for param in &proto.1 {
+            self.str(param);
+        }
+    }
+
+    // pre-order, each node before its children
+    fn expr(&mut self, expr: &ExpressionAST) {
+        for step in expr.walk() {
+            let Step::Enter(expr, _) = step else {
+                continue;
+            };
+            match expr {
+                ExpressionAST::Number(value, ..) => {
+                    self.tag(b'n');
+                    self.bytes(&value.to_bits().to_le_bytes());
+                }
+                ExpressionAST::Variable(name, ..) => {
+                    self.tag(b'v');
+                    self.str(name);
+                }
+                ExpressionAST::Unary(op, ..) => {
+                    self.tag(b'u');
+                    self.char(*op);
+                }
+                ExpressionAST::Binary(op, ..) => {
+                    self.tag(b'b');
+                    self.char(*op);
+                }
+                ExpressionAST::Call(callee, args, ..) => {
+                    self.tag(b'c');
+                    self.str(callee);
+                    self.len(args.len());
+                }
+                ExpressionAST::Error(..) => self.tag(b'?'),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hash_function, hash_item};
+    use crate::ast::{FunctionAST, Item};
+    use crate::parser::parse_file;
+
+    fn function(source: &str) -> FunctionAST {
+        match parse_file(source).unwrap().remove(0) {
+            Item::Function(func) => func,
+            item => panic!("{:?}", item),
+        }
+    }
+
+    #[test]
+    fn structural_hash() {
+        let func = function("def f(x y) x * (y + 2);");
+        // moved, reformatted, commented, documented
+        let moved = function("\n\n## adds\ndef f(x y)\n  x *  # twice\n  (y+2);");
+        assert_ne!(func, moved);
+        assert_eq!(hash_function(&func), hash_function(&moved));
+
+        for other in [
+            "def g(x y) x * (y + 2);",
+            "def f(y x) x * (y + 2);",
+            "def f(x y) x * y + 2;",
+            "def f(x y) x * (y + 3);",
+            "def f(x y) x - (y + 2);",
+            "def f(x y z) x * (y + 2);",
+        ] {
+            assert_ne!(
+                hash_function(&func),
+                hash_function(&function(other)),
+                "{}",
+                other
+            );
+        }
+
+        // an extern is not the function without a body, nor a call the
+        // variable of the same name
+        let items = parse_file("extern f(x); def f(x) x; f; f()").unwrap();
+        let hashes: Vec<_> = items.iter().map(hash_item).collect();
+        assert!((1..4).all(|i| !hashes[..i].contains(&hashes[i])));
+        assert_eq!(hash_function(&func), hash_function(&func.clone()));
+
+        // hashes are stored, they may not change from one build to the next
+        assert_eq!(hash_function(&func), 0x8178_97b1_36fb_18e5);
+    }
+}
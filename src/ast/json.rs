@@ -0,0 +1,369 @@
+use super::{ExpressionAST, FunctionAST, Item, NodeId, PrototypeAST, Step};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Position, Span};
+use std::fmt::Write;
+
+// json export of the ast
+//
+// the schema is stable, fields are only ever added, a breaking change
+// bumps `version`
+//
+//   program   := {"version": 1, "items": [item, ...]}
+//   errors    := {"version": 1, "errors": [error, ...]}
+//   error     := {"severity": "error" | "warning" | "note",
+//                 "code": string | null, "message": string,
+//                 "position": position, "span": span,
+//                 "labels": [{"message": string, "span": span}, ...],
+//                 "help": [string, ...]}
+//   item      := function
+//              | {"kind": "extern", "prototype": prototype}
+//              | {"kind": "expr", "expr": expr}
+//   function  := {"kind": "function", "id": id, "prototype": prototype,
+//                 "body": expr, "span": span}
+//   prototype := {"kind": "prototype", "id": id, "name": string,
+//                 "params": [string, ...], "doc": string | null, "span": span}
+//   expr      := {"kind": "number", "id": id, "value": number | null, "span": span}
+//              | {"kind": "variable", "id": id, "name": string, "span": span}
+//              | {"kind": "unary", "id": id, "op": string, "operand": expr,
+//                 "span": span}
+//              | {"kind": "binary", "id": id, "op": string, "lhs": expr,
+//                 "rhs": expr, "span": span}
+//              | {"kind": "call", "id": id, "callee": string,
+//                 "args": [expr, ...], "span": span}
+//              | {"kind": "error", "id": id, "span": span}
+//   span      := {"start": position, "end": position}
+//   position  := {"offset": number, "line": number, "column": number}
+//   id        := number | null (node not built by the parser)
+//
+// offsets are in bytes, lines and columns start at 1, the name of an
+// anonymous function (top-level expression) is "", a number that is not
+// finite has value null
+
+const VERSION: u32 = 1;
+
+// json of a single function
+pub fn to_json(func: &FunctionAST) -> String {
+    let mut out = String::new();
+    write_function(&mut out, func);
+    out
+}
+
+// json of a whole program
+pub fn program_to_json(items: &[Item]) -> String {
+    let mut out = String::new();
+    write!(out, "{{\"version\":{},\"items\":[", VERSION).unwrap();
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_item(&mut out, item);
+    }
+    out.push_str("]}");
+    out
+}
+
+// json of the errors of a program, e.g. instead of it when it failed to
+// parse
+pub fn errors_to_json(errors: &[Diagnostic]) -> String {
+    let mut out = String::new();
+    write!(out, "{{\"version\":{},\"errors\":[", VERSION).unwrap();
+    for (i, err) in errors.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_diagnostic(&mut out, err);
+    }
+    out.push_str("]}");
+    out
+}
+
+// json of a single error, as in the errors of a program
+pub fn diagnostic_to_json(diagnostic: &Diagnostic) -> String {
+    let mut out = String::new();
+    write_diagnostic(&mut out, diagnostic);
+    out
+}
+
+fn write_diagnostic(out: &mut String, diagnostic: &Diagnostic) {
+    write!(out, "{{\"severity\":\"{}\",\"code\":", diagnostic.severity).unwrap();
+    match diagnostic.code {
+        Some(code) => write_str(out, code),
+        None => out.push_str("null"),
+    }
+    out.push_str(",\"message\":");
+    write_str(out, &diagnostic.message);
+    out.push_str(",\"position\":");
+    write_position(out, diagnostic.pos());
+    out.push_str(",\"labels\":[");
+    for (i, label) in diagnostic.labels.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"message\":");
+        write_str(out, &label.message);
+        write_span(out, label.span);
+    }
+    out.push_str("],\"help\":[");
+    for (i, help) in diagnostic.help.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_str(out, help);
+    }
+    out.push(']');
+    write_span(out, diagnostic.span);
+}
+
+fn write_item(out: &mut String, item: &Item) {
+    match item {
+        Item::Function(func) => write_function(out, func),
+        Item::Extern(proto) => {
+            out.push_str("{\"kind\":\"extern\",\"prototype\":");
+            write_prototype(out, proto);
+            out.push('}');
+        }
+        Item::Expr(expr) => {
+            out.push_str("{\"kind\":\"expr\",\"expr\":");
+            write_expr(out, expr);
+            out.push('}');
+        }
+    }
+}
+
+fn write_function(out: &mut String, func: &FunctionAST) {
+    let FunctionAST(proto, body, id, span) = func;
+    write_node(out, "function", *id);
+    out.push_str(",\"prototype\":");
+    write_prototype(out, proto);
+    out.push_str(",\"body\":");
+    write_expr(out, body);
+    write_span(out, *span);
+}
+
+fn write_prototype(out: &mut String, proto: &PrototypeAST) {
+    let PrototypeAST(name, params, doc, id, span) = proto;
+    write_node(out, "prototype", *id);
+    out.push_str(",\"name\":");
+    write_str(out, name);
+    out.push_str(",\"params\":[");
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_str(out, param);
+    }
+    out.push_str("],\"doc\":");
+    match doc {
+        Some(doc) => write_str(out, doc),
+        None => out.push_str("null"),
+    }
+    write_span(out, *span);
+}
+
+fn write_expr(out: &mut String, expr: &ExpressionAST) {
+    for step in expr.walk() {
+        match step {
+            Step::Enter(expr, parent) => {
+                match parent {
+                    Some((ExpressionAST::Binary(..), 1)) => out.push_str(",\"rhs\":"),
+                    Some((ExpressionAST::Call(..), i)) if i > 0 => out.push(','),
+                    _ => {}
+                }
+                write_fields(out, expr);
+            }
+            Step::Leave(expr, _) => {
+                if let ExpressionAST::Call(..) = expr {
+                    out.push(']');
+                }
+                write_span(out, expr.span());
+            }
+        }
+    }
+}
+
+// open the object of `expr` and write its fields up to its first child
+fn write_fields(out: &mut String, expr: &ExpressionAST) {
+    match expr {
+        ExpressionAST::Number(num, id, _) => {
+            write_node(out, "number", *id);
+            if num.is_finite() {
+                write!(out, ",\"value\":{:?}", num).unwrap();
+            } else {
+                out.push_str(",\"value\":null");
+            }
+        }
+        ExpressionAST::Variable(name, id, _) => {
+            write_node(out, "variable", *id);
+            out.push_str(",\"name\":");
+            write_str(out, name);
+        }
+        ExpressionAST::Unary(op, _, id, _) => {
+            write_node(out, "unary", *id);
+            out.push_str(",\"op\":");
+            write_str(out, op.encode_utf8(&mut [0; 4]));
+            out.push_str(",\"operand\":");
+        }
+        ExpressionAST::Binary(op, _, _, id, _) => {
+            write_node(out, "binary", *id);
+            out.push_str(",\"op\":");
+            write_str(out, op.encode_utf8(&mut [0; 4]));
+            out.push_str(",\"lhs\":");
+        }
+        ExpressionAST::Call(callee, _, id, _) => {
+            write_node(out, "call", *id);
+            out.push_str(",\"callee\":");
+            write_str(out, callee);
+            out.push_str(",\"args\":[");
+        }
+        ExpressionAST::Error(id, _) => write_node(out, "error", *id),
+    }
+}
+
+// open a node object with its kind and id
+fn write_node(out: &mut String, kind: &str, id: NodeId) {
+    write!(out, "{{\"kind\":\"{}\",\"id\":", kind).unwrap();
+    if id == NodeId::DUMMY {
+        out.push_str("null");
+    } else {
+        write!(out, "{}", id.0).unwrap();
+    }
+}
+
+// last field of a node object, closes it
+fn write_span(out: &mut String, span: Span) {
+    out.push_str(",\"span\":{\"start\":");
+    write_position(out, span.start);
+    out.push_str(",\"end\":");
+    write_position(out, span.end);
+    out.push_str("}}");
+}
+
+fn write_position(out: &mut String, pos: Position) {
+    write!(
+        out,
+        "{{\"offset\":{},\"line\":{},\"column\":{}}}",
+        pos.offset, pos.line, pos.column
+    )
+    .unwrap();
+}
+
+pub(crate) fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::{errors_to_json, program_to_json, to_json};
+    use crate::ast::{FunctionAST, Item};
+    use crate::diagnostic::Diagnostic;
+    use crate::parser::{parse_file, Parser};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn json_function() {
+        let Some(Ok(Item::Function(func))) = Parser::from_str("def f(x) x + 1").parse_item() else {
+            panic!("expected a function");
+        };
+
+        let pos = |offset, column| json!({"offset": offset, "line": 1, "column": column});
+        let span = |lo, hi| json!({"start": pos(lo, lo + 1), "end": pos(hi, hi + 1)});
+        let value: Value = serde_json::from_str(&to_json(&func)).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "kind": "function",
+                "id": 4,
+                "prototype": {
+                    "kind": "prototype",
+                    "id": 0,
+                    "name": "f",
+                    "params": ["x"],
+                    "doc": null,
+                    "span": span(4, 8),
+                },
+                "body": {
+                    "kind": "binary",
+                    "id": 3,
+                    "op": "+",
+                    "lhs": {"kind": "variable", "id": 1, "name": "x", "span": span(9, 10)},
+                    "rhs": {"kind": "number", "id": 2, "value": 1.0, "span": span(13, 14)},
+                    "span": span(9, 14),
+                },
+                "span": span(0, 14),
+            })
+        );
+
+        let anon = FunctionAST::anonymous(func.1);
+        let value: Value = serde_json::from_str(&to_json(&anon)).unwrap();
+        assert_eq!(value["id"], Value::Null);
+        assert_eq!(value["prototype"]["name"], "");
+    }
+
+    #[test]
+    fn json_program() {
+        let items = parse_file("## say \"hi\"\\\n\textern g(a); g(1, 2.5); 3 < 4").unwrap();
+        let value: Value = serde_json::from_str(&program_to_json(&items)).unwrap();
+
+        assert_eq!(value["version"], 1);
+        let items = value["items"].as_array().unwrap();
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(items[0]["kind"], "extern");
+        assert_eq!(items[0]["prototype"]["doc"], "say \"hi\"\\");
+        assert_eq!(items[1]["kind"], "expr");
+        assert_eq!(items[1]["expr"]["kind"], "call");
+        assert_eq!(items[1]["expr"]["args"][1]["value"], 2.5);
+        assert_eq!(items[2]["expr"]["op"], "<");
+
+        assert_eq!(program_to_json(&[]), r#"{"version":1,"items":[]}"#);
+    }
+
+    #[test]
+    fn json_errors() {
+        let errors = parse_file("def f(x) x +;\n(1").unwrap_err();
+        let errors: Vec<_> = errors.into_iter().map(Diagnostic::from).collect();
+        let pos = |offset, line, column| json!({"offset": offset, "line": line, "column": column});
+        let value: Value = serde_json::from_str(&errors_to_json(&errors)).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "version": 1,
+                "errors": [
+                    {
+                        "severity": "error",
+                        "code": null,
+                        "message": "expected expression, found ';'",
+                        "position": pos(12, 1, 13),
+                        "span": {"start": pos(12, 1, 13), "end": pos(12, 1, 13)},
+                        "labels": [],
+                        "help": [],
+                    },
+                    {
+                        "severity": "error",
+                        "code": null,
+                        "message": "expected ')' to close '(', found end of input",
+                        "position": pos(16, 2, 3),
+                        "span": {"start": pos(16, 2, 3), "end": pos(16, 2, 3)},
+                        "labels": [{
+                            "message": "'(' opened here",
+                            "span": {"start": pos(14, 2, 1), "end": pos(14, 2, 1)},
+                        }],
+                        "help": [],
+                    },
+                ],
+            })
+        );
+    }
+}
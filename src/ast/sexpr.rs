@@ -0,0 +1,318 @@
+use super::{ExpressionAST, FunctionAST, Item, PrototypeAST, Step};
+use crate::lexer::Span;
+use std::fmt::{self, Write};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+// s-expression form of the ast
+//
+//   expr   := (num 1.5) | (var a) | (unary - expr) | (binary + expr expr)
+//           | (call f expr*) | (error)
+//   item   := (def f (a b) expr) | (extern f (a b)) | expr
+//
+// only the structure is kept, spans, node ids and doc comments are not
+// written and read back as defaults
+
+// error reading an s-expression, offsets are in bytes
+#[derive(PartialEq, Clone, Debug)]
+pub enum SexprError {
+    UnexpectedEof,
+    UnmatchedParen(usize),
+    // a well formed s-expression that is no ast node (printed back)
+    Malformed(String),
+}
+
+pub fn to_sexpr(expr: &ExpressionAST) -> String {
+    let mut out = String::new();
+    write_expr(&mut out, expr);
+    out
+}
+
+pub fn item_to_sexpr(item: &Item) -> String {
+    let mut out = String::new();
+    write_item(&mut out, item);
+    out
+}
+
+// one item per line
+pub fn program_to_sexpr(items: &[Item]) -> String {
+    let mut out = String::new();
+    for item in items {
+        write_item(&mut out, item);
+        out.push('\n');
+    }
+    out
+}
+
+pub fn parse_sexpr(input: &str) -> Result<ExpressionAST, SexprError> {
+    match read_all(input)?.as_slice() {
+        [sexpr] => expr(sexpr),
+        _ => Err(SexprError::Malformed(input.trim().into())),
+    }
+}
+
+pub fn parse_item_sexpr(input: &str) -> Result<Item, SexprError> {
+    match read_all(input)?.as_slice() {
+        [sexpr] => item(sexpr),
+        _ => Err(SexprError::Malformed(input.trim().into())),
+    }
+}
+
+pub fn parse_program_sexpr(input: &str) -> Result<Vec<Item>, SexprError> {
+    read_all(input)?.iter().map(item).collect()
+}
+
+// -------
+// Writing
+// -------
+
+fn write_item(out: &mut String, item: &Item) {
+    match item {
+        Item::Function(FunctionAST(proto, body, ..)) => {
+            out.push_str("(def ");
+            write_prototype(out, proto);
+            out.push(' ');
+            write_expr(out, body);
+            out.push(')');
+        }
+        Item::Extern(proto) => {
+            out.push_str("(extern ");
+            write_prototype(out, proto);
+            out.push(')');
+        }
+        Item::Expr(expr) => write_expr(out, expr),
+    }
+}
+
+fn write_prototype(out: &mut String, proto: &PrototypeAST) {
+    write!(out, "{} ({})", proto.0, proto.1.join(" ")).unwrap();
+}
+
+fn write_expr(out: &mut String, expr: &ExpressionAST) {
+    for step in expr.walk() {
+        match step {
+            Step::Enter(expr, parent) => {
+                if let Some((ExpressionAST::Binary(..), 1) | (ExpressionAST::Call(..), _)) = parent
+                {
+                    out.push(' ');
+                }
+                match expr {
+                    ExpressionAST::Number(num, ..) => write!(out, "(num {}", num).unwrap(),
+                    ExpressionAST::Variable(name, ..) => write!(out, "(var {}", name).unwrap(),
+                    ExpressionAST::Unary(op, ..) => write!(out, "(unary {} ", op).unwrap(),
+                    ExpressionAST::Binary(op, ..) => write!(out, "(binary {} ", op).unwrap(),
+                    ExpressionAST::Call(callee, ..) => write!(out, "(call {}", callee).unwrap(),
+                    ExpressionAST::Error(..) => out.push_str("(error"),
+                }
+            }
+            Step::Leave(..) => out.push(')'),
+        }
+    }
+}
+
+// -------
+// Reading
+// -------
+
+// generic s-expression
+enum Sexpr {
+    Atom(String),
+    List(Vec<Sexpr>),
+}
+
+impl fmt::Display for Sexpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sexpr::Atom(atom) => write!(f, "{}", atom),
+            Sexpr::List(list) => {
+                write!(f, "(")?;
+                for (i, sexpr) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", sexpr)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn read_all(input: &str) -> Result<Vec<Sexpr>, SexprError> {
+    let mut chars = input.char_indices().peekable();
+    let mut all = Vec::new();
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.peek().is_none() {
+            return Ok(all);
+        }
+        all.push(read(&mut chars)?);
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<CharIndices>) {
+    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+}
+
+// sexpr := atom | '(' sexpr* ')'
+fn read(chars: &mut Peekable<CharIndices>) -> Result<Sexpr, SexprError> {
+    skip_whitespace(chars);
+    match chars.next() {
+        None => Err(SexprError::UnexpectedEof),
+        Some((pos, ')')) => Err(SexprError::UnmatchedParen(pos)),
+        Some((_, '(')) => {
+            let mut list = Vec::new();
+            loop {
+                skip_whitespace(chars);
+                if chars.next_if(|&(_, c)| c == ')').is_some() {
+                    return Ok(Sexpr::List(list));
+                }
+                list.push(read(chars)?);
+            }
+        }
+        Some((_, c)) => {
+            let mut atom = c.to_string();
+            while let Some((_, c)) =
+                chars.next_if(|&(_, c)| !c.is_whitespace() && c != '(' && c != ')')
+            {
+                atom.push(c);
+            }
+            Ok(Sexpr::Atom(atom))
+        }
+    }
+}
+
+fn item(sexpr: &Sexpr) -> Result<Item, SexprError> {
+    let Sexpr::List(list) = sexpr else {
+        return Err(SexprError::Malformed(sexpr.to_string()));
+    };
+    match list.as_slice() {
+        [Sexpr::Atom(head), name, params, body] if head == "def" => {
+            let proto =
+                prototype(name, params).ok_or_else(|| SexprError::Malformed(sexpr.to_string()))?;
+            let body = expr(body)?;
+            Ok(Item::Function(FunctionAST::new(
+                proto,
+                body,
+                Span::default(),
+            )))
+        }
+        [Sexpr::Atom(head), name, params] if head == "extern" => {
+            let proto =
+                prototype(name, params).ok_or_else(|| SexprError::Malformed(sexpr.to_string()))?;
+            Ok(Item::Extern(proto))
+        }
+        _ => expr(sexpr).map(Item::Expr),
+    }
+}
+
+fn prototype(name: &Sexpr, params: &Sexpr) -> Option<PrototypeAST> {
+    let (Sexpr::Atom(name), Sexpr::List(params)) = (name, params) else {
+        return None;
+    };
+    let params = params
+        .iter()
+        .map(|param| match param {
+            Sexpr::Atom(param) => Some(param.clone()),
+            Sexpr::List(_) => None,
+        })
+        .collect::<Option<_>>()?;
+    Some(PrototypeAST::new(name.clone(), params, Span::default()))
+}
+
+// operators are single chars
+fn operator(atom: &str) -> Option<char> {
+    let mut chars = atom.chars();
+    match (chars.next(), chars.next()) {
+        (Some(op), None) => Some(op),
+        _ => None,
+    }
+}
+
+fn expr(sexpr: &Sexpr) -> Result<ExpressionAST, SexprError> {
+    use Sexpr::Atom;
+
+    let span = Span::default();
+    let malformed = || SexprError::Malformed(sexpr.to_string());
+
+    let Sexpr::List(list) = sexpr else {
+        return Err(malformed());
+    };
+    match list.as_slice() {
+        [Atom(head), Atom(num)] if head == "num" => {
+            let num = num.parse().map_err(|_| malformed())?;
+            Ok(ExpressionAST::number(num, span))
+        }
+        [Atom(head), Atom(name)] if head == "var" => Ok(ExpressionAST::variable(name, span)),
+        [Atom(head), Atom(op), operand] if head == "unary" => {
+            let op = operator(op).ok_or_else(malformed)?;
+            Ok(ExpressionAST::unary(op, expr(operand)?, span))
+        }
+        [Atom(head), Atom(op), lhs, rhs] if head == "binary" => {
+            let op = operator(op).ok_or_else(malformed)?;
+            Ok(ExpressionAST::binary(op, expr(lhs)?, expr(rhs)?, span))
+        }
+        [Atom(head), Atom(callee), args @ ..] if head == "call" => {
+            let args = args.iter().map(expr).collect::<Result<_, _>>()?;
+            Ok(ExpressionAST::call(callee, args, span))
+        }
+        [Atom(head)] if head == "error" => Ok(ExpressionAST::error(span)),
+        _ => Err(malformed()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        item_to_sexpr, parse_item_sexpr, parse_program_sexpr, parse_sexpr, program_to_sexpr,
+        to_sexpr, SexprError,
+    };
+    use crate::parser::{parse_expr, parse_file};
+
+    #[test]
+    fn sexpr_print() {
+        let expr = parse_expr("a + 1 * f(b, 2.5)").unwrap();
+        assert_eq!(
+            to_sexpr(&expr),
+            "(binary + (var a) (binary * (num 1) (call f (var b) (num 2.5))))"
+        );
+
+        let items = parse_file("## doc\ndef f(x y) x < y; extern g(); g()").unwrap();
+        assert_eq!(
+            program_to_sexpr(&items),
+            "(def f (x y) (binary < (var x) (var y)))\n(extern g ())\n(call g)\n"
+        );
+    }
+
+    #[test]
+    fn sexpr_round_trip() {
+        let source = "def fib(n) fib(n - 1) + fib(n - 2)\nextern sin(x)\n-(1 + 2) * 3 < !sin(0.5)";
+        let text = program_to_sexpr(&parse_file(source).unwrap());
+        let items = parse_program_sexpr(&text).unwrap();
+        assert_eq!(program_to_sexpr(&items), text);
+
+        let item = parse_item_sexpr(" ( extern  f ( a b ) ) ").unwrap();
+        assert_eq!(item_to_sexpr(&item), "(extern f (a b))");
+        assert_eq!(to_sexpr(&parse_sexpr("(error)").unwrap()), "(error)");
+    }
+
+    #[test]
+    fn sexpr_errors() {
+        assert_eq!(parse_sexpr("(num 1"), Err(SexprError::UnexpectedEof));
+        assert_eq!(parse_sexpr("(var a))"), Err(SexprError::UnmatchedParen(7)));
+        assert_eq!(
+            parse_sexpr("(num x)"),
+            Err(SexprError::Malformed("(num x)".into()))
+        );
+        assert_eq!(
+            parse_sexpr("(binary ++ (num 1) (num 2))"),
+            Err(SexprError::Malformed("(binary ++ (num 1) (num 2))".into()))
+        );
+        assert_eq!(
+            parse_item_sexpr("(def f x (num 1))"),
+            Err(SexprError::Malformed("(def f x (num 1))".into()))
+        );
+        assert_eq!(parse_sexpr("a"), Err(SexprError::Malformed("a".into())));
+        assert_eq!(parse_sexpr(""), Err(SexprError::Malformed("".into())));
+    }
+}
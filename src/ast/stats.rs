@@ -0,0 +1,177 @@
+use super::{ExpressionAST, FunctionAST, Item};
+use crate::visit::{self, Visitor};
+use std::fmt;
+
+// size and shape of a program, e.g. to see how close it gets to the
+// parser limits (`ParserConfig::max_depth`)
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Stats {
+    pub nodes: NodeCounts,
+    // deepest expression, a leaf has depth 1
+    pub max_depth: usize,
+    // most arguments of a single call
+    pub max_fan_out: usize,
+    // one entry per definition, in source order
+    pub functions: Vec<FunctionStats>,
+}
+
+// number of nodes of each kind
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct NodeCounts {
+    pub functions: usize,
+    pub externs: usize,
+    pub numbers: usize,
+    pub variables: usize,
+    pub unary: usize,
+    pub binary: usize,
+    pub calls: usize,
+    pub errors: usize,
+}
+
+impl NodeCounts {
+    // expression nodes
+    pub fn exprs(&self) -> usize {
+        self.numbers + self.variables + self.unary + self.binary + self.calls + self.errors
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionStats {
+    pub name: String,
+    pub params: usize,
+    // expression nodes in the body
+    pub size: usize,
+    pub depth: usize,
+}
+
+pub fn stats(items: &[Item]) -> Stats {
+    let mut counter = Counter::default();
+    items.iter().for_each(|item| counter.visit_item(item));
+    counter.stats
+}
+
+#[derive(Default)]
+struct Counter {
+    stats: Stats,
+    depth: usize,
+}
+
+impl Visitor for Counter {
+    fn visit_function(&mut self, func: &FunctionAST) {
+        let before = self.stats.nodes.exprs();
+        let max_depth = std::mem::take(&mut self.stats.max_depth);
+
+        self.stats.nodes.functions += 1;
+        visit::walk_function(self, func);
+
+        let depth = self.stats.max_depth;
+        self.stats.max_depth = depth.max(max_depth);
+        self.stats.functions.push(FunctionStats {
+            name: func.0 .0.clone(),
+            params: func.0 .1.len(),
+            size: self.stats.nodes.exprs() - before,
+            depth,
+        });
+    }
+
+    fn visit_item(&mut self, item: &Item) {
+        if let Item::Extern(_) = item {
+            self.stats.nodes.externs += 1;
+        }
+        visit::walk_item(self, item)
+    }
+
+    fn visit_expr(&mut self, expr: &ExpressionAST) {
+        let nodes = &mut self.stats.nodes;
+        match expr {
+            ExpressionAST::Number(..) => nodes.numbers += 1,
+            ExpressionAST::Variable(..) => nodes.variables += 1,
+            ExpressionAST::Unary(..) => nodes.unary += 1,
+            ExpressionAST::Binary(..) => nodes.binary += 1,
+            ExpressionAST::Call(_, args, ..) => {
+                nodes.calls += 1;
+                self.stats.max_fan_out = self.stats.max_fan_out.max(args.len());
+            }
+            ExpressionAST::Error(..) => nodes.errors += 1,
+        }
+
+        self.depth += 1;
+        self.stats.max_depth = self.stats.max_depth.max(self.depth);
+    }
+
+    fn leave_expr(&mut self, _expr: &ExpressionAST) {
+        self.depth -= 1;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nodes = &self.nodes;
+        writeln!(f, "functions:   {}", nodes.functions)?;
+        writeln!(f, "externs:     {}", nodes.externs)?;
+        writeln!(f, "expressions: {}", nodes.exprs())?;
+        writeln!(f, "  numbers:   {}", nodes.numbers)?;
+        writeln!(f, "  variables: {}", nodes.variables)?;
+        writeln!(f, "  unary:     {}", nodes.unary)?;
+        writeln!(f, "  binary:    {}", nodes.binary)?;
+        writeln!(f, "  calls:     {}", nodes.calls)?;
+        writeln!(f, "  errors:    {}", nodes.errors)?;
+        writeln!(f, "max depth:   {}", self.max_depth)?;
+        writeln!(f, "max fan-out: {}", self.max_fan_out)?;
+        for func in &self.functions {
+            writeln!(
+                f,
+                "def {}/{}: {} nodes, depth {}",
+                func.name, func.params, func.size, func.depth
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{stats, FunctionStats, NodeCounts};
+    use crate::parser::parse_file;
+
+    #[test]
+    fn stats_counts() {
+        let items =
+            parse_file("def f(x y) -x * g(x, y, 1); extern g(a b c); def h() 2; f(1, 2) < 3")
+                .unwrap();
+        let stats = stats(&items);
+
+        assert_eq!(
+            stats.nodes,
+            NodeCounts {
+                functions: 2,
+                externs: 1,
+                numbers: 5,
+                variables: 3,
+                unary: 1,
+                binary: 2,
+                calls: 2,
+                errors: 0,
+            }
+        );
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.max_fan_out, 3);
+        assert_eq!(
+            stats.functions,
+            vec![
+                FunctionStats {
+                    name: "f".into(),
+                    params: 2,
+                    size: 7,
+                    depth: 3,
+                },
+                FunctionStats {
+                    name: "h".into(),
+                    params: 0,
+                    size: 1,
+                    depth: 1,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,1073 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST, Step};
+use crate::ir::{self, BinaryOp, Cond, Inst, Terminator, Value};
+use crate::lexer::{Position, Span};
+use crate::mangle;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+// codegen - lower the ast to llvm ir (chapter 3 of the tutorial)
+//
+// bodies go through `ir::lower` first, their llvm ir is built as text,
+// a module `llvm-as`, `llc` or `lli` read as is
+// every value is a double, functions take and return doubles
+// temporaries are numbered, parameters keep their source names
+// there are no mutable locals (`var`, chapter 7) yet, parameters are
+// never assigned so every value already is an ssa register and no
+// allocas or mem2reg are needed
+
+// entry point of a program, not a valid kaleidoscope identifier
+pub const MAIN: &str = "__kaleidoscope_main";
+
+// profiling, the runtime counts and times the calls of each function by
+// id, `PROFILE_INIT` is generated and called by `MAIN` first
+const PROFILE_INIT: &str = "__kaleidoscope_profile_init";
+const PROFILE_NAME: &str = "__kaleidoscope_profile_name";
+const PROFILE_ENTER: &str = "__kaleidoscope_profile_enter";
+const PROFILE_EXIT: &str = "__kaleidoscope_profile_exit";
+const PROFILE_FUNCTIONS: [(&str, &str); 3] = [
+    (PROFILE_NAME, "i32, i32"),
+    (PROFILE_ENTER, "i32"),
+    (PROFILE_EXIT, "i32"),
+];
+
+// coverage, the runtime counts how often each region of the source ran,
+// `COVERAGE_INIT` is generated and passes the byte range of each region
+const COVERAGE_INIT: &str = "__kaleidoscope_coverage_init";
+const COVERAGE_REGION: &str = "__kaleidoscope_coverage_region";
+const COVERAGE_HIT: &str = "__kaleidoscope_coverage_hit";
+const COVERAGE_FUNCTIONS: [(&str, &str); 2] =
+    [(COVERAGE_REGION, "i32, i32, i32"), (COVERAGE_HIT, "i32")];
+
+// ids a definition is instrumented with, see `Codegen::set_profile` and
+// `Codegen::set_coverage`
+#[derive(Clone, Copy, Default)]
+struct Probes {
+    profile: Option<usize>,
+    region: Option<usize>,
+}
+
+// codegen error - each kind carries the position of the offending node
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum CodegenError {
+    // variable that is no parameter of the enclosing function
+    UnknownVariable {
+        name: String,
+        pos: Position,
+    },
+
+    // call of a function neither defined nor declared before
+    UnknownFunction {
+        name: String,
+        pos: Position,
+    },
+
+    // call or redeclaration with the wrong number of arguments
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        pos: Position,
+    },
+
+    // operator without a builtin lowering
+    UnknownOperator {
+        op: char,
+        pos: Position,
+    },
+
+    // second definition of a function with a body
+    Redefinition {
+        name: String,
+        pos: Position,
+    },
+
+    DuplicateParameter {
+        name: String,
+        pos: Position,
+    },
+
+    // hole left by parser error recovery
+    SyntaxError {
+        pos: Position,
+    },
+}
+
+impl CodegenError {
+    pub fn pos(&self) -> Position {
+        match self {
+            CodegenError::UnknownVariable { pos, .. }
+            | CodegenError::UnknownFunction { pos, .. }
+            | CodegenError::ArityMismatch { pos, .. }
+            | CodegenError::UnknownOperator { pos, .. }
+            | CodegenError::Redefinition { pos, .. }
+            | CodegenError::DuplicateParameter { pos, .. }
+            | CodegenError::SyntaxError { pos } => *pos,
+        }
+    }
+}
+
+// the message, without the position
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodegenError::UnknownVariable { name, .. } => write!(f, "unknown variable '{}'", name),
+            CodegenError::UnknownFunction { name, .. } => write!(f, "unknown function '{}'", name),
+            CodegenError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "'{}' takes {} argument(s), found {}",
+                name, expected, found
+            ),
+            CodegenError::UnknownOperator { op, .. } => write!(f, "unknown operator '{}'", op),
+            CodegenError::Redefinition { name, .. } => write!(f, "redefinition of '{}'", name),
+            CodegenError::DuplicateParameter { name, .. } => {
+                write!(f, "duplicate parameter '{}'", name)
+            }
+            CodegenError::SyntaxError { .. } => write!(f, "invalid expression"),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+pub type CodegenResult<T> = Result<T, CodegenError>;
+
+// externs known to have no side effects, from libm
+const PURE_EXTERNS: &[&str] = &[
+    "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "sinh", "cosh", "tanh", "exp", "log",
+    "log10", "pow", "sqrt", "fabs", "floor", "ceil", "fmod",
+];
+
+// function known to the module
+#[derive(Clone)]
+struct Signature {
+    arity: usize,
+    defined: bool,
+    // calling it has no side effects, see `Codegen::is_pure`
+    pure: bool,
+}
+
+// llvm module under construction, items are added one at a time like
+// the repl reads them
+#[derive(Default)]
+pub struct Codegen {
+    functions: HashMap<String, Signature>,
+    // names in order of first declaration
+    declared: Vec<String>,
+    // name and `define` of each function in order of definition
+    definitions: Vec<(String, String)>,
+    // the ir of each definition, for backends working on it
+    bodies: HashMap<String, ir::Function>,
+    // functions each definition calls
+    callees: HashMap<String, Vec<String>>,
+    // top-level expressions compiled so far
+    anon_exprs: usize,
+    // common subexpression elimination, see `set_cse`
+    cse: bool,
+    // target triple, llc compiles for the host without one
+    target: Option<String>,
+    // call counters and timers, see `set_profile`
+    profile: bool,
+    // names of the functions profiled so far, by id
+    profiled: Vec<String>,
+    // execution counts of regions, see `set_coverage`
+    coverage: bool,
+    // source of each region so far, by id
+    regions: Vec<Span>,
+}
+
+impl Codegen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // compute each pure value of a function body only once, e.g. one call
+    // for `sin(x) * sin(x)`, off by default, applies to functions compiled
+    // afterwards
+    pub fn set_cse(&mut self, cse: bool) {
+        self.cse = cse;
+    }
+
+    // compile for `triple`, e.g. `aarch64-unknown-linux-gnu`, llc picks
+    // the target machine and data layout from the triple in the module
+    pub fn set_target(&mut self, triple: &str) {
+        self.target = Some(triple.into());
+    }
+
+    // count the calls of each function and time them, for functions
+    // compiled afterwards, the runtime prints a report when the program
+    // exits, needs `main_ir`
+    pub fn set_profile(&mut self, profile: bool) {
+        self.profile = profile;
+    }
+
+    // count how often each function body and top-level expression runs,
+    // for those compiled afterwards, the runtime writes the counts of the
+    // source ranges when the program exits, see `coverage`, needs
+    // `main_ir`
+    // without conditionals every expression of a body runs when the body
+    // does, unless a call before it never returns
+    pub fn set_coverage(&mut self, coverage: bool) {
+        self.coverage = coverage;
+    }
+
+    // purity analysis, whether calling a known function has no side
+    // effects: a libm extern or a function calling only pure functions
+    // other externs may do anything, e.g. `printd`
+    pub fn is_pure(&self, name: &str) -> Option<bool> {
+        self.functions.get(name).map(|known| known.pure)
+    }
+
+    // add `item` to the module, returns its ir
+    pub fn compile_item(&mut self, item: &Item) -> CodegenResult<String> {
+        match item {
+            Item::Function(func) => self.compile_function(func),
+            Item::Extern(proto) => self.compile_extern(proto),
+            Item::Expr(expr) => {
+                let name = mangle::anon_expr(self.anon_exprs);
+                let ir = self.define(&name, &[], expr, expr.span().start)?;
+                self.anon_exprs += 1;
+                Ok(ir)
+            }
+        }
+    }
+
+    // `declare` for an extern, a function may be declared more than once
+    pub fn compile_extern(&mut self, proto: &PrototypeAST) -> CodegenResult<String> {
+        let PrototypeAST(name, params, ..) = proto;
+        self.declare(name, params, proto.4.start)?;
+        Ok(declaration(name, params.len()))
+    }
+
+    // `define` for a function, an anonymous function is a top-level
+    // expression
+    pub fn compile_function(&mut self, func: &FunctionAST) -> CodegenResult<String> {
+        let FunctionAST(proto, body, ..) = func;
+        if proto.0.is_empty() {
+            return self.compile_item(&Item::Expr(body.clone()));
+        }
+        self.define(&proto.0, &proto.1, body, proto.4.start)
+    }
+
+    // `compile_item` for each of `items`, the bodies are lowered and
+    // turned into llvm ir on the rayon thread pool, the module is the same
+    // as compiling them one at a time
+    pub fn compile_items(&mut self, items: &[Item]) -> Vec<CodegenResult<String>> {
+        match self.compile_parallel(items) {
+            Some(irs) => irs.into_iter().map(Ok).collect(),
+            // an error depends on the errors before it, e.g. a call of a
+            // function whose body did not lower is a call of an unknown
+            // function, so they are found one item at a time
+            None => items.iter().map(|item| self.compile_item(item)).collect(),
+        }
+    }
+
+    // the ir of each of `items`, None and the module unchanged if any of
+    // them has an error
+    fn compile_parallel(&mut self, items: &[Item]) -> Option<Vec<String>> {
+        // declare all functions in order, on a copy of the module
+        let mut scratch = Codegen {
+            functions: self.functions.clone(),
+            declared: self.declared.clone(),
+            anon_exprs: self.anon_exprs,
+            ..Codegen::default()
+        };
+        // item each new function is first declared in
+        let mut first = HashMap::new();
+        // item, name, parameters, body and probes of each definition
+        let mut defs = Vec::new();
+        let mut irs = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let (name, params, body, pos) = match item {
+                Item::Extern(proto) => (proto.0.clone(), &proto.1[..], None, proto.4.start),
+                Item::Function(FunctionAST(proto, body, ..)) if !proto.0.is_empty() => {
+                    (proto.0.clone(), &proto.1[..], Some(body), proto.4.start)
+                }
+                Item::Function(FunctionAST(_, body, ..)) | Item::Expr(body) => {
+                    let name = mangle::anon_expr(scratch.anon_exprs);
+                    scratch.anon_exprs += 1;
+                    (name, &[][..], Some(body), body.span().start)
+                }
+            };
+            let known = scratch.functions.get(&name);
+            if body.is_some() && known.is_some_and(|known| known.defined) {
+                return None;
+            }
+            if known.is_none() {
+                first.insert(name.clone(), i);
+            }
+            scratch.declare(&name, params, pos).ok()?;
+            irs.push(declaration(&name, params.len()));
+            if let Some(body) = body {
+                scratch.functions.get_mut(&name).unwrap().defined = true;
+                let probes = self.probes(defs.len());
+                defs.push((i, name, params, body, probes));
+            }
+        }
+
+        // a body sees the functions declared up to its own item
+        let arities: HashMap<_, _> = scratch
+            .functions
+            .iter()
+            .map(|(name, known)| (name.clone(), (known.arity, first.get(name).copied())))
+            .collect();
+        let arity_at = |i: usize| {
+            let arities = &arities;
+            move |callee: &str| match arities.get(callee) {
+                Some((_, Some(first))) if *first > i => None,
+                known => known.map(|(arity, _)| *arity),
+            }
+        };
+        let lowered = defs
+            .par_iter()
+            .map(|(i, name, params, body, _)| ir::lower(name, params, body, &arity_at(*i)))
+            .collect::<CodegenResult<Vec<_>>>()
+            .ok()?;
+
+        // purity depends on the definitions before, the callees known to be
+        // pure when each function is defined
+        let mut pure_callees = Vec::new();
+        for ((_, name, ..), func) in defs.iter().zip(&lowered) {
+            let callees = func.callees();
+            let pure: HashSet<_> = callees
+                .iter()
+                .filter(|callee| *callee != name && scratch.functions[*callee].pure)
+                .cloned()
+                .collect();
+            let pure_self = callees
+                .iter()
+                .all(|callee| callee == name || pure.contains(callee));
+            scratch.functions.get_mut(name).unwrap().pure = pure_self;
+            pure_callees.push(pure);
+        }
+
+        let cse = self.cse;
+        let compiled: Vec<_> = lowered
+            .into_par_iter()
+            .zip(pure_callees)
+            .zip(&defs)
+            .map(|((mut func, pure), (i, .., probes))| {
+                if cse {
+                    ir::cse(&mut func, &|callee| pure.contains(callee));
+                }
+                ir::debug_verify(&func, &arity_at(*i));
+                let ir = definition(&func, *probes);
+                (func, ir)
+            })
+            .collect();
+
+        self.functions = scratch.functions;
+        self.declared = scratch.declared;
+        self.anon_exprs = scratch.anon_exprs;
+        for ((i, name, _, body, probes), (func, ir)) in defs.iter().zip(compiled) {
+            self.add_probes(*probes, name, body.span());
+            irs[*i] = ir.clone();
+            self.definitions.push((name.clone(), ir));
+            self.callees.insert(name.clone(), func.callees());
+            self.bodies.insert(name.clone(), func);
+        }
+        Some(irs)
+    }
+
+    // name of the function the last top-level expression compiled to
+    pub fn last_anon_expr(&self) -> Option<String> {
+        let n = self.anon_exprs.checked_sub(1)?;
+        Some(mangle::anon_expr(n))
+    }
+
+    // `define` of a function or `declare` of an extern
+    pub fn function_ir(&self, name: &str) -> Option<String> {
+        let known = self.functions.get(name)?;
+        if !known.defined {
+            return Some(declaration(name, known.arity));
+        }
+        let (_, ir) = self
+            .definitions
+            .iter()
+            .find(|(defined, _)| defined == name)?;
+        Some(ir.clone())
+    }
+
+    // `MAIN`, calling the top-level expressions compiled so far in order
+    pub fn main_ir(&self) -> String {
+        let mut ir = format!("define void @{}() {{\nentry:\n", MAIN);
+        if !self.profiled.is_empty() {
+            writeln!(ir, "  call void @{}()", PROFILE_INIT).unwrap();
+        }
+        if !self.regions.is_empty() {
+            writeln!(ir, "  call void @{}()", COVERAGE_INIT).unwrap();
+        }
+        let anon_exprs = self
+            .definitions
+            .iter()
+            .filter(|(name, _)| mangle::is_anon_expr(name));
+        for (i, (name, _)) in anon_exprs.enumerate() {
+            writeln!(ir, "  %{} = call double @{}()", i, name).unwrap();
+        }
+        ir.push_str("  ret void\n}\n");
+        ir
+    }
+
+    // drop a function again, e.g. a top-level expression once it ran
+    // returns whether it was known
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        if self.functions.remove(name).is_none() {
+            return false;
+        }
+        self.declared.retain(|declared| declared != name);
+        self.definitions.retain(|(defined, _)| defined != name);
+        self.callees.remove(name);
+        self.bodies.remove(name);
+        true
+    }
+
+    // name and arity of each function declared but not defined, in order
+    // of declaration
+    pub fn externs(&self) -> Vec<(&str, usize)> {
+        self.declared
+            .iter()
+            .filter(|name| !self.functions[*name].defined)
+            .map(|name| (name.as_str(), self.functions[name].arity))
+            .collect()
+    }
+
+    // the ir of each definition, in order of definition
+    pub fn bodies(&self) -> Vec<&ir::Function> {
+        self.definitions
+            .iter()
+            .map(|(name, _)| &self.bodies[name])
+            .collect()
+    }
+
+    // dead code elimination for a program run through `MAIN`, drops the
+    // functions and externs no top-level expression calls, directly or
+    // not, returns their names in order of declaration
+    pub fn remove_unreferenced(&mut self) -> Vec<String> {
+        let mut work: Vec<_> = self
+            .declared
+            .iter()
+            .filter(|name| mangle::is_anon_expr(name))
+            .collect();
+        let mut reachable = HashSet::new();
+        while let Some(name) = work.pop() {
+            if reachable.insert(name.clone()) {
+                work.extend(self.callees.get(name).into_iter().flatten());
+            }
+        }
+        let dead: Vec<_> = self
+            .declared
+            .iter()
+            .filter(|name| !reachable.contains(*name))
+            .cloned()
+            .collect();
+        for name in &dead {
+            self.remove_function(name);
+        }
+        dead
+    }
+
+    // the whole module, declarations of functions without a definition
+    // first
+    pub fn module(&self) -> String {
+        let mut out = String::from("; ModuleID = 'kaleidoscope'\n");
+        out.push_str("source_filename = \"kaleidoscope\"\n");
+        if let Some(triple) = &self.target {
+            writeln!(out, "target triple = \"{}\"", triple).unwrap();
+        }
+        for name in &self.declared {
+            let known = &self.functions[name];
+            if !known.defined {
+                out.push('\n');
+                out.push_str(&declaration(name, known.arity));
+            }
+        }
+        for (_, definition) in &self.definitions {
+            out.push('\n');
+            out.push_str(definition);
+        }
+        if !self.profiled.is_empty() {
+            out.push('\n');
+            out.push_str(&self.profile_ir());
+        }
+        if !self.regions.is_empty() {
+            out.push('\n');
+            out.push_str(&self.coverage_ir());
+        }
+        out
+    }
+
+    // the coverage functions of the runtime and `COVERAGE_INIT`
+    fn coverage_ir(&self) -> String {
+        let mut out = String::new();
+        for (name, params) in COVERAGE_FUNCTIONS {
+            writeln!(out, "declare void @{}({})", name, params).unwrap();
+        }
+        writeln!(out, "\ndefine void @{}() {{\nentry:", COVERAGE_INIT).unwrap();
+        for (id, span) in self.regions.iter().enumerate() {
+            let (start, end) = (span.start.offset, span.end.offset);
+            let call = format!(
+                "call void @{}(i32 {}, i32 {}, i32 {})",
+                COVERAGE_REGION, id, start, end
+            );
+            writeln!(out, "  {}", call).unwrap();
+        }
+        out.push_str("  ret void\n}\n");
+        out
+    }
+
+    // the profiling functions of the runtime and `PROFILE_INIT`, which
+    // names each profiled function, one char per call keeps it free of
+    // pointers
+    fn profile_ir(&self) -> String {
+        let mut out = String::new();
+        for (name, params) in PROFILE_FUNCTIONS {
+            writeln!(out, "declare void @{}({})", name, params).unwrap();
+        }
+        writeln!(out, "\ndefine void @{}() {{\nentry:", PROFILE_INIT).unwrap();
+        for (id, name) in self.profiled.iter().enumerate() {
+            for c in mangle::demangle_name(name).bytes() {
+                writeln!(out, "  call void @{}(i32 {}, i32 {})", PROFILE_NAME, id, c).unwrap();
+            }
+        }
+        out.push_str("  ret void\n}\n");
+        out
+    }
+
+    fn declare(&mut self, name: &str, params: &[String], pos: Position) -> CodegenResult<()> {
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].contains(param) {
+                let name = param.clone();
+                return Err(CodegenError::DuplicateParameter { name, pos });
+            }
+        }
+        match self.functions.get(name) {
+            Some(known) if known.arity != params.len() => Err(CodegenError::ArityMismatch {
+                name: name.into(),
+                expected: known.arity,
+                found: params.len(),
+                pos,
+            }),
+            Some(_) => Ok(()),
+            None => {
+                let known = Signature {
+                    arity: params.len(),
+                    defined: false,
+                    pure: PURE_EXTERNS.contains(&name),
+                };
+                self.functions.insert(name.into(), known);
+                self.declared.push(name.into());
+                Ok(())
+            }
+        }
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &ExpressionAST,
+        pos: Position,
+    ) -> CodegenResult<String> {
+        if self.functions.get(name).is_some_and(|known| known.defined) {
+            let name = name.into();
+            return Err(CodegenError::Redefinition { name, pos });
+        }
+        // known while the body is lowered so it can call itself, defined
+        // once the body lowered fine, forgotten again if it was not known
+        // before
+        let new = !self.functions.contains_key(name);
+        self.declare(name, params, pos)?;
+
+        let arity = |callee: &str| self.functions.get(callee).map(|known| known.arity);
+        let mut func = match ir::lower(name, params, body, &arity) {
+            Ok(func) => func,
+            Err(err) => {
+                if new {
+                    self.functions.remove(name);
+                    self.declared.pop();
+                }
+                return Err(err);
+            }
+        };
+        if self.cse {
+            // a call of itself is not known to be pure yet
+            let is_pure = |callee: &str| callee != name && self.functions[callee].pure;
+            ir::cse(&mut func, &is_pure);
+        }
+        ir::debug_verify(&func, &arity);
+        let probes = self.probes(0);
+        let ir = definition(&func, probes);
+        let callees = func.callees();
+        // a call of itself does not make it impure
+        let pure = callees
+            .iter()
+            .all(|callee| callee == name || self.functions[callee].pure);
+
+        let known = self.functions.get_mut(name).unwrap();
+        known.defined = true;
+        known.pure = pure;
+        self.definitions.push((name.into(), ir.clone()));
+        self.callees.insert(name.into(), callees);
+        self.bodies.insert(name.into(), func);
+        self.add_probes(probes, name, body.span());
+        Ok(ir)
+    }
+
+    // probes of the definition `ahead` after the next one
+    fn probes(&self, ahead: usize) -> Probes {
+        Probes {
+            profile: self.profile.then_some(self.profiled.len() + ahead),
+            region: self.coverage.then_some(self.regions.len() + ahead),
+        }
+    }
+
+    fn add_probes(&mut self, probes: Probes, name: &str, body: Span) {
+        if probes.profile.is_some() {
+            self.profiled.push(name.into());
+        }
+        if probes.region.is_some() {
+            self.regions.push(body);
+        }
+    }
+}
+
+fn declaration(name: &str, arity: usize) -> String {
+    format!(
+        "declare double @{}({})\n",
+        name,
+        vec!["double"; arity].join(", ")
+    )
+}
+
+// double constant, in hex as decimals are only accepted if exact
+fn constant(num: f64) -> String {
+    format!("0x{:016X}", num.to_bits())
+}
+
+// the first error lowering `expr` runs into, for backends that need to
+// know a body is valid before they emit anything, `arity` of the known
+// functions
+pub fn check(
+    expr: &ExpressionAST,
+    params: &[String],
+    arity: &dyn Fn(&str) -> Option<usize>,
+) -> Result<(), CodegenError> {
+    // each node before its children
+    for step in expr.walk() {
+        let Step::Enter(expr, _) = step else {
+            continue;
+        };
+        let pos = expr.span().start;
+        match expr {
+            ExpressionAST::Number(..) => {}
+            ExpressionAST::Variable(name, ..) if params.contains(name) => {}
+            ExpressionAST::Variable(name, ..) => {
+                let name = name.clone();
+                return Err(CodegenError::UnknownVariable { name, pos });
+            }
+            ExpressionAST::Unary('-' | '!', ..) => {}
+            ExpressionAST::Binary('+' | '-' | '*' | '<', ..) => {}
+            ExpressionAST::Unary(op, ..) | ExpressionAST::Binary(op, ..) => {
+                return Err(CodegenError::UnknownOperator { op: *op, pos });
+            }
+            ExpressionAST::Call(callee, args, ..) => match arity(callee) {
+                None => {
+                    let name = callee.clone();
+                    return Err(CodegenError::UnknownFunction { name, pos });
+                }
+                Some(expected) if expected != args.len() => {
+                    return Err(CodegenError::ArityMismatch {
+                        name: callee.clone(),
+                        expected,
+                        found: args.len(),
+                        pos,
+                    })
+                }
+                Some(_) => {}
+            },
+            ExpressionAST::Error(..) => return Err(CodegenError::SyntaxError { pos }),
+        }
+    }
+    Ok(())
+}
+
+// `define` of a function, constants are inlined and parameters keep their
+// names, every other value is a numbered temporary, calls are counted
+// and timed and the runtime counts how often the body ran with `probes`
+fn definition(func: &ir::Function, probes: Probes) -> String {
+    let params: Vec<_> = func
+        .params
+        .iter()
+        .map(|p| format!("double %{}", p))
+        .collect();
+    let mut out = format!("define double @{}({}) {{\n", func.name, params.join(", "));
+    let mut operands = HashMap::new();
+    // next temporary
+    let mut next = 0;
+    let operand = |operands: &HashMap<Value, String>, value: &Value| operands[value].clone();
+    let profile_call = |callee: &str| match probes.profile {
+        Some(id) => format!("  call void @{}(i32 {})\n", callee, id),
+        None => String::new(),
+    };
+    for (i, block) in func.blocks.iter().enumerate() {
+        match i {
+            0 => out.push_str("entry:\n"),
+            _ => writeln!(out, "b{}:", i).unwrap(),
+        }
+        if i == 0 {
+            out.push_str(&profile_call(PROFILE_ENTER));
+            if let Some(id) = probes.region {
+                writeln!(out, "  call void @{}(i32 {})", COVERAGE_HIT, id).unwrap();
+            }
+        }
+        for value in &block.insts {
+            let inst = match func.inst(*value) {
+                Inst::Const(num) => {
+                    operands.insert(*value, constant(*num));
+                    continue;
+                }
+                Inst::Param(i) => {
+                    operands.insert(*value, format!("%{}", func.params[*i]));
+                    continue;
+                }
+                Inst::Neg(operand_value) => {
+                    format!("fneg double {}", operand(&operands, operand_value))
+                }
+                Inst::Binary(op, lhs, rhs) => {
+                    let op = match op {
+                        BinaryOp::Add => "fadd",
+                        BinaryOp::Sub => "fsub",
+                        BinaryOp::Mul => "fmul",
+                    };
+                    let (lhs, rhs) = (operand(&operands, lhs), operand(&operands, rhs));
+                    format!("{} double {}, {}", op, lhs, rhs)
+                }
+                Inst::Cmp(cond, lhs, rhs) => {
+                    let cond = match cond {
+                        Cond::Eq => "oeq",
+                        Cond::Ult => "ult",
+                    };
+                    let (lhs, rhs) = (operand(&operands, lhs), operand(&operands, rhs));
+                    format!("fcmp {} double {}, {}", cond, lhs, rhs)
+                }
+                Inst::BoolToF64(cond) => {
+                    format!("uitofp i1 {} to double", operand(&operands, cond))
+                }
+                Inst::Call(callee, args) => {
+                    let args: Vec<_> = args
+                        .iter()
+                        .map(|arg| format!("double {}", operand(&operands, arg)))
+                        .collect();
+                    // guaranteed to reuse the frame, so self recursion runs in
+                    // constant stack space
+                    // nothing may come between it and the `ret`, so not when
+                    // profiling, the time of the call would be taken before
+                    // the time of the calls it makes, profiled self recursion
+                    // uses stack as deep as it recurses
+                    let tail = probes.profile.is_none() && func.is_self_tail_call(*value);
+                    let call = match tail {
+                        true => "musttail call",
+                        false => "call",
+                    };
+                    format!("{} double @{}({})", call, callee, args.join(", "))
+                }
+            };
+            let temp = format!("%{}", next);
+            next += 1;
+            writeln!(out, "  {} = {}", temp, inst).unwrap();
+            operands.insert(*value, temp);
+        }
+        match &block.terminator {
+            Terminator::Return(value) => {
+                out.push_str(&profile_call(PROFILE_EXIT));
+                writeln!(out, "  ret double {}", operand(&operands, value)).unwrap()
+            }
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Codegen, CodegenError};
+    use crate::lexer::Position;
+    use crate::parser::parse_file;
+
+    // compile all items of `input`, returns the module
+    fn compile(input: &str) -> Result<String, CodegenError> {
+        let mut codegen = Codegen::new();
+        for item in parse_file(input).unwrap() {
+            codegen.compile_item(&item)?;
+        }
+        Ok(codegen.module())
+    }
+
+    #[test]
+    fn codegen_module() {
+        let module = compile("extern sin(x); def f(a b) a * sin(b) + 1; f(0.5, 2) < -3").unwrap();
+        assert_eq!(
+            module,
+            "; ModuleID = 'kaleidoscope'
+source_filename = \"kaleidoscope\"
+
+declare double @sin(double)
+
+define double @f(double %a, double %b) {
+entry:
+  %0 = call double @sin(double %b)
+  %1 = fmul double %a, %0
+  %2 = fadd double %1, 0x3FF0000000000000
+  ret double %2
+}
+
+define double @_KE0_() {
+entry:
+  %0 = call double @f(double 0x3FE0000000000000, double 0x4000000000000000)
+  %1 = fneg double 0x4008000000000000
+  %2 = fcmp ult double %0, %1
+  %3 = uitofp i1 %2 to double
+  ret double %3
+}
+"
+        );
+    }
+
+    #[test]
+    fn codegen_items() {
+        let mut codegen = Codegen::new();
+        let items = parse_file("extern f(x); def f(x) !x; f(1)").unwrap();
+        let mut ir = items.iter().map(|item| codegen.compile_item(item).unwrap());
+
+        assert_eq!(ir.next().unwrap(), "declare double @f(double)\n");
+        assert_eq!(
+            ir.next().unwrap(),
+            "define double @f(double %x) {
+entry:
+  %0 = fcmp oeq double %x, 0x0000000000000000
+  %1 = uitofp i1 %0 to double
+  ret double %1
+}
+"
+        );
+        ir.next();
+        drop(ir);
+        assert_eq!(codegen.last_anon_expr().as_deref(), Some("_KE0_"));
+        // defined now, no declaration left
+        assert!(!codegen.module().contains("declare"));
+
+        assert_eq!(
+            codegen.function_ir("_KE0_").unwrap(),
+            "define double @_KE0_() {
+entry:
+  %0 = call double @f(double 0x3FF0000000000000)
+  ret double %0
+}
+"
+        );
+        assert_eq!(codegen.function_ir("g"), None);
+
+        assert_eq!(
+            codegen.main_ir(),
+            "define void @__kaleidoscope_main() {
+entry:
+  %0 = call double @_KE0_()
+  ret void
+}
+"
+        );
+
+        assert!(codegen.remove_function("_KE0_"));
+        assert!(!codegen.module().contains("_KE"));
+        assert!(!codegen.remove_function("_KE0_"));
+    }
+
+    #[test]
+    fn codegen_errors() {
+        let pos = |offset| Position {
+            offset,
+            line: 1,
+            column: offset + 1,
+        };
+        assert_eq!(
+            compile("def f(x) y"),
+            Err(CodegenError::UnknownVariable {
+                name: "y".into(),
+                pos: pos(9)
+            })
+        );
+        assert_eq!(
+            compile("def f(x) g(x)"),
+            Err(CodegenError::UnknownFunction {
+                name: "g".into(),
+                pos: pos(9)
+            })
+        );
+        assert_eq!(
+            compile("extern g(a); g(1, 2)"),
+            Err(CodegenError::ArityMismatch {
+                name: "g".into(),
+                expected: 1,
+                found: 2,
+                pos: pos(13)
+            })
+        );
+        assert_eq!(
+            compile("extern g(a); def g(a b) a"),
+            Err(CodegenError::ArityMismatch {
+                name: "g".into(),
+                expected: 1,
+                found: 2,
+                pos: pos(17)
+            })
+        );
+        assert_eq!(
+            compile("def f() 1; def f() 2"),
+            Err(CodegenError::Redefinition {
+                name: "f".into(),
+                pos: pos(15)
+            })
+        );
+        assert_eq!(
+            compile("def f(x x) x"),
+            Err(CodegenError::DuplicateParameter {
+                name: "x".into(),
+                pos: pos(4)
+            })
+        );
+    }
+
+    #[test]
+    fn codegen_failed_function_is_forgotten() {
+        let mut codegen = Codegen::new();
+        let items = parse_file("def f(x) y; def f(x y) x").unwrap();
+        assert!(codegen.compile_item(&items[0]).is_err());
+        assert!(codegen.compile_item(&items[1]).is_ok());
+        assert!(codegen
+            .module()
+            .contains("define double @f(double %x, double %y)"));
+    }
+
+    #[test]
+    fn codegen_remove_unreferenced() {
+        let mut codegen = Codegen::new();
+        let source = "extern sin(x); extern cos(x); def a(x) sin(x); def b(x) a(x) * 2;
+                      def unused(x) cos(x) + b(x); b(1)";
+        for item in parse_file(source).unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+        assert_eq!(codegen.remove_unreferenced(), ["cos", "unused"]);
+        assert!(codegen.remove_unreferenced().is_empty());
+
+        let module = codegen.module();
+        assert!(module.contains("declare double @sin(double)"));
+        assert!(module.contains("define double @a(double %x)"));
+        assert!(module.contains("define double @b(double %x)"));
+        assert!(!module.contains("cos") && !module.contains("unused"));
+    }
+
+    #[test]
+    fn codegen_cse() {
+        let mut codegen = Codegen::new();
+        codegen.set_cse(true);
+        let source = "extern sin(x); extern printd(x);
+                      def f(x y) sin(x) * sin(x) + (x * y - y * x);
+                      def g(x) f(x, 1) + f(x, 1); def h(x) g(x) + printd(x) * printd(x)";
+        for item in parse_file(source).unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+        assert_eq!(
+            codegen.function_ir("f").unwrap(),
+            "define double @f(double %x, double %y) {
+entry:
+  %0 = call double @sin(double %x)
+  %1 = fmul double %0, %0
+  %2 = fmul double %x, %y
+  %3 = fsub double %2, %2
+  %4 = fadd double %1, %3
+  ret double %4
+}
+"
+        );
+        assert_eq!(codegen.function_ir("g").unwrap().matches("call").count(), 1);
+        assert_eq!(codegen.function_ir("h").unwrap().matches("call").count(), 3);
+
+        let purity = ["sin", "printd", "f", "g", "h"].map(|name| codegen.is_pure(name));
+        let expected = [true, false, true, true, false].map(Some);
+        assert_eq!(purity, expected);
+        assert_eq!(codegen.is_pure("unknown"), None);
+    }
+
+    #[test]
+    fn codegen_self_tail_call() {
+        let module = compile("def f(x) f(x - 1); def g(x) g(x) + 1; def h(x) f(x)").unwrap();
+        assert!(module.contains("  %1 = musttail call double @f(double %0)\n  ret double %1"));
+        assert!(module.contains("  %0 = call double @g(double %x)"));
+        assert!(module.contains("  %0 = call double @f(double %x)"));
+    }
+
+    #[test]
+    fn codegen_profile() {
+        let mut codegen = Codegen::new();
+        codegen.set_profile(true);
+        for item in parse_file("def f(x) f(x - 1); def g(x) x; g(2)").unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+        assert_eq!(
+            codegen.function_ir("f").unwrap(),
+            // no musttail, the time of the recursive calls counts too
+            "define double @f(double %x) {
+entry:
+  call void @__kaleidoscope_profile_enter(i32 0)
+  %0 = fsub double %x, 0x3FF0000000000000
+  %1 = call double @f(double %0)
+  call void @__kaleidoscope_profile_exit(i32 0)
+  ret double %1
+}
+"
+        );
+        assert!(codegen
+            .main_ir()
+            .starts_with("define void @__kaleidoscope_main() {\nentry:\n  call void @__kaleidoscope_profile_init()\n"));
+        let module = codegen.module();
+        // 'g' is 103, '0' of "top-level expression 0" is 48
+        assert!(module.contains("  call void @__kaleidoscope_profile_name(i32 1, i32 103)\n"));
+        assert!(
+            module.contains("  call void @__kaleidoscope_profile_name(i32 2, i32 48)\n  ret void")
+        );
+    }
+
+    #[test]
+    fn codegen_compile_items() {
+        let sources = [
+            "extern sin(x); extern printd(x); extern g(x);
+             def f(x y) sin(x) * sin(x) + g(x) * g(x); 1 + 2;
+             def g(x) sin(x) + f(x, 1); def h(x) g(x) * g(x) + printd(x) * printd(x); h(2)",
+            // errors
+            "def f(x) y; f(1); def g(x) 1; def g(x) 2; extern g(x, y); g(1) + h(1); def h(x) 3",
+        ];
+        for source in sources {
+            let items = parse_file(source).unwrap();
+            let mut one_by_one = Codegen::new();
+            let mut parallel = Codegen::new();
+            one_by_one.set_cse(true);
+            parallel.set_cse(true);
+            let expected: Vec<_> = items
+                .iter()
+                .map(|item| one_by_one.compile_item(item))
+                .collect();
+            assert_eq!(parallel.compile_items(&items), expected);
+            assert_eq!(parallel.module(), one_by_one.module());
+            for name in ["f", "g", "h"] {
+                assert_eq!(parallel.is_pure(name), one_by_one.is_pure(name));
+            }
+            assert_eq!(parallel.main_ir(), one_by_one.main_ir());
+        }
+    }
+}
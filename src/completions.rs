@@ -0,0 +1,252 @@
+use std::fmt::Write;
+
+// completions - scripts completing the commands and flags of a command
+// line in bash, zsh and fish, generated from a description of it
+
+pub struct Cli {
+    pub name: &'static str,
+    // first word instead of a file
+    pub commands: Vec<&'static str>,
+    pub flags: Vec<Flag>,
+}
+
+pub struct Flag {
+    pub name: &'static str,
+    pub arg: Arg,
+}
+
+pub enum Arg {
+    None,
+    // after '=' in the same word, one of the values if there are any
+    Equals(Vec<&'static str>),
+    // the next word, one of the values if there are any, else a file
+    Next(Vec<&'static str>),
+}
+
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+// the completion script of `cli` for `shell`, None for other shells
+pub fn script(cli: &Cli, shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash(cli)),
+        "zsh" => Some(zsh(cli)),
+        "fish" => Some(fish(cli)),
+        _ => None,
+    }
+}
+
+fn bash(cli: &Cli) -> String {
+    let mut out = String::new();
+    let name = cli.name;
+    writeln!(
+        out,
+        "# bash completion for {name}, `{name} completions bash`"
+    )
+    .unwrap();
+    writeln!(out, "_{name}() {{").unwrap();
+    out.push_str(
+        "    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]}
+    local line=${COMP_LINE:0:COMP_POINT}
+    local word=${line##*[[:space:]]}
+    # '=' may break words, the replies replace what cur has after it
+    local prefix=
+    [[ $cur == *=* ]] && prefix=${cur%=*}=
+    case $word in
+",
+    );
+    for flag in &cli.flags {
+        if let Arg::Equals(values) = &flag.arg {
+            writeln!(
+                out,
+                "        {}=*) COMPREPLY=($(compgen -P \"$prefix\" -W \"{}\" -- \"${{word#*=}}\")); return ;;",
+                flag.name,
+                values.join(" ")
+            )
+            .unwrap();
+        }
+    }
+    out.push_str("    esac\n    case $prev in\n");
+    for flag in &cli.flags {
+        match &flag.arg {
+            Arg::Next(values) if !values.is_empty() => writeln!(
+                out,
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;",
+                flag.name,
+                values.join(" ")
+            )
+            .unwrap(),
+            Arg::Next(_) => writeln!(
+                out,
+                "        {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;",
+                flag.name
+            )
+            .unwrap(),
+            _ => {}
+        }
+    }
+    let flags: Vec<_> = cli.flags.iter().map(bash_flag).collect();
+    writeln!(
+        out,
+        "    esac
+    if [[ $cur == -* ]]; then
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))
+        [[ ${{COMPREPLY[0]}} == *= ]] && compopt -o nospace
+        return
+    fi
+    if ((COMP_CWORD == 1)); then
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))
+    fi
+    COMPREPLY+=($(compgen -f -- \"$cur\"))
+}}
+complete -F _{name} {name}",
+        flags.join(" "),
+        cli.commands.join(" "),
+    )
+    .unwrap();
+    out
+}
+
+fn bash_flag(flag: &Flag) -> String {
+    match flag.arg {
+        Arg::Equals(_) => format!("{}=", flag.name),
+        _ => flag.name.to_string(),
+    }
+}
+
+fn zsh(cli: &Cli) -> String {
+    let mut out = String::new();
+    let name = cli.name;
+    writeln!(out, "#compdef {name}").unwrap();
+    writeln!(out, "# zsh completion for {name}, `{name} completions zsh`").unwrap();
+    writeln!(out, "_{name}() {{\n    local state\n    _arguments \\").unwrap();
+    for flag in &cli.flags {
+        let spec = match &flag.arg {
+            Arg::None => flag.name.to_string(),
+            Arg::Equals(values) => format!("{}=-:value:{}", flag.name, zsh_action(values, " ")),
+            Arg::Next(values) => format!("{}:value:{}", flag.name, zsh_action(values, "_files")),
+        };
+        writeln!(out, "        '{}' \\", spec).unwrap();
+    }
+    writeln!(
+        out,
+        "        '*: :->args'
+    if [[ $state == args ]]; then
+        if ((CURRENT == 2)); then
+            _alternative 'commands:command:({})' 'files:file:_files'
+        else
+            _files
+        fi
+    fi
+}}
+_{name} \"$@\"",
+        cli.commands.join(" ")
+    )
+    .unwrap();
+    out
+}
+
+// `values` to choose from, `otherwise` without any
+fn zsh_action(values: &[&str], otherwise: &str) -> String {
+    match values {
+        [] => otherwise.to_string(),
+        values => format!("({})", values.join(" ")),
+    }
+}
+
+fn fish(cli: &Cli) -> String {
+    let mut out = String::new();
+    let name = cli.name;
+    writeln!(
+        out,
+        "# fish completion for {name}, `{name} completions fish`"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "complete -c {name} -n __fish_use_subcommand -a '{}'",
+        cli.commands.join(" ")
+    )
+    .unwrap();
+    for flag in &cli.flags {
+        // a flag with and without a value is completed with it
+        let with_value =
+            |other: &Flag| other.name == flag.name && matches!(other.arg, Arg::Equals(_));
+        if matches!(flag.arg, Arg::None) && cli.flags.iter().any(with_value) {
+            continue;
+        }
+        let option = match flag.name.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None if flag.name.len() == 2 => format!("-s {}", &flag.name[1..]),
+            None => format!("-o {}", &flag.name[1..]),
+        };
+        let arg = match &flag.arg {
+            Arg::None => String::new(),
+            Arg::Equals(values) | Arg::Next(values) if !values.is_empty() => {
+                format!(" -x -a '{}'", values.join(" "))
+            }
+            Arg::Equals(_) => " -x".into(),
+            Arg::Next(_) => " -r".into(),
+        };
+        writeln!(out, "complete -c {name} {option}{arg}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{script, Arg, Cli, Flag};
+
+    #[test]
+    fn completion_scripts() {
+        let cli = Cli {
+            name: "k",
+            commands: vec!["build", "test"],
+            flags: vec![
+                Flag {
+                    name: "--dump",
+                    arg: Arg::None,
+                },
+                Flag {
+                    name: "--dump",
+                    arg: Arg::Equals(vec!["json", "dot"]),
+                },
+                Flag {
+                    name: "-o",
+                    arg: Arg::Next(vec![]),
+                },
+                Flag {
+                    name: "-O2",
+                    arg: Arg::None,
+                },
+            ],
+        };
+        assert_eq!(script(&cli, "powershell"), None);
+
+        let bash = script(&cli, "bash").unwrap();
+        assert!(bash.contains(
+            "        --dump=*) COMPREPLY=($(compgen -P \"$prefix\" -W \"json dot\" -- \"${word#*=}\")); return ;;\n"
+        ));
+        assert!(bash.contains("        -o) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n"));
+        assert!(bash.contains("compgen -W \"--dump --dump= -o -O2\" -- \"$cur\""));
+        assert!(bash.contains("compgen -W \"build test\" -- \"$cur\""));
+        assert!(bash.ends_with("complete -F _k k\n"));
+
+        let zsh = script(&cli, "zsh").unwrap();
+        assert!(zsh.starts_with("#compdef k\n"));
+        assert!(zsh.contains(
+            "        '--dump' \\\n        '--dump=-:value:(json dot)' \\\n        '-o:value:_files' \\\n        '-O2' \\\n"
+        ));
+        assert!(zsh.contains("'commands:command:(build test)'"));
+
+        assert_eq!(
+            script(&cli, "fish").unwrap(),
+            "\
+# fish completion for k, `k completions fish`
+complete -c k -n __fish_use_subcommand -a 'build test'
+complete -c k -l dump -x -a 'json dot'
+complete -c k -s o -r
+complete -c k -o O2
+"
+        );
+    }
+}
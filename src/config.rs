@@ -0,0 +1,253 @@
+use kaleidoscope::driver::Backend;
+use kaleidoscope::lint::{Level, Lint};
+use kaleidoscope::opt::OptLevel;
+
+// config - settings of a project read from `kaleidoscope.toml`, so they
+// need not be repeated on every command line, flags override them
+//
+//   entry = "src/main.ks"    # program of build and friends without files
+//   opt-level = 2            # like -O2
+//   backend = "cranelift"    # or "llvm", the default
+//   libs = ["m", "sqlite3"]  # linked into executables, like -l
+//
+//   [lints]                  # like -A, -W and -D
+//   shadowing = "deny"
+//
+//   [repl]                   # like --prompt and friends
+//   prompt = "{backend}> "
+//   continuation-prompt = "  > "
+//   result-prefix = "= "
+//
+// only the subset of toml the settings need is read: strings, integers,
+// arrays of strings on one line, tables and comments
+
+pub const FILE: &str = "kaleidoscope.toml";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub entry: Option<String>,
+    pub level: Option<OptLevel>,
+    pub backend: Option<Backend>,
+    pub libs: Vec<String>,
+    // in the order they were set
+    pub lints: Vec<(Lint, Level)>,
+    pub prompt: Option<String>,
+    pub continuation_prompt: Option<String>,
+    pub result_prefix: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Array(Vec<String>),
+}
+
+pub fn parse(text: &str) -> Result<Config, ConfigError> {
+    let mut config = Config::default();
+    let mut table = String::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| ConfigError {
+            line: i + 1,
+            message,
+        };
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .ok_or_else(|| error("expected ']'".into()))?;
+            table = name.trim().to_string();
+            if table != "lints" && table != "repl" {
+                return Err(error(format!("unknown table '{}'", table)));
+            }
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected 'key = value'".into()))?;
+        let key = key.trim();
+        let value = parse_value(value.trim()).ok_or_else(|| error("invalid value".into()))?;
+        let expected = |what: &str| error(format!("expected {} for '{}'", what, key));
+        match (table.as_str(), key, value) {
+            ("", "entry", Value::String(path)) => config.entry = Some(path),
+            ("", "entry", _) => return Err(expected("a string")),
+            ("", "opt-level", Value::Integer(n)) => {
+                let level = OptLevel::from_flag(&format!("-O{}", n));
+                config.level = Some(level.ok_or_else(|| expected("0, 1 or 2"))?);
+            }
+            ("", "opt-level", _) => return Err(expected("0, 1 or 2")),
+            ("", "backend", Value::String(name)) => {
+                let backend = Backend::from_name(&name);
+                config.backend =
+                    Some(backend.ok_or_else(|| expected("\"llvm\" or \"cranelift\""))?);
+            }
+            ("", "backend", _) => return Err(expected("\"llvm\" or \"cranelift\"")),
+            ("", "libs", Value::Array(libs)) => config.libs = libs,
+            ("", "libs", _) => return Err(expected("an array of strings")),
+            ("lints", name, value) => {
+                let lint = Lint::from_name(name)
+                    .ok_or_else(|| error(format!("unknown lint '{}'", name)))?;
+                let level = match value {
+                    Value::String(level) if level == "allow" => Level::Allow,
+                    Value::String(level) if level == "warn" => Level::Warn,
+                    Value::String(level) if level == "deny" => Level::Deny,
+                    _ => return Err(expected("\"allow\", \"warn\" or \"deny\"")),
+                };
+                config.lints.push((lint, level));
+            }
+            ("repl", "prompt", Value::String(text)) => config.prompt = Some(text),
+            ("repl", "continuation-prompt", Value::String(text)) => {
+                config.continuation_prompt = Some(text)
+            }
+            ("repl", "result-prefix", Value::String(text)) => config.result_prefix = Some(text),
+            ("repl", "prompt" | "continuation-prompt" | "result-prefix", _) => {
+                return Err(expected("a string"))
+            }
+            (_, key, _) => return Err(error(format!("unknown setting '{}'", key))),
+        }
+    }
+    Ok(config)
+}
+
+// `line` up to a `#` outside of a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    if let Some(items) = text.strip_prefix('[') {
+        let items = items.strip_suffix(']')?.trim();
+        let mut strings = Vec::new();
+        let mut rest = items;
+        while !rest.is_empty() {
+            let (string, after) = parse_string(rest)?;
+            strings.push(string);
+            rest = after.trim_start();
+            rest = match rest.strip_prefix(',') {
+                Some(after) => after.trim_start(),
+                None if rest.is_empty() => rest,
+                None => return None,
+            };
+        }
+        return Some(Value::Array(strings));
+    }
+    if text.starts_with('"') {
+        return match parse_string(text)? {
+            (string, "") => Some(Value::String(string)),
+            _ => None,
+        };
+    }
+    text.parse().ok().map(Value::Integer)
+}
+
+// the basic string `text` starts with and the text after it
+fn parse_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((string, &text[i + 2..])),
+            '\\' => string.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => string.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Backend, Config, ConfigError};
+    use kaleidoscope::lint::{Level, Lint};
+    use kaleidoscope::opt::OptLevel;
+
+    #[test]
+    fn config_parse() {
+        let text = r#"
+            # the project
+            entry = "src/main.ks"  # where it starts
+            opt-level = 2
+            backend = "cranelift"
+            libs = ["m", "sq\"l#ite"]
+
+            [lints]
+            shadowing = "deny"
+            unused-parameter = "allow"
+
+            [repl]
+            prompt = "{backend}> "
+            result-prefix = ""
+        "#;
+        assert_eq!(
+            parse(text).unwrap(),
+            Config {
+                entry: Some("src/main.ks".into()),
+                level: Some(OptLevel::O2),
+                backend: Some(Backend::Cranelift),
+                libs: vec!["m".into(), "sq\"l#ite".into()],
+                lints: vec![
+                    (Lint::Shadowing, Level::Deny),
+                    (Lint::UnusedParameter, Level::Allow)
+                ],
+                prompt: Some("{backend}> ".into()),
+                continuation_prompt: None,
+                result_prefix: Some("".into()),
+            }
+        );
+        assert_eq!(parse("libs = []").unwrap().libs, Vec::<String>::new());
+
+        let error = |line, message: &str| {
+            Err(ConfigError {
+                line,
+                message: message.into(),
+            })
+        };
+        assert_eq!(
+            parse("entry = \"a.ks\"\nopt-level = 3"),
+            error(2, "expected 0, 1 or 2 for 'opt-level'")
+        );
+        assert_eq!(parse("entry = a.ks"), error(1, "invalid value"));
+        assert_eq!(
+            parse("output = \"a\""),
+            error(1, "unknown setting 'output'")
+        );
+        assert_eq!(parse("[build]"), error(1, "unknown table 'build'"));
+        assert_eq!(
+            parse("[lints]\nunused = \"deny\""),
+            error(2, "unknown lint 'unused'")
+        );
+        assert_eq!(parse("libs = [\"m\" \"c\"]"), error(1, "invalid value"));
+        assert_eq!(
+            parse("[repl]\nprompt = 1"),
+            error(2, "expected a string for 'prompt'")
+        );
+        assert_eq!(
+            parse("prompt = \">\""),
+            error(1, "unknown setting 'prompt'")
+        );
+    }
+}
@@ -0,0 +1,128 @@
+// coverage - render the counts a program built with
+// `--instrument=coverage` wrote as annotated source
+//
+// each line is prefixed with how often the innermost region starting on
+// it ran, the parts of regions that never ran are underlined, e.g.
+//
+//         1 | def used(x) x + 1;
+//         0 | def unused(x) x * 2;
+//           |               ^^^^^
+use std::fmt::Write;
+
+// source from byte `start` up to `end` and how often it ran
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub count: u64,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CoverageError {
+    // line of the counts that is no `start end count`
+    Malformed { line: usize },
+    // region outside of the source, counts of another program
+    OutOfSource { line: usize },
+}
+
+// the regions of the counts the runtime wrote for `source`
+pub fn parse(counts: &str, source: &str) -> Result<Vec<Region>, CoverageError> {
+    let mut regions = Vec::new();
+    for (i, text) in counts.lines().enumerate() {
+        let line = i + 1;
+        let fields: Vec<_> = text.split_whitespace().collect();
+        let [start, end, count] = fields[..] else {
+            return Err(CoverageError::Malformed { line });
+        };
+        let (Ok(start), Ok(end), Ok(count)) = (start.parse(), end.parse(), count.parse()) else {
+            return Err(CoverageError::Malformed { line });
+        };
+        let valid = |offset| source.is_char_boundary(offset);
+        if start > end || end > source.len() || !valid(start) || !valid(end) {
+            return Err(CoverageError::OutOfSource { line });
+        }
+        regions.push(Region { start, end, count });
+    }
+    Ok(regions)
+}
+
+// `source` annotated with the counts of `regions`, starting with a
+// summary of how many ran
+pub fn render(source: &str, regions: &[Region]) -> String {
+    let executed = regions.iter().filter(|region| region.count > 0).count();
+    let mut out = format!("{} of {} regions executed\n", executed, regions.len());
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        let end = offset + text.len();
+        // the innermost region starting on the line
+        let count = regions
+            .iter()
+            .filter(|region| (offset..end.max(offset + 1)).contains(&region.start))
+            .min_by_key(|region| region.end - region.start)
+            .map(|region| region.count.to_string())
+            .unwrap_or_default();
+        writeln!(out, "{:>10} | {}", count, text).unwrap();
+
+        let unexecuted = |at: usize| {
+            regions
+                .iter()
+                .any(|region| region.count == 0 && (region.start..region.end).contains(&at))
+        };
+        // not the indentation of a line inside a region
+        let indent = text.len() - text.trim_start().len();
+        let marks: String = text
+            .char_indices()
+            .map(|(i, _)| match i >= indent && unexecuted(offset + i) {
+                true => '^',
+                false => ' ',
+            })
+            .collect();
+        if marks.contains('^') {
+            writeln!(out, "{:>10} | {}", "", marks.trim_end()).unwrap();
+        }
+        offset += line.len();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, render, CoverageError, Region};
+
+    #[test]
+    fn coverage_render() {
+        let source = "def used(x) x + 1;\ndef unused(x)\n  x * 2;\nused(1)\n";
+        let err = parse("12 17 1\n35 40 0\n47 54 1\n", source).unwrap_err();
+        assert_eq!(err, CoverageError::OutOfSource { line: 3 });
+
+        let regions = parse("12 17 1\n35 40 0\n42 49 1\n", source).unwrap();
+        assert_eq!(
+            regions[1],
+            Region {
+                start: 35,
+                end: 40,
+                count: 0
+            }
+        );
+        assert_eq!(
+            render(source, &regions),
+            "2 of 3 regions executed
+         1 | def used(x) x + 1;
+           | def unused(x)
+         0 |   x * 2;
+           |   ^^^^^
+         1 | used(1)
+"
+        );
+
+        assert_eq!(
+            parse("1 2\n", source),
+            Err(CoverageError::Malformed { line: 1 })
+        );
+        assert_eq!(
+            parse("1 2 x\n", source),
+            Err(CoverageError::Malformed { line: 1 })
+        );
+    }
+}
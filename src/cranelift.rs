@@ -0,0 +1,545 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST};
+use crate::codegen::{CodegenError, MAIN};
+use crate::ir::{self, BinaryOp, Cond, Inst, Terminator};
+use crate::lexer::Position;
+use crate::mangle;
+use cranelift_codegen::ir::condcodes::FloatCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, Signature, UserFuncName, Value};
+use cranelift_codegen::isa::OwnedTargetIsa;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module, ModuleError};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use std::collections::HashMap;
+use std::ffi::CString;
+
+// cranelift - pure rust backend, an alternative to llvm for the jit and
+// object files that needs no llvm installation
+//
+// translates the ir of `ir::lower` like `codegen` does, with the same
+// errors for invalid programs
+
+#[derive(Debug)]
+pub enum CraneliftError {
+    Codegen(CodegenError),
+    // the host is no cranelift target
+    Isa(String),
+    Module(Box<ModuleError>),
+    // extern the jit finds no symbol for
+    Unresolved { name: String, pos: Position },
+    // the object file could not be written
+    Object(String),
+}
+
+impl From<CodegenError> for CraneliftError {
+    fn from(err: CodegenError) -> Self {
+        CraneliftError::Codegen(err)
+    }
+}
+
+impl From<ModuleError> for CraneliftError {
+    fn from(err: ModuleError) -> Self {
+        CraneliftError::Module(Box::new(err))
+    }
+}
+
+pub type CraneliftResult<T> = Result<T, CraneliftError>;
+
+// function known to the module
+struct Known {
+    id: FuncId,
+    arity: usize,
+    defined: bool,
+}
+
+// a cranelift module items are compiled into one at a time
+pub struct Backend<M: Module> {
+    module: M,
+    ctx: Context,
+    functions: HashMap<String, Known>,
+    // top-level expressions compiled so far, in order
+    anon_exprs: Vec<FuncId>,
+    // whether there is a symbol for an extern
+    resolve: fn(&str) -> bool,
+    // arity of each callback of the `Registry`
+    callbacks: HashMap<String, usize>,
+}
+
+// the jit, compiled functions run in this process
+pub type Jit = Backend<JITModule>;
+
+// an object file for the host
+pub type Object = Backend<ObjectModule>;
+
+fn host_isa(pic: bool) -> CraneliftResult<OwnedTargetIsa> {
+    let mut flags = settings::builder();
+    flags
+        .set("is_pic", if pic { "true" } else { "false" })
+        .unwrap();
+    flags.set("use_colocated_libcalls", "false").unwrap();
+    let isa = cranelift_native::builder().map_err(|err| CraneliftError::Isa(err.into()))?;
+    isa.finish(settings::Flags::new(flags))
+        .map_err(|err| CraneliftError::Isa(err.to_string()))
+}
+
+// callbacks embedders make callable from the jit, an extern of the same
+// name calls the callback instead of a symbol of the process
+#[derive(Default, Clone)]
+pub struct Registry {
+    // name to arity and address
+    callbacks: HashMap<String, (usize, *const u8)>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // add `callback` as `name`, replaces an earlier one, e.g.
+    // `registry.register("twice", twice as extern "C" fn(f64) -> f64)`
+    pub fn register(&mut self, name: &str, callback: impl Callback) {
+        let callback = (callback.arity(), callback.address());
+        self.callbacks.insert(name.into(), callback);
+    }
+}
+
+// a function the jit can call, `extern "C" fn(f64, ...) -> f64` with up
+// to six arguments
+pub trait Callback {
+    fn arity(&self) -> usize;
+    fn address(&self) -> *const u8;
+}
+
+macro_rules! callback {
+    ($arity:literal $(, $arg:ident)*) => {
+        impl Callback for extern "C" fn($($arg),*) -> f64 {
+            fn arity(&self) -> usize {
+                $arity
+            }
+
+            fn address(&self) -> *const u8 {
+                *self as *const u8
+            }
+        }
+    };
+}
+
+callback!(0);
+callback!(1, f64);
+callback!(2, f64, f64);
+callback!(3, f64, f64, f64);
+callback!(4, f64, f64, f64, f64);
+callback!(5, f64, f64, f64, f64, f64);
+callback!(6, f64, f64, f64, f64, f64, f64);
+
+impl Jit {
+    // externs resolve against the symbols of this process and libm
+    pub fn new() -> CraneliftResult<Self> {
+        Self::with_registry(&Registry::new())
+    }
+
+    // externs resolve against the callbacks of `registry` first
+    pub fn with_registry(registry: &Registry) -> CraneliftResult<Self> {
+        load_libm();
+        let mut builder = JITBuilder::with_isa(host_isa(false)?, default_libcall_names());
+        for (name, (_, address)) in &registry.callbacks {
+            builder.symbol(name, *address);
+        }
+        let mut jit = Backend::with_module(JITModule::new(builder), in_process);
+        jit.callbacks = registry
+            .callbacks
+            .iter()
+            .map(|(name, (arity, _))| (name.clone(), *arity))
+            .collect();
+        Ok(jit)
+    }
+
+    pub fn eval(&mut self, expr: &ExpressionAST) -> CraneliftResult<f64> {
+        let id = self.compile_anon_expr(expr)?;
+        self.module.finalize_definitions()?;
+        let code = self.module.get_finalized_function(id);
+        // SAFETY: top-level expressions are compiled as `fn() -> f64` with
+        // the default calling convention
+        let func = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> f64>(code) };
+        Ok(func())
+    }
+}
+
+// make the symbols of libm visible to `in_process`, the process only
+// links it if rust code uses it, elsewhere it is part of libc
+fn load_libm() {
+    #[cfg(target_os = "linux")]
+    {
+        let name = b"libm.so.6\0";
+        // SAFETY: `name` is nul terminated, loading libm runs no
+        // initializers that could interfere with this process
+        unsafe { libc::dlopen(name.as_ptr().cast(), libc::RTLD_NOW | libc::RTLD_GLOBAL) };
+    }
+}
+
+// the symbol lookup of the jit, unresolved symbols make it panic
+fn in_process(name: &str) -> bool {
+    let Ok(name) = CString::new(name) else {
+        return false;
+    };
+    // SAFETY: `name` is nul terminated
+    let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    !symbol.is_null()
+}
+
+impl Object {
+    pub fn new() -> CraneliftResult<Self> {
+        let builder = ObjectBuilder::new(host_isa(true)?, "kaleidoscope", default_libcall_names())?;
+        // externs are left to the linker
+        Ok(Backend::with_module(ObjectModule::new(builder), |_| true))
+    }
+
+    // the object file, with `codegen::MAIN` calling the top-level
+    // expressions in order if `main` is set
+    pub fn finish(mut self, main: bool) -> CraneliftResult<Vec<u8>> {
+        if main {
+            let signature = self.module.make_signature();
+            let id = self
+                .module
+                .declare_function(MAIN, Linkage::Export, &signature)?;
+            self.ctx.func.signature = signature;
+            self.ctx.func.name = UserFuncName::user(0, id.as_u32());
+
+            let mut builder_ctx = FunctionBuilderContext::new();
+            let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut builder_ctx);
+            let entry = builder.create_block();
+            builder.switch_to_block(entry);
+            for anon_expr in &self.anon_exprs {
+                let callee = self.module.declare_func_in_func(*anon_expr, builder.func);
+                builder.ins().call(callee, &[]);
+            }
+            builder.ins().return_(&[]);
+            builder.seal_all_blocks();
+            builder.finalize();
+
+            self.module.define_function(id, &mut self.ctx)?;
+            self.module.clear_context(&mut self.ctx);
+        }
+        let product = self.module.finish();
+        product
+            .emit()
+            .map_err(|err| CraneliftError::Object(err.to_string()))
+    }
+}
+
+impl<M: Module> Backend<M> {
+    fn with_module(module: M, resolve: fn(&str) -> bool) -> Self {
+        Backend {
+            ctx: module.make_context(),
+            module,
+            functions: HashMap::new(),
+            anon_exprs: Vec::new(),
+            resolve,
+            callbacks: HashMap::new(),
+        }
+    }
+
+    pub fn compile_item(&mut self, item: &Item) -> CraneliftResult<()> {
+        match item {
+            Item::Function(func) => self.compile_function(func),
+            Item::Extern(proto) => self.compile_extern(proto),
+            Item::Expr(expr) => self.compile_anon_expr(expr).map(|_| ()),
+        }
+    }
+
+    pub fn compile_extern(&mut self, proto: &PrototypeAST) -> CraneliftResult<()> {
+        let PrototypeAST(name, params, ..) = proto;
+        let pos = proto.4.start;
+        if let Some(&expected) = self.callbacks.get(name) {
+            if expected != params.len() {
+                return Err(CodegenError::ArityMismatch {
+                    name: name.clone(),
+                    expected,
+                    found: params.len(),
+                    pos,
+                }
+                .into());
+            }
+        } else if !self.functions.contains_key(name) && !(self.resolve)(name) {
+            let name = name.clone();
+            return Err(CraneliftError::Unresolved { name, pos });
+        }
+        self.declare(name, params, pos, Linkage::Import)?;
+        Ok(())
+    }
+
+    pub fn compile_function(&mut self, func: &FunctionAST) -> CraneliftResult<()> {
+        let FunctionAST(proto, body, ..) = func;
+        if proto.0.is_empty() {
+            return self.compile_anon_expr(body).map(|_| ());
+        }
+        self.define(&proto.0, &proto.1, body, proto.4.start)?;
+        Ok(())
+    }
+
+    fn compile_anon_expr(&mut self, expr: &ExpressionAST) -> CraneliftResult<FuncId> {
+        let name = mangle::anon_expr(self.anon_exprs.len());
+        let id = self.define(&name, &[], expr, expr.span().start)?;
+        self.anon_exprs.push(id);
+        Ok(id)
+    }
+
+    fn signature(&self, arity: usize) -> Signature {
+        let mut signature = self.module.make_signature();
+        signature.params = vec![AbiParam::new(types::F64); arity];
+        signature.returns.push(AbiParam::new(types::F64));
+        signature
+    }
+
+    fn declare(
+        &mut self,
+        name: &str,
+        params: &[String],
+        pos: Position,
+        linkage: Linkage,
+    ) -> CraneliftResult<FuncId> {
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].contains(param) {
+                let name = param.clone();
+                return Err(CodegenError::DuplicateParameter { name, pos }.into());
+            }
+        }
+        if let Some(known) = self.functions.get(name) {
+            if known.arity != params.len() {
+                return Err(CodegenError::ArityMismatch {
+                    name: name.into(),
+                    expected: known.arity,
+                    found: params.len(),
+                    pos,
+                }
+                .into());
+            }
+        }
+        let signature = self.signature(params.len());
+        let id = self.module.declare_function(name, linkage, &signature)?;
+        self.functions.entry(name.into()).or_insert(Known {
+            id,
+            arity: params.len(),
+            defined: false,
+        });
+        Ok(id)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &ExpressionAST,
+        pos: Position,
+    ) -> CraneliftResult<FuncId> {
+        if self.functions.get(name).is_some_and(|known| known.defined) {
+            let name = name.into();
+            return Err(CodegenError::Redefinition { name, pos }.into());
+        }
+        // declarations stay in the module, only declare a function once its
+        // body is known to lower, it may call itself
+        let arity = |callee: &str| match self.functions.get(callee) {
+            _ if callee == name => Some(params.len()),
+            known => known.map(|known| known.arity),
+        };
+        let func = ir::lower(name, params, body, &arity)?;
+        ir::debug_verify(&func, &arity);
+        let id = self.declare(name, params, pos, Linkage::Export)?;
+
+        self.ctx.func.signature = self.signature(params.len());
+        self.ctx.func.name = UserFuncName::user(0, id.as_u32());
+        let mut builder_ctx = FunctionBuilderContext::new();
+        let mut lower = Lower {
+            module: &mut self.module,
+            functions: &self.functions,
+            builder: FunctionBuilder::new(&mut self.ctx.func, &mut builder_ctx),
+            values: HashMap::new(),
+        };
+        lower.function(&func);
+        lower.builder.finalize();
+        let result = self.module.define_function(id, &mut self.ctx);
+        self.module.clear_context(&mut self.ctx);
+
+        result?;
+        self.functions.get_mut(name).unwrap().defined = true;
+        Ok(id)
+    }
+}
+
+// translation of a function of the ir
+struct Lower<'a, M: Module> {
+    module: &'a mut M,
+    functions: &'a HashMap<String, Known>,
+    builder: FunctionBuilder<'a>,
+    values: HashMap<ir::Value, Value>,
+}
+
+impl<M: Module> Lower<'_, M> {
+    fn function(&mut self, func: &ir::Function) {
+        let blocks: Vec<_> = func
+            .blocks
+            .iter()
+            .map(|_| self.builder.create_block())
+            .collect();
+        self.builder
+            .append_block_params_for_function_params(blocks[0]);
+        let params = self.builder.block_params(blocks[0]).to_vec();
+        for (block, ir_block) in blocks.iter().zip(&func.blocks) {
+            self.builder.switch_to_block(*block);
+            for value in &ir_block.insts {
+                let lowered = self.inst(func.inst(*value), &params);
+                self.values.insert(*value, lowered);
+            }
+            match ir_block.terminator {
+                Terminator::Return(value) => {
+                    let ret = self.values[&value];
+                    self.builder.ins().return_(&[ret]);
+                }
+            }
+        }
+        self.builder.seal_all_blocks();
+    }
+
+    // 1.0 if `cond` holds, else 0.0
+    fn bool(&mut self, cond: Value) -> Value {
+        let one = self.builder.ins().f64const(1.0);
+        let zero = self.builder.ins().f64const(0.0);
+        self.builder.ins().select(cond, one, zero)
+    }
+
+    fn inst(&mut self, inst: &Inst, params: &[Value]) -> Value {
+        match inst {
+            Inst::Const(num) => self.builder.ins().f64const(*num),
+            Inst::Param(i) => params[*i],
+            Inst::Neg(operand) => self.builder.ins().fneg(self.values[operand]),
+            Inst::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (self.values[lhs], self.values[rhs]);
+                match op {
+                    BinaryOp::Add => self.builder.ins().fadd(lhs, rhs),
+                    BinaryOp::Sub => self.builder.ins().fsub(lhs, rhs),
+                    BinaryOp::Mul => self.builder.ins().fmul(lhs, rhs),
+                }
+            }
+            Inst::Cmp(cond, lhs, rhs) => {
+                let cond = match cond {
+                    Cond::Eq => FloatCC::Equal,
+                    Cond::Ult => FloatCC::UnorderedOrLessThan,
+                };
+                let (lhs, rhs) = (self.values[lhs], self.values[rhs]);
+                self.builder.ins().fcmp(cond, lhs, rhs)
+            }
+            Inst::BoolToF64(cond) => self.bool(self.values[cond]),
+            Inst::Call(callee, args) => {
+                let id = self.functions[callee].id;
+                let callee = self.module.declare_func_in_func(id, self.builder.func);
+                let args: Vec<_> = args.iter().map(|arg| self.values[arg]).collect();
+                let call = self.builder.ins().call(callee, &args);
+                self.builder.inst_results(call)[0]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CraneliftError, Jit, Object, Registry};
+    use crate::ast::Item;
+    use crate::codegen::CodegenError;
+    use crate::parser::parse_file;
+
+    // evaluate the items of `input`, results of the expressions
+    fn eval(jit: &mut Jit, input: &str) -> Vec<f64> {
+        let mut results = Vec::new();
+        for item in parse_file(input).unwrap() {
+            match item {
+                Item::Expr(expr) => results.push(jit.eval(&expr).unwrap()),
+                item => jit.compile_item(&item).unwrap(),
+            }
+        }
+        results
+    }
+
+    #[test]
+    fn cranelift_jit() {
+        let mut jit = Jit::new().unwrap();
+        assert_eq!(
+            eval(&mut jit, "1 + 2 * 3; 4 < 5; 5 < 4; -(1.5); !0; !2"),
+            [7.0, 1.0, 0.0, -1.5, 1.0, 0.0]
+        );
+        assert_eq!(eval(&mut jit, "def sq(x) x * x; sq(3)"), [9.0]);
+        assert_eq!(eval(&mut jit, "sq(sq(2)) + 1"), [17.0]);
+        assert_eq!(
+            eval(&mut jit, "extern sq(x); def f(x) sq(x) < x; f(0.5)"),
+            [1.0]
+        );
+    }
+
+    extern "C" fn twice(x: f64) -> f64 {
+        x * 2.0
+    }
+
+    extern "C" fn answer() -> f64 {
+        42.0
+    }
+
+    #[test]
+    fn cranelift_externs() {
+        let mut registry = Registry::new();
+        registry.register("twice", twice as extern "C" fn(f64) -> f64);
+        registry.register("answer", answer as extern "C" fn() -> f64);
+        let mut jit = Jit::with_registry(&registry).unwrap();
+
+        // libm and the registry
+        let source = "extern cos(x); extern pow(x y); extern twice(x); extern answer();
+                      cos(0) + pow(2, 3); twice(answer())";
+        assert_eq!(eval(&mut jit, source), [9.0, 84.0]);
+
+        let items = parse_file("extern twice(x y)").unwrap();
+        assert!(matches!(
+            jit.compile_item(&items[0]),
+            Err(CraneliftError::Codegen(CodegenError::ArityMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn cranelift_errors() {
+        let mut jit = Jit::new().unwrap();
+        let items =
+            parse_file("def f(x) y; def f(x y) x; f(1); def f() 1; extern nosuchfunction()")
+                .unwrap();
+        assert!(matches!(
+            jit.compile_item(&items[0]),
+            Err(CraneliftError::Codegen(
+                CodegenError::UnknownVariable { .. }
+            ))
+        ));
+        // the failed definition is forgotten
+        jit.compile_item(&items[1]).unwrap();
+        assert!(matches!(
+            jit.compile_item(&items[2]),
+            Err(CraneliftError::Codegen(CodegenError::ArityMismatch { .. }))
+        ));
+        assert!(matches!(
+            jit.compile_item(&items[3]),
+            Err(CraneliftError::Codegen(CodegenError::Redefinition { .. }))
+        ));
+        assert!(matches!(
+            jit.compile_item(&items[4]),
+            Err(CraneliftError::Unresolved { .. })
+        ));
+    }
+
+    #[test]
+    fn cranelift_object() {
+        let mut object = Object::new().unwrap();
+        for item in parse_file("extern printd(x); def f(x) x + 1; printd(f(1))").unwrap() {
+            object.compile_item(&item).unwrap();
+        }
+        let bytes = object.finish(true).unwrap();
+        if cfg!(target_os = "linux") {
+            assert_eq!(bytes[..4], *b"\x7fELF");
+        }
+    }
+}
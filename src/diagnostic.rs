@@ -0,0 +1,137 @@
+#[cfg(feature = "std")]
+use crate::codegen::CodegenError;
+use crate::lexer::{Position, Span};
+use crate::parser::ParseError;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+// diagnostic - a problem in the source reported to the user, by the
+// lexer and parser through `ParseError`, by codegen through
+// `CodegenError` and by lints, the driver renders them with an `Emitter`
+#[cfg(feature = "std")]
+mod emit;
+
+#[cfg(feature = "std")]
+pub use emit::{location, Emitter, JsonEmitter, TerminalEmitter};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+// another place in the source the diagnostic is about, e.g. where a
+// redefined function was first defined
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+// `span` is where the problem is, `code` names the kind of problem for
+// those that can be configured, e.g. the lint, `help` says how to fix it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub span: Span,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            code: None,
+            span,
+            message: message.into(),
+            labels: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Warning, span, message)
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    // start of the primary span
+    pub fn pos(&self) -> Position {
+        self.span.start
+    }
+
+    // the diagnostic with every span moved by `f`, e.g. when the source
+    // before it was edited
+    pub fn map_spans(&self, f: impl Fn(Position) -> Position) -> Self {
+        let span = |span: Span| Span::new(f(span.start), f(span.end));
+        Diagnostic {
+            span: span(self.span),
+            labels: self
+                .labels
+                .iter()
+                .map(|label| Label {
+                    span: span(label.span),
+                    message: label.message.clone(),
+                })
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(err: ParseError) -> Self {
+        let diagnostic = Diagnostic::error(Span::at(err.pos()), err.to_string());
+        match err {
+            ParseError::UnterminatedParen { open, .. } => {
+                diagnostic.with_label(Span::at(open), "'(' opened here")
+            }
+            ParseError::TooDeeplyNested { .. } => {
+                diagnostic.with_help("split the expression into functions")
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<CodegenError> for Diagnostic {
+    fn from(err: CodegenError) -> Self {
+        Diagnostic::error(Span::at(err.pos()), err.to_string())
+    }
+}
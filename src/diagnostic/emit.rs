@@ -0,0 +1,136 @@
+use super::Diagnostic;
+use crate::ast::diagnostic_to_json;
+use crate::lexer::Position;
+use crate::source::SourceMap;
+use std::io::{self, Write};
+
+// emitters render diagnostics for whoever reads them, a person at a
+// terminal or a tool reading json, positions are in `sources`
+pub trait Emitter {
+    fn emit(&mut self, diagnostic: &Diagnostic, sources: &SourceMap) -> io::Result<()>;
+}
+
+// `name:line:column`, `line:column` if the source is not in `sources`
+pub fn location(sources: &SourceMap, pos: Position) -> String {
+    match sources.locate(pos) {
+        Some(at) => at.to_string(),
+        None => format!("{}:{}", pos.line, pos.column),
+    }
+}
+
+// one line per diagnostic, then one per label and help note
+//
+//   error: lib.ks:2:3: expected ')' to close '(', found end of input
+//   note: lib.ks:2:1: '(' opened here
+//   help: ...
+//
+// the code of the diagnostic, if any, follows its message in brackets
+pub struct TerminalEmitter<W> {
+    out: W,
+}
+
+impl<W: Write> TerminalEmitter<W> {
+    pub fn new(out: W) -> Self {
+        TerminalEmitter { out }
+    }
+}
+
+impl<W: Write> Emitter for TerminalEmitter<W> {
+    fn emit(&mut self, diagnostic: &Diagnostic, sources: &SourceMap) -> io::Result<()> {
+        let at = location(sources, diagnostic.pos());
+        write!(
+            self.out,
+            "{}: {}: {}",
+            diagnostic.severity, at, diagnostic.message
+        )?;
+        match diagnostic.code {
+            Some(code) => writeln!(self.out, " [{}]", code)?,
+            None => writeln!(self.out)?,
+        }
+        for label in &diagnostic.labels {
+            let at = location(sources, label.span.start);
+            writeln!(self.out, "note: {}: {}", at, label.message)?;
+        }
+        for help in &diagnostic.help {
+            writeln!(self.out, "help: {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+// one json object per line, an `error` of the schema in ast/json.rs
+pub struct JsonEmitter<W> {
+    out: W,
+}
+
+impl<W: Write> JsonEmitter<W> {
+    pub fn new(out: W) -> Self {
+        JsonEmitter { out }
+    }
+}
+
+impl<W: Write> Emitter for JsonEmitter<W> {
+    fn emit(&mut self, diagnostic: &Diagnostic, _: &SourceMap) -> io::Result<()> {
+        writeln!(self.out, "{}", diagnostic_to_json(diagnostic))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{location, Emitter, JsonEmitter, TerminalEmitter};
+    use crate::diagnostic::Diagnostic;
+    use crate::lexer::{Lexer, Span};
+    use crate::parser::Parser;
+    use crate::source::{SourceFile, SourceMap};
+    use serde_json::Value;
+
+    #[test]
+    fn emitters() {
+        let mut sources = SourceMap::new();
+        sources.add(SourceFile::cmdline("1;\n"));
+        let lib = SourceFile {
+            name: "lib.ks".into(),
+            contents: "def f(x)\n(x + 1;".into(),
+        };
+        let start = sources.add(lib.clone());
+        let lexer = Lexer::new(lib.contents.chars()).starting_at(start);
+        let mut diagnostics = Parser::new(lexer).parse_all().diagnostics;
+        diagnostics.push(
+            Diagnostic::warning(Span::at(start), "unused parameter 'x' of 'f'")
+                .with_code("unused-parameter")
+                .with_help("remove it"),
+        );
+
+        let mut out = Vec::new();
+        let mut emitter = TerminalEmitter::new(&mut out);
+        for diagnostic in &diagnostics {
+            emitter.emit(diagnostic, &sources).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "error: lib.ks:2:7: expected ')' to close '(', found ';'\n\
+             note: lib.ks:2:1: '(' opened here\n\
+             warning: lib.ks:1:1: unused parameter 'x' of 'f' [unused-parameter]\n\
+             help: remove it\n"
+        );
+
+        // positions of sources not in the map
+        assert_eq!(location(&SourceMap::new(), start), "1:1");
+
+        let mut out = Vec::new();
+        let mut emitter = JsonEmitter::new(&mut out);
+        for diagnostic in &diagnostics {
+            emitter.emit(diagnostic, &sources).unwrap();
+        }
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["labels"][0]["span"]["start"]["line"], 2);
+        assert_eq!(lines[1]["severity"], "warning");
+        assert_eq!(lines[1]["code"], "unused-parameter");
+        assert_eq!(lines[1]["help"][0], "remove it");
+    }
+}
@@ -0,0 +1,1233 @@
+// driver - the pipeline of klc, reading the sources, parsing, checking
+// and compiling them and writing what the commands of klc write, the
+// binary parses the command line and calls the command it names
+// errors in the program are reported through the session, errors of the
+// driver itself, e.g. an artifact that can't be written, exit the way
+// klc does: 1 for errors, 2 for usage errors
+mod edit;
+
+use crate::ast::{self, ExpressionAST, Item};
+use crate::codegen::{Codegen, CodegenError};
+use crate::coverage::{self, CoverageError};
+#[cfg(feature = "cranelift")]
+use crate::cranelift;
+use crate::diagnostic::Diagnostic;
+use crate::emit::{self, EmitError};
+use crate::interp::{self, Interp, InterpError};
+use crate::jit::{Jit, JitError};
+use crate::lexer::{Lexer, LexerConfig, Position, ReadChars, Span, Token};
+use crate::lint::{Level, Linter};
+use crate::mlir;
+use crate::parser::{ParseResult, Parser};
+use crate::session::{self, Definitions, PassTime, Session, Times};
+use crate::source::{self, SourceFile};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{Display, Write as _};
+use std::io::{BufRead, BufReader, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+// `--prompt`, `--continuation-prompt` and `--result-prefix`, `{backend}`
+// and `{definitions}` in the prompts become the backend of the repl and
+// the number of functions and externs read so far
+#[derive(Debug, Clone)]
+pub struct Prompts {
+    pub prompt: String,
+    pub continuation: String,
+    pub result: String,
+}
+
+impl Default for Prompts {
+    fn default() -> Self {
+        Prompts {
+            prompt: "ready> ".into(),
+            continuation: "...> ".into(),
+            result: "=> ".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompting {
+    // the line starts an item
+    Item,
+    // the line continues the item of the line before
+    Continuation,
+}
+
+// the prompts of a repl reading from a terminal, shared by the line
+// editor showing them and the repl telling it where items start, sources
+// read without one have no prompts
+struct Prompter {
+    prompts: Prompts,
+    // backend of the repl, for `{backend}`
+    backend: &'static str,
+    // what the next line read is
+    next: Cell<Prompting>,
+}
+
+impl Prompter {
+    // the prompter of a repl of `backend` if it reads from a terminal
+    fn of_repl(backend: &'static str, paths: &[&str], options: &Options) -> Option<Rc<Prompter>> {
+        let prompter = Prompter {
+            prompts: options.prompts.clone(),
+            backend,
+            next: Cell::new(Prompting::Item),
+        };
+        reads_terminal(paths, options).then(|| Rc::new(prompter))
+    }
+
+    // the line read next starts another item
+    fn start_item(&self) {
+        self.next.set(Prompting::Item);
+    }
+
+    // prompt of the next line
+    fn line(&self, definitions: &Definitions) -> String {
+        let template = match self.next.replace(Prompting::Continuation) {
+            Prompting::Item => &self.prompts.prompt,
+            Prompting::Continuation => &self.prompts.continuation,
+        };
+        let definitions = definitions.len().to_string();
+        template
+            .replace("{backend}", self.backend)
+            .replace("{definitions}", &definitions)
+    }
+}
+
+// report an error in the program, klc goes on with the rest of it unless
+// there were `--max-errors` already, `main` exits with 1 if there were any
+fn report(session: &mut Session, message: impl Display) {
+    eprintln!("error: {}", message);
+    session.count_error();
+    stop_at_max_errors(session);
+}
+
+fn stop_at_max_errors(session: &Session) {
+    if session.stopped() {
+        eprintln!("error: stopping after {} errors", session.errors());
+        std::process::exit(1);
+    }
+}
+
+// errors count towards `--max-errors` like those of `report`
+fn report_diagnostic(session: &mut Session, diagnostic: &Diagnostic) {
+    session.emit(diagnostic);
+    stop_at_max_errors(session);
+}
+
+// parser of a source file or stdin
+type SourceParser = Parser<ReadChars<Box<dyn Read>>>;
+
+// run `f` with a parser of each source in turn, the files at `paths` and
+// `-e`, stdin if there are neither, errors are reported with the source
+// they are in, exits once all ran if a file could not be read
+// stdin read from a terminal is prompted for by `prompter`
+fn for_each_source(
+    session: &mut Session,
+    paths: &[&str],
+    options: &Options,
+    prompter: Option<&Rc<Prompter>>,
+    mut f: impl FnMut(&mut Session, SourceParser),
+) {
+    for_each_input(
+        session,
+        paths,
+        options,
+        prompter,
+        |session, input, start| {
+            let lexer = Lexer::with_config(ReadChars::new(input), session.options.lexer);
+            let parser = Parser::with_config(lexer.starting_at(start), session.options.parser);
+            f(session, parser)
+        },
+    )
+}
+
+// `for_each_source` for the input itself, with the position to lex it
+// from, the sources are added to those of the session as they are read
+// stdin is read line by line as it comes, with its command lines run,
+// through a line editor if it is a terminal
+fn for_each_input(
+    session: &mut Session,
+    paths: &[&str],
+    options: &Options,
+    prompter: Option<&Rc<Prompter>>,
+    mut f: impl FnMut(&mut Session, Box<dyn Read>, Position),
+) {
+    if paths.is_empty() && options.cmdline.is_empty() {
+        if !interactive() {
+            let stdin = BufReader::new(std::io::stdin());
+            return read_commands(session, stdin, prompter, f);
+        }
+        let definitions = session.definitions.clone();
+        let line_prompter = prompter.cloned();
+        let prompt = move || match &line_prompter {
+            Some(prompter) => prompter.line(&definitions),
+            None => String::new(),
+        };
+        let definitions = session.definitions.clone();
+        let names = move || {
+            let commands = COMMANDS.into_iter().map(String::from);
+            commands.chain(definitions.names()).collect()
+        };
+        let editor = edit::Editor::new(prompt, names);
+        return read_commands(session, BufReader::new(editor), prompter, f);
+    }
+    let files = paths.iter().map(|path| (*path, SourceFile::read(path)));
+    let cmdline = options
+        .cmdline
+        .iter()
+        .map(|text| (source::CMDLINE, Ok(SourceFile::cmdline(text))));
+    let mut ok = true;
+    for (name, source) in files.chain(cmdline) {
+        match source {
+            Ok(source) => {
+                let start = session.add_source(source.clone());
+                f(session, source.reader(), start);
+            }
+            Err(err) => {
+                eprintln!("error: cannot read {}: {}", name, err);
+                ok = false;
+            }
+        }
+    }
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+// run `f` on stdin read from `input` with its command lines run, failed
+// commands count as errors once `f` is done
+fn read_commands(
+    session: &mut Session,
+    input: impl BufRead + 'static,
+    prompter: Option<&Rc<Prompter>>,
+    mut f: impl FnMut(&mut Session, Box<dyn Read>, Position),
+) {
+    let commands = Commands::new(input, session.definitions.clone(), prompter.cloned());
+    let failed = commands.failed.clone();
+    let start = session.sources.add_stream(source::STDIN);
+    f(session, Box::new(commands), start);
+    for _ in 0..failed.get() {
+        session.count_error();
+    }
+}
+
+// whether stdin is a terminal someone types into, rather than a pipe or
+// a file
+fn interactive() -> bool {
+    std::io::stdin().is_terminal()
+}
+
+// whether the sources are typed on a terminal, without files or `-e`
+fn reads_terminal(paths: &[&str], options: &Options) -> bool {
+    paths.is_empty() && options.cmdline.is_empty() && interactive()
+}
+
+// the next item of a repl, its lines are read after the prompts of
+// `prompter`, items with denied lints are skipped, the others are added to
+// the definitions of the session
+fn read_item(
+    session: &mut Session,
+    parser: &mut SourceParser,
+    prompter: Option<&Rc<Prompter>>,
+    linter: &mut Linter,
+) -> Option<ParseResult<Item>> {
+    loop {
+        let item = parser.parse_item();
+        if let Some(prompter) = prompter {
+            prompter.start_item();
+        }
+        match item? {
+            Ok(item) if !lint(session, linter, &item) => continue,
+            item => {
+                if let Ok(item) = &item {
+                    session.definitions.define(item);
+                }
+                return Some(item);
+            }
+        }
+    }
+}
+
+// commands in stdin, run as their line is read
+//
+//   :load <file>  evaluate the file as if it was typed
+//   :save <file>  write the functions and externs read so far to the file
+const COMMANDS: [&str; 2] = [":load", ":save"];
+
+// the lines of `input` with those starting with ':' run as commands
+struct Commands<R> {
+    input: R,
+    // rest of the line or loaded file not read yet
+    pending: Vec<u8>,
+    // those of the session, for `:save`
+    definitions: Definitions,
+    // commands that failed, errors of the session once the parser reading
+    // from the commands is done
+    failed: Rc<Cell<usize>>,
+    // a command line is an item of its own
+    prompter: Option<Rc<Prompter>>,
+}
+
+impl<R: BufRead> Commands<R> {
+    fn new(input: R, definitions: Definitions, prompter: Option<Rc<Prompter>>) -> Self {
+        Commands {
+            input,
+            pending: Vec::new(),
+            definitions,
+            failed: Rc::default(),
+            prompter,
+        }
+    }
+
+    // what a command line feeds into the session
+    fn run(&self, command: &str) -> Vec<u8> {
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+        let fail = |message: String| {
+            eprintln!("error: {}", message);
+            self.failed.set(self.failed.get() + 1);
+        };
+        match (name, arg) {
+            ("load" | "save", "") => fail(format!("expected a file after ':{}'", name)),
+            ("load", path) => match std::fs::read(path) {
+                Ok(mut text) => {
+                    if !text.ends_with(b"\n") {
+                        text.push(b'\n');
+                    }
+                    return text;
+                }
+                Err(err) => fail(format!("cannot read {}: {}", path, err)),
+            },
+            ("save", path) => {
+                let mut source = String::new();
+                for item in self.definitions.items() {
+                    writeln!(source, "{};", item).unwrap();
+                }
+                if let Err(err) = std::fs::write(path, source) {
+                    fail(format!("cannot write {}: {}", path, err));
+                }
+            }
+            _ => fail(format!(
+                "unknown command ':{}', expected :load <file> or :save <file>",
+                name
+            )),
+        }
+        if let Some(prompter) = &self.prompter {
+            prompter.start_item();
+        }
+        Vec::new()
+    }
+}
+
+impl<R: BufRead> Read for Commands<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pending.is_empty() {
+            let mut line = Vec::new();
+            if self.input.read_until(b'\n', &mut line)? == 0 {
+                return Ok(0);
+            }
+            let text = String::from_utf8_lossy(&line);
+            self.pending = match text.trim().strip_prefix(':') {
+                Some(command) => self.run(command),
+                None => line,
+            };
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+// report what `linter` finds in `item`, returns false if a lint was
+// denied
+fn lint(session: &mut Session, linter: &mut Linter, item: &Item) -> bool {
+    let mut ok = true;
+    for (_, level, diagnostic) in linter.check(item) {
+        report_diagnostic(session, &diagnostic);
+        ok &= level != Level::Deny;
+    }
+    ok
+}
+
+fn report_codegen_error(session: &mut Session, err: CodegenError) {
+    report_diagnostic(session, &err.into());
+}
+
+// the result of a top-level expression in the repls
+fn print_value(prompts: &Prompts, value: f64) {
+    println!("{}{}", prompts.result, value);
+}
+
+fn report_jit_error(session: &mut Session, err: JitError) {
+    match err {
+        JitError::Codegen(err) => report_codegen_error(session, err),
+        JitError::Spawn(err) => report(session, format!("cannot run lli: {}", err)),
+        JitError::Failed(stderr) => report(session, format!("lli failed:\n{}", stderr)),
+    }
+}
+
+// print the ir of functions and externs as they are read, evaluate
+// top-level expressions
+fn handle_item(session: &mut Session, jit: &mut Jit, item: &Item, prompts: &Prompts) {
+    let what = match item {
+        Item::Function(_) => "function definition",
+        Item::Extern(_) => "extern",
+        Item::Expr(expr) => {
+            match jit.eval(expr) {
+                Ok(value) => print_value(prompts, value),
+                Err(err) => report_jit_error(session, err),
+            }
+            return;
+        }
+    };
+    match jit.codegen().compile_item(item) {
+        Ok(ir) => println!("Read {}:\n{}", what, ir),
+        Err(err) => report_codegen_error(session, err),
+    }
+}
+
+fn report_emit_error(path: &Path, err: EmitError) {
+    match err {
+        EmitError::Io(err) => eprintln!("error: cannot write {}: {}", path.display(), err),
+        EmitError::Failed { tool, stderr } => eprintln!("error: {} failed:\n{}", tool, stderr),
+    }
+}
+
+// `--tokens [<file>...]`: lex the files or stdin and print each token,
+// comments included, with its span and class
+pub fn print_tokens(session: &mut Session, paths: &[&str], options: &Options) {
+    let config = LexerConfig {
+        emit_comments: true,
+        ..session.options.lexer
+    };
+    for_each_input(session, paths, options, None, |session, input, start| {
+        let name = source_name(session, start);
+        if let Some(name) = name.filter(|name| name != source::STDIN) {
+            println!("{}:", name);
+        }
+        let mut lexer = Lexer::with_config(ReadChars::new(input), config).starting_at(start);
+        loop {
+            let token = lexer.next_token();
+            if token == Token::Eof {
+                break;
+            }
+            let Span { start, end } = lexer.token_span();
+            let span = format!(
+                "{}:{}-{}:{}",
+                start.line, start.column, end.line, end.column
+            );
+            let text = match &token {
+                Token::Def => "def".into(),
+                Token::Extern => "extern".into(),
+                Token::Identifier(name) => name.clone(),
+                Token::Number(num) => num.to_string(),
+                Token::Char(c) => c.to_string(),
+                Token::Comment(text) | Token::DocComment(text) => format!("{:?}", text),
+                Token::Error(err) => format!("{:?}", err),
+                Token::Eof => unreachable!(),
+            };
+            println!("{:<12} {:<12} {}", span, token.class(), text);
+        }
+    });
+}
+
+// `--dump-ast[=debug|json|sexpr|dot] [<file>...]`: parse the files or
+// stdin and print the items of all of them in `format`, exits after
+// reporting syntax errors, the items are printed regardless
+pub fn dump_ast(session: &mut Session, format: &str, paths: &[&str], options: &Options) {
+    let mut items = Vec::new();
+    let mut ok = true;
+    for_each_source(session, paths, options, None, |session, mut parser| {
+        let out = parser.parse_all();
+        for diagnostic in &out.diagnostics {
+            report_diagnostic(session, diagnostic);
+        }
+        ok &= out.diagnostics.is_empty();
+        items.extend(out.items);
+    });
+    match format {
+        "debug" => println!("{:#?}", items),
+        "json" => println!("{}", ast::program_to_json(&items)),
+        "sexpr" => print!("{}", ast::program_to_sexpr(&items)),
+        "dot" => print!("{}", ast::program_to_dot(&items)),
+        _ => unreachable!("checked by main"),
+    }
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+// `--stats`: parse all of stdin or `-e` and print the size of the program
+pub fn print_stats(session: &mut Session, options: &Options) {
+    let mut items = Vec::new();
+    for_each_source(session, &[], options, None, |session, mut parser| {
+        let out = parser.parse_all();
+        for diagnostic in &out.diagnostics {
+            report_diagnostic(session, diagnostic);
+        }
+        items.extend(out.items);
+    });
+    print!("{}", ast::stats(&items));
+}
+
+// where `phase` and `timed` log to, `-v` and `--time-passes` of the
+// session apart from it, so the phases they run can have the session
+struct PhaseLog {
+    verbose: bool,
+    times: Option<Times>,
+    allocations: Option<fn() -> (usize, usize)>,
+}
+
+impl PhaseLog {
+    fn of(session: &Session) -> Self {
+        PhaseLog {
+            verbose: session.options.verbose,
+            times: session.options.time_passes.then(|| session.times.clone()),
+            allocations: session.options.allocations,
+        }
+    }
+
+    // whether the phases are timed
+    fn timing(&self) -> bool {
+        self.verbose || self.times.is_some()
+    }
+}
+
+// run `f`, with `--time-passes` add its time and allocations as `name` to
+// the times of the session, printed at the end
+fn timed<T>(log: &PhaseLog, name: &str, f: impl FnOnce() -> T) -> T {
+    let Some(times) = &log.times else {
+        return f();
+    };
+    // the row goes before those of the passes `f` runs
+    let mut row = PassTime {
+        name: name.into(),
+        ..PassTime::default()
+    };
+    let index = times.push(row.clone());
+    let count = || log.allocations.map_or((0, 0), |allocations| allocations());
+    let (allocations, bytes) = count();
+    let start = Instant::now();
+    let out = f();
+    row.time = start.elapsed();
+    let (allocations_after, bytes_after) = count();
+    row.allocations = allocations_after - allocations;
+    row.bytes = bytes_after - bytes;
+    times.set(index, row);
+    out
+}
+
+// run the phase `name` of compiling, `timed`, with `-v` log how long it
+// took and what it produced as told by `what`, and the source it ran on
+fn phase<T>(
+    log: &PhaseLog,
+    name: &str,
+    source: Option<&str>,
+    f: impl FnOnce() -> T,
+    what: impl FnOnce(&T) -> String,
+) -> T {
+    if !log.verbose {
+        return timed(log, name, f);
+    }
+    let start = Instant::now();
+    let out = timed(log, name, f);
+    let elapsed = format!("{:.2?}", start.elapsed());
+    let source = match source {
+        Some(path) => format!(" in {}", path),
+        None => String::new(),
+    };
+    eprintln!("{:<9} {:>10}  {}{}", name, elapsed, what(&out), source);
+    out
+}
+
+// name of the source of the session lexed from `start`
+fn source_name(session: &Session, start: Position) -> Option<String> {
+    let (_, file) = session.sources.file(start.offset)?;
+    Some(file.name.clone())
+}
+
+// parse all of a source, lint it and run the ast passes of the session,
+// reports syntax errors and lints, the items and whether there were no
+// errors, when timing the source is lexed once on its own to time that
+fn parse_all(
+    session: &mut Session,
+    input: Box<dyn Read>,
+    start: Position,
+    linter: &mut Linter,
+) -> (Vec<Item>, bool) {
+    let name = source_name(session, start);
+    let source = name.as_deref();
+    let log = PhaseLog::of(session);
+    let input: Box<dyn Read> = match log.timing() {
+        false => input,
+        true => {
+            let mut text = Vec::new();
+            let mut input = input;
+            if let Err(err) = input.read_to_end(&mut text) {
+                report(session, format!("cannot read the source: {}", err));
+            }
+            let config = session.options.lexer;
+            let count = || count_tokens(&text, config);
+            phase(&log, "lex", source, count, |n| format!("{} tokens", n));
+            Box::new(std::io::Cursor::new(text))
+        }
+    };
+    let (lexer, parser) = (session.options.lexer, session.options.parser);
+    let out = phase(
+        &log,
+        "parse",
+        source,
+        || {
+            let lexer = Lexer::with_config(ReadChars::new(input), lexer).starting_at(start);
+            Parser::with_config(lexer, parser).parse_all()
+        },
+        |out| format!("{} items", out.items.len()),
+    );
+    for diagnostic in &out.diagnostics {
+        report_diagnostic(session, diagnostic);
+    }
+    let mut ok = out.diagnostics.is_empty();
+    phase(
+        &log,
+        "analyze",
+        source,
+        || {
+            for item in &out.items {
+                ok &= lint(session, linter, item);
+            }
+        },
+        |_| format!("{} items", out.items.len()),
+    );
+    let mut pipeline = session.pipeline();
+    let items = phase(
+        &log,
+        "optimize",
+        source,
+        || {
+            pipeline.run_with(out.items, |pass, items| {
+                timed(&log, &format!("  {}", pass.name()), || pass.run(items))
+            })
+        },
+        |items| format!("{} items", items.len()),
+    );
+    (items, ok)
+}
+
+fn count_tokens(text: &[u8], config: LexerConfig) -> usize {
+    let mut lexer = Lexer::with_config(ReadChars::new(text), config);
+    std::iter::from_fn(|| Some(lexer.next_token()))
+        .take_while(|token| *token != Token::Eof)
+        .count()
+}
+
+// `parse_all` of the sources at `paths` and `compile` each item, reports
+// all errors, returns whether there were none
+#[cfg(feature = "cranelift")]
+fn compile_all(
+    session: &mut Session,
+    paths: &[&str],
+    options: &Options,
+    mut compile: impl FnMut(&mut Session, &Item) -> bool,
+) -> bool {
+    let mut ok = true;
+    let mut linter = session.linter();
+    for_each_input(session, paths, options, None, |session, input, start| {
+        let (items, parsed) = parse_all(session, input, start, &mut linter);
+        ok &= parsed;
+        let name = source_name(session, start);
+        phase(
+            &PhaseLog::of(session),
+            "codegen",
+            name.as_deref(),
+            || {
+                for item in &items {
+                    ok &= compile(session, item);
+                }
+            },
+            |_| format!("{} items", items.len()),
+        );
+    });
+    ok
+}
+
+// which backend compiles and runs the program
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Backend {
+    #[default]
+    Llvm,
+    Cranelift,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "llvm" => Some(Backend::Llvm),
+            "cranelift" => Some(Backend::Cranelift),
+            _ => None,
+        }
+    }
+}
+
+// options that may come anywhere on the command line
+#[derive(Default)]
+pub struct Options {
+    // those of the phases, the options of the session
+    //
+    //   `-O<n>`, the last one counts
+    //   `--pass=<name>`, passes run after those of the level, see
+    //   `opt::register`
+    //   `--max-errors=<n>`, see `report`
+    //   `-W`, `-A` and `-D <lint>`, the last one for a lint counts
+    //   `-v`, see `phase`
+    //   `--time-passes`, see `timed`
+    pub session: session::Options,
+    // `--target <triple>`, the host if None
+    pub target: Option<String>,
+    // `-j <n>`, threads compiling functions, one per cpu if None
+    pub jobs: Option<usize>,
+    // `--instrument=profile`
+    pub profile: bool,
+    // `--instrument=coverage`
+    pub coverage: bool,
+    // `-o <path>`, where the artifact goes, see `output`
+    pub output: Option<PathBuf>,
+    // `--backend=<name>`, `--cranelift` picks it for one command
+    pub backend: Backend,
+    // `-l <lib>`, libraries linked into executables
+    pub libs: Vec<String>,
+    // program of the commands compiling one without files, see `program`
+    pub entry: Option<String>,
+    pub prompts: Prompts,
+    // `-e <source>`, see `for_each_input`
+    pub cmdline: Vec<String>,
+}
+
+// the sources of a program, `paths` or the entry of the config
+pub fn program<'a>(paths: &[&'a str], options: &'a Options) -> Vec<&'a str> {
+    match (paths, &options.entry) {
+        ([], Some(entry)) => vec![entry.as_str()],
+        _ => paths.to_vec(),
+    }
+}
+
+// where the artifact compiled from the sources at `paths` goes, `-o` or
+// the first source with `extension` instead of its own, None for stdout,
+// with `-o -` or for stdin without `-o`, exits if it would overwrite a
+// source
+fn output(paths: &[&str], options: &Options, extension: &str) -> Option<PathBuf> {
+    let output = match (&options.output, paths.first()) {
+        (Some(output), _) if output == Path::new("-") => return None,
+        (Some(output), _) => output.clone(),
+        (None, Some(source)) => Path::new(source).with_extension(extension),
+        (None, None) => return None,
+    };
+    if paths.iter().any(|path| Path::new(path) == output) {
+        eprintln!("error: {} would overwrite the source", output.display());
+        std::process::exit(1);
+    }
+    Some(output)
+}
+
+// `output` of artifacts that cannot go to stdout, exits for `-o -` and
+// without `-o` for stdin
+fn output_file(paths: &[&str], options: &Options, extension: &str) -> PathBuf {
+    output(paths, options, extension).unwrap_or_else(|| {
+        match options.output {
+            Some(_) => eprintln!("error: the output is not text, name it with -o <file>"),
+            None => eprintln!("error: reading stdin, name the output with -o <file>"),
+        }
+        std::process::exit(2);
+    })
+}
+
+// extension of object files, and executables, for the target
+pub fn object_extension(options: &Options, native: &'static str) -> &'static str {
+    match &options.target {
+        Some(triple) if triple.starts_with("wasm") => "wasm",
+        _ => native,
+    }
+}
+
+// write `text` to `path`, or to stdout if None
+fn write_text(path: Option<PathBuf>, text: &str) {
+    match path {
+        None => print!("{}", text),
+        Some(path) => {
+            if let Err(err) = std::fs::write(&path, text) {
+                eprintln!("error: cannot write {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// compile the sources at `paths`, or stdin, to one llvm module, the
+// functions of each in parallel, None if there were errors
+fn compile(session: &mut Session, paths: &[&str], options: &Options) -> Option<Codegen> {
+    let mut codegen = Codegen::new();
+    codegen.set_cse(session.options.level.cse());
+    codegen.set_profile(options.profile);
+    codegen.set_coverage(options.coverage);
+    if let Some(triple) = &options.target {
+        codegen.set_target(triple);
+    }
+    let mut ok = true;
+    let mut linter = session.linter();
+    let mut sources = Vec::new();
+    for_each_input(session, paths, options, None, |session, input, start| {
+        let (items, parsed) = parse_all(session, input, start, &mut linter);
+        ok &= parsed;
+        sources.push((source_name(session, start), items));
+    });
+    ok &= declare_definitions(session, &mut codegen, &mut sources);
+    for (source, items) in sources {
+        let results = phase(
+            &PhaseLog::of(session),
+            "codegen",
+            source.as_deref(),
+            || codegen.compile_items(&items),
+            |results| format!("{} items", results.len()),
+        );
+        for result in results {
+            if let Err(err) = result {
+                report_codegen_error(session, err);
+                ok = false;
+            }
+        }
+    }
+    ok.then_some(codegen)
+}
+
+// declare the functions the second and later of `sources` define, so the
+// files of a program call each other in any order, reports and drops the
+// definitions of names defined before, returns whether there were none
+fn declare_definitions(
+    session: &mut Session,
+    codegen: &mut Codegen,
+    sources: &mut [(Option<String>, Vec<Item>)],
+) -> bool {
+    let mut ok = true;
+    let mut first = HashMap::new();
+    for (i, (_, items)) in sources.iter_mut().enumerate() {
+        items.retain(|item| {
+            let Item::Function(func) = item else {
+                return true;
+            };
+            let (name, at) = (func.proto().name(), Span::at(func.proto().span().start));
+            if let Some(first) = first.get(name) {
+                let message = format!("redefinition of '{}'", name);
+                let diagnostic =
+                    Diagnostic::error(at, message).with_label(*first, "first defined here");
+                report_diagnostic(session, &diagnostic);
+                ok = false;
+                return false;
+            }
+            first.insert(name.to_string(), at);
+            if i > 0 {
+                if let Err(err) = codegen.compile_extern(func.proto()) {
+                    report_codegen_error(session, err);
+                    ok = false;
+                }
+            }
+            true
+        });
+    }
+    ok
+}
+
+// `--object [<file>...]`, `--bitcode [<file>...]`: compile the files or
+// stdin to one module and write it with `emit`, to `-o` or the first file
+// with `extension`
+pub fn write_module(
+    session: &mut Session,
+    paths: &[&str],
+    options: &Options,
+    extension: &str,
+    emit: fn(&str, &Path) -> Result<(), EmitError>,
+) {
+    let paths = &program(paths, options);
+    let path = output_file(paths, options, extension);
+    let Some(codegen) = compile(session, paths, options) else {
+        std::process::exit(1);
+    };
+    let written = phase(
+        &PhaseLog::of(session),
+        "emit",
+        None,
+        || emit(&codegen.module(), &path),
+        |_| path.display().to_string(),
+    );
+    if let Err(err) = written {
+        report_emit_error(&path, err);
+        std::process::exit(1);
+    }
+}
+
+// `build <file>...`: compile a program to a native executable running its
+// top-level expressions, `-o` or the first file without its extension,
+// functions the program never calls are left out from -O1 on, `-v` lists
+// them
+pub fn build(session: &mut Session, paths: &[&str], options: &Options) {
+    let paths = &program(paths, options);
+    let exe = output_file(paths, options, object_extension(options, ""));
+    let Some(mut codegen) = compile(session, paths, options) else {
+        std::process::exit(1);
+    };
+    let removed = phase(
+        &PhaseLog::of(session),
+        "dce",
+        None,
+        || match session.options.level.dce() {
+            true => codegen.remove_unreferenced(),
+            false => Vec::new(),
+        },
+        |removed| format!("{} functions removed", removed.len()),
+    );
+    for name in removed {
+        if session.options.verbose {
+            eprintln!("removed unreferenced function '{}'", name);
+        }
+    }
+    let module = codegen.module() + "\n" + &codegen.main_ir();
+    let written = phase(
+        &PhaseLog::of(session),
+        "link",
+        None,
+        || emit::write_executable(&module, &exe, &options.libs),
+        |_| exe.display().to_string(),
+    );
+    if let Err(err) = written {
+        report_emit_error(&exe, err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "cranelift")]
+fn report_cranelift_error(session: &mut Session, err: cranelift::CraneliftError) {
+    use cranelift::CraneliftError;
+    match err {
+        CraneliftError::Codegen(err) => report_codegen_error(session, err),
+        CraneliftError::Unresolved { name, pos } => {
+            let message = format!("no symbol for extern '{}'", name);
+            report_diagnostic(session, &Diagnostic::error(Span::at(pos), message))
+        }
+        CraneliftError::Isa(err) | CraneliftError::Object(err) => report(session, err),
+        CraneliftError::Module(err) => report(session, err),
+    }
+}
+
+// compile the sources at `paths`, or stdin, to an object file with
+// cranelift, exits on errors
+#[cfg(feature = "cranelift")]
+fn cranelift_object(
+    session: &mut Session,
+    paths: &[&str],
+    main: bool,
+    options: &Options,
+) -> Vec<u8> {
+    let mut object = cranelift::Object::new().unwrap_or_else(|err| {
+        report_cranelift_error(session, err);
+        std::process::exit(1);
+    });
+    let ok = compile_all(session, paths, options, |session, item| {
+        match object.compile_item(item) {
+            Ok(()) => true,
+            Err(err) => {
+                report_cranelift_error(session, err);
+                false
+            }
+        }
+    });
+    if !ok {
+        std::process::exit(1);
+    }
+    object.finish(main).unwrap_or_else(|err| {
+        report_cranelift_error(session, err);
+        std::process::exit(1);
+    })
+}
+
+// `--cranelift --object [<file>...]`: `write_module` with cranelift
+#[cfg(feature = "cranelift")]
+pub fn cranelift_write_object(session: &mut Session, paths: &[&str], options: &Options) {
+    let paths = &program(paths, options);
+    let path = output_file(paths, options, "o");
+    let bytes = cranelift_object(session, paths, false, options);
+    if let Err(err) = std::fs::write(&path, bytes) {
+        eprintln!("error: cannot write {}: {}", path.display(), err);
+        std::process::exit(1);
+    }
+}
+
+// `--cranelift build <file>...`: `build` with cranelift
+#[cfg(feature = "cranelift")]
+pub fn cranelift_build(session: &mut Session, paths: &[&str], options: &Options) {
+    let paths = &program(paths, options);
+    let exe = output_file(paths, options, object_extension(options, ""));
+    let bytes = cranelift_object(session, paths, true, options);
+    if let Err(err) = emit::link_object(&bytes, &exe, &options.libs) {
+        report_emit_error(&exe, err);
+        std::process::exit(1);
+    }
+}
+
+// `--cranelift`: the repl, compiling with cranelift instead of running lli
+#[cfg(feature = "cranelift")]
+pub fn cranelift_repl(session: &mut Session, options: &Options) {
+    let mut jit = cranelift::Jit::new().unwrap_or_else(|err| {
+        report_cranelift_error(session, err);
+        std::process::exit(1);
+    });
+    let prompter = Prompter::of_repl("cranelift", &[], options);
+    let mut linter = session.linter();
+    let prompter = prompter.as_ref();
+    for_each_source(session, &[], options, prompter, |session, mut parser| {
+        while let Some(item) = read_item(session, &mut parser, prompter, &mut linter) {
+            let result = match item {
+                Ok(Item::Expr(expr)) => jit.eval(&expr).map(|value| {
+                    print_value(&options.prompts, value);
+                }),
+                Ok(item) => jit.compile_item(&item),
+                Err(err) => {
+                    report_diagnostic(session, &err.into());
+                    parser.synchronize();
+                    continue;
+                }
+            };
+            if let Err(err) = result {
+                report_cranelift_error(session, err);
+            }
+        }
+    });
+}
+
+// `--interp`: the repl, evaluating with the interpreter instead of lli
+pub fn interp_repl(session: &mut Session, paths: &[&str], options: &Options) {
+    let prompter = Prompter::of_repl("interp", paths, options);
+    let prompter = prompter.as_ref();
+    let mut interp = Interp::new();
+    let mut linter = session.linter();
+    for_each_source(session, paths, options, prompter, |session, mut parser| {
+        while let Some(item) = read_item(session, &mut parser, prompter, &mut linter) {
+            match item {
+                Ok(item) => match interp.eval_item(&item) {
+                    Ok(Some(value)) => print_value(&options.prompts, value),
+                    Ok(None) => {}
+                    Err(err) => report_interp_error(session, err),
+                },
+                Err(err) => {
+                    report_diagnostic(session, &err.into());
+                    parser.synchronize();
+                }
+            }
+        }
+    });
+}
+
+fn report_interp_error(session: &mut Session, err: InterpError) {
+    report_diagnostic(session, &interp_diagnostic(err));
+}
+
+fn interp_diagnostic(err: InterpError) -> Diagnostic {
+    match err {
+        InterpError::Codegen(err) => err.into(),
+        InterpError::Unbound { name, pos } => {
+            Diagnostic::error(Span::at(pos), format!("no binding for extern '{}'", name))
+        }
+        InterpError::StackOverflow { name, pos } => Diagnostic::error(
+            Span::at(pos),
+            format!(
+                "calls nested too deep calling '{}' (limit {})",
+                name,
+                interp::MAX_CALL_DEPTH
+            ),
+        ),
+    }
+}
+
+// `run [<file>...] [-- <arg>...]`: evaluate the top-level expressions of a
+// program with the interpreter without printing their values, `values`
+// are what `extern argc()` and `extern argv(i)`, from 0, return, argv is
+// NaN outside of them
+pub fn run(session: &mut Session, paths: &[&str], values: Vec<f64>, options: &Options) {
+    let paths = &program(paths, options);
+    let mut interp = Interp::new();
+    let argc = values.len() as f64;
+    interp.bind("argc", 0, move |_| argc);
+    interp.bind("argv", 1, move |args| match args[0] {
+        i if i >= 0.0 && i.fract() == 0.0 => values.get(i as usize).copied().unwrap_or(f64::NAN),
+        _ => f64::NAN,
+    });
+    let mut linter = session.linter();
+    for_each_input(session, paths, options, None, |session, input, start| {
+        let (items, _) = parse_all(session, input, start, &mut linter);
+        for item in &items {
+            if let Err(err) = interp.eval_item(item) {
+                report_interp_error(session, err);
+            }
+        }
+    });
+}
+
+// `test [<file>...]`: run the functions of a program named `test_*` with
+// the interpreter, a test passes if it returns 0 and every `assert(x)` it
+// calls gets a nonzero x, `extern assert(x);` declares it, top-level
+// expressions are not evaluated, exits 1 if a test failed
+pub fn run_tests(session: &mut Session, paths: &[&str], options: &Options) {
+    let paths = &program(paths, options);
+    let failed_asserts = Arc::new(AtomicUsize::new(0));
+    let mut interp = Interp::new();
+    let asserts = failed_asserts.clone();
+    interp.bind("assert", 1, move |args| {
+        if args[0] == 0.0 {
+            asserts.fetch_add(1, Ordering::Relaxed);
+        }
+        0.0
+    });
+    // all files are added before any test runs, tests may call functions
+    // of later files
+    let mut tests = Vec::new();
+    let mut linter = session.linter();
+    for_each_input(session, paths, options, None, |session, input, start| {
+        let (items, _) = parse_all(session, input, start, &mut linter);
+        for item in items {
+            match item {
+                Item::Expr(_) => {}
+                Item::Function(func) if func.proto().name().starts_with("test_") => {
+                    match interp.add_function(&func) {
+                        Ok(_) => tests.push(func.proto().clone()),
+                        Err(err) => report_interp_error(session, err),
+                    }
+                }
+                item => {
+                    if let Err(err) = interp.eval_item(&item) {
+                        report_interp_error(session, err);
+                    }
+                }
+            }
+        }
+    });
+    let mut failed = 0;
+    for proto in &tests {
+        failed_asserts.store(0, Ordering::Relaxed);
+        let call = ExpressionAST::call(proto.name(), vec![], proto.span());
+        let result = interp.eval(&call);
+        let asserts = failed_asserts.load(Ordering::Relaxed);
+        let failure = match result {
+            Err(err) => Some(interp_diagnostic(err).message),
+            Ok(_) if asserts > 0 => Some(format!("{} assert(s) failed", asserts)),
+            Ok(value) if value != 0.0 => Some(format!("returned {}", value)),
+            Ok(_) => None,
+        };
+        match failure {
+            None => println!("test {} ... ok", proto.name()),
+            Some(why) => {
+                println!("test {} ... FAILED: {}", proto.name(), why);
+                failed += 1;
+            }
+        }
+    }
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed, {} failed",
+        result,
+        tests.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+// `--ir [--function <name>] [<file>...]`: compile the files or stdin and
+// write the llvm ir of the module or a single function, to `-o`, the
+// first file with `.ll` or stdout
+pub fn print_ir(session: &mut Session, function: Option<&str>, paths: &[&str], options: &Options) {
+    let paths = &program(paths, options);
+    let path = output(paths, options, "ll");
+    let Some(codegen) = compile(session, paths, options) else {
+        std::process::exit(1);
+    };
+    let ir = match function {
+        None => codegen.module(),
+        Some(name) => codegen.function_ir(name).unwrap_or_else(|| {
+            eprintln!("error: no function '{}'", name);
+            std::process::exit(1);
+        }),
+    };
+    write_text(path, &ir);
+}
+
+// `coverage <file> [<counts>]`: the source of a program built with
+// `--instrument=coverage` annotated with the counts it wrote,
+// `klc.coverage` by default
+pub fn print_coverage(source: &str, counts: &str) {
+    let read = |path: &str| {
+        std::fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("error: cannot read {}: {}", path, err);
+            std::process::exit(1);
+        })
+    };
+    let (source_text, counts_text) = (read(source), read(counts));
+    let regions = coverage::parse(&counts_text, &source_text).unwrap_or_else(|err| {
+        match err {
+            CoverageError::Malformed { line } => {
+                eprintln!("error: {}:{}: expected 'start end count'", counts, line)
+            }
+            CoverageError::OutOfSource { line } => {
+                eprintln!("error: {}:{}: region outside of {}", counts, line, source)
+            }
+        }
+        std::process::exit(1);
+    });
+    print!("{}", coverage::render(&source_text, &regions));
+}
+
+// `--mlir [<file>...]`: compile the files or stdin and write the module
+// as mlir, to `-o`, the first file with `.mlir` or stdout
+pub fn print_mlir(session: &mut Session, paths: &[&str], options: &Options) {
+    let paths = &program(paths, options);
+    let path = output(paths, options, "mlir");
+    let Some(codegen) = compile(session, paths, options) else {
+        std::process::exit(1);
+    };
+    write_text(path, &mlir::module(&codegen));
+}
+
+// evaluate the files at `paths` in order, or stdin if there are none,
+// functions persist from one file to the next, the banner and prompts
+// are only shown if stdin is a terminal
+pub fn repl(session: &mut Session, paths: &[&str], options: &Options) {
+    let prompter = Prompter::of_repl("llvm", paths, options);
+    if prompter.is_some() {
+        println!("Evaluate stdin");
+        println!("ENTER to evaluate current input");
+        println!("C-c   to exit");
+    }
+    let prompter = prompter.as_ref();
+    let mut jit = Jit::new();
+    let mut linter = session.linter();
+    for_each_source(session, paths, options, prompter, |session, mut parser| {
+        while let Some(item) = read_item(session, &mut parser, prompter, &mut linter) {
+            match item {
+                Ok(item) => handle_item(session, &mut jit, &item, &options.prompts),
+                Err(err) => {
+                    report_diagnostic(session, &err.into());
+                    parser.synchronize();
+                }
+            }
+        }
+    });
+
+    // the module with everything read
+    print!("{}", jit.codegen().module());
+}
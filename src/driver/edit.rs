@@ -0,0 +1,224 @@
+use crate::lexer::KEYWORDS;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+// edit - line editing of stdin on a terminal, tab completes the keywords
+// and the names and commands of the repl
+//
+// stty switches the terminal out of canonical mode while a line is read
+// and back once it is entered, so evaluating it prints like it always
+// did, without stty lines are read as the terminal edits them
+
+pub struct Editor<P, F> {
+    // prompt of the next line
+    prompt: P,
+    // names to complete besides the keywords
+    names: F,
+    // prompt of the line being read, shown again below the names to
+    // choose from
+    shown: String,
+    // rest of the entered line not read yet
+    pending: Vec<u8>,
+}
+
+enum Edited {
+    Line(Vec<u8>),
+    Eof,
+    // C-c
+    Interrupt,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Completion {
+    None,
+    // text to add to the line
+    Insert(String),
+    // names the word could become
+    Choose(Vec<String>),
+}
+
+impl<P: FnMut() -> String, F: Fn() -> Vec<String>> Editor<P, F> {
+    pub fn new(prompt: P, names: F) -> Self {
+        Editor {
+            prompt,
+            names,
+            shown: String::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // the next line with its newline, empty at the end of the input
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        self.shown = (self.prompt)();
+        eprint!("{}", self.shown);
+        let Some(saved) = stty(&["-g"]) else {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            return Ok(line.into_bytes());
+        };
+        stty(&["-icanon", "-echo", "-isig", "min", "1"]);
+        let edited = self.edit();
+        stty(&[saved.trim()]);
+        match edited? {
+            Edited::Line(line) => Ok(line),
+            Edited::Eof => Ok(Vec::new()),
+            Edited::Interrupt => {
+                eprintln!();
+                std::process::exit(130);
+            }
+        }
+    }
+
+    // a line typed on the terminal, echoed to stderr
+    fn edit(&self) -> io::Result<Edited> {
+        let mut stdin = io::stdin().lock();
+        let mut stderr = io::stderr();
+        let mut line = Vec::new();
+        let mut byte = [0];
+        loop {
+            if stdin.read(&mut byte)? == 0 {
+                return Ok(match line.is_empty() {
+                    true => Edited::Eof,
+                    false => Edited::Line(line),
+                });
+            }
+            match byte[0] {
+                b'\n' | b'\r' => {
+                    line.push(b'\n');
+                    eprintln!();
+                    return Ok(Edited::Line(line));
+                }
+                3 => return Ok(Edited::Interrupt),
+                4 if line.is_empty() => return Ok(Edited::Eof),
+                b'\t' => self.complete(&mut line),
+                8 | 127 if !line.is_empty() => {
+                    // the whole last character
+                    while let Some(b) = line.pop() {
+                        if b & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                    eprint!("\x08 \x08");
+                }
+                // escape sequences of keys like the arrows are ignored
+                0x1b => {
+                    stdin.read_exact(&mut byte)?;
+                    if byte[0] == b'[' || byte[0] == b'O' {
+                        while stdin.read(&mut byte)? == 1 && !(0x40..=0x7e).contains(&byte[0]) {}
+                    }
+                }
+                b if b < 0x20 || b == 127 => {}
+                b => {
+                    line.push(b);
+                    stderr.write_all(&[b])?;
+                }
+            }
+            stderr.flush()?;
+        }
+    }
+
+    fn complete(&self, line: &mut Vec<u8>) {
+        let text = String::from_utf8_lossy(line).into_owned();
+        let names = (self.names)();
+        let names = KEYWORDS.into_iter().chain(names.iter().map(String::as_str));
+        match complete(&text, names) {
+            Completion::None => {}
+            Completion::Insert(rest) => {
+                eprint!("{}", rest);
+                line.extend(rest.bytes());
+            }
+            Completion::Choose(names) => {
+                eprint!("\n{}\n{}{}", names.join("  "), self.shown, text);
+            }
+        }
+    }
+}
+
+impl<P: FnMut() -> String, F: Fn() -> Vec<String>> Read for Editor<P, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.pending = self.read_line()?;
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+// run stty on the terminal of stdin, its output if it succeeded
+fn stty(args: &[&str]) -> Option<String> {
+    let out = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+// how the word `line` ends with completes to one of `names`, the rest of
+// the only name it starts, else what all names it starts have in common
+// after it, else those names
+pub fn complete<'a>(line: &str, names: impl IntoIterator<Item = &'a str>) -> Completion {
+    let mut start = line
+        .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .map_or(0, |i| i + 1);
+    // commands start with ':'
+    if line[..start].trim_start() == ":" {
+        start -= 1;
+    }
+    let word = &line[start..];
+    let mut matches: Vec<&str> = names
+        .into_iter()
+        .filter(|name| name.starts_with(word))
+        .collect();
+    matches.sort_unstable();
+    matches.dedup();
+    let Some(first) = matches.first() else {
+        return Completion::None;
+    };
+    let common = matches.iter().fold(first.len(), |common, name| {
+        let same = first.bytes().zip(name.bytes()).take_while(|(a, b)| a == b);
+        same.count().min(common)
+    });
+    if common > word.len() {
+        return Completion::Insert(first[word.len()..common].to_string());
+    }
+    match matches.len() {
+        1 => Completion::None,
+        _ => Completion::Choose(matches.into_iter().map(String::from).collect()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{complete, Completion};
+
+    #[test]
+    fn complete_names() {
+        let names = ["def", "extern", "fib", "fibs", "fib_iter", "foo", "fib"];
+        let insert = |rest: &str| Completion::Insert(rest.into());
+        assert_eq!(complete("de", names), insert("f"));
+        assert_eq!(complete("1 + fo", names), insert("o"));
+        assert_eq!(complete("f(fi", names), insert("b"));
+        assert_eq!(
+            complete("fib", names),
+            Completion::Choose(vec!["fib".into(), "fib_iter".into(), "fibs".into()])
+        );
+        assert_eq!(complete("fib_", names), insert("iter"));
+        assert_eq!(complete("foo", names), Completion::None);
+        assert_eq!(complete("bar", names), Completion::None);
+        assert_eq!(complete("def e", names), insert("xtern"));
+
+        let names = [":load", ":save", "load"];
+        assert_eq!(complete(" :lo", names), insert("ad"));
+        assert_eq!(
+            complete(":", names),
+            Completion::Choose(vec![":load".into(), ":save".into()])
+        );
+        assert_eq!(complete("lo", names), insert("ad"));
+    }
+}
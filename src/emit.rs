@@ -0,0 +1,275 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// emit - write a compiled module out with the llvm tools (chapter 8)
+//
+// the module text is piped into the tool, which must be on the PATH
+
+#[derive(Debug)]
+pub enum EmitError {
+    // the tool could not be started or its output not written
+    Io(io::Error),
+    // the tool failed, with its stderr
+    Failed { tool: &'static str, stderr: String },
+}
+
+impl From<io::Error> for EmitError {
+    fn from(err: io::Error) -> Self {
+        EmitError::Io(err)
+    }
+}
+
+// runtime linked into executables, `main`, `printd`, `putchard` and the
+// heap with arrays and strings
+const RUNTIME: &str = include_str!("../runtime/kaleidoscope.c");
+
+// object file for the host target
+pub fn write_object(module: &str, path: &Path) -> Result<(), EmitError> {
+    let mut llc = Command::new("llc");
+    llc.args(["-filetype=obj", "-relocation-model=pic", "-o"])
+        .arg(path);
+    run("llc", llc, module)
+}
+
+// assembly of the target, as the object file would contain it
+pub fn write_assembly(module: &str, path: &Path) -> Result<(), EmitError> {
+    let mut llc = Command::new("llc");
+    llc.args(["-filetype=asm", "-relocation-model=pic", "-o"])
+        .arg(path);
+    run("llc", llc, module)
+}
+
+// executable of a module with `codegen::MAIN` defined, its object is
+// linked with the runtime, libm and `libs` by the c compiler
+pub fn write_executable(module: &str, path: &Path, libs: &[String]) -> Result<(), EmitError> {
+    in_temp_dir(|dir| {
+        let object = dir.join("module.o");
+        write_object(module, &object)?;
+        link(&object, path, libs, dir)
+    })
+}
+
+// executable of an object file with `codegen::MAIN` defined, see
+// `write_executable`
+pub fn link_object(object: &[u8], path: &Path, libs: &[String]) -> Result<(), EmitError> {
+    in_temp_dir(|dir| {
+        let object_path = dir.join("module.o");
+        std::fs::write(&object_path, object)?;
+        link(&object_path, path, libs, dir)
+    })
+}
+
+fn link(object: &Path, path: &Path, libs: &[String], dir: &Path) -> Result<(), EmitError> {
+    let runtime = dir.join("runtime.c");
+    std::fs::write(&runtime, RUNTIME)?;
+
+    let mut cc = Command::new("cc");
+    cc.arg(runtime).arg(object);
+    cc.args(libs.iter().map(|lib| format!("-l{}", lib)));
+    cc.args(["-lm", "-o"]).arg(path);
+    run("cc", cc, "")
+}
+
+// object file of the runtime without `main`, for the jit
+pub fn write_runtime_object(path: &Path) -> Result<(), EmitError> {
+    let mut cc = Command::new("cc");
+    cc.args(["-DKLC_NO_MAIN", "-fPIC", "-c", "-x", "c", "-", "-o"])
+        .arg(path);
+    run("cc", cc, RUNTIME)
+}
+
+// run `f` with a fresh temporary directory, removed again afterwards
+fn in_temp_dir<T>(f: impl FnOnce(&Path) -> Result<T, EmitError>) -> Result<T, EmitError> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("klc-build-{}-{}", std::process::id(), n));
+    std::fs::create_dir_all(&dir)?;
+    let result = f(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+// llvm bitcode, as read by `opt`, `llc` and friends
+pub fn write_bitcode(module: &str, path: &Path) -> Result<(), EmitError> {
+    let mut llvm_as = Command::new("llvm-as");
+    llvm_as.arg("-o").arg(path);
+    run("llvm-as", llvm_as, module)
+}
+
+// run `command` of `tool` with `input` as stdin
+fn run(tool: &'static str, mut command: Command, input: &str) -> Result<(), EmitError> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr).trim_end().into();
+        return Err(EmitError::Failed { tool, stderr });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_assembly, write_bitcode, write_executable, write_object, EmitError};
+    use crate::codegen::Codegen;
+    use crate::parser::parse_file;
+    use std::process::Command;
+
+    fn available(tool: &str) -> bool {
+        let version = Command::new(tool).arg("--version").output();
+        version.is_ok_and(|out| out.status.success())
+    }
+
+    #[test]
+    fn emit_object() {
+        if !available("llc") {
+            return;
+        }
+        let mut codegen = Codegen::new();
+        for item in parse_file("extern sin(x); def f(x) sin(x) * 2").unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("klc-emit-{}.o", std::process::id()));
+        write_object(&codegen.module(), &path).unwrap();
+        let object = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!object.is_empty());
+
+        let err = write_object("not a module", &path).unwrap_err();
+        assert!(matches!(err, EmitError::Failed { tool: "llc", .. }));
+
+        write_assembly(&codegen.module(), &path).unwrap();
+        let assembly = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(assembly.contains("f:"));
+        assert!(assembly.contains("sin"));
+    }
+
+    #[test]
+    fn emit_object_for_target() {
+        let llc = Command::new("llc").arg("--version").output();
+        if !llc.is_ok_and(|out| String::from_utf8_lossy(&out.stdout).contains("aarch64")) {
+            return;
+        }
+        let mut codegen = Codegen::new();
+        codegen.set_target("aarch64-unknown-linux-gnu");
+        for item in parse_file("def f(x) x * 2").unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("klc-emit-{}-aarch64.o", std::process::id()));
+        write_object(&codegen.module(), &path).unwrap();
+        let object = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // elf with e_machine EM_AARCH64
+        assert_eq!(object[..4], *b"\x7fELF");
+        assert_eq!(object[18..20], [0xb7, 0]);
+
+        codegen.set_target("nonsense");
+        let err = write_object(&codegen.module(), &path).unwrap_err();
+        assert!(matches!(err, EmitError::Failed { tool: "llc", .. }));
+    }
+
+    #[test]
+    fn emit_bitcode() {
+        if !available("llvm-as") {
+            return;
+        }
+        let mut codegen = Codegen::new();
+        for item in parse_file("def f(x) x + 1").unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("klc-emit-{}.bc", std::process::id()));
+        write_bitcode(&codegen.module(), &path).unwrap();
+        let bitcode = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bitcode[..4], *b"BC\xc0\xde");
+
+        let err = write_bitcode("not a module", &path).unwrap_err();
+        assert!(matches!(
+            err,
+            EmitError::Failed {
+                tool: "llvm-as",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn emit_executable() {
+        if !available("llc") || !available("cc") {
+            return;
+        }
+        let mut codegen = Codegen::new();
+        let source = "extern printd(x); extern putchard(c);
+                      def twice(x) x * 2;
+                      printd(twice(21)); putchard(75); putchard(10)";
+        for item in parse_file(source).unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+        let module = codegen.module() + "\n" + &codegen.main_ir();
+
+        let path = std::env::temp_dir().join(format!("klc-emit-{}", std::process::id()));
+        write_executable(&module, &path, &[]).unwrap();
+        let out = Command::new(&path).output().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(out.status.success());
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "42.000000\nK\n");
+
+        // libraries named are linked, ones that do not exist fail
+        write_executable(&module, &path, &["c".into()]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let err = write_executable(&module, &path, &["nosuchlib".into()]).unwrap_err();
+        assert!(matches!(err, EmitError::Failed { tool: "cc", .. }));
+    }
+
+    #[test]
+    fn emit_runtime() {
+        if !available("llc") || !available("cc") {
+            return;
+        }
+        let externs = "extern arraynew(n); extern arrayget(a, i); extern arrayset(a, i, x);
+                       extern arraylen(a); extern stringnew(n); extern stringset(s, i, c);
+                       extern stringcat(a, b); extern prints(s); extern heapfree(x);
+                       extern printd(x); extern putchard(c);";
+        let programs = [
+            "def fill(a) arrayset(a, 0, 1.5) + arrayset(a, 2, 4);
+             def sum(a) arrayget(a, 0) + arrayget(a, 1) + arrayget(a, 2) + arraylen(a);
+             def show(a) fill(a) * 0 + printd(sum(a)) + heapfree(a);
+             def hi(s) stringset(s, 0, 72) + stringset(s, 1, 105);
+             def twice(s) hi(s) * 0 + prints(stringcat(s, s)) + heapfree(s);
+             show(arraynew(3)); twice(stringnew(2)); putchard(10)",
+            "arrayget(arraynew(2), 2)",
+        ];
+        let mut outputs = Vec::new();
+        for program in programs {
+            let mut codegen = Codegen::new();
+            for item in parse_file(&format!("{} {}", externs, program)).unwrap() {
+                codegen.compile_item(&item).unwrap();
+            }
+            let module = codegen.module() + "\n" + &codegen.main_ir();
+            let path = std::env::temp_dir().join(format!("klc-runtime-{}", std::process::id()));
+            write_executable(&module, &path, &[]).unwrap();
+            outputs.push(Command::new(&path).output().unwrap());
+            std::fs::remove_file(&path).unwrap();
+        }
+        assert!(outputs[0].status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&outputs[0].stdout),
+            "8.500000\nHiHi\n"
+        );
+        assert_eq!(outputs[1].status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8_lossy(&outputs[1].stderr),
+            "error: index out of bounds: 2\n"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::ast::{errors_to_json, program_to_json, write_json_str};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use crate::Error;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::UnwindSafe;
+
+// ffi - the parser and compiler for c and anything that calls c, e.g.
+// editors or teaching uis, results are json strings of the schema in
+// ast/json.rs
+//
+//   // the program, or its errors
+//   char *ks_parse(const char *source);
+//   // {"version": 1, "ir": string}, or the errors of the program
+//   char *ks_compile(const char *source);
+//   void ks_free(char *json);
+//
+// every returned string is owned by the caller and freed with `ks_free`,
+// NULL is returned for a NULL source or one that is not utf8
+// a panic, a bug of the compiler, doesn't unwind into c, the call returns
+// it as the only error instead
+
+// json of the items of `source`, or of its errors
+//
+// SAFETY: `source` is NULL or a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn ks_parse(source: *const c_char) -> *mut c_char {
+    // SAFETY: as required of the caller
+    let Some(source) = (unsafe { source_str(source) }) else {
+        return std::ptr::null_mut();
+    };
+    guard(|| match crate::parse(source) {
+        Ok(items) => into_c(program_to_json(&items)),
+        Err(err) => into_c(errors_to_json(&diagnostics(err))),
+    })
+}
+
+// json with the llvm ir `source` compiles to, or with its errors
+//
+// SAFETY: `source` is NULL or a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn ks_compile(source: *const c_char) -> *mut c_char {
+    // SAFETY: as required of the caller
+    let Some(source) = (unsafe { source_str(source) }) else {
+        return std::ptr::null_mut();
+    };
+    guard(|| match crate::compile(source) {
+        Ok(ir) => {
+            let mut json = String::from("{\"version\":1,\"ir\":");
+            write_json_str(&mut json, &ir);
+            json.push('}');
+            into_c(json)
+        }
+        Err(err) => into_c(errors_to_json(&diagnostics(err))),
+    })
+}
+
+// free a string returned by `ks_parse` or `ks_compile`, NULL is ignored
+//
+// SAFETY: `json` is NULL or was returned by them and not freed before
+#[no_mangle]
+pub unsafe extern "C" fn ks_free(json: *mut c_char) {
+    if !json.is_null() {
+        // SAFETY: as required of the caller, it came from `into_c`
+        drop(unsafe { CString::from_raw(json) });
+    }
+}
+
+// SAFETY: `source` is NULL or a nul terminated string
+unsafe fn source_str<'a>(source: *const c_char) -> Option<&'a str> {
+    if source.is_null() {
+        return None;
+    }
+    // SAFETY: as required of the caller
+    unsafe { CStr::from_ptr(source) }.to_str().ok()
+}
+
+// `f`, or the errors json of its panic, unwinding out of an `extern "C"`
+// function aborts
+fn guard(f: impl FnOnce() -> *mut c_char + UnwindSafe) -> *mut c_char {
+    std::panic::catch_unwind(f).unwrap_or_else(|panic| {
+        let message = match panic.downcast_ref::<&str>() {
+            Some(message) => message,
+            None => panic.downcast_ref::<String>().map_or("", |s| s),
+        };
+        let message = format!("internal compiler error: {}", message);
+        into_c(errors_to_json(&[Diagnostic::error(
+            Span::default(),
+            message,
+        )]))
+    })
+}
+
+// json never holds a nul, control characters are escaped
+fn into_c(json: String) -> *mut c_char {
+    CString::new(json).unwrap().into_raw()
+}
+
+fn diagnostics(err: Error) -> Vec<Diagnostic> {
+    match err {
+        Error::Lex(err) => vec![Diagnostic::error(Span::default(), err.to_string())],
+        Error::Parse(errors) => errors.into_iter().map(Diagnostic::from).collect(),
+        Error::Codegen(errors) => errors.into_iter().map(Diagnostic::from).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{guard, ks_compile, ks_free, ks_parse};
+    use serde_json::Value;
+    use std::ffi::{c_char, CStr};
+
+    // call `f` like c would and free its result
+    fn call(f: unsafe extern "C" fn(*const c_char) -> *mut c_char, source: &CStr) -> String {
+        let json = unsafe { f(source.as_ptr()) };
+        assert!(!json.is_null());
+        let text = unsafe { CStr::from_ptr(json) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        unsafe { ks_free(json) };
+        text
+    }
+
+    #[test]
+    fn c_abi() {
+        let json: Value = serde_json::from_str(&call(ks_parse, c"extern f(x);")).unwrap();
+        assert_eq!(json["items"][0]["prototype"]["name"], "f");
+        let json: Value = serde_json::from_str(&call(ks_parse, c"def (x)")).unwrap();
+        assert_eq!(json["errors"].as_array().unwrap().len(), 1);
+
+        let json: Value = serde_json::from_str(&call(ks_compile, c"def f(x) x + 1;")).unwrap();
+        assert!(json["ir"]
+            .as_str()
+            .unwrap()
+            .contains("define double @f(double %x)"));
+        let json: Value = serde_json::from_str(&call(ks_compile, c"g(1);")).unwrap();
+        assert_eq!(json["errors"][0]["message"], "unknown function 'g'");
+        assert_eq!(json["errors"][0]["position"]["column"], 1);
+
+        assert!(unsafe { ks_parse(std::ptr::null()) }.is_null());
+        assert!(unsafe { ks_compile(c"\xff".as_ptr()) }.is_null());
+        unsafe { ks_free(std::ptr::null_mut()) };
+
+        // a panic comes back as an error instead of unwinding into c
+        let json = guard(|| panic!("boom {}", 1));
+        let text = unsafe { std::ffi::CString::from_raw(json) };
+        let json: Value = serde_json::from_str(text.to_str().unwrap()).unwrap();
+        assert_eq!(
+            json["errors"][0]["message"],
+            "internal compiler error: boom 1"
+        );
+    }
+}
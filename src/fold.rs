@@ -0,0 +1,150 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST};
+
+// fold - rebuild the ast node by node, taking ownership of the input
+// override the `fold_*` methods of the nodes to rewrite and call the
+// matching `walk_*` function from them to rebuild the child nodes
+// expressions are the exception, `walk_expr` folds them bottom up without
+// recursion and `fold_expr` gets each node with its children folded
+pub trait Fold {
+    fn fold_item(&mut self, item: Item) -> Item {
+        walk_item(self, item)
+    }
+
+    fn fold_function(&mut self, func: FunctionAST) -> FunctionAST {
+        walk_function(self, func)
+    }
+
+    fn fold_prototype(&mut self, proto: PrototypeAST) -> PrototypeAST {
+        proto
+    }
+
+    fn fold_expr(&mut self, expr: ExpressionAST) -> ExpressionAST {
+        expr
+    }
+}
+
+pub fn walk_item<F: Fold + ?Sized>(f: &mut F, item: Item) -> Item {
+    match item {
+        Item::Function(func) => Item::Function(f.fold_function(func)),
+        Item::Extern(proto) => Item::Extern(f.fold_prototype(proto)),
+        Item::Expr(expr) => Item::Expr(walk_expr(f, expr)),
+    }
+}
+
+pub fn walk_function<F: Fold + ?Sized>(f: &mut F, func: FunctionAST) -> FunctionAST {
+    let FunctionAST(proto, body, id, span) = func;
+    FunctionAST(f.fold_prototype(proto), walk_expr(f, body), id, span)
+}
+
+pub fn walk_expr<F: Fold + ?Sized>(f: &mut F, expr: ExpressionAST) -> ExpressionAST {
+    // the nodes being folded with the number of their children done, the
+    // children are taken out, folded and put back in place
+    let mut stack = vec![(expr, 0)];
+    loop {
+        let (expr, done) = stack.last_mut().unwrap();
+        if let Some(child) = expr.child_mut(*done) {
+            let child = child.take();
+            stack.push((child, 0));
+            continue;
+        }
+        let (expr, _) = stack.pop().unwrap();
+        let expr = f.fold_expr(expr);
+        match stack.last_mut() {
+            Some((parent, done)) => {
+                *parent.child_mut(*done).unwrap() = expr;
+                *done += 1;
+            }
+            None => return expr,
+        }
+    }
+}
+
+// compose two passes, (a, b) runs `a` over the whole function, then `b`
+impl<A: Fold, B: Fold> Fold for (A, B) {
+    fn fold_item(&mut self, item: Item) -> Item {
+        let item = self.0.fold_item(item);
+        self.1.fold_item(item)
+    }
+
+    fn fold_function(&mut self, func: FunctionAST) -> FunctionAST {
+        let func = self.0.fold_function(func);
+        self.1.fold_function(func)
+    }
+
+    fn fold_prototype(&mut self, proto: PrototypeAST) -> PrototypeAST {
+        let proto = self.0.fold_prototype(proto);
+        self.1.fold_prototype(proto)
+    }
+
+    fn fold_expr(&mut self, expr: ExpressionAST) -> ExpressionAST {
+        let expr = self.0.fold_expr(expr);
+        self.1.fold_expr(expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Fold;
+    use crate::ast::{ExpressionAST, Item, PrototypeAST};
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Item {
+        let mut out = Parser::from_str(input).parse_all();
+        out.items.remove(0)
+    }
+
+    // desugar `sq(e)` into `e * e`
+    struct DesugarSquare;
+
+    impl Fold for DesugarSquare {
+        fn fold_expr(&mut self, mut expr: ExpressionAST) -> ExpressionAST {
+            match &mut expr {
+                ExpressionAST::Call(callee, args, _, span) if callee == "sq" && args.len() == 1 => {
+                    let arg = args.remove(0);
+                    ExpressionAST::binary('*', arg.clone(), arg, *span)
+                }
+                _ => expr,
+            }
+        }
+    }
+
+    // rename every occurrence of a symbol
+    struct Rename(&'static str, &'static str);
+
+    impl Fold for Rename {
+        fn fold_prototype(&mut self, mut proto: PrototypeAST) -> PrototypeAST {
+            for arg in proto.1.iter_mut().filter(|arg| *arg == self.0) {
+                *arg = self.1.into();
+            }
+            proto
+        }
+
+        fn fold_expr(&mut self, expr: ExpressionAST) -> ExpressionAST {
+            match &expr {
+                ExpressionAST::Variable(name, id, span) if name == self.0 => {
+                    ExpressionAST::Variable(self.1.into(), *id, *span)
+                }
+                _ => expr,
+            }
+        }
+    }
+
+    #[test]
+    fn fold_desugar() {
+        let func = DesugarSquare.fold_item(parse("def f(x) sq(x + 1) + sq(sq(2))"));
+        assert_eq!(
+            func.to_string(),
+            "def f(x) (x + 1) * (x + 1) + 2 * 2 * (2 * 2)"
+        );
+    }
+
+    #[test]
+    fn fold_compose() {
+        let mut passes = (DesugarSquare, Rename("x", "y"));
+        let func = passes.fold_item(parse("def f(x z) sq(x) - z"));
+        assert_eq!(func.to_string(), "def f(y, z) y * y - z");
+
+        let proto = passes.fold_item(parse("extern g(x)"));
+        assert_eq!(proto.to_string(), "extern g(y)");
+    }
+}
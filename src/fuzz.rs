@@ -0,0 +1,44 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// random inputs for the fuzz tests, a xorshift generator, deterministic so
+// failures reproduce
+//
+//   fuzz(SEED, 2000, |run, random| {
+//       let input = random.pieces(&["def", "(", "x"], 32);
+//       ...
+//   });
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random(seed)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // less than `len` random bytes
+    pub fn bytes(&mut self, len: u64) -> Vec<u8> {
+        (0..self.next() % len).map(|_| self.next() as u8).collect()
+    }
+
+    // less than `len` of `pieces`, in random order, joined
+    pub fn pieces(&mut self, pieces: &[&str], len: u64) -> String {
+        (0..self.next() % len)
+            .map(|_| pieces[(self.next() % pieces.len() as u64) as usize])
+            .collect()
+    }
+}
+
+// `f` called `runs` times with the number of the run and the generator
+pub fn fuzz(seed: u64, runs: usize, mut f: impl FnMut(usize, &mut Random)) {
+    let mut random = Random::new(seed);
+    for run in 0..runs {
+        f(run, &mut random);
+    }
+}
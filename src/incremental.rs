@@ -0,0 +1,296 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, NodeId, PrototypeAST};
+use crate::diagnostic::Diagnostic;
+use crate::fold::{self, Fold};
+use crate::lexer::{Lexer, Position, Span};
+use crate::parser::{ParseOutput, Parser, ParserConfig};
+use crate::visit::{self, Visitor};
+use std::ops::Range;
+
+// replacement of `range` (byte offsets into the old source) by `text`
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+// reparse `source`, the old source with `edit` applied, reusing the items
+// of `old` the edit can't have changed
+//
+// items in front of the edit are kept as they are and parsing restarts
+// right after them, it stops again once it reaches the start of an old
+// item behind the edit, those are kept with their spans moved
+// the result equals parsing `source` from scratch, node ids aside
+pub fn reparse(
+    old: &ParseOutput,
+    edit: &TextEdit,
+    source: &str,
+    config: ParserConfig,
+) -> ParseOutput {
+    let items = &old.items;
+    let delta = edit.text.len() as isize - edit.range.len() as isize;
+    let moved = |offset: usize| (offset as isize + delta) as usize;
+
+    // an item is safe if the edit is past the first token of the next
+    // item and the char after it, the tokens ending it are unchanged then
+    let prefix = (0..items.len())
+        .take_while(|&i| {
+            let next = items.get(i + 1);
+            next.is_some_and(|next| {
+                let start = next.span().start;
+                start.offset < edit.range.start
+                    && first_token_end(source, start).offset < edit.range.start
+            })
+        })
+        .count();
+    // errors between two items may belong to either of them, keep them
+    // out of the reused part
+    let mut prefix = prefix;
+    while prefix > 0
+        && errors_in(
+            old,
+            items[prefix - 1].span().end,
+            items[prefix].span().start,
+        )
+    {
+        prefix -= 1;
+    }
+    let restart = match prefix {
+        0 => Position::default(),
+        n => items[n - 1].span().end,
+    };
+
+    let lexer = Lexer::new(source[restart.offset..].chars()).starting_at(restart);
+    let mut parser = Parser::with_config(lexer, config);
+    let mut max_id = MaxId(None);
+    items.iter().for_each(|item| max_id.visit_item(item));
+    parser.set_next_id(max_id.0.map_or(0, |id| id + 1));
+
+    let mut out = ParseOutput {
+        items: items[..prefix].to_vec(),
+        diagnostics: old
+            .diagnostics
+            .iter()
+            .filter(|d| d.pos().offset < restart.offset)
+            .cloned()
+            .collect(),
+    };
+    let mut errors = Vec::new();
+
+    // old items behind the edit, candidates to resynchronize with
+    let mut next = (prefix..items.len())
+        .find(|&i| items[i].span().start.offset >= edit.range.end)
+        .unwrap_or(items.len());
+    let mut resync = None;
+    loop {
+        parser.skip_separators();
+        let at = parser.cur_span().start;
+        while next < items.len() && moved(items[next].span().start.offset) < at.offset {
+            next += 1;
+        }
+        // same text from here on, parsed in the same state
+        if next < items.len()
+            && moved(items[next].span().start.offset) == at.offset
+            && parser.cur_doc() == doc(&items[next])
+            && !errors_in(old, items[next].span().start, items[next].span().start)
+        {
+            resync = Some(Shift {
+                from: items[next].span().start,
+                to: at,
+            });
+            break;
+        }
+        if !parser.parse_next_item(&mut out.items, &mut errors) {
+            break;
+        }
+    }
+
+    out.diagnostics
+        .extend(errors.into_iter().map(Diagnostic::from));
+    if let Some(mut shift) = resync {
+        out.items.extend(
+            items[next..]
+                .iter()
+                .map(|item| shift.fold_item(item.clone())),
+        );
+        out.diagnostics.extend(
+            old.diagnostics
+                .iter()
+                .filter(|d| d.pos().offset >= shift.from.offset)
+                .map(|d| d.map_spans(|pos| shift.position(pos))),
+        );
+    }
+    out
+}
+
+// end of the token at `pos` in `source`
+fn first_token_end(source: &str, pos: Position) -> Position {
+    let mut lexer = Lexer::new(source[pos.offset..].chars()).starting_at(pos);
+    lexer.next_token();
+    lexer.token_span().end
+}
+
+// doc comment the parser attached to `item`
+fn doc(item: &Item) -> Option<&str> {
+    match item {
+        Item::Function(func) => func.0.doc(),
+        Item::Extern(proto) => proto.doc(),
+        Item::Expr(_) => None,
+    }
+}
+
+// any error reported in [from, to]
+fn errors_in(old: &ParseOutput, from: Position, to: Position) -> bool {
+    let range = from.offset..=to.offset;
+    old.diagnostics
+        .iter()
+        .any(|d| range.contains(&d.pos().offset))
+}
+
+// largest node id in use
+struct MaxId(Option<u32>);
+
+impl MaxId {
+    fn add(&mut self, id: NodeId) {
+        if id != NodeId::DUMMY {
+            self.0 = self.0.max(Some(id.0));
+        }
+    }
+}
+
+impl Visitor for MaxId {
+    fn visit_function(&mut self, func: &FunctionAST) {
+        self.add(func.2);
+        visit::walk_function(self, func)
+    }
+
+    fn visit_prototype(&mut self, proto: &PrototypeAST) {
+        self.add(proto.3);
+    }
+
+    fn visit_expr(&mut self, expr: &ExpressionAST) {
+        self.add(expr.id());
+    }
+}
+
+// move positions at or after `from` along with it to `to`
+struct Shift {
+    from: Position,
+    to: Position,
+}
+
+impl Shift {
+    fn position(&self, pos: Position) -> Position {
+        let column = if pos.line == self.from.line {
+            pos.column - self.from.column + self.to.column
+        } else {
+            pos.column
+        };
+        Position {
+            offset: pos.offset - self.from.offset + self.to.offset,
+            line: pos.line - self.from.line + self.to.line,
+            column,
+        }
+    }
+
+    fn span(&self, span: Span) -> Span {
+        Span::new(self.position(span.start), self.position(span.end))
+    }
+}
+
+impl Fold for Shift {
+    fn fold_function(&mut self, func: FunctionAST) -> FunctionAST {
+        let mut func = fold::walk_function(self, func);
+        func.3 = self.span(func.3);
+        func
+    }
+
+    fn fold_prototype(&mut self, mut proto: PrototypeAST) -> PrototypeAST {
+        proto.4 = self.span(proto.4);
+        proto
+    }
+
+    fn fold_expr(&mut self, mut expr: ExpressionAST) -> ExpressionAST {
+        let span = expr.span_mut();
+        *span = self.span(*span);
+        expr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{reparse, TextEdit};
+    use crate::ast::Item;
+    use crate::fuzz::fuzz;
+    use crate::lexer::Lexer;
+    use crate::parser::{ParseOutput, Parser, ParserConfig};
+
+    fn parse(source: &str, config: ParserConfig) -> ParseOutput {
+        Parser::with_config(Lexer::new(source.chars()), config).parse_all()
+    }
+
+    // apply `edit` to `source`, reparse and compare with a full parse
+    fn check(source: &str, edit: TextEdit, config: ParserConfig) -> (ParseOutput, ParseOutput) {
+        let old = parse(source, config);
+        let mut new = source.to_string();
+        new.replace_range(edit.range.clone(), &edit.text);
+
+        let out = reparse(&old, &edit, &new, config);
+        assert_eq!(out, parse(&new, config), "{:?} -> {:?}", source, new);
+        (old, out)
+    }
+
+    fn edit(lo: usize, hi: usize, text: &str) -> TextEdit {
+        TextEdit {
+            range: lo..hi,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn reparse_reuses_items() {
+        let source = "def a(x) x;\ndef b(y) y + 1;\n## c\ndef c(z) z";
+        let config = ParserConfig::default();
+        let (old, out) = check(source, edit(25, 26, "20 * y"), config);
+
+        let id = |item: &Item| match item {
+            Item::Function(func) => func.2,
+            _ => unreachable!(),
+        };
+        assert_eq!(id(&out.items[0]), id(&old.items[0]));
+        assert_ne!(id(&out.items[1]), id(&old.items[1]));
+        assert_eq!(id(&out.items[2]), id(&old.items[2]));
+    }
+
+    #[test]
+    fn reparse_merges_and_splits_items() {
+        let config = ParserConfig::default();
+        check("1 x 2", edit(1, 2, " +"), config);
+        check("1 + x 2", edit(2, 3, ""), config);
+        check("def f() 1\nfoo", edit(0, 0, "## doc\n"), config);
+        check("## doc\ndef f() 1\ng()", edit(0, 3, "#"), config);
+        check("f(1); g(2", edit(9, 9, ")"), config);
+        check("f(1); g(2)", edit(0, 5, ""), config);
+    }
+
+    #[test]
+    fn fuzz_reparse_matches_full_parse() {
+        const PIECES: &[&str] = &[
+            "def", "extern", "(", ")", ",", ";", "+", "*", "<", "a", "f", "1", "## d\n", "#c\n",
+            " ", "\n",
+        ];
+
+        fuzz(0x9e3779b97f4a7c15, 1000, |i, random| {
+            let source = random.pieces(PIECES, 24);
+            let replacement = random.pieces(PIECES, 4);
+            let lo = source.len() * i / 1000;
+            let hi = (lo + replacement.len() % 5).min(source.len());
+            let edit = edit(lo, hi, &replacement);
+
+            let config = ParserConfig {
+                recover: i % 2 == 0,
+                ..ParserConfig::default()
+            };
+            check(&source, edit, config);
+        });
+    }
+}
@@ -0,0 +1,364 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST, Step};
+use crate::codegen::{check, CodegenError};
+use crate::lexer::Position;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+// interp - evaluate the ast directly, without any backend
+//
+// functions are kept as their bodies and called by walking them, externs
+// call rust functions bound by name, the usual libm functions, `printd`
+// and `putchard` are bound from the start
+// bodies are checked like the backends do when they are added, so
+// evaluating one only fails if calls nest too deep
+
+// calls nested deeper than this fail, without conditionals every
+// recursion is endless
+pub const MAX_CALL_DEPTH: usize = 256;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum InterpError {
+    Codegen(CodegenError),
+    // extern without a binding
+    Unbound { name: String, pos: Position },
+    // call nested deeper than `MAX_CALL_DEPTH`
+    StackOverflow { name: String, pos: Position },
+}
+
+impl From<CodegenError> for InterpError {
+    fn from(err: CodegenError) -> Self {
+        InterpError::Codegen(err)
+    }
+}
+
+pub type InterpResult<T> = Result<T, InterpError>;
+
+// rust function an extern calls, gets as many arguments as it was bound
+// with
+pub type Binding = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+enum Function {
+    Extern {
+        arity: usize,
+        binding: Binding,
+    },
+    Defined {
+        params: Vec<String>,
+        body: ExpressionAST,
+    },
+}
+
+impl Function {
+    fn arity(&self) -> usize {
+        match self {
+            Function::Extern { arity, .. } => *arity,
+            Function::Defined { params, .. } => params.len(),
+        }
+    }
+}
+
+pub struct Interp {
+    functions: HashMap<String, Function>,
+    // name to arity and binding
+    bindings: HashMap<String, (usize, Binding)>,
+}
+
+impl Default for Interp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interp {
+    // with the bindings of the runtime and libm
+    pub fn new() -> Self {
+        let mut interp = Self::without_bindings();
+        let unary = [
+            ("sin", f64::sin as fn(f64) -> f64),
+            ("cos", f64::cos),
+            ("tan", f64::tan),
+            ("sqrt", f64::sqrt),
+            ("exp", f64::exp),
+            ("log", f64::ln),
+            ("fabs", f64::abs),
+            ("floor", f64::floor),
+        ];
+        for (name, f) in unary {
+            interp.bind(name, 1, move |args| f(args[0]));
+        }
+        interp.bind("pow", 2, |args| args[0].powf(args[1]));
+        interp.bind("printd", 1, |args| {
+            println!("{:.6}", args[0]);
+            0.0
+        });
+        interp.bind("putchard", 1, |args| {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(&[args[0] as u8]);
+            let _ = stdout.flush();
+            0.0
+        });
+        interp
+    }
+
+    pub fn without_bindings() -> Self {
+        Interp {
+            functions: HashMap::new(),
+            bindings: HashMap::new(),
+        }
+    }
+
+    // bind externs named `name` to `f`, replaces an earlier binding
+    pub fn bind(
+        &mut self,
+        name: &str,
+        arity: usize,
+        f: impl Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    ) {
+        self.bindings.insert(name.into(), (arity, Arc::new(f)));
+    }
+
+    // add a function or extern, evaluate a top-level expression
+    pub fn eval_item(&mut self, item: &Item) -> InterpResult<Option<f64>> {
+        match item {
+            Item::Expr(expr) => self.eval(expr).map(Some),
+            Item::Extern(proto) => self.add_extern(proto).map(|_| None),
+            Item::Function(func) => self.add_function(func),
+        }
+    }
+
+    pub fn add_extern(&mut self, proto: &PrototypeAST) -> InterpResult<()> {
+        let PrototypeAST(name, params, ..) = proto;
+        let pos = proto.4.start;
+        check_params(params, pos)?;
+        if let Some(known) = self.functions.get(name) {
+            return check_arity(name, known.arity(), params.len(), pos);
+        }
+        let Some((arity, binding)) = self.bindings.get(name) else {
+            let name = name.clone();
+            return Err(InterpError::Unbound { name, pos });
+        };
+        check_arity(name, *arity, params.len(), pos)?;
+        let function = Function::Extern {
+            arity: *arity,
+            binding: binding.clone(),
+        };
+        self.functions.insert(name.clone(), function);
+        Ok(())
+    }
+
+    // define a function, an anonymous function is a top-level expression
+    // and evaluated
+    pub fn add_function(&mut self, func: &FunctionAST) -> InterpResult<Option<f64>> {
+        let FunctionAST(proto, body, ..) = func;
+        let (name, params, pos) = (&proto.0, &proto.1, proto.4.start);
+        if name.is_empty() {
+            return self.eval(body).map(Some);
+        }
+        check_params(params, pos)?;
+        match self.functions.get(name) {
+            Some(Function::Defined { .. }) => {
+                let name = name.clone();
+                return Err(CodegenError::Redefinition { name, pos }.into());
+            }
+            Some(known) => check_arity(name, known.arity(), params.len(), pos)?,
+            None => {}
+        }
+        // it may call itself
+        let arity = |callee: &str| match self.functions.get(callee) {
+            _ if callee == name => Some(params.len()),
+            known => known.map(Function::arity),
+        };
+        check(body, params, &arity)?;
+
+        let function = Function::Defined {
+            params: params.clone(),
+            body: body.clone(),
+        };
+        self.functions.insert(name.clone(), function);
+        Ok(None)
+    }
+
+    pub fn eval(&mut self, expr: &ExpressionAST) -> InterpResult<f64> {
+        let arity = |callee: &str| self.functions.get(callee).map(Function::arity);
+        check(expr, &[], &arity)?;
+        self.expr(expr, &[], &[], 0)
+    }
+
+    // value of a checked `expr` with `params` bound to `args`, `depth`
+    // calls deep, the operands are evaluated on a stack, only calls recurse
+    fn expr(
+        &self,
+        expr: &ExpressionAST,
+        params: &[String],
+        args: &[f64],
+        depth: usize,
+    ) -> InterpResult<f64> {
+        let mut values: Vec<f64> = Vec::new();
+        for step in expr.walk() {
+            let expr = match step {
+                // the depth is checked before the arguments are evaluated
+                Step::Enter(call @ ExpressionAST::Call(callee, ..), _)
+                    if depth == MAX_CALL_DEPTH =>
+                {
+                    let name = callee.clone();
+                    let pos = call.span().start;
+                    return Err(InterpError::StackOverflow { name, pos });
+                }
+                Step::Enter(..) => continue,
+                Step::Leave(expr, _) => expr,
+            };
+            let value = match expr {
+                ExpressionAST::Number(num, ..) => *num,
+                ExpressionAST::Variable(name, ..) => {
+                    let i = params.iter().position(|param| param == name).unwrap();
+                    args[i]
+                }
+                ExpressionAST::Unary(op, ..) => {
+                    let value = values.pop().unwrap();
+                    match op {
+                        '-' => -value,
+                        _ => bool(value == 0.0),
+                    }
+                }
+                ExpressionAST::Binary(op, ..) => {
+                    let rhs = values.pop().unwrap();
+                    let lhs = values.pop().unwrap();
+                    match op {
+                        '+' => lhs + rhs,
+                        '-' => lhs - rhs,
+                        '*' => lhs * rhs,
+                        // unordered counts as less, like `fcmp ult`
+                        _ => bool(lhs < rhs || lhs.is_nan() || rhs.is_nan()),
+                    }
+                }
+                ExpressionAST::Call(callee, call_args, ..) => {
+                    let values = values.split_off(values.len() - call_args.len());
+                    match &self.functions[callee] {
+                        Function::Extern { binding, .. } => binding(&values),
+                        Function::Defined { params, body } => {
+                            self.expr(body, params, &values, depth + 1)?
+                        }
+                    }
+                }
+                ExpressionAST::Error(..) => unreachable!("checked before evaluation"),
+            };
+            values.push(value);
+        }
+        Ok(values.pop().unwrap())
+    }
+}
+
+fn bool(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn check_params(params: &[String], pos: Position) -> InterpResult<()> {
+    for (i, param) in params.iter().enumerate() {
+        if params[..i].contains(param) {
+            let name = param.clone();
+            return Err(CodegenError::DuplicateParameter { name, pos }.into());
+        }
+    }
+    Ok(())
+}
+
+fn check_arity(name: &str, expected: usize, found: usize, pos: Position) -> InterpResult<()> {
+    if expected != found {
+        let name = name.into();
+        return Err(CodegenError::ArityMismatch {
+            name,
+            expected,
+            found,
+            pos,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Interp, InterpError};
+    use crate::codegen::CodegenError;
+    use crate::parser::parse_file;
+    use std::sync::{Arc, Mutex};
+
+    // evaluate the items of `input`, results of the expressions
+    fn eval(interp: &mut Interp, input: &str) -> Vec<f64> {
+        let items = parse_file(input).unwrap();
+        items
+            .iter()
+            .filter_map(|item| interp.eval_item(item).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn interp_eval() {
+        let mut interp = Interp::new();
+        assert_eq!(
+            eval(&mut interp, "1 + 2 * 3; 4 < 5; -(1.5); !0; !2"),
+            [7.0, 1.0, -1.5, 1.0, 0.0]
+        );
+
+        // functions persist across inputs
+        assert_eq!(eval(&mut interp, "def sq(x) x * x; sq(3)"), [9.0]);
+        assert_eq!(eval(&mut interp, "sq(sq(2)) + !0"), [17.0]);
+        assert_eq!(eval(&mut interp, "extern cos(x); cos(0)"), [1.0]);
+    }
+
+    #[test]
+    fn interp_bindings() {
+        let out = Arc::new(Mutex::new(String::new()));
+        let mut interp = Interp::without_bindings();
+        let printed = out.clone();
+        interp.bind("putchard", 1, move |args| {
+            printed.lock().unwrap().push(args[0] as u8 as char);
+            0.0
+        });
+        assert_eq!(
+            eval(
+                &mut interp,
+                "extern putchard(c); putchard(75) + putchard(10)"
+            ),
+            [0.0]
+        );
+        assert_eq!(*out.lock().unwrap(), "K\n");
+    }
+
+    #[test]
+    fn interp_errors() {
+        let mut interp = Interp::new();
+        let items = parse_file(
+            "x; def f(x, x) 1; extern sin(x, y); extern nosuchfunction();
+             def g(x) g(x); g(1); def g(x) 2",
+        )
+        .unwrap();
+        let errors: Vec<_> = items.iter().map(|item| interp.eval_item(item)).collect();
+        assert!(matches!(
+            errors[0],
+            Err(InterpError::Codegen(CodegenError::UnknownVariable { .. }))
+        ));
+        assert!(matches!(
+            errors[1],
+            Err(InterpError::Codegen(
+                CodegenError::DuplicateParameter { .. }
+            ))
+        ));
+        assert!(matches!(
+            errors[2],
+            Err(InterpError::Codegen(CodegenError::ArityMismatch { .. }))
+        ));
+        assert!(matches!(errors[3], Err(InterpError::Unbound { .. })));
+        assert_eq!(errors[4], Ok(None));
+        assert!(matches!(errors[5], Err(InterpError::StackOverflow { .. })));
+        assert!(matches!(
+            errors[6],
+            Err(InterpError::Codegen(CodegenError::Redefinition { .. }))
+        ));
+    }
+}
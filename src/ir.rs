@@ -0,0 +1,589 @@
+use crate::ast::{ExpressionAST, Step};
+use crate::codegen::{check, CodegenResult};
+use std::collections::HashMap;
+use std::fmt;
+
+// ir - ssa form of function bodies, what the backends compile
+//
+// a body lowers into a `Function` of basic blocks, every value is
+// defined once by an instruction and has a type, the backends (llvm ir
+// in `codegen`, cranelift, javascript) only translate instructions so
+// optimizations on the ir, like `cse`, serve all of them
+// without conditionals a body is a single block so far
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    F64,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    Eq,
+    // less than or unordered, like `fcmp ult`
+    Ult,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Const(f64),
+    // the parameter at an index, defined at the start of the entry block
+    Param(usize),
+    Neg(Value),
+    Binary(BinaryOp, Value, Value),
+    // a `Bool`
+    Cmp(Cond, Value, Value),
+    // 1.0 for true, 0.0 for false
+    BoolToF64(Value),
+    Call(String, Vec<Value>),
+}
+
+impl Inst {
+    // the values it uses
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Inst::Const(_) | Inst::Param(_) => Vec::new(),
+            Inst::Neg(value) | Inst::BoolToF64(value) => vec![*value],
+            Inst::Binary(_, lhs, rhs) | Inst::Cmp(_, lhs, rhs) => vec![*lhs, *rhs],
+            Inst::Call(_, args) => args.clone(),
+        }
+    }
+
+    fn map_operands(&mut self, f: impl Fn(Value) -> Value) {
+        match self {
+            Inst::Const(_) | Inst::Param(_) => {}
+            Inst::Neg(value) | Inst::BoolToF64(value) => *value = f(*value),
+            Inst::Binary(_, lhs, rhs) | Inst::Cmp(_, lhs, rhs) => {
+                *lhs = f(*lhs);
+                *rhs = f(*rhs);
+            }
+            Inst::Call(_, args) => args.iter_mut().for_each(|arg| *arg = f(*arg)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Return(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    // values defined in the block, in order
+    pub insts: Vec<Value>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    // instruction and type of each value, indexed by `Value`
+    pub values: Vec<(Inst, Type)>,
+    // the entry block first
+    pub blocks: Vec<Block>,
+}
+
+impl Function {
+    pub fn inst(&self, value: Value) -> &Inst {
+        &self.values[value.0].0
+    }
+
+    pub fn ty(&self, value: Value) -> Type {
+        self.values[value.0].1
+    }
+
+    // whether `value` is a call of the function itself whose result its
+    // block returns right away, the recursion loops in place if the
+    // backend turns it into a jump
+    pub fn is_self_tail_call(&self, value: Value) -> bool {
+        let is_self_call =
+            matches!(self.inst(value), Inst::Call(callee, _) if *callee == self.name);
+        is_self_call
+            && self.blocks.iter().any(|block| {
+                block.insts.last() == Some(&value) && block.terminator == Terminator::Return(value)
+            })
+    }
+
+    // functions called, each once, in order of the first call
+    pub fn callees(&self) -> Vec<String> {
+        let mut callees: Vec<String> = Vec::new();
+        for block in &self.blocks {
+            for value in &block.insts {
+                if let Inst::Call(callee, _) = self.inst(*value) {
+                    if !callees.contains(callee) {
+                        callees.push(callee.clone());
+                    }
+                }
+            }
+        }
+        callees
+    }
+}
+
+// lower a function body, `arity` of the known functions, the errors are
+// those of `codegen::check`
+pub fn lower(
+    name: &str,
+    params: &[String],
+    body: &ExpressionAST,
+    arity: &dyn Fn(&str) -> Option<usize>,
+) -> CodegenResult<Function> {
+    check(body, params, arity)?;
+    let mut builder = Builder {
+        params,
+        values: Vec::new(),
+        insts: Vec::new(),
+    };
+    for i in 0..params.len() {
+        builder.push(Inst::Param(i), Type::F64);
+    }
+    let ret = builder.expr(body);
+    Ok(Function {
+        name: name.into(),
+        params: params.to_vec(),
+        values: builder.values,
+        blocks: vec![Block {
+            insts: builder.insts,
+            terminator: Terminator::Return(ret),
+        }],
+    })
+}
+
+// instructions of the block under construction
+struct Builder<'a> {
+    params: &'a [String],
+    values: Vec<(Inst, Type)>,
+    insts: Vec<Value>,
+}
+
+impl Builder<'_> {
+    fn push(&mut self, inst: Inst, ty: Type) -> Value {
+        let value = Value(self.values.len());
+        self.values.push((inst, ty));
+        self.insts.push(value);
+        value
+    }
+
+    fn bool(&mut self, cond: Cond, lhs: Value, rhs: Value) -> Value {
+        let cmp = self.push(Inst::Cmp(cond, lhs, rhs), Type::Bool);
+        self.push(Inst::BoolToF64(cmp), Type::F64)
+    }
+
+    // `check`ed before, anything else is unreachable
+    // the operands are lowered before their operator, on a stack
+    fn expr(&mut self, expr: &ExpressionAST) -> Value {
+        let mut values = Vec::new();
+        for step in expr.walk() {
+            let Step::Leave(expr, _) = step else {
+                continue;
+            };
+            let value = match expr {
+                ExpressionAST::Number(num, ..) => self.push(Inst::Const(*num), Type::F64),
+                ExpressionAST::Variable(name, ..) => {
+                    Value(self.params.iter().position(|p| p == name).unwrap())
+                }
+                ExpressionAST::Unary(op, ..) => {
+                    let operand = values.pop().unwrap();
+                    match op {
+                        '-' => self.push(Inst::Neg(operand), Type::F64),
+                        '!' => {
+                            let zero = self.push(Inst::Const(0.0), Type::F64);
+                            self.bool(Cond::Eq, operand, zero)
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                ExpressionAST::Binary(op, ..) => {
+                    let rhs = values.pop().unwrap();
+                    let lhs = values.pop().unwrap();
+                    match op {
+                        '+' => self.push(Inst::Binary(BinaryOp::Add, lhs, rhs), Type::F64),
+                        '-' => self.push(Inst::Binary(BinaryOp::Sub, lhs, rhs), Type::F64),
+                        '*' => self.push(Inst::Binary(BinaryOp::Mul, lhs, rhs), Type::F64),
+                        '<' => self.bool(Cond::Ult, lhs, rhs),
+                        _ => unreachable!(),
+                    }
+                }
+                ExpressionAST::Call(callee, args, ..) => {
+                    let args = values.split_off(values.len() - args.len());
+                    self.push(Inst::Call(callee.clone(), args), Type::F64)
+                }
+                ExpressionAST::Error(..) => unreachable!(),
+            };
+            values.push(value);
+        }
+        values.pop().unwrap()
+    }
+}
+
+// common subexpression elimination, an instruction computing the same
+// value as an earlier one in its block is dropped and its uses take the
+// earlier value, calls only if `is_pure` says the callee has no side
+// effects
+pub fn cse(func: &mut Function, is_pure: &dyn Fn(&str) -> bool) {
+    let mut replaced: HashMap<Value, Value> = HashMap::new();
+    let replace = |replaced: &HashMap<Value, Value>, value| *replaced.get(&value).unwrap_or(&value);
+    for block in &mut func.blocks {
+        // instructions keyed by their text with operands replaced,
+        // `a + b` is `b + a`
+        let mut seen: HashMap<String, Value> = HashMap::new();
+        let mut insts = Vec::new();
+        for value in std::mem::take(&mut block.insts) {
+            let inst = &mut func.values[value.0].0;
+            inst.map_operands(|operand| replace(&replaced, operand));
+            if let Inst::Binary(BinaryOp::Add | BinaryOp::Mul, lhs, rhs) = inst {
+                if rhs < lhs {
+                    std::mem::swap(lhs, rhs);
+                }
+            }
+            let pure = match inst {
+                Inst::Call(callee, _) => is_pure(callee),
+                _ => true,
+            };
+            if pure {
+                let key = format!("{:?}", inst);
+                if let Some(earlier) = seen.get(&key) {
+                    replaced.insert(value, *earlier);
+                    continue;
+                }
+                seen.insert(key, value);
+            }
+            insts.push(value);
+        }
+        block.insts = insts;
+        let Terminator::Return(ret) = &mut block.terminator;
+        *ret = replace(&replaced, *ret);
+    }
+}
+
+// broken invariant of a `Function`, a bug in whatever built it
+#[derive(Debug, PartialEq, Clone)]
+pub enum VerifyError {
+    NoBlocks,
+    // used but not defined before, `user` is None for a terminator
+    Undefined { value: Value, user: Option<Value> },
+    // defined by more than one instruction
+    Redefined(Value),
+    // operand or result of the wrong type
+    WrongType { value: Value, expected: Type },
+    // parameter out of range or outside the entry block
+    BadParam(Value),
+    UnknownFunction { value: Value, name: String },
+    ArityMismatch { value: Value, name: String },
+}
+
+// check the structural invariants of `func`: the entry block exists,
+// every value is defined once before it is used, operands and results
+// have the right types, parameters exist and calls match the arity of
+// the known functions, every block ends in its terminator by
+// construction
+pub fn verify(func: &Function, arity: &dyn Fn(&str) -> Option<usize>) -> Result<(), VerifyError> {
+    if func.blocks.is_empty() {
+        return Err(VerifyError::NoBlocks);
+    }
+    let mut defined = vec![false; func.values.len()];
+    let expect = |value: Value, expected: Type| match func.ty(value) == expected {
+        true => Ok(()),
+        false => Err(VerifyError::WrongType { value, expected }),
+    };
+    for (i, block) in func.blocks.iter().enumerate() {
+        for &value in &block.insts {
+            if defined.get(value.0) != Some(&false) {
+                return Err(VerifyError::Redefined(value));
+            }
+            let inst = func.inst(value);
+            for operand in inst.operands() {
+                if defined.get(operand.0) != Some(&true) {
+                    let user = Some(value);
+                    return Err(VerifyError::Undefined {
+                        value: operand,
+                        user,
+                    });
+                }
+            }
+            match inst {
+                Inst::Const(_) => expect(value, Type::F64)?,
+                Inst::Param(index) if i > 0 || *index >= func.params.len() => {
+                    return Err(VerifyError::BadParam(value));
+                }
+                Inst::Param(_) => expect(value, Type::F64)?,
+                Inst::Neg(operand) => {
+                    expect(*operand, Type::F64)?;
+                    expect(value, Type::F64)?;
+                }
+                Inst::Binary(_, lhs, rhs) => {
+                    expect(*lhs, Type::F64)?;
+                    expect(*rhs, Type::F64)?;
+                    expect(value, Type::F64)?;
+                }
+                Inst::Cmp(_, lhs, rhs) => {
+                    expect(*lhs, Type::F64)?;
+                    expect(*rhs, Type::F64)?;
+                    expect(value, Type::Bool)?;
+                }
+                Inst::BoolToF64(cond) => {
+                    expect(*cond, Type::Bool)?;
+                    expect(value, Type::F64)?;
+                }
+                Inst::Call(name, args) => {
+                    match arity(name) {
+                        None => {
+                            let name = name.clone();
+                            return Err(VerifyError::UnknownFunction { value, name });
+                        }
+                        Some(arity) if arity != args.len() => {
+                            let name = name.clone();
+                            return Err(VerifyError::ArityMismatch { value, name });
+                        }
+                        Some(_) => {}
+                    }
+                    args.iter().try_for_each(|arg| expect(*arg, Type::F64))?;
+                    expect(value, Type::F64)?;
+                }
+            }
+            defined[value.0] = true;
+        }
+        let Terminator::Return(ret) = block.terminator;
+        if defined.get(ret.0) != Some(&true) {
+            return Err(VerifyError::Undefined {
+                value: ret,
+                user: None,
+            });
+        }
+        expect(ret, Type::F64)?;
+    }
+    Ok(())
+}
+
+// `verify` in debug builds, after every step of the pipeline that builds
+// or rewrites a function
+pub fn debug_verify(func: &Function, arity: &dyn Fn(&str) -> Option<usize>) {
+    if cfg!(debug_assertions) {
+        if let Err(err) = verify(func, arity) {
+            panic!("invalid ir: {:?}\n{}", err, func);
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::F64 => write!(f, "f64"),
+            Type::Bool => write!(f, "bool"),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inst::Const(num) => write!(f, "const {:?}", num),
+            Inst::Param(i) => write!(f, "param {}", i),
+            Inst::Neg(value) => write!(f, "neg {}", value),
+            Inst::Binary(op, lhs, rhs) => {
+                let op = match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Sub => "sub",
+                    BinaryOp::Mul => "mul",
+                };
+                write!(f, "{} {}, {}", op, lhs, rhs)
+            }
+            Inst::Cmp(cond, lhs, rhs) => {
+                let cond = match cond {
+                    Cond::Eq => "eq",
+                    Cond::Ult => "ult",
+                };
+                write!(f, "cmp {} {}, {}", cond, lhs, rhs)
+            }
+            Inst::BoolToF64(value) => write!(f, "bool_to_f64 {}", value),
+            Inst::Call(callee, args) => {
+                let args: Vec<_> = args.iter().map(Value::to_string).collect();
+                write!(f, "call {}({})", callee, args.join(", "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "function {}({}) {{", self.name, self.params.join(", "))?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{}:", i)?;
+            for value in &block.insts {
+                writeln!(
+                    f,
+                    "  {}: {} = {}",
+                    value,
+                    self.ty(*value),
+                    self.inst(*value)
+                )?;
+            }
+            match block.terminator {
+                Terminator::Return(value) => writeln!(f, "  return {}", value)?,
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cse, lower, verify, Function, Inst, Terminator, Type, Value, VerifyError};
+    use crate::ast::Item;
+    use crate::codegen::CodegenError;
+    use crate::parser::parse_file;
+
+    // lower the function defined by `input`, calls of itself, `sin` and
+    // `printd` are known
+    fn lower_function(input: &str) -> Result<Function, CodegenError> {
+        let Item::Function(func) = &parse_file(input).unwrap()[0] else {
+            panic!("not a function: {}", input);
+        };
+        let arity = |callee: &str| match callee {
+            "sin" | "printd" => Some(1),
+            _ if callee == func.0 .0 => Some(func.0 .1.len()),
+            _ => None,
+        };
+        lower(&func.0 .0, &func.0 .1, &func.1, &arity)
+    }
+
+    #[test]
+    fn ir_lower() {
+        let func = lower_function("def f(x y) sin(x) * -y + !(x < 2)").unwrap();
+        assert_eq!(
+            func.to_string(),
+            "function f(x, y) {
+b0:
+  v0: f64 = param 0
+  v1: f64 = param 1
+  v2: f64 = call sin(v0)
+  v3: f64 = neg v1
+  v4: f64 = mul v2, v3
+  v5: f64 = const 2.0
+  v6: bool = cmp ult v0, v5
+  v7: f64 = bool_to_f64 v6
+  v8: f64 = const 0.0
+  v9: bool = cmp eq v7, v8
+  v10: f64 = bool_to_f64 v9
+  v11: f64 = add v4, v10
+  return v11
+}"
+        );
+        assert_eq!(func.callees(), ["sin"]);
+
+        assert!(matches!(
+            lower_function("def f(x) y"),
+            Err(CodegenError::UnknownVariable { .. })
+        ));
+    }
+
+    #[test]
+    fn ir_cse() {
+        let mut func =
+            lower_function("def f(x y) sin(x) * sin(x) + (x * y - y * x) + printd(1) * printd(1)")
+                .unwrap();
+        cse(&mut func, &|callee| callee == "sin");
+        assert_eq!(
+            func.to_string(),
+            "function f(x, y) {
+b0:
+  v0: f64 = param 0
+  v1: f64 = param 1
+  v2: f64 = call sin(v0)
+  v4: f64 = mul v2, v2
+  v5: f64 = mul v0, v1
+  v7: f64 = sub v5, v5
+  v8: f64 = add v4, v7
+  v9: f64 = const 1.0
+  v10: f64 = call printd(v9)
+  v12: f64 = call printd(v9)
+  v13: f64 = mul v10, v12
+  v14: f64 = add v8, v13
+  return v14
+}"
+        );
+    }
+
+    #[test]
+    fn ir_verify() {
+        let arity = |callee: &str| (callee == "sin").then_some(1);
+        let valid = lower_function("def f(x) sin(x) < 1").unwrap();
+        assert_eq!(verify(&valid, &arity), Ok(()));
+
+        let mut func = valid.clone();
+        func.blocks[0].insts.swap(1, 3);
+        let undefined = VerifyError::Undefined {
+            value: Value(1),
+            user: Some(Value(3)),
+        };
+        assert_eq!(verify(&func, &arity), Err(undefined));
+
+        let mut func = valid.clone();
+        func.blocks[0].insts.push(Value(1));
+        assert_eq!(verify(&func, &arity), Err(VerifyError::Redefined(Value(1))));
+
+        let mut func = valid.clone();
+        func.values[3].1 = Type::F64;
+        let wrong_type = VerifyError::WrongType {
+            value: Value(3),
+            expected: Type::Bool,
+        };
+        assert_eq!(verify(&func, &arity), Err(wrong_type));
+
+        let mut func = valid.clone();
+        func.values[0].0 = Inst::Param(1);
+        assert_eq!(verify(&func, &arity), Err(VerifyError::BadParam(Value(0))));
+
+        let mut func = valid.clone();
+        func.values[1].0 = Inst::Call("sin".into(), vec![]);
+        assert!(matches!(
+            verify(&func, &arity),
+            Err(VerifyError::ArityMismatch { .. })
+        ));
+        assert!(matches!(
+            verify(&valid, &|_| None),
+            Err(VerifyError::UnknownFunction { .. })
+        ));
+
+        let mut func = valid.clone();
+        func.blocks[0].terminator = Terminator::Return(Value(9));
+        let undefined = VerifyError::Undefined {
+            value: Value(9),
+            user: None,
+        };
+        assert_eq!(verify(&func, &arity), Err(undefined));
+
+        let mut func = valid;
+        func.blocks.clear();
+        assert_eq!(verify(&func, &arity), Err(VerifyError::NoBlocks));
+    }
+
+    #[test]
+    fn ir_self_tail_call() {
+        let f = lower_function("def f(x) f(x - 1)").unwrap();
+        assert!(f.is_self_tail_call(Value(3)));
+        assert!(!f.is_self_tail_call(Value(2)));
+
+        let g = lower_function("def g(x) g(x) + 1").unwrap();
+        assert!(!g.is_self_tail_call(Value(1)));
+        let h = lower_function("def h(x) sin(x)").unwrap();
+        assert!(!h.is_self_tail_call(Value(1)));
+    }
+}
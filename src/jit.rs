@@ -0,0 +1,246 @@
+use crate::ast::{ExpressionAST, Item};
+use crate::codegen::{Codegen, CodegenError, MAIN};
+use crate::emit::{self, EmitError};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// jit - evaluate top-level expressions as they are read (chapter 4)
+//
+// the module compiled so far is run by `lli`, llvm's jit, with an entry
+// point calling the expression and writing the bits of the result as
+// hex to stdout, functions and externs persist across expressions,
+// an expression is dropped again once it ran
+// output of the program itself is passed through to stdout
+// the runtime executables link is built with the c compiler on the first
+// evaluation and loaded as an extra object, so `printd`, arrays and
+// strings work like they do in executables
+
+#[derive(Debug)]
+pub enum JitError {
+    Codegen(CodegenError),
+    // lli could not be started
+    Spawn(io::Error),
+    // lli failed, with its stderr
+    Failed(String),
+}
+
+impl From<CodegenError> for JitError {
+    fn from(err: CodegenError) -> Self {
+        JitError::Codegen(err)
+    }
+}
+
+pub struct Jit {
+    codegen: Codegen,
+    lli: String,
+    // object file of the runtime, once built
+    runtime: Option<PathBuf>,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jit {
+    // lli from the PATH
+    pub fn new() -> Self {
+        Self::with_lli("lli")
+    }
+
+    pub fn with_lli(lli: impl Into<String>) -> Self {
+        Jit {
+            codegen: Codegen::new(),
+            lli: lli.into(),
+            runtime: None,
+        }
+    }
+
+    // the module functions and externs are added to
+    pub fn codegen(&mut self) -> &mut Codegen {
+        &mut self.codegen
+    }
+
+    // add a function or extern, evaluate a top-level expression
+    pub fn eval_item(&mut self, item: &Item) -> Result<Option<f64>, JitError> {
+        match item {
+            Item::Expr(expr) => self.eval(expr).map(Some),
+            _ => {
+                self.codegen.compile_item(item)?;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn eval(&mut self, expr: &ExpressionAST) -> Result<f64, JitError> {
+        self.codegen.compile_item(&Item::Expr(expr.clone()))?;
+        let runtime = match self.runtime() {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                self.codegen
+                    .remove_function(&self.codegen.last_anon_expr().unwrap());
+                return Err(err);
+            }
+        };
+        let name = self.codegen.last_anon_expr().unwrap();
+        let mut module = self.codegen.module();
+        self.codegen.remove_function(&name);
+        module.push('\n');
+        module.push_str(&entry(&name));
+
+        let mut lli = Command::new(&self.lli)
+            .arg(format!("--entry-function={}", MAIN))
+            .arg(format!("--extra-object={}", runtime.display()))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(JitError::Spawn)?;
+        // lli reads the whole module before it runs anything
+        lli.stdin
+            .take()
+            .unwrap()
+            .write_all(module.as_bytes())
+            .map_err(JitError::Spawn)?;
+        let out = lli.wait_with_output().map_err(JitError::Spawn)?;
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            return Err(JitError::Failed(stderr.trim_end().into()));
+        }
+
+        // the result is the last 16 chars before the final newline
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let printed = stdout.strip_suffix('\n').unwrap_or(&stdout);
+        let split = printed.len().saturating_sub(16);
+        let bits = printed
+            .get(split..)
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| JitError::Failed(format!("unexpected output {:?}", stdout)))?;
+        print!("{}", &printed[..split]);
+        io::stdout().flush().map_err(JitError::Spawn)?;
+        Ok(f64::from_bits(bits))
+    }
+
+    // the runtime object, built the first time
+    fn runtime(&mut self) -> Result<PathBuf, JitError> {
+        if let Some(runtime) = &self.runtime {
+            return Ok(runtime.clone());
+        }
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("klc-runtime-{}-{}.o", std::process::id(), n);
+        let path = std::env::temp_dir().join(name);
+        emit::write_runtime_object(&path).map_err(|err| {
+            JitError::Failed(match err {
+                EmitError::Io(err) => format!("cannot build the runtime: {}", err),
+                EmitError::Failed { tool, stderr } => {
+                    format!("cannot build the runtime, {} failed:\n{}", tool, stderr)
+                }
+            })
+        })?;
+        self.runtime = Some(path.clone());
+        Ok(path)
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(runtime) = &self.runtime {
+            let _ = std::fs::remove_file(runtime);
+        }
+    }
+}
+
+// entry point calling `name` and printing its result, one putchar per
+// hex digit keeps it free of pointers, which changed syntax across llvm
+// versions
+fn entry(name: &str) -> String {
+    let mut ir = String::from("declare i32 @putchar(i32)\n\n");
+    writeln!(ir, "define i32 @{}() {{\nentry:", MAIN).unwrap();
+    writeln!(ir, "  %result = call double @{}()", name).unwrap();
+    ir.push_str("  %bits = bitcast double %result to i64\n");
+    for i in 0..16 {
+        let shift = 60 - 4 * i;
+        writeln!(ir, "  %shifted{0} = lshr i64 %bits, {1}", i, shift).unwrap();
+        writeln!(ir, "  %nibble{0} = and i64 %shifted{0}, 15", i).unwrap();
+        writeln!(ir, "  %digit{0} = trunc i64 %nibble{0} to i32", i).unwrap();
+        writeln!(ir, "  %decimal{0} = icmp ult i32 %digit{0}, 10", i).unwrap();
+        // '0' + digit or 'a' - 10 + digit
+        writeln!(ir, "  %base{0} = select i1 %decimal{0}, i32 48, i32 87", i).unwrap();
+        writeln!(ir, "  %char{0} = add i32 %digit{0}, %base{0}", i).unwrap();
+        writeln!(ir, "  call i32 @putchar(i32 %char{0})", i).unwrap();
+    }
+    ir.push_str("  call i32 @putchar(i32 10)\n  ret i32 0\n}\n");
+    ir
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Jit, JitError};
+    use crate::codegen::CodegenError;
+    use crate::parser::parse_file;
+    use std::process::Command;
+
+    // the tests need llvm's lli on the PATH
+    fn lli_available() -> bool {
+        let version = Command::new("lli").arg("--version").output();
+        version.is_ok_and(|out| out.status.success())
+    }
+
+    // evaluate the items of `input`, results of the expressions
+    fn eval(jit: &mut Jit, input: &str) -> Vec<f64> {
+        let items = parse_file(input).unwrap();
+        items
+            .iter()
+            .filter_map(|item| jit.eval_item(item).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn jit_eval() {
+        if !lli_available() {
+            return;
+        }
+        let mut jit = Jit::new();
+        assert_eq!(eval(&mut jit, "1 + 2 * 3; 4 < 5; -(1.5)"), [7.0, 1.0, -1.5]);
+
+        // functions persist across inputs, expressions do not
+        assert_eq!(eval(&mut jit, "def sq(x) x * x; sq(3)"), [9.0]);
+        assert_eq!(eval(&mut jit, "sq(sq(2)) + !0"), [17.0]);
+        assert!(!jit.codegen().module().contains("_KE"));
+
+        // externs resolve against lli, which links libm, and the runtime
+        assert_eq!(eval(&mut jit, "extern cos(x); cos(0)"), [1.0]);
+        assert_eq!(
+            eval(
+                &mut jit,
+                "extern arraynew(n); extern arrayset(a, i, x); extern arraylen(a);
+                 def len(a) arrayset(a, 1, 5) * 0 + arraylen(a); len(arraynew(4))"
+            ),
+            [4.0]
+        );
+    }
+
+    #[test]
+    fn jit_errors() {
+        if !lli_available() {
+            return;
+        }
+        let mut jit = Jit::new();
+        let items = parse_file("x; extern nosuchfunction(); nosuchfunction()").unwrap();
+        assert!(matches!(
+            jit.eval_item(&items[0]),
+            Err(JitError::Codegen(CodegenError::UnknownVariable { .. }))
+        ));
+        jit.eval_item(&items[1]).unwrap();
+        assert!(matches!(jit.eval_item(&items[2]), Err(JitError::Failed(_))));
+
+        let mut jit = Jit::with_lli("/nonexistent/lli");
+        let items = parse_file("1").unwrap();
+        assert!(matches!(jit.eval_item(&items[0]), Err(JitError::Spawn(_))));
+    }
+}
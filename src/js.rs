@@ -0,0 +1,343 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST};
+use crate::codegen::{CodegenError, CodegenResult};
+use crate::ir::{self, BinaryOp, Cond, Inst, Terminator, Value};
+use crate::lexer::Position;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// js - lower the ast to javascript, for running programs in a browser
+//
+// the program is a single function `kaleidoscope(externs)` returning the
+// values of the top-level expressions in order, each extern is bound to
+// the shim of the same name in `externs`, e.g.
+//
+//   kaleidoscope({ putchard: (c) => { out(String.fromCharCode(c)); return 0; } })
+//
+// kaleidoscope names are prefixed with `$` so they cannot clash with
+// javascript keywords or globals
+
+// function known to the program
+struct Known {
+    arity: usize,
+    defined: bool,
+}
+
+// program under construction, items are added one at a time like the
+// repl reads them
+#[derive(Default)]
+pub struct Js {
+    functions: HashMap<String, Known>,
+    // names in order of first declaration
+    declared: Vec<String>,
+    // function definitions and top-level expressions in order
+    items: Vec<String>,
+}
+
+impl Js {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // add `item` to the program, returns its javascript
+    pub fn compile_item(&mut self, item: &Item) -> CodegenResult<String> {
+        match item {
+            Item::Function(func) => self.compile_function(func),
+            Item::Extern(proto) => self.compile_extern(proto),
+            Item::Expr(expr) => self.compile_anon_expr(expr),
+        }
+    }
+
+    // binding of an extern to its shim, a function may be declared more
+    // than once
+    pub fn compile_extern(&mut self, proto: &PrototypeAST) -> CodegenResult<String> {
+        let PrototypeAST(name, params, ..) = proto;
+        self.declare(name, params, proto.4.start)?;
+        Ok(binding(name))
+    }
+
+    // function declaration, an anonymous function is a top-level
+    // expression
+    pub fn compile_function(&mut self, func: &FunctionAST) -> CodegenResult<String> {
+        let FunctionAST(proto, body, ..) = func;
+        if proto.0.is_empty() {
+            return self.compile_anon_expr(body);
+        }
+        let (name, params, pos) = (&proto.0, &proto.1, proto.4.start);
+        if self.functions.get(name).is_some_and(|known| known.defined) {
+            let name = name.clone();
+            return Err(CodegenError::Redefinition { name, pos });
+        }
+        // only declared once the body is known to lower, it may call itself
+        let func = self.lower(name, params, body, true)?;
+        self.declare(name, params, pos)?;
+
+        let (mut js, ret) = statements(&func);
+        writeln!(js, "return {};", ret).unwrap();
+        let params: Vec<_> = params.iter().map(|param| format!("${}", param)).collect();
+        let js = format!(
+            "function ${}({}) {{\n{}}}\n",
+            name,
+            params.join(", "),
+            indent(&js)
+        );
+        self.functions.get_mut(name).unwrap().defined = true;
+        self.items.push(js.clone());
+        Ok(js)
+    }
+
+    fn compile_anon_expr(&mut self, body: &ExpressionAST) -> CodegenResult<String> {
+        let func = self.lower("", &[], body, false)?;
+        let (statements, ret) = statements(&func);
+        let push = format!("results.push({});\n", ret);
+        // a block keeps the values local
+        let js = match statements.is_empty() {
+            true => push,
+            false => format!("{{\n{}}}\n", indent(&(statements + &push))),
+        };
+        self.items.push(js.clone());
+        Ok(js)
+    }
+
+    // the whole program, bindings of functions without a definition first,
+    // function declarations are hoisted so calls of functions defined later
+    // work like they do in the module
+    pub fn program(&self) -> String {
+        let mut out = String::from("function kaleidoscope(externs) {\n");
+        out.push_str("  const results = [];\n");
+        for name in &self.declared {
+            if !self.functions[name].defined {
+                write!(out, "  {}", binding(name)).unwrap();
+            }
+        }
+        for item in &self.items {
+            for line in item.lines() {
+                writeln!(out, "  {}", line).unwrap();
+            }
+        }
+        out.push_str("  return results;\n}\n");
+        out
+    }
+
+    // `recursive` if the body may call `name`
+    fn lower(
+        &self,
+        name: &str,
+        params: &[String],
+        body: &ExpressionAST,
+        recursive: bool,
+    ) -> CodegenResult<ir::Function> {
+        let arity = |callee: &str| match self.functions.get(callee) {
+            _ if recursive && callee == name => Some(params.len()),
+            known => known.map(|known| known.arity),
+        };
+        let func = ir::lower(name, params, body, &arity)?;
+        ir::debug_verify(&func, &arity);
+        Ok(func)
+    }
+
+    fn declare(&mut self, name: &str, params: &[String], pos: Position) -> CodegenResult<()> {
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].contains(param) {
+                let name = param.clone();
+                return Err(CodegenError::DuplicateParameter { name, pos });
+            }
+        }
+        match self.functions.get(name) {
+            Some(known) if known.arity != params.len() => Err(CodegenError::ArityMismatch {
+                name: name.into(),
+                expected: known.arity,
+                found: params.len(),
+                pos,
+            }),
+            Some(_) => Ok(()),
+            None => {
+                let known = Known {
+                    arity: params.len(),
+                    defined: false,
+                };
+                self.functions.insert(name.into(), known);
+                self.declared.push(name.into());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn binding(name: &str) -> String {
+    format!("const ${0} = externs[\"{0}\"];\n", name)
+}
+
+// a `const` per value of a function of the ir, and the operand of the
+// value it returns, comparisons yield 1 or 0 like the other backends
+fn statements(func: &ir::Function) -> (String, String) {
+    let mut out = String::new();
+    let mut operands = HashMap::new();
+    let operand = |operands: &HashMap<Value, String>, value: &Value| operands[value].clone();
+    // a body is a single block so far
+    let block = &func.blocks[0];
+    for value in &block.insts {
+        let js = match func.inst(*value) {
+            Inst::Const(num) => {
+                operands.insert(*value, number(*num));
+                continue;
+            }
+            Inst::Param(i) => {
+                operands.insert(*value, format!("${}", func.params[*i]));
+                continue;
+            }
+            Inst::Neg(operand_value) => format!("-{}", operand(&operands, operand_value)),
+            Inst::Binary(op, lhs, rhs) => {
+                let op = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                };
+                let (lhs, rhs) = (operand(&operands, lhs), operand(&operands, rhs));
+                format!("{} {} {}", lhs, op, rhs)
+            }
+            Inst::Cmp(Cond::Eq, lhs, rhs) => {
+                let (lhs, rhs) = (operand(&operands, lhs), operand(&operands, rhs));
+                format!("{} === {}", lhs, rhs)
+            }
+            // unordered counts as less, like `fcmp ult`
+            Inst::Cmp(Cond::Ult, lhs, rhs) => {
+                let (lhs, rhs) = (operand(&operands, lhs), operand(&operands, rhs));
+                format!("!({} >= {})", lhs, rhs)
+            }
+            Inst::BoolToF64(cond) => format!("{} ? 1 : 0", operand(&operands, cond)),
+            Inst::Call(callee, args) => {
+                let args: Vec<_> = args.iter().map(|arg| operand(&operands, arg)).collect();
+                format!("${}({})", callee, args.join(", "))
+            }
+        };
+        writeln!(out, "const {} = {};", value, js).unwrap();
+        operands.insert(*value, value.to_string());
+    }
+    let Terminator::Return(ret) = &block.terminator;
+    (out, operand(&operands, ret))
+}
+
+// `js` indented by one level
+fn indent(js: &str) -> String {
+    let mut out = String::new();
+    for line in js.lines() {
+        writeln!(out, "  {}", line).unwrap();
+    }
+    out
+}
+
+fn number(num: f64) -> String {
+    if num.is_nan() {
+        "NaN".into()
+    } else if num.is_infinite() {
+        if num > 0.0 { "Infinity" } else { "(-Infinity)" }.into()
+    } else if num.is_sign_negative() {
+        format!("({:?})", num)
+    } else {
+        format!("{:?}", num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Js;
+    use crate::codegen::CodegenError;
+    use crate::parser::parse_file;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    fn compile(input: &str) -> Js {
+        let mut js = Js::new();
+        for item in parse_file(input).unwrap() {
+            js.compile_item(&item).unwrap();
+        }
+        js
+    }
+
+    #[test]
+    fn js_program() {
+        let js = compile("extern sin(x); def f(x, y) sin(x) * -y + !(x < 2); f(1, 2.5); 3");
+        assert_eq!(
+            js.program(),
+            "function kaleidoscope(externs) {
+  const results = [];
+  const $sin = externs[\"sin\"];
+  function $f($x, $y) {
+    const v2 = $sin($x);
+    const v3 = -$y;
+    const v4 = v2 * v3;
+    const v6 = !($x >= 2.0);
+    const v7 = v6 ? 1 : 0;
+    const v9 = v7 === 0.0;
+    const v10 = v9 ? 1 : 0;
+    const v11 = v4 + v10;
+    return v11;
+  }
+  {
+    const v2 = $f(1.0, 2.5);
+    results.push(v2);
+  }
+  results.push(3.0);
+  return results;
+}
+"
+        );
+
+        // an extern defined later is no binding
+        let js = compile("extern g(x); def f(x) g(x); def g(x) x");
+        assert!(!js.program().contains("const $g"));
+    }
+
+    #[test]
+    fn js_errors() {
+        let mut js = Js::new();
+        let items =
+            parse_file("def f(x) y; def f(x, x) 1; def f(x) f(x); def f(x) 2; f()").unwrap();
+        let errors: Vec<_> = items
+            .iter()
+            .map(|item| js.compile_item(item).map(|_| ()))
+            .collect();
+        assert!(matches!(
+            errors[0],
+            Err(CodegenError::UnknownVariable { .. })
+        ));
+        assert!(matches!(
+            errors[1],
+            Err(CodegenError::DuplicateParameter { .. })
+        ));
+        assert_eq!(errors[2], Ok(()));
+        assert!(matches!(errors[3], Err(CodegenError::Redefinition { .. })));
+        assert!(matches!(errors[4], Err(CodegenError::ArityMismatch { .. })));
+    }
+
+    #[test]
+    fn js_run() {
+        let version = Command::new("node").arg("--version").output();
+        if !version.is_ok_and(|out| out.status.success()) {
+            return;
+        }
+        let js = compile(
+            "extern putchard(c);
+             def sq(x) x * x;
+             sq(sq(3)) - 26; putchard(75); 1 < 0",
+        );
+        let script = js.program()
+            + "const out = [];
+               const shims = { putchard: (c) => { out.push(String.fromCharCode(c)); return 0; } };
+               console.log(kaleidoscope(shims).join(' ') + ' ' + out.join(''));";
+
+        let mut node = Command::new("node")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        node.stdin
+            .take()
+            .unwrap()
+            .write_all(script.as_bytes())
+            .unwrap();
+        let out = node.wait_with_output().unwrap();
+        assert!(out.status.success());
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "55 0 0 K\n");
+    }
+}
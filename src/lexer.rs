@@ -1,11 +1,255 @@
+#[cfg(any(test, feature = "arbitrary"))]
+pub(crate) mod arbitrary;
+
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+// words that are not identifiers
+pub const KEYWORDS: [&str; 2] = ["def", "extern"];
+
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Token {
     Eof,
     Def,                // def
     Extern,             // extern
-    Identifier(String), // \p{Aphabetic}\w*
+    Identifier(String), // [a-zA-Z][a-zA-Z0-9_]*
     Number(f64),        // \d+\.?\d*
     Char(char),         //
+    Comment(String),    // # ... (only with LexerConfig::emit_comments)
+    DocComment(String), // ## ...
+    Error(LexError),    // malformed input, lexing continues after it
+}
+
+impl Token {
+    // kind of token for listings, e.g. `keyword` or `operator`
+    pub fn class(&self) -> &'static str {
+        match self {
+            Token::Eof => "eof",
+            Token::Def | Token::Extern => "keyword",
+            Token::Identifier(_) => "identifier",
+            Token::Number(_) => "number",
+            Token::Char('(' | ')' | ',' | ';') => "punctuation",
+            Token::Char(_) => "operator",
+            Token::Comment(_) => "comment",
+            Token::DocComment(_) => "doc-comment",
+            Token::Error(_) => "error",
+        }
+    }
+}
+
+// the token as error messages name it, e.g. `';'` or `identifier 'x'`
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Eof => write!(f, "end of input"),
+            Token::Def => write!(f, "'def'"),
+            Token::Extern => write!(f, "'extern'"),
+            Token::Identifier(name) => write!(f, "identifier '{}'", name),
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Char(c) => write!(f, "'{}'", c),
+            Token::Comment(_) => write!(f, "comment"),
+            Token::DocComment(_) => write!(f, "doc comment"),
+            Token::Error(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum LexError {
+    InvalidChar(char),        // non ascii or control character
+    IdentifierTooLong(usize), // limit that was exceeded
+    NumberTooLong(usize),     // limit that was exceeded
+    CommentTooLong(usize),    // limit that was exceeded
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::InvalidChar(c) => write!(f, "invalid character {:?}", c),
+            LexError::IdentifierTooLong(limit) => {
+                write!(f, "identifier longer than {} bytes", limit)
+            }
+            LexError::NumberTooLong(limit) => write!(f, "number longer than {} bytes", limit),
+            LexError::CommentTooLong(limit) => write!(f, "comment longer than {} bytes", limit),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LexError {}
+
+// location of a char in the source, line and column start at 1
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub offset: usize, // in bytes
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    // position after `c`
+    pub(crate) fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+// source range [start, end) of a token or ast node
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+
+    // empty span at `pos`, e.g. for errors only known by where they are
+    pub fn at(pos: Position) -> Self {
+        Span::new(pos, pos)
+    }
+
+    // span from the start of self to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+// lexer limits - bound the memory a single token may take
+#[derive(Clone, Copy, Debug)]
+pub struct LexerConfig {
+    pub max_identifier_len: usize,
+    pub max_number_len: usize,
+    pub max_comment_len: usize,
+    // emit `Token::Comment` instead of discarding comments
+    pub emit_comments: bool,
+}
+
+impl Default for LexerConfig {
+    fn default() -> Self {
+        LexerConfig {
+            max_identifier_len: 1024,
+            max_number_len: 128,
+            max_comment_len: 4096,
+            emit_comments: false,
+        }
+    }
+}
+
+// chars decoded from a `Read` source in buffered chunks
+// invalid utf8 is replaced by U+FFFD, io errors end the input
+#[cfg(feature = "std")]
+pub struct ReadChars<R> {
+    reader: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    eof: bool,
+}
+
+#[cfg(feature = "std")]
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+#[cfg(feature = "std")]
+impl<R: Read> ReadChars<R> {
+    pub fn new(reader: R) -> Self {
+        ReadChars {
+            reader,
+            buf: vec![0; READ_CHUNK_SIZE].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            eof: false,
+        }
+    }
+
+    // keep unconsumed bytes (a partial utf8 sequence) and read the next chunk
+    fn refill(&mut self) {
+        self.buf.copy_within(self.pos..self.len, 0);
+        self.len -= self.pos;
+        self.pos = 0;
+
+        loop {
+            match self.reader.read(&mut self.buf[self.len..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.len += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => self.eof = true,
+            }
+            return;
+        }
+    }
+}
+
+// length of the utf8 sequence started by `b`, 0 if `b` can not start one
+#[cfg(feature = "std")]
+fn utf8_width(b: u8) -> usize {
+    match b {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 0,
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for ReadChars<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            let avail = &self.buf[self.pos..self.len];
+            if avail.is_empty() {
+                if self.eof {
+                    return None;
+                }
+                self.refill();
+                continue;
+            }
+
+            let width = utf8_width(avail[0]);
+            if avail.len() < width && !self.eof {
+                self.refill();
+                continue;
+            }
+
+            return match std::str::from_utf8(&avail[..width.clamp(1, avail.len())]) {
+                Ok(s) => {
+                    self.pos += width;
+                    s.chars().next()
+                }
+                Err(_) => {
+                    self.pos += 1;
+                    Some(char::REPLACEMENT_CHARACTER)
+                }
+            };
+        }
+    }
 }
 
 pub struct Lexer<I>
@@ -14,27 +258,119 @@ where
 {
     input: I,
     last_char: Option<char>,
+    config: LexerConfig,
+    pos: Position,         // position of `last_char`
+    token_start: Position, // position of the last lexed token
+    token_end: Position,
 }
 
 impl<I> Lexer<I>
 where
     I: Iterator<Item = char>,
 {
-    pub fn new(mut input: I) -> Lexer<I> {
+    pub fn new(input: I) -> Lexer<I> {
+        Self::with_config(input, LexerConfig::default())
+    }
+
+    pub fn with_config(mut input: I, config: LexerConfig) -> Lexer<I> {
         let last_char = input.next();
-        Lexer { input, last_char }
+        Lexer {
+            input,
+            last_char,
+            config,
+            pos: Position::default(),
+            token_start: Position::default(),
+            token_end: Position::default(),
+        }
     }
 
     fn step(&mut self) -> Option<char> {
+        if let Some(c) = self.last_char {
+            self.pos.advance(c);
+        }
         self.last_char = self.input.next();
         self.last_char
     }
 
+    // drop the rest of an over long token without buffering it
+    fn skip_while(&mut self, pred: impl Fn(char) -> bool) {
+        while matches!(self.step(), Some(c) if pred(c)) {}
+    }
+
+    // rest of the line after the comment marker, starting at `last_char`
+    fn lex_comment(&mut self) -> Result<String, LexError> {
+        let mut comment = String::new();
+
+        let mut next = self.last_char;
+        while let Some(c) = next {
+            if c == '\r' || c == '\n' {
+                break;
+            }
+            if comment.len() == self.config.max_comment_len {
+                self.skip_while(|c| c != '\r' && c != '\n');
+                return Err(LexError::CommentTooLong(self.config.max_comment_len));
+            }
+            comment.push(c);
+            next = self.step();
+        }
+
+        Ok(comment)
+    }
+
+    // lex `input` as the continuation of a source at `pos`, so positions
+    // stay relative to the whole source
+    pub fn starting_at(mut self, pos: Position) -> Self {
+        self.pos = pos;
+        self.token_start = pos;
+        self.token_end = pos;
+        self
+    }
+
+    // start position of the token last returned by `next_token`
+    pub fn token_start(&self) -> Position {
+        self.token_start
+    }
+
+    // source range of the token last returned by `next_token`
+    pub fn token_span(&self) -> Span {
+        Span::new(self.token_start, self.token_end)
+    }
+
     // lex and return next token
     pub fn next_token(&mut self) -> Token {
-        // skip white space
-        while matches!(self.last_char, Some(c) if c.is_ascii_whitespace()) {
-            self.step();
+        let token = self.lex_token();
+        self.token_end = self.pos;
+        token
+    }
+
+    fn lex_token(&mut self) -> Token {
+        // skip white space and plain comments, in a loop rather than lexing
+        // again after each comment, a long run of them would recurse
+        loop {
+            while matches!(self.last_char, Some(c) if c.is_ascii_whitespace()) {
+                self.step();
+            }
+            self.token_start = self.pos;
+            if self.last_char != Some('#') {
+                break;
+            }
+
+            // comment := '#' [^\r\n]*
+            // doc comment := '##' [^\r\n]*
+            // doc comments are always emitted, plain ones are skipped or
+            // captured
+            if self.step() == Some('#') {
+                self.step();
+                return self
+                    .lex_comment()
+                    .map_or_else(Token::Error, Token::DocComment);
+            }
+            if self.config.emit_comments {
+                return self.lex_comment().map_or_else(Token::Error, Token::Comment);
+            }
+            while !matches!(self.last_char, None | Some('\r' | '\n')) {
+                self.step();
+            }
         }
 
         // unpack last char or return EOF
@@ -44,17 +380,23 @@ where
             return Token::Eof;
         };
 
-        // Identifier: [a-zA-Z][a-zA-Z0-9]*
+        // Identifier: [a-zA-Z][a-zA-Z0-9_]*
         if last_char.is_ascii_alphabetic() {
             let mut identifier = String::new();
             identifier.push(last_char);
+            let is_identifier = |c: char| c.is_ascii_alphanumeric() || c == '_';
 
             while let Some(c) = self.step() {
-                if c.is_ascii_alphanumeric() {
-                    identifier.push(c)
-                } else {
+                if !is_identifier(c) {
                     break;
                 }
+                if identifier.len() == self.config.max_identifier_len {
+                    self.skip_while(is_identifier);
+                    return Token::Error(LexError::IdentifierTooLong(
+                        self.config.max_identifier_len,
+                    ));
+                }
+                identifier.push(c)
             }
 
             match identifier.as_ref() {
@@ -72,37 +414,44 @@ where
             num.push(last_char);
 
             while let Some(c) = self.step() {
-                if c.is_ascii_digit() || c == '.' {
-                    num.push(c)
-                } else {
+                if !(c.is_ascii_digit() || c == '.') {
                     break;
                 }
+                if num.len() == self.config.max_number_len {
+                    self.skip_while(|c| c.is_ascii_digit() || c == '.');
+                    return Token::Error(LexError::NumberTooLong(self.config.max_number_len));
+                }
+                num.push(c)
             }
 
             let num: f64 = num.parse().unwrap_or_default();
             return Token::Number(num);
         }
 
-        // skip comment
-        if last_char == '#' {
-            loop {
-                match self.step() {
-                    Some(c) if c == '\r' || c == '\n' => return self.next_token(),
-                    None => return Token::Eof,
-                    _ => {}
-                }
-            }
-        }
-
         // advance last char
         self.step();
+
+        // report bad char, lexing resumes with the next one
+        if !last_char.is_ascii() || last_char.is_ascii_control() {
+            return Token::Error(LexError::InvalidChar(last_char));
+        }
+
         Token::Char(last_char)
     }
 }
 
+#[cfg(feature = "std")]
+impl<R: Read> Lexer<ReadChars<R>> {
+    // lex any reader, input is read in chunks and decoded as utf8
+    pub fn from_reader(reader: R) -> Self {
+        Lexer::new(ReadChars::new(reader))
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Lexer, Token};
+    use super::{LexError, Lexer, LexerConfig, Position, Span, Token};
+    use std::io::Read;
 
     #[test]
     fn test_identifier() {
@@ -111,6 +460,12 @@ mod test {
         assert_eq!(Token::Identifier("b".into()), lexer.next_token());
         assert_eq!(Token::Identifier("c".into()), lexer.next_token());
         assert_eq!(Token::Eof, lexer.next_token());
+
+        let mut lexer = Lexer::new("test_a1_ _b".chars());
+        assert_eq!(Token::Identifier("test_a1_".into()), lexer.next_token());
+        assert_eq!(Token::Char('_'), lexer.next_token());
+        assert_eq!(Token::Identifier("b".into()), lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
     }
 
     #[test]
@@ -147,6 +502,57 @@ mod test {
         assert_eq!(Token::Identifier("abc".into()), lexer.next_token());
         assert_eq!(Token::Identifier("xyz".into()), lexer.next_token());
         assert_eq!(Token::Eof, lexer.next_token());
+
+        // skipped in a loop, a long run of them doesn't recurse
+        let input = "#\n".repeat(300_000) + "xyz";
+        let mut lexer = Lexer::new(input.chars());
+        assert_eq!(Token::Identifier("xyz".into()), lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
+    }
+
+    #[test]
+    fn test_comment_tokens() {
+        let config = LexerConfig {
+            emit_comments: true,
+            ..LexerConfig::default()
+        };
+
+        let mut lexer = Lexer::with_config("abc # comment \n# last".chars(), config);
+        assert_eq!(Token::Identifier("abc".into()), lexer.next_token());
+        assert_eq!(Token::Comment(" comment ".into()), lexer.next_token());
+        assert_eq!(Token::Comment(" last".into()), lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
+
+        let config = LexerConfig {
+            max_comment_len: 3,
+            ..config
+        };
+        let mut lexer = Lexer::with_config("#abcd\nx".chars(), config);
+        assert_eq!(
+            Token::Error(LexError::CommentTooLong(3)),
+            lexer.next_token()
+        );
+        assert_eq!(Token::Identifier("x".into()), lexer.next_token());
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let mut lexer = Lexer::new("## adds\n##\n#\n# plain\ndef".chars());
+        assert_eq!(Token::DocComment(" adds".into()), lexer.next_token());
+        assert_eq!(Token::DocComment("".into()), lexer.next_token());
+        assert_eq!(Token::Def, lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
+
+        let config = LexerConfig {
+            max_comment_len: 3,
+            ..LexerConfig::default()
+        };
+        let mut lexer = Lexer::with_config("##abcd\nx".chars(), config);
+        assert_eq!(
+            Token::Error(LexError::CommentTooLong(3)),
+            lexer.next_token()
+        );
+        assert_eq!(Token::Identifier("x".into()), lexer.next_token());
     }
 
     #[test]
@@ -176,4 +582,171 @@ mod test {
         assert_eq!(Token::Identifier("c".into()), lexer.next_token());
         assert_eq!(Token::Eof, lexer.next_token());
     }
+
+    #[test]
+    fn test_token_limits() {
+        let config = LexerConfig {
+            max_identifier_len: 4,
+            max_number_len: 3,
+            ..LexerConfig::default()
+        };
+
+        let mut lexer = Lexer::with_config("abcd abcde 123 1234 x".chars(), config);
+        assert_eq!(Token::Identifier("abcd".into()), lexer.next_token());
+        assert_eq!(
+            Token::Error(LexError::IdentifierTooLong(4)),
+            lexer.next_token()
+        );
+        assert_eq!(Token::Number(123f64), lexer.next_token());
+        assert_eq!(Token::Error(LexError::NumberTooLong(3)), lexer.next_token());
+        assert_eq!(Token::Identifier("x".into()), lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
+
+        // huge identifier is not buffered
+        let input = (0..1 << 20).map(|_| 'a');
+        let mut lexer = Lexer::new(input);
+        assert_eq!(
+            Token::Error(LexError::IdentifierTooLong(1024)),
+            lexer.next_token()
+        );
+        assert_eq!(Token::Eof, lexer.next_token());
+    }
+
+    #[test]
+    fn test_token_start() {
+        let pos = |offset, line, column| Position {
+            offset,
+            line,
+            column,
+        };
+
+        let mut lexer = Lexer::new("def foo # c\n  €bar".chars());
+        assert_eq!(Token::Def, lexer.next_token());
+        assert_eq!(pos(0, 1, 1), lexer.token_start());
+        assert_eq!(Token::Identifier("foo".into()), lexer.next_token());
+        assert_eq!(pos(4, 1, 5), lexer.token_start());
+        assert_eq!(Token::Error(LexError::InvalidChar('€')), lexer.next_token());
+        assert_eq!(pos(14, 2, 3), lexer.token_start());
+        assert_eq!(Token::Identifier("bar".into()), lexer.next_token());
+        assert_eq!(pos(17, 2, 4), lexer.token_start());
+        assert_eq!(Span::new(pos(17, 2, 4), pos(20, 2, 7)), lexer.token_span());
+        assert_eq!(Token::Eof, lexer.next_token());
+        assert_eq!(Span::new(pos(20, 2, 7), pos(20, 2, 7)), lexer.token_span());
+    }
+
+    #[test]
+    fn test_invalid_chars() {
+        let mut lexer = Lexer::new("a \u{1} b € c".chars());
+        assert_eq!(Token::Identifier("a".into()), lexer.next_token());
+        assert_eq!(
+            Token::Error(LexError::InvalidChar('\u{1}')),
+            lexer.next_token()
+        );
+        assert_eq!(Token::Identifier("b".into()), lexer.next_token());
+        assert_eq!(Token::Error(LexError::InvalidChar('€')), lexer.next_token());
+        assert_eq!(Token::Identifier("c".into()), lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
+    }
+
+    // hands out a single byte per read call
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.split_first() {
+                Some((b, rest)) if !buf.is_empty() => {
+                    buf[0] = *b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let mut lexer = Lexer::from_reader("def foo(x) x + 1.5".as_bytes());
+        assert_eq!(Token::Def, lexer.next_token());
+        assert_eq!(Token::Identifier("foo".into()), lexer.next_token());
+        assert_eq!(Token::Char('('), lexer.next_token());
+        assert_eq!(Token::Identifier("x".into()), lexer.next_token());
+        assert_eq!(Token::Char(')'), lexer.next_token());
+        assert_eq!(Token::Identifier("x".into()), lexer.next_token());
+        assert_eq!(Token::Char('+'), lexer.next_token());
+        assert_eq!(Token::Number(1.5), lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
+
+        // multi byte chars split across reads, invalid utf8 is replaced
+        let mut lexer = Lexer::from_reader(Trickle("a € \u{1F600}".as_bytes()));
+        assert_eq!(Token::Identifier("a".into()), lexer.next_token());
+        assert_eq!(Token::Error(LexError::InvalidChar('€')), lexer.next_token());
+        assert_eq!(
+            Token::Error(LexError::InvalidChar('\u{1F600}')),
+            lexer.next_token()
+        );
+        assert_eq!(Token::Eof, lexer.next_token());
+
+        let mut lexer = Lexer::from_reader(&b"a \xff b \xe2\x82"[..]);
+        assert_eq!(Token::Identifier("a".into()), lexer.next_token());
+        let invalid = Token::Error(LexError::InvalidChar(char::REPLACEMENT_CHARACTER));
+        assert_eq!(invalid, lexer.next_token());
+        assert_eq!(Token::Identifier("b".into()), lexer.next_token());
+        assert_eq!(invalid, lexer.next_token());
+        assert_eq!(invalid, lexer.next_token());
+        assert_eq!(Token::Eof, lexer.next_token());
+    }
+
+    #[test]
+    fn test_token_class() {
+        let mut lexer = Lexer::new("def f(x) x < 1; extern".chars());
+        let mut classes = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            classes.push(token.class());
+            if token == Token::Eof {
+                break;
+            }
+        }
+        assert_eq!(
+            classes,
+            [
+                "keyword",
+                "identifier",
+                "punctuation",
+                "identifier",
+                "punctuation",
+                "identifier",
+                "operator",
+                "number",
+                "punctuation",
+                "keyword",
+                "eof"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_display() {
+        let mut lexer = Lexer::new("def x 1.5 ; é".chars());
+        let mut names = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            names.push(token.to_string());
+            if token == Token::Eof {
+                break;
+            }
+        }
+        assert_eq!(
+            names,
+            [
+                "'def'",
+                "identifier 'x'",
+                "number 1.5",
+                "';'",
+                "invalid character 'é'",
+                "end of input"
+            ]
+        );
+    }
 }
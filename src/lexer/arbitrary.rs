@@ -0,0 +1,129 @@
+use super::{LexError, Token};
+use ::arbitrary::{Arbitrary, Result, Unstructured};
+
+// random tokens for fuzzing and property tests
+//
+// only tokens the lexer returns for some input are generated, spelled
+// with whitespace between them they lex back the same (comments with
+// `LexerConfig::emit_comments`): identifiers are short and never
+// keywords, numbers are small and finite, chars are the ascii punctuation
+// that starts no other token, comments are words, errors are invalid
+// chars, there is no `Eof`
+
+// a short identifier that is no keyword
+pub(crate) fn identifier(u: &mut Unstructured) -> Result<String> {
+    let mut name = char::from(b'a' + u.int_in_range(0..=25)?).to_string();
+    if u.arbitrary()? {
+        name.push(char::from(b'0' + u.int_in_range(0..=9)?));
+    }
+    Ok(name)
+}
+
+// multiples of 1/8 print exactly
+pub(crate) fn number(u: &mut Unstructured) -> Result<f64> {
+    Ok(f64::from(u.arbitrary::<u16>()?) / 8.0)
+}
+
+// one to three words
+fn comment(u: &mut Unstructured) -> Result<String> {
+    let words = (0..u.int_in_range(1..=3)?)
+        .map(|_| identifier(u))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(words.join(" "))
+}
+
+impl<'a> Arbitrary<'a> for LexError {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        const INVALID: [char; 5] = ['\u{0}', '\u{1b}', '\u{7f}', 'é', '€'];
+        Ok(LexError::InvalidChar(*u.choose(&INVALID)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Token {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=7)? {
+            0 => Token::Def,
+            1 => Token::Extern,
+            2 => Token::Identifier(identifier(u)?),
+            3 => Token::Number(number(u)?),
+            4 => {
+                let chars: Vec<char> = (b'!'..=b'~')
+                    .map(char::from)
+                    .filter(|&c| c.is_ascii_punctuation() && c != '#' && c != '.')
+                    .collect();
+                Token::Char(*u.choose(&chars)?)
+            }
+            5 => Token::Comment(comment(u)?),
+            6 => Token::DocComment(comment(u)?),
+            _ => Token::Error(u.arbitrary()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fuzz::fuzz;
+    use crate::lexer::{LexError, Lexer, LexerConfig, Token};
+    use crate::parser::{Parser, ParserConfig};
+    use arbitrary::{Arbitrary, Unstructured};
+
+    // source the lexer reads back as `tokens`
+    fn spell(tokens: &[Token]) -> String {
+        let mut source = String::new();
+        for token in tokens {
+            match token {
+                Token::Def => source.push_str("def"),
+                Token::Extern => source.push_str("extern"),
+                Token::Identifier(name) => source.push_str(name),
+                Token::Number(num) => source.push_str(&num.to_string()),
+                Token::Char(c) | Token::Error(LexError::InvalidChar(c)) => source.push(*c),
+                Token::Comment(text) => source.push_str(&format!("#{}\n", text)),
+                Token::DocComment(text) => source.push_str(&format!("##{}\n", text)),
+                token => panic!("{:?} is never generated", token),
+            }
+            source.push(' ');
+        }
+        source
+    }
+
+    #[test]
+    fn fuzz_lex_round_trip() {
+        fuzz(0x2545f4914f6cdd1d, 2000, |_, random| {
+            let bytes = random.bytes(256);
+            let tokens = Vec::<Token>::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+
+            let source = spell(&tokens);
+            let config = LexerConfig {
+                emit_comments: true,
+                ..LexerConfig::default()
+            };
+            let mut lexer = Lexer::with_config(source.chars(), config);
+            let lexed: Vec<_> = std::iter::from_fn(|| Some(lexer.next_token()))
+                .take_while(|token| *token != Token::Eof)
+                .collect();
+            assert_eq!(lexed, tokens, "spelled as {:?}", source);
+        });
+    }
+
+    // what the fuzz targets in fuzz/ check, on any bytes and on token
+    // streams that get further into the parser: no panics
+    #[test]
+    fn fuzz_no_panics() {
+        fuzz(0x9e3779b97f4a7c15, 2000, |_, random| {
+            let bytes = random.bytes(256);
+            let mut lexer = Lexer::from_reader(bytes.as_slice());
+            while lexer.next_token() != Token::Eof {}
+
+            let tokens = Vec::<Token>::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            let source = spell(&tokens);
+            for source in [String::from_utf8_lossy(&bytes).as_ref(), &source] {
+                let _ = Parser::from_str(source).parse_program();
+                let config = ParserConfig {
+                    max_depth: 8,
+                    recover: true,
+                };
+                let _ = Parser::with_config(Lexer::new(source.chars()), config).parse_all();
+            }
+        });
+    }
+}
@@ -0,0 +1,292 @@
+// kaleidoscope - the compiler of klc as a library, to embed the frontend
+// or any later phase in other programs
+//
+//   let items = kaleidoscope::parse("def twice(x) x * 2;")?;
+//   let ir = kaleidoscope::compile("def twice(x) x * 2;")?;
+//
+// `lex`, `parse` and `compile` run the phases on a whole source with the
+// defaults of klc and fail with an `Error`, the error of each phase
+// converts into it with `?`, the modules below them are there to
+// configure each phase, e.g. `parser::Parser` with its limits or
+// `codegen::Codegen` with a target, the driver in main.rs is built from
+// them
+//
+// without the default feature `std` only the lexer, the parser and the
+// ast build, with `no_std` and `alloc`, e.g. for a wasm plugin host that
+// wants just the parser
+//
+// stability - new syntax and new errors add variants, so `Token`,
+// `LexError`, `ExpressionAST`, `Item`, `ParseError`, `CodegenError` and
+// `Error` are non_exhaustive and matches on them need a `_` arm, the
+// fields of `PrototypeAST` and `FunctionAST` may change and are read with
+// their accessors and built with the constructors in `ast`, variants
+// are only added, never removed or renamed
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod ast;
+#[cfg(feature = "std")]
+pub mod codegen;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "cranelift")]
+pub mod cranelift;
+pub mod diagnostic;
+#[cfg(feature = "std")]
+pub mod driver;
+#[cfg(feature = "std")]
+pub mod emit;
+// the safety requirements are `SAFETY:` comments like everywhere else
+#[cfg(feature = "ffi")]
+#[allow(clippy::missing_safety_doc)]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod fold;
+#[cfg(test)]
+mod fuzz;
+#[cfg(feature = "std")]
+pub mod incremental;
+#[cfg(feature = "std")]
+pub mod interp;
+#[cfg(feature = "std")]
+pub mod ir;
+#[cfg(feature = "std")]
+pub mod jit;
+#[cfg(feature = "std")]
+pub mod js;
+pub mod lexer;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod mangle;
+#[cfg(feature = "std")]
+pub mod mlir;
+pub mod operator;
+#[cfg(feature = "std")]
+pub mod opt;
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod query;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod source;
+#[cfg(feature = "std")]
+pub mod visit;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use ast::Item;
+#[cfg(feature = "std")]
+use codegen::{Codegen, CodegenError};
+use core::fmt;
+use lexer::{LexError, Lexer, Position, Token};
+#[cfg(feature = "std")]
+use opt::OptLevel;
+use parser::ParseError;
+
+// error of `parse` and `compile`, or of any phase
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum Error {
+    Lex(LexError),
+    // all syntax errors of a source
+    Parse(Vec<ParseError>),
+    // all errors lowering the items of a source
+    #[cfg(feature = "std")]
+    Codegen(Vec<CodegenError>),
+}
+
+// one `line:column: message` line per error
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lex(err) => write!(f, "{}", err),
+            Error::Parse(errors) => write_lines(
+                f,
+                errors
+                    .iter()
+                    .map(|err| (err.pos(), err as &dyn fmt::Display)),
+            ),
+            #[cfg(feature = "std")]
+            Error::Codegen(errors) => write_lines(
+                f,
+                errors
+                    .iter()
+                    .map(|err| (err.pos(), err as &dyn fmt::Display)),
+            ),
+        }
+    }
+}
+
+fn write_lines<'a>(
+    f: &mut fmt::Formatter,
+    errors: impl Iterator<Item = (Position, &'a dyn fmt::Display)>,
+) -> fmt::Result {
+    for (i, (pos, err)) in errors.enumerate() {
+        if i > 0 {
+            writeln!(f)?;
+        }
+        write!(f, "{}:{}: {}", pos.line, pos.column, err)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+impl From<LexError> for Error {
+    fn from(err: LexError) -> Self {
+        Error::Lex(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(vec![err])
+    }
+}
+
+impl From<Vec<ParseError>> for Error {
+    fn from(errors: Vec<ParseError>) -> Self {
+        Error::Parse(errors)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<CodegenError> for Error {
+    fn from(err: CodegenError) -> Self {
+        Error::Codegen(vec![err])
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Vec<CodegenError>> for Error {
+    fn from(errors: Vec<CodegenError>) -> Self {
+        Error::Codegen(errors)
+    }
+}
+
+// the tokens of `source` up to the end, without `Token::Eof`, malformed
+// input is a `Token::Error` among them
+pub fn lex(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(source.chars());
+    core::iter::from_fn(|| Some(lexer.next_token()))
+        .take_while(|token| *token != Token::Eof)
+        .collect()
+}
+
+// the items of `source`, or all syntax errors in it
+pub fn parse(source: &str) -> Result<Vec<Item>, Error> {
+    Ok(parser::parse_file(source)?)
+}
+
+// the llvm ir of the module `source` compiles to at the default level,
+// top-level expressions become functions named like in the repl, or all
+// errors in it
+#[cfg(feature = "std")]
+pub fn compile(source: &str) -> Result<String, Error> {
+    let items = parse(source)?;
+    let level = OptLevel::default();
+    let items = level.pipeline().run(items);
+    let mut codegen = Codegen::new();
+    codegen.set_cse(level.cse());
+    let errors: Vec<_> = codegen
+        .compile_items(&items)
+        .into_iter()
+        .filter_map(Result::err)
+        .collect();
+    match errors.is_empty() {
+        true => Ok(codegen.module()),
+        false => Err(errors.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, lex, parse, Error};
+    use crate::lexer::{LexError, Token};
+    use crate::parser::Parser;
+    use crate::{ast, codegen, interp, ir, jit, js, lint, opt, parser, query, session};
+
+    #[test]
+    fn library_api() {
+        assert_eq!(
+            lex("def f(x) # twice\n x * 2"),
+            [
+                Token::Def,
+                Token::Identifier("f".into()),
+                Token::Char('('),
+                Token::Identifier("x".into()),
+                Token::Char(')'),
+                Token::Identifier("x".into()),
+                Token::Char('*'),
+                Token::Number(2.0),
+            ]
+        );
+
+        let items = parse("def twice(x) x * 2; twice(1)").unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(matches!(parse("def (x)"), Err(Error::Parse(errors)) if errors.len() == 1));
+
+        let ir = compile("def twice(x) x * (1 + 1);").unwrap();
+        assert!(ir.contains("define double @twice(double %x)"));
+        assert!(ir.contains("fmul double %x, 0x4000000000000000"));
+        let err = compile("def f(x) y; g(1);").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:10: unknown variable 'y'\n1:13: unknown function 'g'"
+        );
+    }
+
+    #[test]
+    fn error_conversions() {
+        fn ir(source: &str) -> Result<String, Box<dyn std::error::Error>> {
+            parse(source)?;
+            Ok(compile(source)?)
+        }
+        assert!(ir("def f(x) x;").is_ok());
+        assert_eq!(
+            ir("1 + €").unwrap_err().to_string(),
+            "1:5: invalid input: invalid character '€'"
+        );
+
+        let err = Error::from(LexError::NumberTooLong(128));
+        assert_eq!(err.to_string(), "number longer than 128 bytes");
+        let err = parse("(1").unwrap_err();
+        let Error::Parse(errors) = &err else {
+            panic!("{:?}", err);
+        };
+        assert_eq!(Error::from(errors[0].clone()), err);
+    }
+
+    // what an embedder parses and compiles with moves across threads,
+    // none of it may hold an Rc or RefCell
+    #[test]
+    fn send_and_sync() {
+        fn send_sync<T: Send + Sync>() {}
+
+        send_sync::<ast::NodeId>();
+        send_sync::<ast::ExpressionAST>();
+        send_sync::<ast::PrototypeAST>();
+        send_sync::<ast::FunctionAST>();
+        send_sync::<ast::Item>();
+        send_sync::<Token>();
+        send_sync::<Error>();
+        send_sync::<Parser<core::str::Chars<'static>>>();
+        send_sync::<parser::ParseOutput>();
+        send_sync::<parser::PushParser>();
+        send_sync::<opt::Pipeline>();
+        send_sync::<codegen::Codegen>();
+        send_sync::<lint::Linter>();
+        send_sync::<interp::Interp>();
+        send_sync::<jit::Jit>();
+        send_sync::<js::Js>();
+        send_sync::<ir::Function>();
+        send_sync::<session::Session>();
+        send_sync::<query::Database>();
+    }
+}
@@ -0,0 +1,200 @@
+use crate::ast::{ExpressionAST, Item, PrototypeAST};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::lexer::Span;
+use crate::visit::{walk_expr, Visitor};
+use std::collections::HashSet;
+
+// lint - warnings about programs that compile but likely do not do what
+// was meant, each lint is allowed, warned about or denied by its name
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Lint {
+    // parameter the body of its function never uses
+    UnusedParameter,
+    // parameter named like a function defined before it, calls still
+    // go to the function
+    Shadowing,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 2] = [Lint::UnusedParameter, Lint::Shadowing];
+
+    // name of the lint on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::Shadowing => "shadowing",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Level {
+    Allow,
+    Warn,
+    // reported as an error
+    Deny,
+}
+
+// level of each lint, all warn by default
+#[derive(Debug, Clone)]
+pub struct Levels([Level; Lint::ALL.len()]);
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels([Level::Warn; Lint::ALL.len()])
+    }
+}
+
+impl Levels {
+    pub fn get(&self, lint: Lint) -> Level {
+        self.0[lint as usize]
+    }
+
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.0[lint as usize] = level;
+    }
+}
+
+// checks items in program order, remembering the functions defined so far
+#[derive(Default)]
+pub struct Linter {
+    levels: Levels,
+    functions: HashSet<String>,
+}
+
+impl Linter {
+    pub fn new(levels: Levels) -> Self {
+        Linter {
+            levels,
+            functions: HashSet::new(),
+        }
+    }
+
+    // what the lints not allowed find in `item`, with their level, as
+    // warnings or, for denied lints, errors with the name of the lint as
+    // code
+    pub fn check(&mut self, item: &Item) -> Vec<(Lint, Level, Diagnostic)> {
+        let found = self.find(item);
+        found
+            .into_iter()
+            .map(|(lint, diagnostic)| (lint, self.levels.get(lint), diagnostic))
+            .filter(|(_, level, _)| *level != Level::Allow)
+            .map(|(lint, level, mut diagnostic)| {
+                if level == Level::Deny {
+                    diagnostic.severity = Severity::Error;
+                }
+                (lint, level, diagnostic.with_code(lint.name()))
+            })
+            .collect()
+    }
+
+    // what all lints find in `item`, at the position of the prototype for
+    // problems with parameters
+    fn find(&mut self, item: &Item) -> Vec<(Lint, Diagnostic)> {
+        let mut found = Vec::new();
+        match item {
+            Item::Function(func) => {
+                self.functions.insert(func.0 .0.clone());
+                self.shadowing(&func.0, &mut found);
+                let mut used = Variables::default();
+                walk_expr(&mut used, &func.1);
+                for param in &func.0 .1 {
+                    if !used.0.contains(param) {
+                        let message = format!("unused parameter '{}' of '{}'", param, func.0 .0);
+                        found.push((Lint::UnusedParameter, diagnostic(&func.0, message)));
+                    }
+                }
+            }
+            Item::Extern(proto) => {
+                self.functions.insert(proto.0.clone());
+                self.shadowing(proto, &mut found);
+            }
+            Item::Expr(_) => {}
+        }
+        found
+    }
+
+    fn shadowing(&self, proto: &PrototypeAST, found: &mut Vec<(Lint, Diagnostic)>) {
+        for param in &proto.1 {
+            if self.functions.contains(param) {
+                let message = format!(
+                    "parameter '{}' of '{}' shadows the function '{}'",
+                    param, proto.0, param
+                );
+                found.push((Lint::Shadowing, diagnostic(proto, message)));
+            }
+        }
+    }
+}
+
+fn diagnostic(proto: &PrototypeAST, message: String) -> Diagnostic {
+    Diagnostic::warning(Span::at(proto.4.start), message)
+}
+
+// names of the variables an expression references
+#[derive(Default)]
+struct Variables(HashSet<String>);
+
+impl Visitor for Variables {
+    fn visit_expr(&mut self, expr: &ExpressionAST) {
+        if let ExpressionAST::Variable(name, ..) = expr {
+            self.0.insert(name.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Level, Levels, Lint, Linter};
+    use crate::diagnostic::Severity;
+    use crate::parser::parse_file;
+
+    #[test]
+    fn lint_items() {
+        let items =
+            parse_file("extern sin(x); def f(x y) x * 2; def g(sin) sin(sin); g(1)").unwrap();
+        let check = |levels| {
+            let mut linter = Linter::new(levels);
+            items
+                .iter()
+                .flat_map(|item| linter.check(item))
+                .map(|(lint, level, diagnostic)| (lint, level, diagnostic.pos().column))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            check(Levels::default()),
+            [
+                (Lint::UnusedParameter, Level::Warn, 20),
+                (Lint::Shadowing, Level::Warn, 38),
+            ]
+        );
+        let mut linter = Linter::default();
+        let messages: Vec<_> = items
+            .iter()
+            .flat_map(|item| linter.check(item))
+            .map(|(_, _, diagnostic)| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "unused parameter 'y' of 'f'",
+                "parameter 'sin' of 'g' shadows the function 'sin'"
+            ]
+        );
+
+        let mut levels = Levels::default();
+        levels.set(Lint::from_name("shadowing").unwrap(), Level::Deny);
+        levels.set(Lint::UnusedParameter, Level::Allow);
+        assert_eq!(check(levels.clone()), [(Lint::Shadowing, Level::Deny, 38)]);
+        let mut linter = Linter::new(levels);
+        let found: Vec<_> = items.iter().flat_map(|item| linter.check(item)).collect();
+        assert_eq!(found[0].2.severity, Severity::Error);
+        assert_eq!(found[0].2.code, Some("shadowing"));
+        assert_eq!(Lint::from_name("unused"), None);
+    }
+}
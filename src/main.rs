@@ -1,73 +1,434 @@
-mod lexer;
-mod parser;
-
-use lexer::{Lexer, Token};
-use parser::Parser;
-use std::io::Read;
-
-fn handle_definition<I>(p: &mut Parser<I>)
-where
-    I: Iterator<Item = char>,
-{
-    match p.parse_definition() {
-        Ok(expr) => println!("parse 'def'\n{:?}", expr),
-        Err(err) => {
-            eprint!("error: {:?}", err);
-            p.get_next_token();
+mod completions;
+mod config;
+mod timing;
+
+use config::Config;
+use kaleidoscope::diagnostic::TerminalEmitter;
+use kaleidoscope::driver::{self, Backend, Options};
+use kaleidoscope::emit;
+use kaleidoscope::lint::{Level, Lint};
+use kaleidoscope::mangle;
+use kaleidoscope::opt::{self, OptLevel};
+use kaleidoscope::session::{self, Session};
+
+// exit status of a bug in klc, as opposed to 1 for errors in the program
+// and 2 for usage errors
+const EXIT_INTERNAL: i32 = 3;
+
+// the options `config` sets, see `config::FILE`
+fn config_options(config: Config) -> Options {
+    let mut options = Options {
+        session: session::Options {
+            level: config.level.unwrap_or_default(),
+            ..session::Options::default()
+        },
+        backend: config.backend.unwrap_or_default(),
+        libs: config.libs,
+        entry: config.entry,
+        ..Options::default()
+    };
+    for (lint, level) in config.lints {
+        options.session.lints.set(lint, level);
+    }
+    let prompts = &mut options.prompts;
+    for (text, prompt) in [
+        (config.prompt, &mut prompts.prompt),
+        (config.continuation_prompt, &mut prompts.continuation),
+        (config.result_prefix, &mut prompts.result),
+    ] {
+        if let Some(text) = text {
+            *prompt = text;
         }
     }
+    options
 }
 
-fn handle_extern<I>(p: &mut Parser<I>)
-where
-    I: Iterator<Item = char>,
-{
-    match p.parse_extern() {
-        Ok(expr) => println!("parse 'extern'\n{:?}", expr),
+// the settings of `config::FILE` in the current directory, if there is one
+fn load_config() -> Config {
+    let text = match std::fs::read_to_string(config::FILE) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Config::default(),
         Err(err) => {
-            eprint!("error: {:?}", err);
-            p.get_next_token();
+            eprintln!("error: cannot read {}: {}", config::FILE, err);
+            std::process::exit(1);
+        }
+    };
+    config::parse(&text).unwrap_or_else(|err| {
+        eprintln!("error: {}:{}: {}", config::FILE, err.line, err.message);
+        std::process::exit(2);
+    })
+}
+
+// take the options out of `args`, on top of `options`
+fn options(args: Vec<String>, mut options: Options) -> (Options, Vec<String>) {
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if let Some(level) = OptLevel::from_flag(&arg) {
+            options.session.level = level;
+        } else if arg == "--target" {
+            options.target = Some(args.next().unwrap_or_else(|| usage()));
+        } else if let Some(kind) = arg.strip_prefix("--instrument=") {
+            match kind {
+                "profile" => options.profile = true,
+                "coverage" => options.coverage = true,
+                _ => usage(),
+            }
+        } else if arg == "-j" || arg == "--jobs" {
+            let jobs = args.next().and_then(|jobs| jobs.parse().ok());
+            options.jobs = Some(jobs.filter(|jobs| *jobs > 0).unwrap_or_else(|| usage()));
+        } else if let Some(max) = arg.strip_prefix("--max-errors=") {
+            let max = max.parse().ok().filter(|max| *max > 0);
+            options.session.max_errors = Some(max.unwrap_or_else(|| usage()));
+        } else if let Some(level) = lint_level(&arg) {
+            let name = match &arg[2..] {
+                "" => args.next().unwrap_or_else(|| usage()),
+                name => name.to_string(),
+            };
+            let lint = Lint::from_name(&name).unwrap_or_else(|| {
+                let names: Vec<_> = Lint::ALL.iter().map(|lint| lint.name()).collect();
+                eprintln!(
+                    "error: unknown lint '{}', expected {}",
+                    name,
+                    names.join(", ")
+                );
+                std::process::exit(2);
+            });
+            options.session.lints.set(lint, level);
+        } else if let Some(name) = arg.strip_prefix("--pass=") {
+            if opt::pass(name).is_none() {
+                let names = opt::pass_names().join(", ");
+                eprintln!("error: unknown pass '{}', expected {}", name, names);
+                std::process::exit(2);
+            }
+            options.session.passes.push(name.to_string());
+        } else if arg == "-v" || arg == "--verbose" {
+            options.session.verbose = true;
+        } else if arg == "--time-passes" {
+            options.session.time_passes = true;
+        } else if let Some(name) = arg.strip_prefix("--backend=") {
+            options.backend = Backend::from_name(name).unwrap_or_else(|| usage());
+        } else if let Some(lib) = arg.strip_prefix("-l") {
+            let lib = match lib {
+                "" => args.next().unwrap_or_else(|| usage()),
+                lib => lib.to_string(),
+            };
+            options.libs.push(lib);
+        } else if let Some(text) = arg.strip_prefix("--prompt=") {
+            options.prompts.prompt = text.to_string();
+        } else if let Some(text) = arg.strip_prefix("--continuation-prompt=") {
+            options.prompts.continuation = text.to_string();
+        } else if let Some(text) = arg.strip_prefix("--result-prefix=") {
+            options.prompts.result = text.to_string();
+        } else if arg == "-o" {
+            options.output = Some(args.next().unwrap_or_else(|| usage()).into());
+        } else if arg == "-e" {
+            options.cmdline.push(args.next().unwrap_or_else(|| usage()));
+        } else if arg == "--" {
+            // the arguments of the program `run` runs
+            rest.push(arg);
+            rest.extend(args.by_ref());
+        } else {
+            rest.push(arg);
         }
     }
+    (options, rest)
 }
 
-fn handle_top_level_expression<I>(p: &mut Parser<I>)
-where
-    I: Iterator<Item = char>,
-{
-    match p.parse_top_level_expr() {
-        Ok(expr) => println!("parse top-level expression\n{:?}", expr),
-        Err(err) => {
-            eprint!("error: {:?}", err);
-            p.get_next_token();
+// level of `-W <lint>`, `-A <lint>` and `-D <lint>`, also spelled
+// `-W<lint>` and so on
+fn lint_level(arg: &str) -> Option<Level> {
+    match arg.get(..2)? {
+        "-W" => Some(Level::Warn),
+        "-A" => Some(Level::Allow),
+        "-D" => Some(Level::Deny),
+        _ => None,
+    }
+}
+
+// `--cranelift ...`: the repl, `--object` and `build` with cranelift
+// instead of llvm
+#[cfg(feature = "cranelift")]
+fn cranelift_main(session: &mut Session, args: &[&str], options: &Options) {
+    if options.target.is_some() {
+        eprintln!("error: --target is not supported with --cranelift");
+        std::process::exit(2);
+    }
+    if options.profile || options.coverage {
+        eprintln!("error: --instrument is not supported with --cranelift");
+        std::process::exit(2);
+    }
+    match args {
+        [] => driver::cranelift_repl(session, options),
+        ["--object", paths @ ..] if are_paths(paths) => {
+            driver::cranelift_write_object(session, paths, options)
+        }
+        ["build", paths @ ..] if builds(paths, options) => {
+            driver::cranelift_build(session, paths, options)
         }
+        _ => usage(),
     }
 }
 
-fn main() {
-    println!("Lex stdin");
-    println!("ENTER to lex current input");
-    println!("C-c   to exit");
-    let lexer = Lexer::new(std::io::stdin().bytes().filter_map(|v| {
-        let v = v.ok()?;
-        Some(v.into())
-    }));
+const USAGE: &str = "\
+usage: klc [<file>...] | --interp [<file>...] | --tokens [<file>...]
+           | --dump-ast[=debug|json|sexpr|dot] [<file>...]
+       klc --object [<file>...] | --bitcode [<file>...]
+           | --ir [--function <name>] [<file>...] | --mlir [<file>...]
+       klc --emit=tokens|ast|ir|bc|obj|asm|exe [<file>...]
+       klc --stats | --demangle <symbol>...
+       klc build <file>...
+       klc coverage <file> [<counts>]
+       klc run [<file>...] [-- <number>...]
+       klc test [<file>...]
+       klc completions bash|zsh|fish
+       klc --cranelift [--object [<file>...] | build <file>...]
+options: -e <source>, a program on the command line, read after the files
+            instead of stdin, errors in it are at <cmdline>
+         -o <path>, where an artifact goes, by default the first file with
+            the extension of the artifact (none for executables, .wasm for
+            wasm targets, .s for asm), stdout for --ir and --mlir of stdin
+            and for -o -
+         -O0 | -O1 (default) | -O2, --target <triple> (llvm only),
+         --pass=<name>, run an ast pass after those of the level
+         -j <threads> (llvm only), --instrument=profile|coverage (llvm only)
+         --max-errors=<n>, stop at the n-th error
+         -v, log each phase of compiling with its time and output
+         --time-passes, print a table of the time and allocations of each
+            phase and optimization pass when done
+         -W <lint> | -A <lint> | -D <lint>, warn about (default), allow or
+            deny a lint: unused-parameter, shadowing
+         -l <lib>, link executables with lib, --backend=llvm|cranelift
+         --prompt=<text> (\"ready> \"), --continuation-prompt=<text>
+            (\"...> \"), prompts of the repls on a terminal, {backend} and
+            {definitions} in them are replaced, --result-prefix=<text>
+            (\"=> \"), printed before results
+kaleidoscope.toml in the current directory sets defaults for entry (the
+program to compile without files), opt-level, backend, libs, [lints] and
+[repl], options override it
+the repls on a terminal complete names with tab, :load <file> evaluates a
+file, :save <file> writes the functions and externs read so far
+exit status: 0 on success, 1 if there were errors in the program, 2 for
+             usage errors, 3 for internal errors of klc";
+
+// the commands and flags USAGE describes, for `completions`
+fn cli() -> completions::Cli {
+    use completions::{Arg, Flag};
+    let flag = |name, arg| Flag { name, arg };
+    let lints = || Lint::ALL.iter().map(|lint| lint.name()).collect();
+    let mut flags: Vec<_> = [
+        "--interp",
+        "--tokens",
+        "--dump-ast",
+        "--object",
+        "--bitcode",
+        "--ir",
+        "--mlir",
+        "--stats",
+        "--demangle",
+        "--cranelift",
+        "-O0",
+        "-O1",
+        "-O2",
+        "-v",
+        "--verbose",
+        "--time-passes",
+    ]
+    .into_iter()
+    .map(|name| flag(name, Arg::None))
+    .collect();
+    flags.extend([
+        flag(
+            "--dump-ast",
+            Arg::Equals(vec!["debug", "json", "sexpr", "dot"]),
+        ),
+        flag("--function", Arg::Next(vec![])),
+        flag(
+            "--emit",
+            Arg::Equals(vec!["tokens", "ast", "ir", "bc", "obj", "asm", "exe"]),
+        ),
+        flag("-o", Arg::Next(vec![])),
+        flag("-e", Arg::Next(vec![])),
+        flag("--target", Arg::Next(vec![])),
+        flag("-j", Arg::Next(vec![])),
+        flag("--jobs", Arg::Next(vec![])),
+        flag("--instrument", Arg::Equals(vec!["profile", "coverage"])),
+        flag("--max-errors", Arg::Equals(vec![])),
+        flag("--pass", Arg::Equals(opt::pass_names())),
+        flag("-W", Arg::Next(lints())),
+        flag("-A", Arg::Next(lints())),
+        flag("-D", Arg::Next(lints())),
+        flag("-l", Arg::Next(vec![])),
+        flag("--backend", Arg::Equals(vec!["llvm", "cranelift"])),
+        flag("--prompt", Arg::Equals(vec![])),
+        flag("--continuation-prompt", Arg::Equals(vec![])),
+        flag("--result-prefix", Arg::Equals(vec![])),
+    ]);
+    completions::Cli {
+        name: "klc",
+        commands: vec!["build", "coverage", "run", "test", "completions"],
+        flags,
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+// `--emit=<stage> [<file>...]`: the output of one stage of the pipeline,
+// the tokens through an executable, like the command of the stage would
+// write it
+fn emit_stage(session: &mut Session, stage: &str, args: &[&str], options: &Options) {
+    match stage {
+        "ir" => print_ir(session, args, options),
+        "exe" if builds(args, options) => driver::build(session, args, options),
+        _ if !are_paths(args) => usage(),
+        "tokens" => driver::print_tokens(session, args, options),
+        "ast" => driver::dump_ast(session, "debug", args, options),
+        "bc" => driver::write_module(session, args, options, "bc", emit::write_bitcode),
+        "obj" => {
+            let extension = driver::object_extension(options, "o");
+            driver::write_module(session, args, options, extension, emit::write_object)
+        }
+        "asm" => driver::write_module(session, args, options, "s", emit::write_assembly),
+        _ => usage(),
+    }
+}
 
-    let mut parser = Parser::new(lexer);
+// `--ir [--function <name>] [<file>...]`
+fn print_ir(session: &mut Session, args: &[&str], options: &Options) {
+    let (function, paths) = match args {
+        ["--function", name, paths @ ..] => (Some(*name), paths),
+        paths => (None, paths),
+    };
+    if !are_paths(paths) {
+        usage();
+    }
+    driver::print_ir(session, function, paths, options)
+}
 
-    // throw first coin & init cur_token
-    parser.get_next_token();
+// `run [<file>...] [-- <number>...]`
+fn run(session: &mut Session, args: &[&str], options: &Options) {
+    let (paths, program_args) = match args.iter().position(|arg| *arg == "--") {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &[][..]),
+    };
+    if !are_paths(paths) {
+        usage();
+    }
+    let values = program_args
+        .iter()
+        .map(|arg| {
+            arg.parse().unwrap_or_else(|_| {
+                eprintln!("error: argument '{}' is not a number", arg);
+                std::process::exit(2);
+            })
+        })
+        .collect();
+    driver::run(session, paths, values, options)
+}
 
-    loop {
-        match *parser.cur_token() {
-            Token::Eof => break,
-            Token::Char(';') => {
-                // ignore top level exp
-                parser.get_next_token();
+fn main() {
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("internal error: {}", info);
+        eprintln!("this is a bug in klc, please report it with the input that caused it");
+        std::process::exit(EXIT_INTERNAL);
+    }));
+    let config = config_options(load_config());
+    let (mut options, args) = options(std::env::args().skip(1).collect(), config);
+    options.session.allocations = Some(timing::allocations);
+    #[cfg(not(feature = "cranelift"))]
+    if options.backend == Backend::Cranelift {
+        eprintln!("error: klc is built without the cranelift backend");
+        std::process::exit(2);
+    }
+    let emitter = TerminalEmitter::new(std::io::stderr());
+    let session = &mut Session::new(options.session.clone(), emitter);
+    if let Some(jobs) = options.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .expect("the thread pool is built once");
+    }
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["--stats"] => driver::print_stats(session, &options),
+        ["--tokens", paths @ ..] if are_paths(paths) => {
+            driver::print_tokens(session, paths, &options)
+        }
+        [dump, paths @ ..] if dump_format(dump).is_some() && are_paths(paths) => {
+            driver::dump_ast(session, dump_format(dump).unwrap(), paths, &options)
+        }
+        ["--interp", paths @ ..] if are_paths(paths) => {
+            driver::interp_repl(session, paths, &options)
+        }
+        ["--object", paths @ ..] if are_paths(paths) => {
+            let extension = driver::object_extension(&options, "o");
+            driver::write_module(session, paths, &options, extension, emit::write_object)
+        }
+        ["--bitcode", paths @ ..] if are_paths(paths) => {
+            driver::write_module(session, paths, &options, "bc", emit::write_bitcode)
+        }
+        ["--ir", rest @ ..] => print_ir(session, rest, &options),
+        [emit, rest @ ..] if emit.starts_with("--emit=") => {
+            emit_stage(session, &emit["--emit=".len()..], rest, &options)
+        }
+        ["--mlir", paths @ ..] if are_paths(paths) => driver::print_mlir(session, paths, &options),
+        ["--demangle", symbols @ ..] if !symbols.is_empty() => {
+            for symbol in symbols {
+                println!("{}", mangle::demangle_name(symbol));
             }
-            Token::Def => handle_definition(&mut parser),
-            Token::Extern => handle_extern(&mut parser),
-            _ => handle_top_level_expression(&mut parser),
         }
+        ["build", paths @ ..] if builds(paths, &options) => driver::build(session, paths, &options),
+        ["build", ..] => usage(),
+        ["coverage", source] => driver::print_coverage(source, "klc.coverage"),
+        ["coverage", source, counts] => driver::print_coverage(source, counts),
+        ["run", rest @ ..] => run(session, rest, &options),
+        ["test", paths @ ..] if are_paths(paths) => driver::run_tests(session, paths, &options),
+        ["completions", shell] => match completions::script(&cli(), shell) {
+            Some(script) => print!("{}", script),
+            None => {
+                let shells = completions::SHELLS.join(", ");
+                eprintln!("error: unknown shell '{}', expected {}", shell, shells);
+                std::process::exit(2);
+            }
+        },
+        #[cfg(feature = "cranelift")]
+        ["--cranelift", rest @ ..] => cranelift_main(session, rest, &options),
+        #[cfg(feature = "cranelift")]
+        args if options.backend == Backend::Cranelift => cranelift_main(session, args, &options),
+        paths if are_paths(paths) => driver::repl(session, paths, &options),
+        _ => usage(),
     }
+    if session.options.time_passes {
+        eprint!("{}", timing::table(&session.times.take()));
+    }
+    if session.errors() > 0 {
+        std::process::exit(1);
+    }
+}
+
+// format of `--dump-ast[=<format>]`, debug by default
+fn dump_format(arg: &str) -> Option<&str> {
+    match arg.strip_prefix("--dump-ast")? {
+        "" => Some("debug"),
+        format => format
+            .strip_prefix('=')
+            .filter(|format| ["debug", "json", "sexpr", "dot"].contains(format)),
+    }
+}
+
+// the sources of `build`, files or the entry of the config
+fn builds(paths: &[&str], options: &Options) -> bool {
+    are_paths(paths) && !driver::program(paths, options).is_empty()
+}
+
+// no options or commands
+fn are_paths(args: &[&str]) -> bool {
+    args.iter().all(|arg| !arg.starts_with('-'))
 }
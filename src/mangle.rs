@@ -0,0 +1,109 @@
+// mangle - names of the symbols the compiler generates
+//
+//   symbol := name                 functions and externs, as written, so
+//                                  they link against c
+//           | "_KE" n "_"          top-level expression n
+//           | "_KB" hex "_"        binary operator definition, the code
+//           | "_KU" hex "_"        unary operator definition   point in hex
+//           | "_KA" arity "_" name overload of a function by arity
+//
+// kaleidoscope identifiers never start with an underscore, so the
+// generated symbols cannot clash with user functions
+
+pub fn anon_expr(n: usize) -> String {
+    format!("_KE{}_", n)
+}
+
+pub fn is_anon_expr(symbol: &str) -> bool {
+    matches!(demangle(symbol), Some(Symbol::AnonExpr(_)))
+}
+
+pub fn binary_op(op: char) -> String {
+    format!("_KB{:x}_", op as u32)
+}
+
+pub fn unary_op(op: char) -> String {
+    format!("_KU{:x}_", op as u32)
+}
+
+pub fn overload(name: &str, arity: usize) -> String {
+    format!("_KA{}_{}", arity, name)
+}
+
+// what a generated symbol stands for
+#[derive(Debug, PartialEq, Clone)]
+pub enum Symbol {
+    AnonExpr(usize),
+    BinaryOp(char),
+    UnaryOp(char),
+    Overload(String, usize),
+}
+
+// None if `symbol` is no generated symbol
+pub fn demangle(symbol: &str) -> Option<Symbol> {
+    let rest = symbol.strip_prefix("_K")?;
+    let kind = rest.chars().next()?;
+    let (number, name) = rest[1..].split_once('_')?;
+    let op = || {
+        let code = u32::from_str_radix(number, 16).ok()?;
+        char::from_u32(code).filter(|_| name.is_empty())
+    };
+    match kind {
+        'E' if name.is_empty() => number.parse().ok().map(Symbol::AnonExpr),
+        'B' => op().map(Symbol::BinaryOp),
+        'U' => op().map(Symbol::UnaryOp),
+        'A' if !name.is_empty() => {
+            let arity = number.parse().ok()?;
+            Some(Symbol::Overload(name.into(), arity))
+        }
+        _ => None,
+    }
+}
+
+// `symbol` readable, unchanged if it is no generated symbol
+pub fn demangle_name(symbol: &str) -> String {
+    match demangle(symbol) {
+        Some(Symbol::AnonExpr(n)) => format!("top-level expression {}", n),
+        Some(Symbol::BinaryOp(op)) => format!("binary{}", op),
+        Some(Symbol::UnaryOp(op)) => format!("unary{}", op),
+        Some(Symbol::Overload(name, arity)) => format!("{}/{}", name, arity),
+        None => symbol.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{anon_expr, binary_op, demangle, demangle_name, overload, unary_op, Symbol};
+
+    #[test]
+    fn mangle_round_trip() {
+        let symbols = [
+            (anon_expr(12), Symbol::AnonExpr(12)),
+            (binary_op('|'), Symbol::BinaryOp('|')),
+            (unary_op('!'), Symbol::UnaryOp('!')),
+            (overload("f", 2), Symbol::Overload("f".into(), 2)),
+        ];
+        for (symbol, expected) in symbols {
+            assert_eq!(demangle(&symbol), Some(expected));
+        }
+        assert_eq!(binary_op('|'), "_KB7c_");
+
+        let names = ["_KE0_", "_KB7c_", "_KU21_", "_KA2_f", "printd"].map(demangle_name);
+        assert_eq!(
+            names,
+            [
+                "top-level expression 0",
+                "binary|",
+                "unary!",
+                "f/2",
+                "printd"
+            ]
+        );
+
+        for symbol in [
+            "_K", "_KE", "_KEx_", "_KE1_f", "_KBzz_", "_KA1_", "_KX1_", "f",
+        ] {
+            assert_eq!(demangle(symbol), None, "{}", symbol);
+        }
+    }
+}
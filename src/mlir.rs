@@ -0,0 +1,131 @@
+use crate::codegen::Codegen;
+use crate::ir::{BinaryOp, Cond, Function, Inst, Terminator, Value};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// mlir - experimental backend writing the module as mlir with the func
+// and arith dialects, e.g. for `mlir-opt` and friends
+//
+// it translates the ir codegen keeps for each definition, so the module
+// is checked and optimized the same way, parameters keep their names,
+// other values are named by their number in the ir
+
+// the whole module, externs as private declarations
+pub fn module(codegen: &Codegen) -> String {
+    let mut out = String::from("module {\n");
+    for (name, arity) in codegen.externs() {
+        let params = vec!["f64"; arity].join(", ");
+        writeln!(out, "  func.func private @{}({}) -> f64", name, params).unwrap();
+    }
+    for func in codegen.bodies() {
+        for line in function(func).lines() {
+            writeln!(out, "  {}", line).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+pub fn function(func: &Function) -> String {
+    let params: Vec<_> = func.params.iter().map(|p| format!("%{}: f64", p)).collect();
+    let mut out = format!(
+        "func.func @{}({}) -> f64 {{\n",
+        func.name,
+        params.join(", ")
+    );
+    let mut operands = HashMap::new();
+    let operand = |operands: &HashMap<Value, String>, value: &Value| operands[value].clone();
+    // a body is a single block so far
+    let block = &func.blocks[0];
+    for value in &block.insts {
+        let op = match func.inst(*value) {
+            Inst::Param(i) => {
+                operands.insert(*value, format!("%{}", func.params[*i]));
+                continue;
+            }
+            // the bits in hex, exact for every double
+            Inst::Const(num) => format!("arith.constant 0x{:016X} : f64", num.to_bits()),
+            Inst::Neg(operand_value) => {
+                format!("arith.negf {} : f64", operand(&operands, operand_value))
+            }
+            Inst::Binary(op, lhs, rhs) => {
+                let op = match op {
+                    BinaryOp::Add => "addf",
+                    BinaryOp::Sub => "subf",
+                    BinaryOp::Mul => "mulf",
+                };
+                let (lhs, rhs) = (operand(&operands, lhs), operand(&operands, rhs));
+                format!("arith.{} {}, {} : f64", op, lhs, rhs)
+            }
+            Inst::Cmp(cond, lhs, rhs) => {
+                let cond = match cond {
+                    Cond::Eq => "oeq",
+                    Cond::Ult => "ult",
+                };
+                let (lhs, rhs) = (operand(&operands, lhs), operand(&operands, rhs));
+                format!("arith.cmpf {}, {}, {} : f64", cond, lhs, rhs)
+            }
+            Inst::BoolToF64(cond) => {
+                format!("arith.uitofp {} : i1 to f64", operand(&operands, cond))
+            }
+            Inst::Call(callee, args) => {
+                let args: Vec<_> = args.iter().map(|arg| operand(&operands, arg)).collect();
+                let types = vec!["f64"; args.len()].join(", ");
+                format!(
+                    "func.call @{}({}) : ({}) -> f64",
+                    callee,
+                    args.join(", "),
+                    types
+                )
+            }
+        };
+        let name = format!("%{}", value.0);
+        writeln!(out, "  {} = {}", name, op).unwrap();
+        operands.insert(*value, name);
+    }
+    let Terminator::Return(ret) = &block.terminator;
+    writeln!(out, "  func.return {} : f64\n}}", operand(&operands, ret)).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::module;
+    use crate::codegen::Codegen;
+    use crate::parser::parse_file;
+
+    #[test]
+    fn mlir_module() {
+        let mut codegen = Codegen::new();
+        let source = "extern sin(x); def f(a b) a * sin(b) + 1 < -a; f(!0, 2)";
+        for item in parse_file(source).unwrap() {
+            codegen.compile_item(&item).unwrap();
+        }
+        assert_eq!(
+            module(&codegen),
+            "module {
+  func.func private @sin(f64) -> f64
+  func.func @f(%a: f64, %b: f64) -> f64 {
+    %2 = func.call @sin(%b) : (f64) -> f64
+    %3 = arith.mulf %a, %2 : f64
+    %4 = arith.constant 0x3FF0000000000000 : f64
+    %5 = arith.addf %3, %4 : f64
+    %6 = arith.negf %a : f64
+    %7 = arith.cmpf ult, %5, %6 : f64
+    %8 = arith.uitofp %7 : i1 to f64
+    func.return %8 : f64
+  }
+  func.func @_KE0_() -> f64 {
+    %0 = arith.constant 0x0000000000000000 : f64
+    %1 = arith.constant 0x0000000000000000 : f64
+    %2 = arith.cmpf oeq, %0, %1 : f64
+    %3 = arith.uitofp %2 : i1 to f64
+    %4 = arith.constant 0x4000000000000000 : f64
+    %5 = func.call @f(%3, %4) : (f64, f64) -> f64
+    func.return %5 : f64
+  }
+}
+"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+// associativity of a binary operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,  // a - b - c == (a - b) - c
+    Right, // a = b = c == a = (b = c)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinaryOp {
+    pub precedence: isize,
+    pub assoc: Assoc,
+}
+
+// binary and prefix (unary) operators known to the parser, can be
+// extended at runtime
+// operators are single ascii chars, the lexer reports anything else as error
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorTable {
+    binary: [Option<BinaryOp>; 128],
+    unary: [bool; 128],
+}
+
+impl OperatorTable {
+    // table without any operators
+    pub fn empty() -> Self {
+        OperatorTable {
+            binary: [None; 128],
+            unary: [false; 128],
+        }
+    }
+
+    // register (or redefine) binary operator `op`
+    // panics if `op` is not ascii or `precedence` is negative
+    pub fn insert(&mut self, op: char, precedence: isize, assoc: Assoc) {
+        assert!(op.is_ascii(), "operator must be an ascii char");
+        assert!(precedence >= 0, "precedence must not be negative");
+        self.binary[op as usize] = Some(BinaryOp { precedence, assoc });
+    }
+
+    pub fn remove(&mut self, op: char) -> Option<BinaryOp> {
+        self.binary.get_mut(op as usize)?.take()
+    }
+
+    pub fn get(&self, op: char) -> Option<BinaryOp> {
+        *self.binary.get(op as usize)?
+    }
+
+    // precedence of binary operator `op`, -1 if `op` is not one
+    pub fn precedence(&self, op: char) -> isize {
+        self.get(op).map_or(-1, |op| op.precedence)
+    }
+
+    // register prefix operator `op`, it binds tighter than any binary one
+    // panics if `op` is not ascii
+    pub fn insert_unary(&mut self, op: char) {
+        assert!(op.is_ascii(), "operator must be an ascii char");
+        self.unary[op as usize] = true;
+    }
+
+    pub fn remove_unary(&mut self, op: char) -> bool {
+        self.unary.get_mut(op as usize).is_some_and(core::mem::take)
+    }
+
+    pub fn is_unary(&self, op: char) -> bool {
+        self.unary.get(op as usize).copied().unwrap_or(false)
+    }
+}
+
+// the binary operators of the kaleidoscope tutorial, negation and not
+impl Default for OperatorTable {
+    fn default() -> Self {
+        let mut ops = OperatorTable::empty();
+        ops.insert('<', 10, Assoc::Left);
+        ops.insert('+', 20, Assoc::Left);
+        ops.insert('-', 20, Assoc::Left);
+        ops.insert('*', 40, Assoc::Left);
+        ops.insert_unary('-');
+        ops.insert_unary('!');
+        ops
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Assoc, BinaryOp, OperatorTable};
+
+    #[test]
+    fn operator_table() {
+        let mut ops = OperatorTable::default();
+        assert_eq!(ops.precedence('+'), 20);
+        assert_eq!(ops.precedence('/'), -1);
+        assert_eq!(ops.precedence('€'), -1);
+
+        ops.insert('/', 40, Assoc::Left);
+        ops.insert('+', 30, Assoc::Right);
+        assert_eq!(ops.precedence('/'), 40);
+        assert_eq!(
+            ops.get('+'),
+            Some(BinaryOp {
+                precedence: 30,
+                assoc: Assoc::Right
+            })
+        );
+
+        assert!(ops.remove('<').is_some());
+        assert_eq!(ops.get('<'), None);
+        assert_eq!(ops.remove('€'), None);
+
+        assert!(ops.is_unary('-') && ops.is_unary('!'));
+        assert!(!ops.is_unary('+') && !ops.is_unary('€'));
+        ops.insert_unary('~');
+        assert!(ops.is_unary('~'));
+        assert!(ops.remove_unary('~'));
+        assert!(!ops.remove_unary('~'));
+        assert!(!ops.remove_unary('€'));
+    }
+}
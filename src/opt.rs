@@ -0,0 +1,240 @@
+// opt - optimization passes over the ast, run before any backend
+//
+// a pass takes the whole program so it can look across functions,
+// a `Pipeline` runs passes in the order they were added
+//
+// the driver picks passes by `OptLevel`, in the order they run:
+//   -O0  none
+//   -O1  const-fold on the ast, dce of built executables (the default)
+//   -O2  const-fold on the ast, cse in codegen, dce of built executables
+// cse and dce work on the module as codegen builds it, see
+// `Codegen::set_cse` and `Codegen::remove_unreferenced`, there is no
+// inliner yet and no mutable locals for mem2reg to promote
+//
+// passes of the ast can be added without touching the levels: `register`
+// one by name and `--pass=<name>` runs it after those of the level
+mod const_fold;
+
+use crate::ast::Item;
+use std::sync::Mutex;
+
+pub use const_fold::ConstFold;
+
+// Send + Sync like the rest of a session, pipelines move across threads
+pub trait Pass: Send + Sync {
+    // short name, e.g. for reports of what ran
+    fn name(&self) -> &'static str;
+
+    fn run(&mut self, items: Vec<Item>) -> Vec<Item>;
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Pipeline {
+    // no passes, runs the program through unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(self, pass: impl Pass + 'static) -> Self {
+        self.with_boxed(Box::new(pass))
+    }
+
+    pub fn with_boxed(mut self, pass: Box<dyn Pass>) -> Self {
+        self.passes.push(pass);
+        self
+    }
+
+    // names of the passes in the order they run
+    pub fn passes(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn run(&mut self, items: Vec<Item>) -> Vec<Item> {
+        self.run_with(items, |pass, items| pass.run(items))
+    }
+
+    // `run` with each pass called through `wrap`, which gets the pass and
+    // its input, e.g. to time the passes
+    pub fn run_with(
+        &mut self,
+        items: Vec<Item>,
+        mut wrap: impl FnMut(&mut dyn Pass, Vec<Item>) -> Vec<Item>,
+    ) -> Vec<Item> {
+        self.passes
+            .iter_mut()
+            .fold(items, |items, pass| wrap(pass.as_mut(), items))
+    }
+}
+
+// a pass made of a function, for passes that keep nothing between runs
+pub struct FnPass<F> {
+    pub name: &'static str,
+    pub f: F,
+}
+
+impl<F: FnMut(Vec<Item>) -> Vec<Item> + Send + Sync> Pass for FnPass<F> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&mut self, items: Vec<Item>) -> Vec<Item> {
+        (self.f)(items)
+    }
+}
+
+// makes a new pass for each pipeline
+type MakePass = Box<dyn Fn() -> Box<dyn Pass> + Send>;
+
+// passes `register`ed, in the order they were
+static REGISTRY: Mutex<Vec<(&'static str, MakePass)>> = Mutex::new(Vec::new());
+
+// make `pass` find the pass `make` returns by `name`, replaces a pass
+// registered before under the name, built-in passes cannot be replaced
+pub fn register(name: &'static str, make: impl Fn() -> Box<dyn Pass> + Send + 'static) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|(known, _)| *known != name);
+    registry.push((name, Box::new(make)));
+}
+
+// a new pass named `name`, built in or registered
+pub fn pass(name: &str) -> Option<Box<dyn Pass>> {
+    if name == ConstFold.name() {
+        return Some(Box::new(ConstFold));
+    }
+    let registry = REGISTRY.lock().unwrap();
+    let (_, make) = registry.iter().find(|(known, _)| *known == name)?;
+    Some(make())
+}
+
+// names `pass` knows, the built-in passes first
+pub fn pass_names() -> Vec<&'static str> {
+    let registry = REGISTRY.lock().unwrap();
+    let registered = registry.iter().map(|(name, _)| *name);
+    [ConstFold.name()].into_iter().chain(registered).collect()
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OptLevel {
+    O0,
+    #[default]
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    // level of a `-O<n>` flag
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+
+    // passes over the ast, run before codegen
+    pub fn pipeline(self) -> Pipeline {
+        match self {
+            OptLevel::O0 => Pipeline::new(),
+            OptLevel::O1 | OptLevel::O2 => Pipeline::new().with(ConstFold),
+        }
+    }
+
+    pub fn cse(self) -> bool {
+        self == OptLevel::O2
+    }
+
+    pub fn dce(self) -> bool {
+        self != OptLevel::O0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pass, pass_names, register, ConstFold, FnPass, OptLevel, Pass, Pipeline};
+    use crate::ast::Item;
+    use crate::parser::parse_file;
+
+    // drops top-level expressions
+    struct NoExprs;
+
+    impl Pass for NoExprs {
+        fn name(&self) -> &'static str {
+            "no-exprs"
+        }
+
+        fn run(&mut self, items: Vec<Item>) -> Vec<Item> {
+            items
+                .into_iter()
+                .filter(|item| !matches!(item, Item::Expr(_)))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn pipeline_order() {
+        let mut pipeline = Pipeline::new().with(ConstFold).with(NoExprs);
+        assert_eq!(pipeline.passes(), ["const-fold", "no-exprs"]);
+
+        let items = parse_file("def f(x) 1 + 2 + x; 3 * 4").unwrap();
+        let items: Vec<_> = pipeline.run(items).iter().map(Item::to_string).collect();
+        assert_eq!(items, ["def f(x) 3 + x"]);
+
+        let items = parse_file("1 + 2").unwrap();
+        assert_eq!(Pipeline::new().run(items.clone()), items);
+
+        let mut ran = Vec::new();
+        let items = pipeline.run_with(items, |pass, items| {
+            ran.push((pass.name(), items.len()));
+            pass.run(items)
+        });
+        assert_eq!(ran, [("const-fold", 1), ("no-exprs", 1)]);
+        assert_eq!(items, []);
+    }
+
+    #[test]
+    fn registered_passes() {
+        register("no-exprs", || Box::new(NoExprs));
+        register("reverse", || {
+            Box::new(FnPass {
+                name: "reverse",
+                f: |items: Vec<Item>| items.into_iter().rev().collect(),
+            })
+        });
+        assert!(pass("nope").is_none());
+        assert!(pass_names().starts_with(&["const-fold"]));
+        assert!(pass_names().contains(&"no-exprs"));
+
+        let mut pipeline = ["reverse", "const-fold", "no-exprs"]
+            .into_iter()
+            .fold(Pipeline::new(), |pipeline, name| {
+                pipeline.with_boxed(pass(name).unwrap())
+            });
+        assert_eq!(pipeline.passes(), ["reverse", "const-fold", "no-exprs"]);
+        let items = parse_file("def f(x) 1 + x; 2 * 3; def g(y) y - 1").unwrap();
+        let items: Vec<_> = pipeline.run(items).iter().map(Item::to_string).collect();
+        assert_eq!(items, ["def g(y) y - 1", "def f(x) 1 + x"]);
+    }
+
+    #[test]
+    fn opt_levels() {
+        let levels = ["-O0", "-O1", "-O2"].map(|flag| OptLevel::from_flag(flag).unwrap());
+        assert_eq!(levels, [OptLevel::O0, OptLevel::O1, OptLevel::O2]);
+        assert_eq!(OptLevel::from_flag("-O3"), None);
+        assert_eq!(OptLevel::default(), OptLevel::O1);
+
+        let passes = levels.map(|level| (level.pipeline().passes(), level.cse(), level.dce()));
+        assert_eq!(
+            passes,
+            [
+                (vec![], false, false),
+                (vec!["const-fold"], false, true),
+                (vec!["const-fold"], true, true),
+            ]
+        );
+    }
+}
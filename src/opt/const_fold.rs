@@ -0,0 +1,94 @@
+use super::Pass;
+use crate::ast::{ExpressionAST, Item};
+use crate::fold::Fold;
+
+// constant folding - evaluate operators on number literals at compile
+// time, `2 * 3 + x` becomes `6 + x`
+// only the builtin operators fold, with the semantics the backends give
+// them, operands are never reordered as that changes rounding
+pub struct ConstFold;
+
+impl Fold for ConstFold {
+    fn fold_expr(&mut self, expr: ExpressionAST) -> ExpressionAST {
+        let value = match &expr {
+            ExpressionAST::Unary(op, operand, ..) => match (op, number(operand)) {
+                ('-', Some(value)) => -value,
+                ('!', Some(value)) => bool(value == 0.0),
+                _ => return expr,
+            },
+            ExpressionAST::Binary(op, lhs, rhs, ..) => match (op, number(lhs), number(rhs)) {
+                ('+', Some(lhs), Some(rhs)) => lhs + rhs,
+                ('-', Some(lhs), Some(rhs)) => lhs - rhs,
+                ('*', Some(lhs), Some(rhs)) => lhs * rhs,
+                // unordered counts as less, like `fcmp ult`
+                ('<', Some(lhs), Some(rhs)) => bool(lhs < rhs || lhs.is_nan() || rhs.is_nan()),
+                _ => return expr,
+            },
+            _ => return expr,
+        };
+        match expr {
+            ExpressionAST::Unary(_, _, id, span) | ExpressionAST::Binary(_, _, _, id, span) => {
+                ExpressionAST::Number(value, id, span)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Pass for ConstFold {
+    fn name(&self) -> &'static str {
+        "const-fold"
+    }
+
+    fn run(&mut self, items: Vec<Item>) -> Vec<Item> {
+        items.into_iter().map(|item| self.fold_item(item)).collect()
+    }
+}
+
+fn number(expr: &ExpressionAST) -> Option<f64> {
+    match expr {
+        ExpressionAST::Number(value, ..) => Some(*value),
+        _ => None,
+    }
+}
+
+fn bool(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConstFold;
+    use crate::fold::Fold;
+    use crate::parser::parse_file;
+
+    // before and after folding, as printed
+    const GOLDEN: &[(&str, &str)] = &[
+        ("2 * 3 + x", "6 + x"),
+        ("x + 2 * 3", "x + 6"),
+        // no reassociation
+        ("1 + x + 2", "1 + x + 2"),
+        ("-(1 + 2) * -x", "-3 * -x"),
+        ("!0 + !2", "1"),
+        ("1 < 2; 2 < 1", "1; 0"),
+        ("f(1 + 1, x * (2 - 2))", "f(2, x * 0)"),
+        ("def f(x) x * (4 - 1)", "def f(x) x * 3"),
+        ("extern g(x)", "extern g(x)"),
+    ];
+
+    #[test]
+    fn const_fold_golden() {
+        for (before, after) in GOLDEN {
+            let folded: Vec<_> = parse_file(before)
+                .unwrap()
+                .into_iter()
+                .map(|item| ConstFold.fold_item(item).to_string())
+                .collect();
+            assert_eq!(folded.join("; "), *after, "folding {:?}", before);
+        }
+    }
+}
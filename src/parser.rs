@@ -1,31 +1,117 @@
-use crate::lexer::{Lexer, Token};
-
-#[derive(Debug, PartialEq)]
-pub enum ExpressionAST {
-    // number - expression class for numeric literals
-    Number(f64),
+use crate::ast::{ExpressionAST, FunctionAST, Item, NodeId, PrototypeAST};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{LexError, Lexer, Position, Span, Token};
+use crate::operator::{Assoc, BinaryOp, OperatorTable};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+mod push;
+
+pub use push::PushParser;
+
+// parse error - each kind carries the position of the offending token
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum ParseError {
+    // `found` where the grammar requires `expected`
+    UnexpectedToken {
+        found: Token,
+        expected: &'static str,
+        pos: Position,
+    },
+
+    // '(' expression not closed by ')', `open` is where the '(' is
+    UnterminatedParen {
+        found: Token,
+        open: Position,
+        pos: Position,
+    },
+
+    // lexer reported malformed input
+    Lex {
+        error: LexError,
+        pos: Position,
+    },
+
+    // expressions nested deeper than `ParserConfig::max_depth`
+    TooDeeplyNested {
+        limit: usize,
+        pos: Position,
+    },
+}
 
-    // variable - expression class for referencing a variable
-    Variable(String),
+impl ParseError {
+    pub fn pos(&self) -> Position {
+        match self {
+            ParseError::UnexpectedToken { pos, .. }
+            | ParseError::UnterminatedParen { pos, .. }
+            | ParseError::Lex { pos, .. }
+            | ParseError::TooDeeplyNested { pos, .. } => *pos,
+        }
+    }
+}
 
-    // binary - expression class for binary operator
-    Binary(char, Box<ExpressionAST>, Box<ExpressionAST>),
+// the message, without the position
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken {
+                found, expected, ..
+            } => write!(f, "expected {}, found {}", expected, found),
+            ParseError::UnterminatedParen { found, .. } => {
+                write!(f, "expected ')' to close '(', found {}", found)
+            }
+            ParseError::Lex { error, .. } => write!(f, "invalid input: {}", error),
+            ParseError::TooDeeplyNested { limit, .. } => {
+                write!(f, "expression too deeply nested (limit {})", limit)
+            }
+        }
+    }
+}
 
-    // call - expression class for function calls
-    Call(String, Vec<ExpressionAST>),
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Lex { error, .. } => Some(error),
+            _ => None,
+        }
+    }
 }
 
-// PrototypeAST - represents the "prototype" for a function
-// captures - names and argument names
-#[derive(Debug, PartialEq)]
-pub struct PrototypeAST(String, Vec<String>);
+// output of parsing a whole input with error recovery
+#[derive(Debug, Default, PartialEq)]
+pub struct ParseOutput {
+    pub items: Vec<Item>,
+    pub diagnostics: Vec<Diagnostic>,
+}
 
-// FunctionAST - represent function definition
-#[derive(Debug, PartialEq)]
-pub struct FunctionAST(PrototypeAST, ExpressionAST);
+// parse result - ParseError as err type
+pub type ParseResult<T> = Result<T, ParseError>;
+
+// parser limits and error handling
+#[derive(Clone, Copy, Debug)]
+pub struct ParserConfig {
+    // max nesting of expressions (parentheses, call arguments, prefix
+    // operators), bounds the recursion of the parser so hostile input
+    // can't overflow the stack, operator chains are flat and don't count
+    pub max_depth: usize,
+    // leave `ExpressionAST::Error` holes for malformed expressions and
+    // items instead of dropping them, errors are still reported
+    pub recover: bool,
+}
 
-// parse result - string as err type
-type ParseResult<T> = Result<T, String>;
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            max_depth: 256,
+            recover: false,
+        }
+    }
+}
 
 // parser
 pub struct Parser<I>
@@ -34,6 +120,15 @@ where
 {
     lexer: Lexer<I>,
     cur_token: Option<Token>,
+    cur_span: Span,
+    cur_doc: Option<String>, // doc comment preceding cur token
+    prev_end: Position,      // end of the last eaten token
+    lookahead: VecDeque<(Token, Span, Option<String>)>, // tokens lexed past cur token
+    config: ParserConfig,
+    depth: usize, // current expression nesting
+    operators: OperatorTable,
+    next_id: u32,
+    errors: Vec<ParseError>, // recovered from, not yet taken
 }
 
 impl<I> Parser<I>
@@ -41,10 +136,52 @@ where
     I: Iterator<Item = char>,
 {
     pub fn new(lexer: Lexer<I>) -> Self {
-        Parser {
+        Self::with_config(lexer, ParserConfig::default())
+    }
+
+    // the parser reads the first token right away (throws the first coin)
+    pub fn with_config(lexer: Lexer<I>, config: ParserConfig) -> Self {
+        let mut parser = Parser {
             lexer,
             cur_token: None,
-        }
+            cur_span: Span::default(),
+            cur_doc: None,
+            prev_end: Position::default(),
+            lookahead: VecDeque::new(),
+            config,
+            depth: 0,
+            operators: OperatorTable::default(),
+            next_id: 0,
+            errors: Vec::new(),
+        };
+        parser.get_next_token();
+        parser
+    }
+
+    // binary operators recognized by the parser
+    pub fn operators(&self) -> &OperatorTable {
+        &self.operators
+    }
+
+    pub fn operators_mut(&mut self) -> &mut OperatorTable {
+        &mut self.operators
+    }
+
+    // errors recovered from (see ParserConfig::recover) since the last call
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        core::mem::take(&mut self.errors)
+    }
+
+    // ids handed out from here on start at `first`
+    pub(crate) fn set_next_id(&mut self, first: u32) {
+        self.next_id = first;
+    }
+
+    // fresh id for the node being built
+    fn node_id(&mut self) -> NodeId {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        id
     }
 
     // --------------------
@@ -52,16 +189,102 @@ where
     // --------------------
 
     // impl global var `int CurToken`
-    // panics if parser does NOT have cur token
+    // cur token is only missing while it is taken apart, reads as eof then
     pub fn cur_token(&self) -> &Token {
-        self.cur_token
-            .as_ref()
-            .expect("Parser: Expected cur_token!")
+        self.cur_token.as_ref().unwrap_or(&Token::Eof)
+    }
+
+    pub fn cur_span(&self) -> Span {
+        self.cur_span
     }
 
-    // advance `cur_token` by getting next token from lexer
+    #[cfg(feature = "std")]
+    pub(crate) fn cur_doc(&self) -> Option<&str> {
+        self.cur_doc.as_deref()
+    }
+
+    // build error for an unexpected `cur_token`
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        match self.cur_token.clone() {
+            Some(Token::Error(error)) => ParseError::Lex {
+                error,
+                pos: self.cur_span.start,
+            },
+            found => ParseError::UnexpectedToken {
+                found: found.unwrap_or(Token::Eof),
+                expected,
+                pos: self.cur_span.start,
+            },
+        }
+    }
+
+    // advance `cur_token` by getting next token from the lookahead buffer
+    // or the lexer
     pub fn get_next_token(&mut self) {
-        self.cur_token = Some(self.lexer.next_token());
+        self.prev_end = self.cur_span.end;
+        let (token, span, doc) = match self.lookahead.pop_front() {
+            Some(next) => next,
+            None => self.lex_token(),
+        };
+        self.cur_token = Some(token);
+        self.cur_span = span;
+        self.cur_doc = doc;
+    }
+
+    // token after cur token, without advancing
+    pub fn peek_token(&mut self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    // second token after cur token, without advancing
+    pub fn peek2(&mut self) -> &Token {
+        self.peek_nth(1)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> &Token {
+        while self.lookahead.len() <= n {
+            let next = self.lex_token();
+            self.lookahead.push_back(next);
+        }
+        &self.lookahead[n].0
+    }
+
+    // comments (if the lexer emits them) carry no meaning for the grammar,
+    // doc comment lines are joined and kept along the token they precede
+    fn lex_token(&mut self) -> (Token, Span, Option<String>) {
+        let mut doc: Option<String> = None;
+        loop {
+            match self.lexer.next_token() {
+                Token::Comment(_) => {}
+                Token::DocComment(line) => {
+                    let line = line.strip_prefix(' ').unwrap_or(&line);
+                    match &mut doc {
+                        Some(doc) => {
+                            doc.push('\n');
+                            doc.push_str(line);
+                        }
+                        None => doc = Some(line.into()),
+                    }
+                }
+                token => return (token, self.lexer.token_span(), doc),
+            }
+        }
+    }
+
+    // span from `start` up to the end of the last eaten token
+    fn span_from(&self, start: Span) -> Span {
+        Span::new(start.start, self.prev_end)
+    }
+
+    // skip tokens after an error up to the next item boundary
+    // (';', 'def', 'extern' or eof) so one error does not cascade
+    pub fn synchronize(&mut self) {
+        while !matches!(
+            self.cur_token(),
+            Token::Eof | Token::Char(';') | Token::Def | Token::Extern
+        ) {
+            self.get_next_token();
+        }
     }
 
     // ------------------------
@@ -72,28 +295,38 @@ where
     fn parse_number_expr(&mut self) -> ParseResult<ExpressionAST> {
         match *self.cur_token() {
             Token::Number(number) => {
+                let span = self.cur_span;
                 // eat number token
                 self.get_next_token();
-                Ok(ExpressionAST::Number(number))
+                Ok(ExpressionAST::Number(number, self.node_id(), span))
             }
-            _ => unreachable!(),
+            _ => Err(self.unexpected("number")),
         }
     }
 
     // paren_expr := '(' expression ')'
     fn parse_parenthesis_expr(&mut self) -> ParseResult<ExpressionAST> {
+        let start = self.cur_span;
         // eat ( token
-        assert_eq!(*self.cur_token(), Token::Char('('));
+        if *self.cur_token() != Token::Char('(') {
+            return Err(self.unexpected("'('"));
+        }
         self.get_next_token();
 
-        let v = self.parse_expression()?;
+        let mut v = self.parse_expression()?;
 
         if *self.cur_token() == Token::Char(')') {
             // eat ) token
             self.get_next_token();
+            // the node covers its parentheses
+            *v.span_mut() = self.span_from(start);
             Ok(v)
         } else {
-            Err("expected ')'".into())
+            Err(ParseError::UnterminatedParen {
+                found: self.cur_token().clone(),
+                open: start.start,
+                pos: self.cur_span.start,
+            })
         }
     }
 
@@ -101,42 +334,45 @@ where
     //      := identifier
     //      := identifier '(' expression* ')'
     fn parse_identifier_expr(&mut self) -> ParseResult<ExpressionAST> {
+        let start = self.cur_span;
         let id_name = match self.cur_token.take() {
             Some(Token::Identifier(id)) => {
                 // eat identifier token
                 self.get_next_token();
                 id
             }
-            _ => unreachable!(),
+            other => {
+                // plug back cur token
+                self.cur_token = other;
+                return Err(self.unexpected("identifier"));
+            }
         };
 
         if *self.cur_token() != Token::Char('(') {
-            Ok(ExpressionAST::Variable(id_name))
+            Ok(ExpressionAST::Variable(id_name, self.node_id(), start))
         } else {
             // eat ( token
             self.get_next_token();
             let mut args: Vec<ExpressionAST> = Vec::new();
 
             // collect arguments
-            if *self.cur_token() != Token::Char(')') {
-                loop {
-                    let arg = self.parse_expression()?;
-                    args.push(arg);
-
-                    if *self.cur_token() == Token::Char(')') {
-                        // eat ) token
-                        self.get_next_token();
-                        break;
-                    }
-
-                    if *self.cur_token() != Token::Char(',') {
-                        return Err("expected ')' or ',' in argument list".into());
-                    }
+            // args := (expression (',' expression)* ','?)?
+            while *self.cur_token() != Token::Char(')') {
+                // a missing argument (`f(a,,b)`, `f(,)`) fails right here
+                let arg = self.parse_expression()?;
+                args.push(arg);
+
+                match *self.cur_token() {
+                    // eat , token
+                    Token::Char(',') => self.get_next_token(),
+                    Token::Char(')') => {}
+                    _ => return Err(self.unexpected("')' or ',' in argument list")),
                 }
-
-                self.get_next_token();
             }
-            Ok(ExpressionAST::Call(id_name, args))
+            // eat ) token
+            self.get_next_token();
+            let span = self.span_from(start);
+            Ok(ExpressionAST::Call(id_name, args, self.node_id(), span))
         }
     }
 
@@ -149,56 +385,141 @@ where
             Token::Identifier(_) => self.parse_identifier_expr(),
             Token::Number(_) => self.parse_number_expr(),
             Token::Char('(') => self.parse_parenthesis_expr(),
-            _ => Err("unkown token when expecting an expression".into()),
+            _ if self.config.recover => Ok(self.recover_expr()),
+            _ => Err(self.unexpected("expression")),
         }
     }
 
+    // record the error at cur token and leave a hole in its place
+    // item boundaries are kept for `parse_item`, and so are ')' and ','
+    // inside parentheses or call arguments so the enclosing list can go on
+    fn recover_expr(&mut self) -> ExpressionAST {
+        let error = self.unexpected("expression");
+        self.errors.push(error);
+
+        let keep = match self.cur_token() {
+            Token::Eof | Token::Def | Token::Extern | Token::Char(';') => true,
+            Token::Char(')' | ',') => self.depth > 1,
+            _ => false,
+        };
+        let span = if keep {
+            Span::new(self.cur_span.start, self.cur_span.start)
+        } else {
+            let span = self.cur_span;
+            self.get_next_token();
+            span
+        };
+        ExpressionAST::Error(self.node_id(), span)
+    }
+
+    // unary
+    //      := primary
+    //      := unary_op unary
+    // prefix operators are collected first so long runs of them don't
+    // recurse, each one still counts as a level of nesting
+    fn parse_unary(&mut self) -> ParseResult<ExpressionAST> {
+        let mut ops = Vec::new();
+        while let Token::Char(op) = *self.cur_token() {
+            if !self.operators.is_unary(op) {
+                break;
+            }
+            if self.depth + ops.len() == self.config.max_depth {
+                return Err(self.too_deep(self.cur_span.start));
+            }
+            ops.push((op, self.cur_span));
+            // eat op token
+            self.get_next_token();
+        }
+
+        let mut expr = self.parse_primary()?;
+        while let Some((op, start)) = ops.pop() {
+            let span = start.to(expr.span());
+            expr = ExpressionAST::Unary(op, Box::new(expr), self.node_id(), span);
+        }
+        Ok(expr)
+    }
+
     // -------------------------
     // Binary Expression Parsing
     // -------------------------
 
     // expression
-    //      := primary bin op rhs
+    //      := unary bin op rhs
     fn parse_expression(&mut self) -> ParseResult<ExpressionAST> {
-        let lhs = self.parse_primary()?;
-        self.parse_bin_op_rhs(0, lhs)
+        if self.depth == self.config.max_depth {
+            return Err(self.too_deep(self.cur_span.start));
+        }
+
+        self.depth += 1;
+        let expr = self
+            .parse_unary()
+            .and_then(|lhs| self.parse_bin_op_rhs(lhs));
+        self.depth -= 1;
+        expr
+    }
+
+    fn too_deep(&self, pos: Position) -> ParseError {
+        ParseError::TooDeeplyNested {
+            limit: self.config.max_depth,
+            pos,
+        }
     }
 
     // bin op rhs
-    //      := ('+' primar)*
-    fn parse_bin_op_rhs(
-        &mut self,
-        expr_prec: isize,
-        mut lhs: ExpressionAST,
-    ) -> ParseResult<ExpressionAST> {
+    //      := (binop unary)*
+    //
+    // operator precedence parsing with an explicit stack of pending
+    // operators instead of recursion, so long operator chains parse in
+    // constant stack space
+    //
+    //   lhs BINOP1 rhs BINOP2 ...
+    //       prec1      prec2
+    // BINOP1 is reduced before BINOP2 is pushed if prec1 > prec2, or if
+    // prec1 == prec2 and BINOP2 is left associative
+    fn parse_bin_op_rhs(&mut self, lhs: ExpressionAST) -> ParseResult<ExpressionAST> {
+        // pending (lhs, bin op, precedence), precedence increasing to the top
+        let mut pending: Vec<(ExpressionAST, char, isize)> = Vec::new();
+        let mut rhs = lhs;
+
         loop {
-            let token_prec = get_token_precedence(self.cur_token());
+            let (token_prec, right_assoc) = match self.get_token_operator() {
+                Some(op) => (op.precedence, op.assoc == Assoc::Right),
+                None => (-1, false),
+            };
+
+            // reduce pending bin ops binding tighter than the next one
+            let reduces = |prec: isize| prec > token_prec || (prec == token_prec && !right_assoc);
+            while pending.last().is_some_and(|&(_, _, prec)| reduces(prec)) {
+                if let Some((lhs, binop, _)) = pending.pop() {
+                    let span = lhs.span().to(rhs.span());
+                    let id = self.node_id();
+                    rhs = ExpressionAST::Binary(binop, Box::new(lhs), Box::new(rhs), id, span);
+                }
+            }
 
-            // not a bin op or precendence too small
-            if token_prec < expr_prec {
-                return Ok(lhs);
+            // not a bin op, everything is reduced
+            if token_prec < 0 {
+                return Ok(rhs);
             }
 
-            let binop = match self.cur_token.take() {
-                Some(Token::Char(c)) => {
-                    // eat bin op token
-                    self.get_next_token();
-                    c
-                }
-                _ => unreachable!(),
+            let binop = match *self.cur_token() {
+                Token::Char(c) => c,
+                _ => return Err(self.unexpected("binary operator")),
             };
+            // eat bin op token
+            self.get_next_token();
 
-            // lhs BINOP1 rhs BINOP2 remrhs
-            //     tok_prec   next_prec
-            // parse primary expr after bin op
-            let mut rhs = self.parse_primary()?;
-            let next_prec = get_token_precedence(self.cur_token());
-            if token_prec < next_prec {
-                // binop2 has higher precendence than binop1, recurse into remrhs
-                rhs = self.parse_bin_op_rhs(token_prec + 1, rhs)?
-            }
+            // parse unary expr after bin op
+            pending.push((rhs, binop, token_prec));
+            rhs = self.parse_unary()?;
+        }
+    }
 
-            lhs = ExpressionAST::Binary(binop, Box::new(lhs), Box::new(rhs));
+    // get the bin op of cur token
+    fn get_token_operator(&self) -> Option<BinaryOp> {
+        match *self.cur_token() {
+            Token::Char(op) => self.operators.get(op),
+            _ => None,
         }
     }
 
@@ -206,6 +527,7 @@ where
     // Parsing the rest
     // ----------------
     fn parse_prototype(&mut self) -> ParseResult<PrototypeAST> {
+        let start = self.cur_span;
         let id_name = match self.cur_token.take() {
             Some(Token::Identifier(id)) => {
                 // eat identifier token
@@ -215,20 +537,31 @@ where
             other => {
                 // plug back cur token
                 self.cur_token = other;
-                return Err("expected function name in prototype".into());
+                return Err(self.unexpected("function name in prototype"));
             }
         };
 
         if *self.cur_token() != Token::Char('(') {
-            return Err("expected function name in prototype".into());
+            return Err(self.unexpected("'(' in prototype"));
         }
 
+        // eat ( token
+        self.get_next_token();
+
+        // params := (identifier (','? identifier)* ','?)?
+        // the comma between parameters is optional, `f(a b)` and `f(a, b)`
+        // are the same
         let mut args: Vec<String> = Vec::new();
         loop {
-            self.get_next_token();
             match self.cur_token.take() {
-                Some(Token::Identifier(arg)) => args.push(arg),
-                Some(Token::Char(',')) => {}
+                Some(Token::Identifier(arg)) => {
+                    args.push(arg);
+                    // eat identifier and , token
+                    self.get_next_token();
+                    if *self.cur_token() == Token::Char(',') {
+                        self.get_next_token();
+                    }
+                }
                 other => {
                     self.cur_token = other;
                     break;
@@ -237,105 +570,287 @@ where
         }
 
         if *self.cur_token() != Token::Char(')') {
-            return Err("expected ')' in prototype".into());
+            return Err(self.unexpected("parameter name or ')' in prototype"));
         }
         // eat ) token
         self.get_next_token();
 
-        Ok(PrototypeAST(id_name, args))
+        let span = self.span_from(start);
+        Ok(PrototypeAST(id_name, args, None, self.node_id(), span))
     }
 
     // definition := 'def' protype expression
     pub fn parse_definition(&mut self) -> ParseResult<FunctionAST> {
+        let start = self.cur_span;
         // eat def token
-        assert_eq!(*self.cur_token(), Token::Def);
+        if *self.cur_token() != Token::Def {
+            return Err(self.unexpected("'def'"));
+        }
+        let doc = self.cur_doc.take();
         self.get_next_token();
 
-        let proto = self.parse_prototype()?;
+        let mut proto = self.parse_prototype()?;
+        proto.2 = doc;
         let expr = self.parse_expression()?;
 
-        Ok(FunctionAST(proto, expr))
+        let span = self.span_from(start);
+        Ok(FunctionAST(proto, expr, self.node_id(), span))
     }
 
     // external := 'extern' prototype
+    // the span of an extern prototype covers the 'extern' keyword
     pub fn parse_extern(&mut self) -> ParseResult<PrototypeAST> {
+        let start = self.cur_span;
         // eat extern token
-        assert_eq!(*self.cur_token(), Token::Extern);
+        if *self.cur_token() != Token::Extern {
+            return Err(self.unexpected("'extern'"));
+        }
+        let doc = self.cur_doc.take();
         self.get_next_token();
 
-        self.parse_prototype()
+        let mut proto = self.parse_prototype()?;
+        proto.2 = doc;
+        proto.4 = self.span_from(start);
+        Ok(proto)
+    }
+
+    // parse to eof, collecting a diagnostic for every error instead of
+    // stopping at the first one
+    pub fn parse_all(&mut self) -> ParseOutput {
+        let (items, errors) = self.parse_items();
+        ParseOutput {
+            items,
+            diagnostics: errors.into_iter().map(Diagnostic::from).collect(),
+        }
+    }
+
+    // parse the whole token stream into items, fails with every error found
+    pub fn parse_program(&mut self) -> Result<Vec<Item>, Vec<ParseError>> {
+        match self.parse_items() {
+            (items, errors) if errors.is_empty() => Ok(items),
+            (_, errors) => Err(errors),
+        }
+    }
+
+    // program := item*
+    // resynchronizes after each error and keeps going up to eof
+    // with recovery a failed item is kept as a hole over the skipped input
+    fn parse_items(&mut self) -> (Vec<Item>, Vec<ParseError>) {
+        let mut items = Vec::new();
+        let mut errors = Vec::new();
+        while self.parse_next_item(&mut items, &mut errors) {}
+        (items, errors)
+    }
+
+    // parse one item into `items` and its errors into `errors`,
+    // false at eof
+    pub(crate) fn parse_next_item(
+        &mut self,
+        items: &mut Vec<Item>,
+        errors: &mut Vec<ParseError>,
+    ) -> bool {
+        self.skip_separators();
+        let start = self.cur_span;
+        let Some(item) = self.parse_item() else {
+            return false;
+        };
+        errors.append(&mut self.errors);
+        match item {
+            Ok(item) => items.push(item),
+            Err(err) => {
+                errors.push(err);
+                self.synchronize();
+                if self.config.recover {
+                    let end = if self.prev_end.offset < start.start.offset {
+                        start.start
+                    } else {
+                        self.prev_end
+                    };
+                    let hole = ExpressionAST::Error(self.node_id(), Span::new(start.start, end));
+                    items.push(Item::Expr(hole));
+                }
+            }
+        }
+        true
+    }
+
+    // ignore top level ';'
+    pub(crate) fn skip_separators(&mut self) {
+        while *self.cur_token() == Token::Char(';') {
+            self.get_next_token();
+        }
+    }
+
+    // item := definition | external | top_level_expr
+    // skips ';' between items, None at eof
+    // after an error call `synchronize` before parsing the next item
+    pub fn parse_item(&mut self) -> Option<ParseResult<Item>> {
+        self.skip_separators();
+
+        let item = match *self.cur_token() {
+            Token::Eof => return None,
+            Token::Def => self.parse_definition().map(Item::Function),
+            Token::Extern => self.parse_extern().map(Item::Extern),
+            _ => self.parse_expression().map(Item::Expr),
+        };
+        Some(item)
     }
 
     // top_level_expr := expression
     pub fn parse_top_level_expr(&mut self) -> ParseResult<FunctionAST> {
         let e = self.parse_expression()?;
-        let proto = PrototypeAST("".into(), Vec::new());
-        Ok(FunctionAST(proto, e))
+        let span = e.span();
+        let proto = PrototypeAST("".into(), Vec::new(), None, self.node_id(), span);
+        Ok(FunctionAST(proto, e, self.node_id(), span))
+    }
+}
+
+impl<'a> Parser<core::str::Chars<'a>> {
+    // parser over a string, can't be `FromStr` as it borrows the input
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &'a str) -> Self {
+        Parser::new(Lexer::new(input.chars()))
     }
 }
 
-// get the bin op precedence
-fn get_token_precedence(tok: &Token) -> isize {
-    match tok {
-        Token::Char('<') => 10,
-        Token::Char('+') => 20,
-        Token::Char('-') => 20,
-        Token::Char('*') => 40,
-        _ => -1,
+// parse the expression at the start of `input`
+pub fn parse_expr(input: &str) -> ParseResult<ExpressionAST> {
+    Parser::from_str(input).parse_expression()
+}
+
+// parse `input` as exactly one expression, trailing tokens are an error
+pub fn parse_complete_expr(input: &str) -> ParseResult<ExpressionAST> {
+    let mut parser = Parser::from_str(input);
+    let expr = parser.parse_expression()?;
+    match parser.cur_token() {
+        Token::Eof => Ok(expr),
+        _ => Err(parser.unexpected("end of input")),
     }
 }
 
+// parse all items of `input`, fails with every error found
+pub fn parse_file(input: &str) -> Result<Vec<Item>, Vec<ParseError>> {
+    Parser::from_str(input).parse_program()
+}
+
 #[cfg(test)]
 mod test {
     use std::vec;
 
-    use super::{ExpressionAST, FunctionAST, Parser, PrototypeAST};
-    use crate::lexer::Lexer;
-
-    fn parser(input: &str) -> Parser<std::str::Chars> {
-        let l = Lexer::new(input.chars());
-        let mut p = Parser::new(l);
-
-        // drop inital coin, init cur_tok
-        p.get_next_token();
+    use super::{
+        parse_complete_expr, parse_expr, parse_file, ExpressionAST, FunctionAST, Item, NodeId,
+        ParseError, Parser, ParserConfig, PrototypeAST,
+    };
+    use crate::diagnostic::Diagnostic;
+    use crate::fuzz::fuzz;
+    use crate::lexer::{LexError, Lexer, LexerConfig, Position, Span, Token};
+    use crate::operator::Assoc;
+
+    fn parser(input: &str) -> Parser<std::str::Chars<'_>> {
+        Parser::from_str(input)
+    }
 
-        p
+    // span of input[lo..hi]
+    fn span(input: &str, lo: usize, hi: usize) -> Span {
+        let pos = |offset: usize| {
+            let mut pos = Position::default();
+            input[..offset].chars().for_each(|c| pos.advance(c));
+            pos
+        };
+        Span::new(pos(lo), pos(hi))
     }
 
     #[test]
     fn parse_number() {
-        let mut p = parser("13.37");
+        let input = "13.37";
+        let mut p = parser(input);
 
-        assert_eq!(p.parse_number_expr(), Ok(ExpressionAST::Number(13.37f64)));
+        assert_eq!(
+            p.parse_number_expr(),
+            Ok(ExpressionAST::Number(
+                13.37f64,
+                NodeId::DUMMY,
+                span(input, 0, 5)
+            ))
+        );
     }
 
     #[test]
     fn parse_variable() {
-        let mut p = parser("foop");
+        let input = "foop";
+        let mut p = parser(input);
         assert_eq!(
             p.parse_identifier_expr(),
-            Ok(ExpressionAST::Variable("foop".into()))
+            Ok(ExpressionAST::Variable(
+                "foop".into(),
+                NodeId::DUMMY,
+                span(input, 0, 4)
+            ))
         )
     }
 
     #[test]
     fn parse_primary() {
-        let mut p = parser("1337 foop \n bla(123)");
+        let input = "1337 foop \n bla(123)";
+        let s = |lo, hi| span(input, lo, hi);
+        let mut p = parser(input);
 
-        assert_eq!(p.parse_primary(), Ok(ExpressionAST::Number(1337f64)));
+        assert_eq!(
+            p.parse_primary(),
+            Ok(ExpressionAST::Number(1337f64, NodeId::DUMMY, s(0, 4)))
+        );
         assert_eq!(
             p.parse_identifier_expr(),
-            Ok(ExpressionAST::Variable("foop".into()))
+            Ok(ExpressionAST::Variable(
+                "foop".into(),
+                NodeId::DUMMY,
+                s(5, 9)
+            ))
         );
         assert_eq!(
             p.parse_primary(),
             Ok(ExpressionAST::Call(
                 "bla".into(),
-                vec![ExpressionAST::Number(123f64)]
+                vec![ExpressionAST::Number(123f64, NodeId::DUMMY, s(16, 19))],
+                NodeId::DUMMY,
+                s(12, 20)
             ))
         );
     }
 
+    #[test]
+    fn parse_call_args() {
+        let input = "f(a, (b), g()) + 1";
+        let s = |lo, hi| span(input, lo, hi);
+        let mut p = parser(input);
+
+        let call = ExpressionAST::Call(
+            "f".into(),
+            vec![
+                ExpressionAST::Variable("a".into(), NodeId::DUMMY, s(2, 3)),
+                ExpressionAST::Variable("b".into(), NodeId::DUMMY, s(5, 8)),
+                ExpressionAST::Call("g".into(), vec![], NodeId::DUMMY, s(10, 13)),
+            ],
+            NodeId::DUMMY,
+            s(0, 14),
+        );
+        let sum = ExpressionAST::Binary(
+            '+',
+            Box::new(call),
+            Box::new(ExpressionAST::Number(1f64, NodeId::DUMMY, s(17, 18))),
+            NodeId::DUMMY,
+            s(0, 18),
+        );
+
+        assert_eq!(p.parse_expression(), Ok(sum));
+
+        // trailing comma
+        let call = |input| parse_complete_expr(input).map(|e| e.to_string());
+        assert_eq!(call("f(a, g(b,),)"), Ok("f(a, g(b))".into()));
+        assert!(call("f(,)").is_err());
+        assert!(call("f(a,,)").is_err());
+    }
+
     #[test]
     fn parse_binary_op() {
         // operator before RHS has higher precendence
@@ -345,18 +860,24 @@ mod test {
         //     +     c
         //    / \
         //   a   b
-        let mut p = parser("a + b - c");
+        let input = "a + b - c";
+        let s = |lo, hi| span(input, lo, hi);
+        let mut p = parser(input);
 
         let bin_expr_ab = ExpressionAST::Binary(
             '+',
-            Box::new(ExpressionAST::Variable("a".into())),
-            Box::new(ExpressionAST::Variable("b".into())),
+            Box::new(ExpressionAST::Variable("a".into(), NodeId::DUMMY, s(0, 1))),
+            Box::new(ExpressionAST::Variable("b".into(), NodeId::DUMMY, s(4, 5))),
+            NodeId::DUMMY,
+            s(0, 5),
         );
 
         let bin_expr_abc = ExpressionAST::Binary(
             '-',
             Box::new(bin_expr_ab),
-            Box::new(ExpressionAST::Variable("c".into())),
+            Box::new(ExpressionAST::Variable("c".into(), NodeId::DUMMY, s(8, 9))),
+            NodeId::DUMMY,
+            s(0, 9),
         );
 
         assert_eq!(p.parse_expression(), Ok(bin_expr_abc));
@@ -371,17 +892,23 @@ mod test {
         //     a   *
         //        / \
         //       b   c
-        let mut p = parser("a + b * c");
+        let input = "a + b * c";
+        let s = |lo, hi| span(input, lo, hi);
+        let mut p = parser(input);
 
         let bin_expr_bc = ExpressionAST::Binary(
             '*',
-            Box::new(ExpressionAST::Variable("b".into())),
-            Box::new(ExpressionAST::Variable("c".into())),
+            Box::new(ExpressionAST::Variable("b".into(), NodeId::DUMMY, s(4, 5))),
+            Box::new(ExpressionAST::Variable("c".into(), NodeId::DUMMY, s(8, 9))),
+            NodeId::DUMMY,
+            s(4, 9),
         );
         let bin_expr_abc = ExpressionAST::Binary(
             '+',
-            Box::new(ExpressionAST::Variable("a".into())),
+            Box::new(ExpressionAST::Variable("a".into(), NodeId::DUMMY, s(0, 1))),
             Box::new(bin_expr_bc),
+            NodeId::DUMMY,
+            s(0, 9),
         );
 
         assert_eq!(p.parse_expression(), Ok(bin_expr_abc));
@@ -389,34 +916,729 @@ mod test {
 
     #[test]
     fn parse_prototype() {
-        let mut p = parser("foo(a,b)");
-
-        let proto = PrototypeAST("foo".into(), vec!["a".into(), "b".into()]);
+        let input = "foo(a,b)";
+        let mut p = parser(input);
+
+        let proto = PrototypeAST(
+            "foo".into(),
+            vec!["a".into(), "b".into()],
+            None,
+            NodeId::DUMMY,
+            span(input, 0, 8),
+        );
 
         assert_eq!(p.parse_prototype(), Ok(proto));
+
+        // optional and trailing commas
+        for input in ["foo(a b)", "foo(a, b,)", "foo(a b,)"] {
+            let proto = parser(input).parse_prototype().unwrap();
+            assert_eq!(proto.1, vec!["a", "b"]);
+        }
+        assert!(parser("foo(,a)").parse_prototype().is_err());
+        assert!(parser("foo(a,,)").parse_prototype().is_err());
     }
 
     #[test]
     fn parse_definition() {
-        let mut p = parser("def bar( arg0, arg1) arg0 + arg1");
-
-        let proto = PrototypeAST("bar".into(), vec!["arg0".into(), "arg1".into()]);
+        let input = "def bar( arg0, arg1) arg0 + arg1";
+        let s = |lo, hi| span(input, lo, hi);
+        let mut p = parser(input);
+
+        let proto = PrototypeAST(
+            "bar".into(),
+            vec!["arg0".into(), "arg1".into()],
+            None,
+            NodeId::DUMMY,
+            s(4, 20),
+        );
         let body = ExpressionAST::Binary(
             '+',
-            Box::new(ExpressionAST::Variable("arg0".into())),
-            Box::new(ExpressionAST::Variable("arg1".into())),
+            Box::new(ExpressionAST::Variable(
+                "arg0".into(),
+                NodeId::DUMMY,
+                s(21, 25),
+            )),
+            Box::new(ExpressionAST::Variable(
+                "arg1".into(),
+                NodeId::DUMMY,
+                s(28, 32),
+            )),
+            NodeId::DUMMY,
+            s(21, 32),
         );
-        let func = FunctionAST(proto, body);
+        let func = FunctionAST(proto, body, NodeId::DUMMY, s(0, 32));
 
         assert_eq!(p.parse_definition(), Ok(func));
     }
 
     #[test]
     fn parse_extern() {
-        let mut p = parser("extern bar()");
-
-        let proto = PrototypeAST("bar".into(), vec![]);
+        let input = "extern bar()";
+        let mut p = parser(input);
+
+        let proto = PrototypeAST(
+            "bar".into(),
+            vec![],
+            None,
+            NodeId::DUMMY,
+            span(input, 0, 12),
+        );
 
         assert_eq!(p.parse_extern(), Ok(proto));
     }
+
+    #[test]
+    fn parse_skips_comment_tokens() {
+        let config = LexerConfig {
+            emit_comments: true,
+            ..LexerConfig::default()
+        };
+        let input = "# doc\nextern bar() # tail";
+        let mut p = Parser::new(Lexer::with_config(input.chars(), config));
+
+        assert_eq!(
+            p.parse_extern(),
+            Ok(PrototypeAST(
+                "bar".into(),
+                vec![],
+                None,
+                NodeId::DUMMY,
+                span(input, 6, 18)
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_doc_comments() {
+        let input = "## adds\n##   two numbers\ndef add(a b) a+b\n\
+                     ## stray\n1;\n\
+                     # plain\nextern sin(x)\n\
+                     ## cosine\n# between\nextern cos(x)";
+        let items = parser(input).parse_program().unwrap();
+
+        let docs: Vec<_> = items
+            .iter()
+            .map(|item| match item {
+                Item::Function(func) => func.0.doc(),
+                Item::Extern(proto) => proto.doc(),
+                Item::Expr(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            docs,
+            vec![Some("adds\n  two numbers"), None, None, Some("cosine")]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let pos = |offset, line, column| Position {
+            offset,
+            line,
+            column,
+        };
+
+        let mut p = parser("(a + b;");
+        assert_eq!(
+            p.parse_expression(),
+            Err(ParseError::UnterminatedParen {
+                found: Token::Char(';'),
+                open: pos(0, 1, 1),
+                pos: pos(6, 1, 7),
+            })
+        );
+
+        let mut p = parser("foo(a b)");
+        assert_eq!(
+            p.parse_expression(),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Identifier("b".into()),
+                expected: "')' or ',' in argument list",
+                pos: pos(6, 1, 7),
+            })
+        );
+
+        let mut p = parser("foo(a,,b)");
+        assert_eq!(
+            p.parse_expression(),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Char(','),
+                expected: "expression",
+                pos: pos(6, 1, 7),
+            })
+        );
+
+        let mut p = parser("def foo(a,,b) 1");
+        assert_eq!(
+            p.parse_definition(),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Char(','),
+                expected: "parameter name or ')' in prototype",
+                pos: pos(10, 1, 11),
+            })
+        );
+
+        let mut p = parser("def\n 1(a)");
+        let err = p.parse_definition().unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::UnexpectedToken {
+                found: Token::Number(_),
+                ..
+            }
+        ));
+        assert_eq!(err.pos(), pos(5, 2, 2));
+
+        let mut p = parser("1 + €");
+        assert_eq!(
+            p.parse_expression(),
+            Err(ParseError::Lex {
+                error: LexError::InvalidChar('€'),
+                pos: pos(4, 1, 5),
+            })
+        );
+    }
+
+    #[test]
+    fn synchronize() {
+        let input = "foo(1 2 3) 4; def bar() 1 extern (a) b c extern baz()";
+        let mut p = parser(input);
+
+        assert!(p.parse_top_level_expr().is_err());
+        p.synchronize();
+        assert_eq!(*p.cur_token(), Token::Char(';'));
+        p.get_next_token();
+
+        assert!(p.parse_definition().is_ok());
+        assert!(p.parse_extern().is_err());
+        p.synchronize();
+        assert_eq!(
+            p.parse_extern(),
+            Ok(PrototypeAST(
+                "baz".into(),
+                Vec::new(),
+                None,
+                NodeId::DUMMY,
+                span(input, 41, 53)
+            ))
+        );
+        p.synchronize();
+        assert_eq!(*p.cur_token(), Token::Eof);
+    }
+
+    #[test]
+    fn parse_all() {
+        let input = "def foo(a) a; 1 +; extern bar(x)\ndef (b) b;\nfoo(2)";
+        let s = |lo, hi| span(input, lo, hi);
+        let mut p = Parser::from_str(input);
+
+        let out = p.parse_all();
+        assert_eq!(
+            out.items,
+            vec![
+                Item::Function(FunctionAST(
+                    PrototypeAST(
+                        "foo".into(),
+                        vec!["a".into()],
+                        None,
+                        NodeId::DUMMY,
+                        s(4, 10)
+                    ),
+                    ExpressionAST::Variable("a".into(), NodeId::DUMMY, s(11, 12)),
+                    NodeId::DUMMY,
+                    s(0, 12)
+                )),
+                Item::Extern(PrototypeAST(
+                    "bar".into(),
+                    vec!["x".into()],
+                    None,
+                    NodeId::DUMMY,
+                    s(19, 32)
+                )),
+                Item::Expr(ExpressionAST::Call(
+                    "foo".into(),
+                    vec![ExpressionAST::Number(2f64, NodeId::DUMMY, s(48, 49))],
+                    NodeId::DUMMY,
+                    s(44, 50)
+                )),
+            ]
+        );
+        assert_eq!(
+            out.diagnostics,
+            vec![
+                Diagnostic::error(
+                    Span::at(Position {
+                        offset: 17,
+                        line: 1,
+                        column: 18
+                    }),
+                    "expected expression, found ';'"
+                ),
+                Diagnostic::error(
+                    Span::at(Position {
+                        offset: 37,
+                        line: 2,
+                        column: 5
+                    }),
+                    "expected function name in prototype, found '('"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_wrong_entry_point() {
+        // entry points called on the wrong token return errors, no panics
+        assert!(parser("a").parse_number_expr().is_err());
+        assert!(parser("a").parse_parenthesis_expr().is_err());
+        assert!(parser("1").parse_identifier_expr().is_err());
+        assert!(parser("extern a()").parse_definition().is_err());
+        assert!(parser("def a() 1").parse_extern().is_err());
+    }
+
+    #[test]
+    fn fuzz_parse_never_panics() {
+        const PIECES: &[&str] = &[
+            "def", "extern", "(", ")", ",", ";", "+", "-", "*", "<", "!", "a", "foo", "1", "2.5",
+            "1.2.3", ".", "#c\n", "€", "\u{0}", " ", "\n",
+        ];
+
+        fuzz(0x2545f4914f6cdd1d, 2000, |_, random| {
+            let input = random.pieces(PIECES, 32);
+
+            let mut p = Parser::from_str(&input);
+            p.parse_all();
+
+            let config = ParserConfig {
+                recover: true,
+                ..ParserConfig::default()
+            };
+            let mut p = Parser::with_config(Lexer::new(input.chars()), config);
+            p.parse_all();
+        });
+    }
+
+    #[test]
+    fn parse_recover() {
+        let input = "def f(x) x + ; f(a,,b); 1 + ) 2; def (y) y; 3";
+        let config = ParserConfig {
+            recover: true,
+            ..ParserConfig::default()
+        };
+        let mut p = Parser::with_config(Lexer::new(input.chars()), config);
+
+        let out = p.parse_all();
+        let items: Vec<_> = out.items.iter().map(|item| item.to_string()).collect();
+        assert_eq!(
+            items,
+            vec![
+                "def f(x) x + <error>",
+                "f(a, <error>, b)",
+                "1 + <error>",
+                "2",
+                "<error>",
+                "3"
+            ]
+        );
+        assert_eq!(
+            out.items[4],
+            Item::Expr(ExpressionAST::Error(NodeId::DUMMY, span(input, 33, 42)))
+        );
+
+        let found: Vec<_> = out.diagnostics.iter().map(|d| d.pos().column).collect();
+        assert_eq!(found, vec![14, 20, 29, 38]);
+    }
+
+    #[test]
+    fn parse_program() {
+        let input = "extern sin(x); def f(x) sin(x) * 2;\n f(1)";
+        let s = |lo, hi| span(input, lo, hi);
+        let mut p = Parser::from_str(input);
+
+        let body = ExpressionAST::Binary(
+            '*',
+            Box::new(ExpressionAST::Call(
+                "sin".into(),
+                vec![ExpressionAST::Variable(
+                    "x".into(),
+                    NodeId::DUMMY,
+                    s(28, 29),
+                )],
+                NodeId::DUMMY,
+                s(24, 30),
+            )),
+            Box::new(ExpressionAST::Number(2f64, NodeId::DUMMY, s(33, 34))),
+            NodeId::DUMMY,
+            s(24, 34),
+        );
+        assert_eq!(
+            p.parse_program(),
+            Ok(vec![
+                Item::Extern(PrototypeAST(
+                    "sin".into(),
+                    vec!["x".into()],
+                    None,
+                    NodeId::DUMMY,
+                    s(0, 13)
+                )),
+                Item::Function(FunctionAST(
+                    PrototypeAST("f".into(), vec!["x".into()], None, NodeId::DUMMY, s(19, 23)),
+                    body,
+                    NodeId::DUMMY,
+                    s(15, 34)
+                )),
+                Item::Expr(ExpressionAST::Call(
+                    "f".into(),
+                    vec![ExpressionAST::Number(1f64, NodeId::DUMMY, s(39, 40))],
+                    NodeId::DUMMY,
+                    s(37, 41)
+                )),
+            ])
+        );
+
+        let mut p = Parser::from_str("def f(x) x; (1; def (x) 2; extern g()");
+        let errors = p.parse_program().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], ParseError::UnterminatedParen { .. }));
+        assert!(matches!(errors[1], ParseError::UnexpectedToken { .. }));
+    }
+
+    #[test]
+    fn parse_depth_limit() {
+        let config = ParserConfig {
+            max_depth: 3,
+            ..ParserConfig::default()
+        };
+        let parse = |input: &str| {
+            let mut p = Parser::with_config(Lexer::new(input.chars()), config);
+            p.parse_expression()
+        };
+
+        assert!(parse("((1))").is_ok());
+        assert!(parse("f(g(1), (2))").is_ok());
+        assert_eq!(
+            parse("f(g((1)))"),
+            Err(ParseError::TooDeeplyNested {
+                limit: 3,
+                pos: Position {
+                    offset: 5,
+                    line: 1,
+                    column: 6
+                }
+            })
+        );
+
+        // a prefix operator is a level of nesting, an operator chain is
+        // not, the error is at the token that goes over the limit
+        let too_deep = |input: &str, offset| match parse(input) {
+            Err(ParseError::TooDeeplyNested { limit: 3, pos }) => pos.offset == offset,
+            _ => false,
+        };
+        for input in [
+            "a + b + c + d",
+            "a * b + c * d < e",
+            "--a",
+            "-a + --b",
+            "f(-a)",
+        ] {
+            assert!(parse(input).is_ok(), "{}", input);
+        }
+        assert!(too_deep("---a", 2));
+        assert!(too_deep("-(--a)", 3));
+        assert!(too_deep("f(--a)", 3));
+
+        // hostile input is rejected instead of overflowing the stack, a
+        // chain as long is fine
+        let input = "(".repeat(100_000);
+        assert!(matches!(
+            parser(&input).parse_expression(),
+            Err(ParseError::TooDeeplyNested { limit: 256, .. })
+        ));
+        let input = vec!["1"; 100_000].join(" * ");
+        assert!(parse(&input).is_ok());
+    }
+
+    #[test]
+    fn parse_binary_op_mixed() {
+        let mut p = parser("a < b + c * d - e * f < g");
+        let e = p.parse_expression().unwrap();
+        assert_eq!(e.to_string(), "a < b + c * d - e * f < g");
+        assert_eq!(e.span(), span("a < b + c * d - e * f < g", 0, 25));
+
+        // ((a < ((b + (c * d)) - (e * f))) < g)
+        let ExpressionAST::Binary('<', lhs, g, ..) = &e else {
+            panic!("expected '<' at the root");
+        };
+        assert!(matches!(**g, ExpressionAST::Variable(ref name, ..) if name == "g"));
+        let ExpressionAST::Binary('<', _, sub, ..) = &**lhs else {
+            panic!("expected '<' on the lhs");
+        };
+        assert!(matches!(**sub, ExpressionAST::Binary('-', ..)));
+    }
+
+    #[test]
+    fn parse_long_operator_chain() {
+        let input = vec!["a"; 100_000].join(" + ");
+        let e = parser(&input).parse_expression().unwrap();
+
+        // left associative, the chain grows down the lhs
+        let mut depth = 0;
+        let mut node = &e;
+        while let ExpressionAST::Binary('+', lhs, ..) = node {
+            depth += 1;
+            node = lhs;
+        }
+        assert_eq!(depth, 99_999);
+
+        // printed, cloned, compared and dropped without recursion
+        assert_eq!(e.to_string(), input);
+        assert_eq!(e.clone(), e);
+    }
+
+    #[test]
+    fn parse_custom_operators() {
+        let mut p = parser("a / b + c | d");
+        p.operators_mut().insert('/', 40, Assoc::Left);
+        p.operators_mut().insert('|', 5, Assoc::Left);
+        p.operators_mut().remove('+');
+
+        // '+' is no operator anymore, the expression ends before it
+        let e = p.parse_expression().unwrap();
+        assert!(matches!(e, ExpressionAST::Binary('/', ..)));
+        assert_eq!(*p.cur_token(), Token::Char('+'));
+
+        let mut p = parser("a | b < c");
+        p.operators_mut().insert('|', 5, Assoc::Left);
+        let e = p.parse_expression().unwrap();
+        let ExpressionAST::Binary('|', _, rhs, ..) = &e else {
+            panic!("expected '|' at the root");
+        };
+        assert!(matches!(**rhs, ExpressionAST::Binary('<', ..)));
+    }
+
+    #[test]
+    fn parse_unary() {
+        let input = "-a * -(b + 1)";
+        let s = |lo, hi| span(input, lo, hi);
+
+        // unary operators bind tighter than any binary one
+        let expr = ExpressionAST::Binary(
+            '*',
+            Box::new(ExpressionAST::Unary(
+                '-',
+                Box::new(ExpressionAST::Variable("a".into(), NodeId::DUMMY, s(1, 2))),
+                NodeId::DUMMY,
+                s(0, 2),
+            )),
+            Box::new(ExpressionAST::Unary(
+                '-',
+                Box::new(ExpressionAST::Binary(
+                    '+',
+                    Box::new(ExpressionAST::Variable("b".into(), NodeId::DUMMY, s(7, 8))),
+                    Box::new(ExpressionAST::Number(1f64, NodeId::DUMMY, s(11, 12))),
+                    NodeId::DUMMY,
+                    s(6, 13),
+                )),
+                NodeId::DUMMY,
+                s(5, 13),
+            )),
+            NodeId::DUMMY,
+            s(0, 13),
+        );
+        assert_eq!(parser(input).parse_expression(), Ok(expr));
+
+        let e = parse_complete_expr("a - !-b").unwrap();
+        let ExpressionAST::Binary('-', _, rhs, ..) = &e else {
+            panic!("expected '-' at the root");
+        };
+        let ExpressionAST::Unary('!', neg, ..) = &**rhs else {
+            panic!("expected '!'");
+        };
+        assert!(matches!(**neg, ExpressionAST::Unary('-', ..)));
+
+        // user defined prefix operator
+        let mut p = parser("~a");
+        assert!(p.parse_expression().is_err());
+        let mut p = parser("~a");
+        p.operators_mut().insert_unary('~');
+        assert!(matches!(
+            p.parse_expression(),
+            Ok(ExpressionAST::Unary('~', ..))
+        ));
+
+        // long runs of prefix operators don't recurse while parsing
+        let input = "-".repeat(255) + "x";
+        assert!(parse_complete_expr(&input).is_ok());
+    }
+
+    #[test]
+    fn parse_associativity() {
+        let parse = |input: &str| {
+            let mut p = parser(input);
+            p.operators_mut().insert('=', 2, Assoc::Right);
+            p.operators_mut().insert('^', 60, Assoc::Right);
+            p.parse_expression().unwrap()
+        };
+        let var = |name: &str, e: &ExpressionAST| matches!(e, ExpressionAST::Variable(v, ..) if v == name);
+
+        //     =
+        //    / \
+        //   a   =
+        //      / \
+        //     b   c
+        let ExpressionAST::Binary('=', a, bc, ..) = &parse("a = b = c") else {
+            panic!("expected '=' at the root");
+        };
+        assert!(var("a", a));
+        let ExpressionAST::Binary('=', b, c, ..) = &**bc else {
+            panic!("expected '=' on the rhs");
+        };
+        assert!(var("b", b) && var("c", c));
+
+        //     =
+        //    / \
+        //   x   +
+        //      / \
+        //     ^   1
+        //    / \
+        //   a   ^
+        //      / \
+        //     b   c
+        let ExpressionAST::Binary('=', x, sum, ..) = &parse("x = a ^ b ^ c + 1") else {
+            panic!("expected '=' at the root");
+        };
+        assert!(var("x", x));
+        let ExpressionAST::Binary('+', pow, ..) = &**sum else {
+            panic!("expected '+' on the rhs");
+        };
+        let ExpressionAST::Binary('^', a, bc, ..) = &**pow else {
+            panic!("expected '^' on the lhs");
+        };
+        assert!(var("a", a));
+        assert!(matches!(**bc, ExpressionAST::Binary('^', ..)));
+
+        // left associative operators are unchanged
+        let ExpressionAST::Binary('-', ab, c, ..) = &parse("a - b - c") else {
+            panic!("expected '-' at the root");
+        };
+        assert!(var("c", c));
+        assert!(matches!(**ab, ExpressionAST::Binary('-', ..)));
+    }
+
+    #[test]
+    fn parse_helpers() {
+        let expr = parse_expr("a * (b + 1)").unwrap();
+        assert_eq!(expr.to_string(), "a * (b + 1)");
+        assert!(parse_expr(")").is_err());
+
+        assert_eq!(parse_complete_expr("a * (b + 1) # c\n"), Ok(expr));
+        assert_eq!(parse_expr("a b").map(|e| e.to_string()), Ok("a".into()));
+        assert_eq!(
+            parse_complete_expr("a b"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Identifier("b".into()),
+                expected: "end of input",
+                pos: Position {
+                    offset: 2,
+                    line: 1,
+                    column: 3
+                },
+            })
+        );
+        assert!(parse_complete_expr("1;").is_err());
+
+        let items = parse_file("extern f(x); def g(x) f(x) + 1; g(2)").unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(matches!(items[2], Item::Expr(ExpressionAST::Call(..))));
+
+        let errors = parse_file("def (x) 1; g(;").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn node_ids() {
+        fn expr_ids(e: &ExpressionAST, ids: &mut Vec<NodeId>) {
+            ids.push(e.id());
+            match e {
+                ExpressionAST::Binary(_, lhs, rhs, ..) => {
+                    expr_ids(lhs, ids);
+                    expr_ids(rhs, ids);
+                }
+                ExpressionAST::Call(_, args, ..) => args.iter().for_each(|arg| expr_ids(arg, ids)),
+                _ => {}
+            }
+        }
+
+        let mut p = parser("def f(x) x + 1; extern g(); f(g()) * 2");
+        let mut ids = Vec::new();
+        while let Some(item) = p.parse_item() {
+            match item.unwrap() {
+                Item::Function(func) => {
+                    ids.extend([func.0 .3, func.2]);
+                    expr_ids(&func.1, &mut ids);
+                }
+                Item::Extern(proto) => ids.push(proto.3),
+                Item::Expr(expr) => expr_ids(&expr, &mut ids),
+            }
+        }
+
+        // every node got its own id
+        assert_eq!(ids.len(), 10);
+        assert!(!ids.contains(&NodeId::DUMMY));
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 10);
+
+        // equality ignores ids
+        let e = ExpressionAST::Number(1.0, NodeId(7), Span::default());
+        assert_eq!(
+            e,
+            ExpressionAST::Number(1.0, NodeId::DUMMY, Span::default())
+        );
+    }
+
+    #[test]
+    fn peek_tokens() {
+        let input = "foo ( 1 )";
+        let mut p = parser(input);
+
+        assert_eq!(*p.cur_token(), Token::Identifier("foo".into()));
+        assert_eq!(*p.peek_token(), Token::Char('('));
+        assert_eq!(*p.peek2(), Token::Number(1f64));
+        assert_eq!(*p.peek_token(), Token::Char('('));
+        assert_eq!(*p.cur_token(), Token::Identifier("foo".into()));
+
+        // peeked tokens keep their spans
+        assert_eq!(
+            p.parse_expression(),
+            Ok(ExpressionAST::Call(
+                "foo".into(),
+                vec![ExpressionAST::Number(
+                    1f64,
+                    NodeId::DUMMY,
+                    span(input, 6, 7)
+                )],
+                NodeId::DUMMY,
+                span(input, 0, 9)
+            ))
+        );
+        assert_eq!(*p.peek_token(), Token::Eof);
+        assert_eq!(*p.peek2(), Token::Eof);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let items = parse_file("## doc\ndef f(x) x * (x + 1); extern g(a b); g(1, f(2))").unwrap();
+
+        let json = serde_json::to_string(&items).unwrap();
+        let back: Vec<Item> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, items);
+        // ids are left out of equality, check they survive as well
+        assert_eq!(serde_json::to_string(&back).unwrap(), json);
+
+        let token: Token =
+            serde_json::from_str(&serde_json::to_string(&Token::Number(1.5)).unwrap()).unwrap();
+        assert_eq!(token, Token::Number(1.5));
+    }
 }
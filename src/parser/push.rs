@@ -0,0 +1,233 @@
+use super::{ParseOutput, Parser, ParserConfig};
+use crate::lexer::{Lexer, Position, Token};
+use crate::operator::OperatorTable;
+use alloc::string::String;
+
+// push parser - parse input fed in chunks as it arrives, e.g. from a
+// socket or an editor buffer, instead of from an iterator over all of it
+//
+//   let mut parser = PushParser::new();
+//   for chunk in chunks {
+//       let out = parser.push(chunk);   // the items the chunk completed
+//   }
+//   let out = parser.finish();          // the rest
+//
+// an item is complete at a ';' outside parentheses, the input up to it is
+// parsed then and the rest kept for the next chunk, items and errors are
+// the same as parsing all of the input at once, node ids included
+// an item not ended by ';' comes with the chunk completing a later one or
+// with `finish`
+// a '(' never closed holds back everything after it, whether the ';'
+// after it are inside the parentheses is only known once they close, so
+// the items after it come with `finish`, the errors are those of parsing
+// all of the input then too
+//
+// each chunk is lexed once, but for the last token before it, which it
+// may continue
+pub struct PushParser {
+    // input after the last complete item
+    buffer: String,
+    // where the buffer starts
+    start: Position,
+    // where lexing the buffer resumes, the start of its last token, and
+    // the parentheses open there
+    scanned: Position,
+    depth: usize,
+    config: ParserConfig,
+    operators: OperatorTable,
+    next_id: u32,
+}
+
+impl Default for PushParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PushParser {
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        PushParser {
+            buffer: String::new(),
+            start: Position::default(),
+            scanned: Position::default(),
+            depth: 0,
+            config,
+            operators: OperatorTable::default(),
+            next_id: 0,
+        }
+    }
+
+    // start at `pos`, e.g. a source of a `SourceMap`
+    pub fn starting_at(mut self, pos: Position) -> Self {
+        self.start = pos;
+        self.scanned = pos;
+        self
+    }
+
+    // binary operators recognized, for the items completed from here on
+    pub fn operators_mut(&mut self) -> &mut OperatorTable {
+        &mut self.operators
+    }
+
+    // add `chunk` to the input, the items it completed with their errors
+    pub fn push(&mut self, chunk: &str) -> ParseOutput {
+        self.buffer.push_str(chunk);
+        match self.complete() {
+            Some(end) => {
+                let out = self.parse(end.offset - self.start.offset);
+                self.start = end;
+                // the ';' outside parentheses was the last token
+                if self.scanned.offset < end.offset {
+                    self.scanned = end;
+                    self.depth = 0;
+                }
+                out
+            }
+            None => ParseOutput::default(),
+        }
+    }
+
+    // the end of the input, the items not completed yet
+    pub fn finish(mut self) -> ParseOutput {
+        self.parse(self.buffer.len())
+    }
+
+    // the end of the last ';' outside parentheses in the buffer, lexed
+    // from where the last call stopped
+    //
+    // a ';' is a token of its own, the tokens up to it are the same
+    // whatever the next chunks bring, as are all tokens but the last
+    fn complete(&mut self) -> Option<Position> {
+        let rest = &self.buffer[self.scanned.offset - self.start.offset..];
+        let mut lexer = Lexer::new(rest.chars()).starting_at(self.scanned);
+        let mut depth = self.depth;
+        let mut end = None;
+        loop {
+            let token = lexer.next_token();
+            if token == Token::Eof {
+                return end;
+            }
+            // resume from the start of the last token, with the
+            // parentheses open before it
+            self.scanned = lexer.token_start();
+            self.depth = depth;
+            match token {
+                Token::Char('(') => depth += 1,
+                Token::Char(')') => depth = depth.saturating_sub(1),
+                Token::Char(';') if depth == 0 => end = Some(lexer.token_span().end),
+                _ => {}
+            }
+        }
+    }
+
+    // parse the first `len` bytes of the buffer and drop them
+    fn parse(&mut self, len: usize) -> ParseOutput {
+        let lexer = Lexer::new(self.buffer[..len].chars()).starting_at(self.start);
+        let mut parser = Parser::with_config(lexer, self.config);
+        parser.operators_mut().clone_from(&self.operators);
+        parser.set_next_id(self.next_id);
+        let out = parser.parse_all();
+        self.next_id = parser.next_id;
+        self.buffer.drain(..len);
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PushParser;
+    use crate::ast::Item;
+    use crate::lexer::Lexer;
+    use crate::parser::{parse_file, ParseOutput, Parser, ParserConfig};
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    // `input` fed in chunks of `size` chars
+    fn push_parse(input: &str, size: usize, config: ParserConfig) -> ParseOutput {
+        let chars: Vec<char> = input.chars().collect();
+        let mut parser = PushParser::with_config(config);
+        let mut out = ParseOutput::default();
+        let mut add = |more: ParseOutput| {
+            out.items.extend(more.items);
+            out.diagnostics.extend(more.diagnostics);
+        };
+        for chunk in chars.chunks(size) {
+            add(parser.push(&chunk.iter().collect::<String>()));
+        }
+        add(parser.finish());
+        out
+    }
+
+    #[test]
+    fn push_parser() {
+        let recover = ParserConfig {
+            recover: true,
+            ..ParserConfig::default()
+        };
+        let inputs = [
+            "def twice(x) x * 2;\ntwice(3);\nextern sin(x); sin(1)",
+            "## doc\ndef f(x)\n  (x + 1) # comment; not an end\n  * 2;;f(1.5)",
+            "def f(x (1; 2);\n def g(x) x +; g(é);\n123.456 def h() 1",
+            "f(1, g(2;3), 4);\n)(;1",
+        ];
+        for input in inputs {
+            for config in [ParserConfig::default(), recover] {
+                let whole = Parser::with_config(Lexer::new(input.chars()), config).parse_all();
+                for size in 1..=input.len() {
+                    let pushed = push_parse(input, size, config);
+                    assert_eq!(pushed, whole, "{:?} in chunks of {}", input, size);
+                    // node ids are not compared by ==
+                    let ids = |out: &ParseOutput| {
+                        let id = |item: &Item| match item {
+                            Item::Function(func) => func.id(),
+                            Item::Extern(proto) => proto.id(),
+                            Item::Expr(expr) => expr.id(),
+                        };
+                        out.items.iter().map(id).collect::<Vec<_>>()
+                    };
+                    assert_eq!(ids(&pushed), ids(&whole));
+                }
+            }
+        }
+
+        // items come as the chunk completing them arrives
+        let mut parser = PushParser::new();
+        assert!(parser.push("def f(x) x").items.is_empty());
+        assert_eq!(parser.push(" + 1; f(").items.len(), 1);
+        assert!(parser.push("2)").items.is_empty());
+        let out = parser.finish();
+        assert_eq!(out.items.len(), 1);
+        assert_eq!(out.items[0].span().start.column, 17);
+
+        // lexing resumes at the last token, which the next chunk continues
+        let mut parser = PushParser::new();
+        parser.push("def f(x) (x + 1");
+        assert_eq!((parser.scanned.offset, parser.depth), (14, 1));
+        assert_eq!(parser.push("0) * 2;\n f(").items.len(), 1);
+        assert_eq!((parser.scanned.offset, parser.depth), (25, 0));
+        parser.push("1");
+        assert_eq!((parser.scanned.offset, parser.depth), (26, 1));
+        assert_eq!(parser.finish().diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn push_parser_unclosed_paren() {
+        // the ';' after an unclosed '(' may yet be inside the parentheses,
+        // nothing after it comes until `finish`
+        let input = "def f(x) x; f(1; f(2);\nf(3);\n";
+        let mut parser = PushParser::new();
+        assert_eq!(parser.push("def f(x) x; f(1").items.len(), 1);
+        assert!(parser.push("; f(2);\n").items.is_empty());
+        assert!(parser.push("f(3);\n").items.is_empty());
+        let mut out = parser.finish();
+        out.items
+            .insert(0, parse_file("def f(x) x;").unwrap().remove(0));
+        let whole = Parser::new(Lexer::new(input.chars())).parse_all();
+        assert_eq!(out, whole);
+        assert!(!out.diagnostics.is_empty());
+    }
+}
@@ -0,0 +1,249 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST, Step};
+use crate::operator::{Assoc, BinaryOp, OperatorTable};
+use std::fmt;
+
+// print the ast back as kaleidoscope source
+// parentheses are only emitted where precedence requires them, Display
+// assumes the default operators, see `Source` for other operator tables
+
+// `expr` printed with precedences from `ops`
+pub struct Source<'a> {
+    pub expr: &'a ExpressionAST,
+    pub ops: &'a OperatorTable,
+}
+
+impl fmt::Display for Source<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self.expr, self.ops)
+    }
+}
+
+impl fmt::Display for ExpressionAST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, &OperatorTable::default())
+    }
+}
+
+fn write_expr(f: &mut fmt::Formatter, expr: &ExpressionAST, ops: &OperatorTable) -> fmt::Result {
+    for step in expr.walk() {
+        match step {
+            Step::Enter(expr, parent) => {
+                if let Some((parent, i)) = parent {
+                    match parent {
+                        ExpressionAST::Binary(op, ..) if i == 1 => write!(f, " {} ", op)?,
+                        ExpressionAST::Call(..) if i > 0 => write!(f, ", ")?,
+                        _ => {}
+                    }
+                    if parenthesized(expr, parent, i, ops) {
+                        write!(f, "(")?;
+                    }
+                }
+                match expr {
+                    ExpressionAST::Number(num, ..) => write!(f, "{}", num)?,
+                    ExpressionAST::Variable(name, ..) => write!(f, "{}", name)?,
+                    ExpressionAST::Error(..) => write!(f, "<error>")?,
+                    ExpressionAST::Unary(op, ..) => write!(f, "{}", op)?,
+                    ExpressionAST::Binary(..) => {}
+                    ExpressionAST::Call(callee, ..) => write!(f, "{}(", callee)?,
+                }
+            }
+            Step::Leave(expr, parent) => {
+                if let ExpressionAST::Call(..) = expr {
+                    write!(f, ")")?;
+                }
+                if parent.is_some_and(|(parent, i)| parenthesized(expr, parent, i, ops)) {
+                    write!(f, ")")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// whether `expr`, child `i` of `parent`, binds weaker than its place needs
+// operators missing from `ops` are always parenthesized
+fn parenthesized(
+    expr: &ExpressionAST,
+    parent: &ExpressionAST,
+    i: usize,
+    ops: &OperatorTable,
+) -> bool {
+    let ExpressionAST::Binary(op, ..) = expr else {
+        return false;
+    };
+    let min_prec = match parent {
+        // binary operands are parenthesized, prefix operators bind tighter
+        ExpressionAST::Unary(..) => isize::MAX,
+        ExpressionAST::Binary(parent_op, ..) => {
+            // for a left associative operator a rhs of equal precedence
+            // needs parentheses but a lhs does not, the other way around
+            // for right associative ones
+            // operands of an unknown operator are parenthesized
+            let (lhs_prec, rhs_prec) = match ops.get(*parent_op) {
+                Some(BinaryOp {
+                    precedence,
+                    assoc: Assoc::Left,
+                }) => (precedence, precedence + 1),
+                Some(BinaryOp {
+                    precedence,
+                    assoc: Assoc::Right,
+                }) => (precedence + 1, precedence),
+                None => (isize::MAX, isize::MAX),
+            };
+            if i == 0 {
+                lhs_prec
+            } else {
+                rhs_prec
+            }
+        }
+        _ => return false,
+    };
+    ops.get(*op)
+        .filter(|op| op.precedence >= min_prec)
+        .is_none()
+}
+
+impl fmt::Display for PrototypeAST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.0, self.1.join(", "))
+    }
+}
+
+// doc comment lines of a prototype, each on its own line
+fn write_doc(f: &mut fmt::Formatter, proto: &PrototypeAST) -> fmt::Result {
+    for line in proto.doc().into_iter().flat_map(str::lines) {
+        writeln!(f, "## {}", line)?;
+    }
+    Ok(())
+}
+
+// top-level expressions (anonymous functions) print as their body
+impl fmt::Display for FunctionAST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 .0.is_empty() {
+            write!(f, "{}", self.1)
+        } else {
+            write_doc(f, &self.0)?;
+            write!(f, "def {} {}", self.0, self.1)
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Function(func) => write!(f, "{}", func),
+            Item::Extern(proto) => {
+                write_doc(f, proto)?;
+                write!(f, "extern {}", proto)
+            }
+            Item::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Source;
+    use crate::ast::Item;
+    use crate::operator::{Assoc, OperatorTable};
+    use crate::parser::Parser;
+
+    // parse `input` as a sequence of items and print them
+    fn print(input: &str) -> String {
+        let out = Parser::from_str(input).parse_all();
+        assert_eq!(out.diagnostics, vec![]);
+
+        let items: Vec<_> = out.items.iter().map(|item| item.to_string()).collect();
+        items.join("; ")
+    }
+
+    #[test]
+    fn print_minimal_parens() {
+        assert_eq!(print("(a + b) + c"), "a + b + c");
+        assert_eq!(print("a + (b + c)"), "a + (b + c)");
+        assert_eq!(print("(a + b) * c"), "(a + b) * c");
+        assert_eq!(print("a + (b * c)"), "a + b * c");
+        assert_eq!(print("a - (b - c) < ((d))"), "a - (b - c) < d");
+        assert_eq!(print("(f((1), g(x*(y+2))))"), "f(1, g(x * (y + 2)))");
+    }
+
+    #[test]
+    fn print_items() {
+        assert_eq!(print("def foo(a b) a*b"), "def foo(a, b) a * b");
+        assert_eq!(print("def bar() 1.5; 2"), "def bar() 1.5; 2");
+        assert_eq!(print("extern sin(x);;"), "extern sin(x)");
+        assert_eq!(
+            print("##sine\n## of x\nextern sin(x)"),
+            "## sine\n## of x\nextern sin(x)"
+        );
+    }
+
+    #[test]
+    fn print_round_trip() {
+        let inputs = [
+            "def fib(n) fib(n - 1) + fib(n - 2)",
+            "a < b < c",
+            "a < (b < c)",
+            "(a * (b - c)) * (d + e - f)",
+            "def f(x, y) g(x, y * (x - y), 0.25) < 1000000",
+            "((((1))))",
+            "-a * -(b + 1) - !!c",
+            "a - -(-b)",
+        ];
+
+        for input in inputs {
+            // printing is a fixpoint: print(parse(print(ast))) == print(ast)
+            let printed = print(input);
+            assert_eq!(print(&printed), printed, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn print_custom_operators() {
+        let mut ops = OperatorTable::default();
+        ops.insert('|', 5, Assoc::Left);
+        ops.insert('/', 40, Assoc::Left);
+
+        let mut p = Parser::from_str("(a | b) / c + d | e");
+        *p.operators_mut() = ops.clone();
+        let Some(Ok(Item::Expr(expr))) = p.parse_item() else {
+            panic!("expected an expression");
+        };
+
+        let source = Source {
+            expr: &expr,
+            ops: &ops,
+        };
+        assert_eq!(source.to_string(), "(a | b) / c + d | e");
+
+        // unknown operators are parenthesized
+        assert_eq!(expr.to_string(), "(((a | b) / c) + d) | e");
+    }
+
+    #[test]
+    fn print_associativity() {
+        let mut ops = OperatorTable::default();
+        ops.insert('^', 60, Assoc::Right);
+        ops.insert('=', 2, Assoc::Right);
+
+        let print = |input: &str| {
+            let mut p = Parser::from_str(input);
+            *p.operators_mut() = ops.clone();
+            let Some(Ok(Item::Expr(expr))) = p.parse_item() else {
+                panic!("expected an expression");
+            };
+            Source {
+                expr: &expr,
+                ops: &ops,
+            }
+            .to_string()
+        };
+
+        assert_eq!(print("a ^ (b ^ c)"), "a ^ b ^ c");
+        assert_eq!(print("(a ^ b) ^ c"), "(a ^ b) ^ c");
+        assert_eq!(print("a = (b = c + 1)"), "a = b = c + 1");
+        assert_eq!(print("(a = b) = c"), "(a = b) = c");
+        assert_eq!(print("(a - b) ^ 2 * c"), "(a - b) ^ 2 * c");
+    }
+}
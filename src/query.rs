@@ -0,0 +1,369 @@
+use crate::ast::{FunctionAST, Item, PrototypeAST};
+use crate::codegen::{Codegen, CodegenResult};
+use crate::incremental::{self, TextEdit};
+use crate::lexer::{Lexer, Span, Token};
+use crate::parser::{ParseOutput, Parser, ParserConfig};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// query - the phases as memoized queries over the sources of a program,
+// for tools that compile the same program over and over as it is edited,
+// e.g. a repl, a watch mode or a language server
+//
+//   tokens(file)      the tokens of a source with their spans
+//   ast(file)         its items and syntax errors
+//   signatures(file)  name and arity of the functions and externs it has
+//   function(name)    the definition of a function in any source
+//   codegen(name)     the `define` of a function, or its error
+//
+// `set_source` starts a new revision, a query whose inputs are the same
+// as when it last ran returns what it returned then, a query that runs
+// again and returns the same as before doesn't make the queries using it
+// run again, e.g. editing the body of a function changes no signature, so
+// only the codegen of that function runs again
+// the ast of a source is reparsed with `incremental::reparse` from the
+// ast of its last revision
+
+// number of `set_source` calls that changed a source
+pub type Revision = u64;
+
+// tokens of a source with their spans, without `Token::Eof`
+pub type Tokens = Arc<Vec<(Token, Span)>>;
+
+// name and arity of each function and extern of a source
+pub type Signatures = Arc<Vec<(String, usize)>>;
+
+#[derive(Default)]
+pub struct Database {
+    revision: Revision,
+    config: ParserConfig,
+    // sources by name, in the order they were first set
+    names: Vec<String>,
+    sources: HashMap<String, Source>,
+    tokens: HashMap<String, Memo<Tokens>>,
+    // with the text the ast was parsed from, to reparse the next one
+    ast: HashMap<String, (Memo<Arc<ParseOutput>>, Arc<str>)>,
+    signatures: HashMap<String, Memo<Signatures>>,
+    functions: HashMap<String, Memo<Option<Arc<FunctionAST>>>>,
+    codegen: HashMap<String, Memo<Option<CodegenResult<String>>>>,
+    // queries run since `executed` was last called, e.g. `ast(a.ks)`
+    executed: Vec<String>,
+}
+
+struct Source {
+    text: Arc<str>,
+    changed_at: Revision,
+}
+
+// value of a query as of `verified_at`, the last revision it changed in
+struct Memo<T> {
+    value: T,
+    changed_at: Revision,
+    verified_at: Revision,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        Database {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    // set the text of the source `name`, adds it if it is new
+    pub fn set_source(&mut self, name: &str, text: &str) {
+        if self
+            .sources
+            .get(name)
+            .is_some_and(|source| &*source.text == text)
+        {
+            return;
+        }
+        self.revision += 1;
+        if !self.sources.contains_key(name) {
+            self.names.push(name.into());
+        }
+        let source = Source {
+            text: text.into(),
+            changed_at: self.revision,
+        };
+        self.sources.insert(name.into(), source);
+    }
+
+    // names of the sources, in the order they were first set
+    pub fn sources(&self) -> &[String] {
+        &self.names
+    }
+
+    // the queries run since the last call, e.g. `codegen(f)`, to see what
+    // an edit made run again
+    pub fn executed(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.executed)
+    }
+
+    pub fn tokens(&mut self, file: &str) -> Option<Tokens> {
+        let source = self.sources.get(file)?;
+        let (text, changed_at) = (source.text.clone(), source.changed_at);
+        let revision = self.revision;
+        let executed = &mut self.executed;
+        let memo = memoize(&mut self.tokens, file, revision, changed_at, || {
+            executed.push(format!("tokens({})", file));
+            let mut lexer = Lexer::new(text.chars());
+            let mut tokens = Vec::new();
+            loop {
+                match lexer.next_token() {
+                    Token::Eof => break,
+                    token => tokens.push((token, lexer.token_span())),
+                }
+            }
+            Arc::new(tokens)
+        });
+        Some(memo.value.clone())
+    }
+
+    pub fn ast(&mut self, file: &str) -> Option<Arc<ParseOutput>> {
+        let source = self.sources.get(file)?;
+        let (text, changed_at) = (source.text.clone(), source.changed_at);
+        let revision = self.revision;
+        let config = self.config;
+        let mut old = None;
+        if let Some((memo, parsed)) = self.ast.get_mut(file) {
+            if memo.verified_at == revision || changed_at <= memo.verified_at {
+                memo.verified_at = revision;
+                return Some(memo.value.clone());
+            }
+            old = Some((memo.value.clone(), parsed.clone()));
+        }
+
+        self.executed.push(format!("ast({})", file));
+        let value = match &old {
+            Some((ast, parsed)) => Arc::new(incremental::reparse(
+                ast,
+                &edit(parsed, &text),
+                &text,
+                config,
+            )),
+            None => Arc::new(Parser::with_config(Lexer::new(text.chars()), config).parse_all()),
+        };
+        let memo = match old {
+            Some((ast, _)) if ast == value => {
+                let (memo, _) = &self.ast[file];
+                Memo {
+                    value: ast,
+                    changed_at: memo.changed_at,
+                    verified_at: revision,
+                }
+            }
+            _ => Memo {
+                value: value.clone(),
+                changed_at: revision,
+                verified_at: revision,
+            },
+        };
+        let value = memo.value.clone();
+        self.ast.insert(file.into(), (memo, text));
+        Some(value)
+    }
+
+    // functions and externs of `file` by name and arity, a function
+    // declared twice is there twice
+    pub fn signatures(&mut self, file: &str) -> Option<Signatures> {
+        let ast = self.ast(file)?;
+        let changed_at = self.ast[file].0.changed_at;
+        let revision = self.revision;
+        let executed = &mut self.executed;
+        let memo = memoize(&mut self.signatures, file, revision, changed_at, || {
+            executed.push(format!("signatures({})", file));
+            let signatures = ast.items.iter().filter_map(|item| match item {
+                Item::Function(FunctionAST(proto, ..)) | Item::Extern(proto) => {
+                    Some((proto.name().to_string(), proto.params().len()))
+                }
+                Item::Expr(_) => None,
+            });
+            Arc::new(signatures.filter(|(name, _)| !name.is_empty()).collect())
+        });
+        Some(memo.value.clone())
+    }
+
+    // the first definition of `name` in the sources in order
+    pub fn function(&mut self, name: &str) -> Option<Arc<FunctionAST>> {
+        let mut changed_at = 0;
+        let mut asts = Vec::new();
+        for file in self.names.clone() {
+            let ast = self.ast(&file)?;
+            changed_at = changed_at.max(self.ast[&file].0.changed_at);
+            asts.push(ast);
+        }
+        let revision = self.revision;
+        let executed = &mut self.executed;
+        let memo = memoize(&mut self.functions, name, revision, changed_at, || {
+            executed.push(format!("function({})", name));
+            let mut items = asts.iter().flat_map(|ast| &ast.items);
+            items.find_map(|item| match item {
+                Item::Function(func) if func.proto().name() == name => Some(Arc::new(func.clone())),
+                _ => None,
+            })
+        });
+        memo.value.clone()
+    }
+
+    // the `define` of the function `name` with every function and extern
+    // of the sources declared, None if there is no such function
+    //
+    // functions are declared like externs, a call of another function is
+    // impure then and not eliminated with cse
+    pub fn codegen(&mut self, name: &str) -> Option<CodegenResult<String>> {
+        let func = self.function(name);
+        let mut changed_at = self.functions[name].changed_at;
+        let mut signatures = Vec::new();
+        for file in self.names.clone() {
+            signatures.extend(self.signatures(&file)?.iter().cloned());
+            changed_at = changed_at.max(self.signatures[&file].changed_at);
+        }
+        let revision = self.revision;
+        let executed = &mut self.executed;
+        let memo = memoize(&mut self.codegen, name, revision, changed_at, || {
+            executed.push(format!("codegen({})", name));
+            let func = func?;
+            let mut codegen = Codegen::new();
+            for (callee, arity) in &signatures {
+                let params = (0..*arity).map(|i| format!("x{}", i)).collect();
+                let proto = PrototypeAST::new(callee.clone(), params, Span::default());
+                // a mismatch is reported where the function is compiled
+                let _ = codegen.compile_extern(&proto);
+            }
+            Some(codegen.compile_function(&func))
+        });
+        memo.value.clone()
+    }
+}
+
+// the memo of `key` in `table` as of `revision`, `compute` runs if the
+// inputs changed at `changed_at` after it was verified, an equal value
+// keeps its `changed_at`
+fn memoize<'a, T: PartialEq>(
+    table: &'a mut HashMap<String, Memo<T>>,
+    key: &str,
+    revision: Revision,
+    changed_at: Revision,
+    compute: impl FnOnce() -> T,
+) -> &'a Memo<T> {
+    match table.get_mut(key) {
+        Some(memo) if changed_at <= memo.verified_at => memo.verified_at = revision,
+        Some(memo) => {
+            let value = compute();
+            if value != memo.value {
+                memo.value = value;
+                memo.changed_at = revision;
+            }
+            memo.verified_at = revision;
+        }
+        None => {
+            let memo = Memo {
+                value: compute(),
+                changed_at: revision,
+                verified_at: revision,
+            };
+            table.insert(key.into(), memo);
+        }
+    }
+    &table[key]
+}
+
+// the edit turning `old` into `new`, between their common prefix and
+// suffix
+fn edit(old: &str, new: &str) -> TextEdit {
+    let prefix = old
+        .char_indices()
+        .zip(new.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(old.len().min(new.len()), |((i, _), _)| i);
+    let suffix = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum::<usize>();
+    TextEdit {
+        range: prefix..old.len() - suffix,
+        text: new[prefix..new.len() - suffix].into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{edit, Database};
+    use crate::incremental::TextEdit;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn queries() {
+        let mut db = Database::new();
+        db.set_source("lib.ks", "def twice(x) x * 2;\nextern sin(x);");
+        db.set_source("main.ks", "def f(x) twice(sin(x));\ndef g(x) x + 1;");
+        let f = db.codegen("f").unwrap().unwrap();
+        assert!(f.contains("call double @twice(double %0)"));
+        assert!(db.codegen("g").unwrap().is_ok());
+        assert!(db.codegen("h").is_none());
+        assert_eq!(db.signatures("main.ks").unwrap().len(), 2);
+        assert_eq!(db.tokens("lib.ks").unwrap().len(), 15);
+        db.executed();
+
+        // nothing changed, nothing runs
+        let revision = db.revision();
+        db.set_source("lib.ks", "def twice(x) x * 2;\nextern sin(x);");
+        assert_eq!(db.revision(), revision);
+        db.codegen("f");
+        assert!(db.executed().is_empty());
+
+        // a body, no signature changes, the other function is not compiled
+        // again
+        db.set_source("main.ks", "def f(x) twice(sin(x));\ndef g(x) x + 2;");
+        assert_eq!(db.codegen("f"), Some(Ok(f.clone())));
+        db.codegen("g");
+        assert_eq!(
+            db.executed(),
+            [
+                "ast(main.ks)",
+                "function(f)",
+                "signatures(main.ks)",
+                "function(g)",
+                "codegen(g)"
+            ]
+        );
+
+        // a signature, every function is compiled again
+        db.set_source("lib.ks", "def twice(x y) x * 2;\nextern sin(x);");
+        let err = db.codegen("f").unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "'twice' takes 2 argument(s), found 1");
+        assert!(db.executed().contains(&"codegen(f)".to_string()));
+
+        // the ast of the new text is that of parsing it from scratch
+        let text = "def twice(x y) x * y;\nextern sin(x);";
+        db.set_source("lib.ks", text);
+        let parsed = Parser::new(Lexer::new(text.chars())).parse_all();
+        assert_eq!(*db.ast("lib.ks").unwrap(), parsed);
+    }
+
+    #[test]
+    fn text_edits() {
+        let at = |lo: usize, hi: usize, text: &str| TextEdit {
+            range: lo..hi,
+            text: text.into(),
+        };
+        assert_eq!(edit("x * 2;", "x * 20;"), at(5, 5, "0"));
+        assert_eq!(edit("f(é);", "f(è);"), at(2, 4, "è"));
+        assert_eq!(edit("aaa", "aa"), at(2, 3, ""));
+        assert_eq!(edit("ab", "ab"), at(2, 2, ""));
+    }
+}
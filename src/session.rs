@@ -0,0 +1,248 @@
+use crate::ast::Item;
+use crate::diagnostic::{Diagnostic, Emitter, Severity};
+use crate::lexer::{LexerConfig, Position};
+use crate::lint::{Levels, Linter};
+use crate::opt::{self, OptLevel, Pipeline};
+use crate::parser::ParserConfig;
+use crate::source::{SourceFile, SourceMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+// session - the state of compiling one program that every phase shares,
+// its options, the sources read, where diagnostics go and the functions
+// and externs defined so far, the driver passes it through the phases,
+// a repl keeps it from one item to the next
+
+pub struct Session {
+    pub options: Options,
+    pub sources: SourceMap,
+    pub definitions: Definitions,
+    // what the phases and passes took, with `time_passes`
+    pub times: Times,
+    emitter: Box<dyn Emitter + Send + Sync>,
+    // errors reported so far
+    errors: usize,
+}
+
+// options of the phases, those of a driver alone, e.g. where artifacts
+// go, are the driver's
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub lexer: LexerConfig,
+    pub parser: ParserConfig,
+    pub level: OptLevel,
+    // passes run after those of the level, see `opt::pass`
+    pub passes: Vec<String>,
+    pub lints: Levels,
+    // the session stops at the n-th error, see `Session::stopped`
+    pub max_errors: Option<usize>,
+    // log the phases as they run
+    pub verbose: bool,
+    // keep what the phases and passes took in `Session::times`
+    pub time_passes: bool,
+    // allocations and bytes allocated so far, counted by the owner of the
+    // global allocator for `time_passes`, none are counted if None
+    pub allocations: Option<fn() -> (usize, usize)>,
+}
+
+impl Session {
+    pub fn new(options: Options, emitter: impl Emitter + Send + Sync + 'static) -> Self {
+        Session {
+            options,
+            sources: SourceMap::new(),
+            definitions: Definitions::default(),
+            times: Times::default(),
+            emitter: Box::new(emitter),
+            errors: 0,
+        }
+    }
+
+    // add `file` to the sources, the position to lex it from
+    pub fn add_source(&mut self, file: SourceFile) -> Position {
+        self.sources.add(file)
+    }
+
+    // render `diagnostic` with the emitter, errors are counted
+    pub fn emit(&mut self, diagnostic: &Diagnostic) {
+        // a diagnostic that cannot be written has nowhere else to go
+        let _ = self.emitter.emit(diagnostic, &self.sources);
+        if diagnostic.severity == Severity::Error {
+            self.errors += 1;
+        }
+    }
+
+    // count an error reported other than as a diagnostic, e.g. a file
+    // that cannot be read
+    pub fn count_error(&mut self) {
+        self.errors += 1;
+    }
+
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    // whether there were `max_errors` errors, a driver stops then
+    pub fn stopped(&self) -> bool {
+        self.options.max_errors == Some(self.errors)
+    }
+
+    // the passes of the level and those of `passes`, names of no pass are
+    // left out
+    pub fn pipeline(&self) -> Pipeline {
+        let passes = self
+            .options
+            .passes
+            .iter()
+            .filter_map(|name| opt::pass(name));
+        passes.fold(self.options.level.pipeline(), Pipeline::with_boxed)
+    }
+
+    pub fn linter(&self) -> Linter {
+        Linter::new(self.options.lints.clone())
+    }
+}
+
+// functions and externs defined so far, in the order they were first
+// declared, a function replacing its extern, clones share them, e.g. with
+// a line editor completing their names while the parser reads from it
+#[derive(Debug, Clone, Default)]
+pub struct Definitions(Arc<Mutex<Vec<Item>>>);
+
+impl Definitions {
+    // add `item` unless it redefines one or has no name
+    pub fn define(&self, item: &Item) {
+        let Some(name) = item.name() else {
+            return;
+        };
+        let mut items = self.lock();
+        match items.iter().position(|known| known.name() == Some(name)) {
+            None => items.push(item.clone()),
+            Some(i) if matches!((&items[i], item), (Item::Extern(_), Item::Function(_))) => {
+                items[i] = item.clone()
+            }
+            Some(_) => {}
+        }
+    }
+
+    pub fn items(&self) -> Vec<Item> {
+        self.lock().clone()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.lock()
+            .iter()
+            .filter_map(Item::name)
+            .map(String::from)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Item>> {
+        // items are only pushed or replaced whole, a panic holding the lock
+        // leaves them as they were
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// what a phase or pass took, with the allocations a driver counted while
+// it ran, passes are named with an indent under the phase running them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassTime {
+    pub name: String,
+    pub time: Duration,
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+// the times of the phases and passes in the order they started, clones
+// share them, e.g. with a pass timed inside the phase running it
+#[derive(Debug, Clone, Default)]
+pub struct Times(Arc<Mutex<Vec<PassTime>>>);
+
+impl Times {
+    // add `time`, its index to `set` it once it is known
+    pub fn push(&self, time: PassTime) -> usize {
+        let mut times = self.lock();
+        times.push(time);
+        times.len() - 1
+    }
+
+    pub fn set(&self, index: usize, time: PassTime) {
+        self.lock()[index] = time;
+    }
+
+    pub fn take(&self) -> Vec<PassTime> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<PassTime>> {
+        // like `Definitions::lock`
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Options, PassTime, Session};
+    use crate::diagnostic::{Diagnostic, TerminalEmitter};
+    use crate::lexer::Span;
+    use crate::opt::OptLevel;
+    use crate::parser::parse_file;
+    use crate::source::SourceFile;
+
+    #[test]
+    fn session() {
+        let options = Options {
+            max_errors: Some(2),
+            level: OptLevel::O0,
+            passes: vec!["const-fold".into()],
+            ..Options::default()
+        };
+        let mut session = Session::new(options, TerminalEmitter::new(std::io::sink()));
+        let start = session.add_source(SourceFile::cmdline("f(1);"));
+        assert_eq!(
+            session.sources.file(start.offset).unwrap().1.name,
+            "<cmdline>"
+        );
+
+        session.emit(&Diagnostic::warning(Span::at(start), "unused"));
+        assert_eq!(session.errors(), 0);
+        session.emit(&Diagnostic::error(Span::at(start), "unknown function 'f'"));
+        assert!(!session.stopped());
+        session.count_error();
+        assert!(session.stopped());
+        assert_eq!(session.pipeline().passes(), ["const-fold"]);
+
+        // a function replaces its extern, redefinitions are dropped
+        let items = parse_file("extern f(x); def g() 1; def f(x) x; def g() 2; 3").unwrap();
+        let definitions = session.definitions.clone();
+        for item in &items {
+            definitions.define(item);
+        }
+        assert_eq!(session.definitions.names(), ["f", "g"]);
+        assert_eq!(session.definitions.items()[0], items[2]);
+        assert_eq!(session.definitions.items()[1], items[1]);
+
+        // a phase is added as it starts, before the passes it runs
+        let times = session.times.clone();
+        let phase = times.push(PassTime::default());
+        let pass = |name: &str| PassTime {
+            name: name.into(),
+            ..PassTime::default()
+        };
+        times.push(pass("  const-fold"));
+        times.set(phase, pass("optimize"));
+        assert_eq!(
+            session.times.take(),
+            [pass("optimize"), pass("  const-fold")]
+        );
+        assert!(session.times.take().is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::lexer::Position;
+use std::fmt;
+use std::io::{self, Read};
+
+// source - a source of a program with the name diagnostics give it, a
+// file by its path, stdin as `<stdin>` and `-e` on the command line as
+// `<cmdline>`
+
+pub const STDIN: &str = "<stdin>";
+pub const CMDLINE: &str = "<cmdline>";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub contents: String,
+}
+
+impl SourceFile {
+    pub fn read(path: &str) -> io::Result<SourceFile> {
+        Ok(SourceFile {
+            name: path.into(),
+            contents: std::fs::read_to_string(path)?,
+        })
+    }
+
+    // all of stdin
+    pub fn stdin() -> io::Result<SourceFile> {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        Ok(SourceFile {
+            name: STDIN.into(),
+            contents,
+        })
+    }
+
+    pub fn cmdline(contents: &str) -> SourceFile {
+        SourceFile {
+            name: CMDLINE.into(),
+            contents: contents.into(),
+        }
+    }
+
+    // the contents to lex
+    pub fn reader(self) -> Box<dyn Read> {
+        Box::new(io::Cursor::new(self.contents.into_bytes()))
+    }
+}
+
+// the sources of a program one after the other in one space of byte
+// offsets, a source is lexed starting at the position `add` returns so
+// the offset of every position says which source it is in, e.g. for
+// diagnostics on items of several files compiled together
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    // sources with the offset they start at, in order
+    files: Vec<(usize, SourceFile)>,
+    // offset the next source starts at
+    end: usize,
+    // the last source is read as it comes, e.g. from a terminal, without
+    // contents and with every offset from its start
+    streaming: bool,
+}
+
+// a place in a source by its name, line and column start at 1, columns
+// count chars like the lexer does
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Location<'a> {
+    pub name: &'a str,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.name, self.line, self.column)
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // add `file` after the sources before it, the position to lex it from
+    pub fn add(&mut self, file: SourceFile) -> Position {
+        assert!(!self.streaming, "a source read as it comes is the last");
+        let start = self.end;
+        // one past the end, the end of a source is not the start of the
+        // next
+        self.end += file.contents.len() + 1;
+        self.files.push((start, file));
+        Position {
+            offset: start,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    // add the source `name` read as it comes, the last one, only
+    // `locate` finds where in it a position is
+    pub fn add_stream(&mut self, name: impl Into<String>) -> Position {
+        let start = self.add(SourceFile {
+            name: name.into(),
+            contents: String::new(),
+        });
+        self.streaming = true;
+        start
+    }
+
+    // the source `offset` is in, its end included, with the offset it
+    // starts at
+    pub fn file(&self, offset: usize) -> Option<(usize, &SourceFile)> {
+        let i = self.files.partition_point(|(start, _)| *start <= offset);
+        let (start, file) = self.files.get(i.checked_sub(1)?)?;
+        let streamed = self.streaming && i == self.files.len();
+        (streamed || offset - start <= file.contents.len()).then_some((*start, file))
+    }
+
+    // the source of `pos`, at the line and column the lexer gave it
+    pub fn locate(&self, pos: Position) -> Option<Location<'_>> {
+        let (_, file) = self.file(pos.offset)?;
+        Some(Location {
+            name: &file.name,
+            line: pos.line,
+            column: pos.column,
+        })
+    }
+
+    // the source, line and column of `offset`
+    pub fn lookup(&self, offset: usize) -> Option<Location<'_>> {
+        let (start, file) = self.file(offset)?;
+        let before = file.contents.get(..offset - start)?;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some(Location {
+            name: &file.name,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Location, SourceFile, SourceMap, CMDLINE, STDIN};
+    use crate::lexer::{Lexer, Position, Token};
+    use std::io::Read;
+
+    #[test]
+    fn source_files() {
+        let source = SourceFile::cmdline("1 + 2");
+        assert_eq!(source.name, CMDLINE);
+        let mut contents = String::new();
+        source.reader().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "1 + 2");
+
+        let source = SourceFile::read("Cargo.toml").unwrap();
+        assert_eq!(source.name, "Cargo.toml");
+        assert!(source.contents.starts_with("[package]"));
+        assert!(SourceFile::read("no/such.ks").is_err());
+    }
+
+    #[test]
+    fn source_map() {
+        let file = |name: &str, contents: &str| SourceFile {
+            name: name.into(),
+            contents: contents.into(),
+        };
+        let mut map = SourceMap::new();
+        let main = map.add(file("main.ks", "def f(x)\n  x;"));
+        let lib = map.add(file("lib.ks", "# é\nextern g();"));
+        assert_eq!((main.offset, lib.offset), (0, 14));
+
+        let at = |name, line, column| Some(Location { name, line, column });
+        assert_eq!(map.lookup(11), at("main.ks", 2, 3));
+        // the end of a source
+        assert_eq!(map.lookup(13), at("main.ks", 2, 5));
+        assert_eq!(map.lookup(14), at("lib.ks", 1, 1));
+        assert_eq!(map.lookup(19), at("lib.ks", 2, 1));
+        assert_eq!(map.lookup(30), at("lib.ks", 2, 12));
+        assert_eq!(map.lookup(31), None);
+        assert_eq!(map.file(20).unwrap().1.name, "lib.ks");
+        assert_eq!(at("lib.ks", 2, 1).unwrap().to_string(), "lib.ks:2:1");
+
+        // positions of a source lexed from where it was added map back to
+        // where the lexer says they are
+        let mut lexer =
+            Lexer::new(map.file(lib.offset).unwrap().1.contents.chars()).starting_at(lib);
+        while lexer.next_token() != Token::Eof {
+            let start = lexer.token_start();
+            assert_eq!(
+                map.lookup(start.offset),
+                at("lib.ks", start.line, start.column)
+            );
+            assert_eq!(map.locate(start), map.lookup(start.offset));
+        }
+
+        // a terminal after the files, its positions are where the lexer
+        // says they are
+        let stdin = map.add_stream(STDIN);
+        assert_eq!(stdin.offset, 31);
+        let pos = Position {
+            offset: 1000,
+            line: 40,
+            column: 2,
+        };
+        assert_eq!(map.locate(pos), at(STDIN, 40, 2));
+        assert_eq!(map.lookup(pos.offset), None);
+    }
+}
@@ -0,0 +1,138 @@
+use kaleidoscope::session::PassTime;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// timing - wall clock and allocations of the phases of compiling and of
+// the optimization passes, for `--time-passes`
+//
+// allocations are counted by the global allocator, on all threads, a
+// reallocation counts as an allocation of its new size
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn count(bytes: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+// allocations and bytes allocated since klc started
+pub fn allocations() -> (usize, usize) {
+    (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        BYTES.load(Ordering::Relaxed),
+    )
+}
+
+// `rows` as a table, rows of the same name, e.g. a phase of each file,
+// added up where the first one was, with the total of the phases last
+pub fn table(rows: &[PassTime]) -> String {
+    let mut merged: Vec<PassTime> = Vec::new();
+    for row in rows {
+        match merged.iter_mut().find(|merged| merged.name == row.name) {
+            Some(merged) => {
+                merged.time += row.time;
+                merged.allocations += row.allocations;
+                merged.bytes += row.bytes;
+            }
+            None => merged.push(row.clone()),
+        }
+    }
+    let phases = merged.iter().filter(|row| !row.name.starts_with(' '));
+    let total = phases.fold(
+        PassTime {
+            name: "total".into(),
+            time: Duration::ZERO,
+            allocations: 0,
+            bytes: 0,
+        },
+        |total, row| PassTime {
+            time: total.time + row.time,
+            allocations: total.allocations + row.allocations,
+            bytes: total.bytes + row.bytes,
+            ..total
+        },
+    );
+    let mut out = format!(
+        "{:<16} {:>12} {:>12} {:>12}\n",
+        "phase", "time", "allocations", "bytes"
+    );
+    for row in merged.iter().chain([&total]) {
+        let time = format!("{:.2?}", row.time);
+        writeln!(
+            out,
+            "{:<16} {:>12} {:>12} {:>12}",
+            row.name, time, row.allocations, row.bytes
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocations, table, PassTime};
+    use std::time::Duration;
+
+    #[test]
+    fn timing_table() {
+        let row = |name: &str, micros, allocations, bytes| PassTime {
+            name: name.into(),
+            time: Duration::from_micros(micros),
+            allocations,
+            bytes,
+        };
+        let rows = [
+            row("parse", 30, 4, 400),
+            row("optimize", 10, 2, 64),
+            row("  const-fold", 8, 2, 64),
+            row("parse", 20, 1, 100),
+        ];
+        assert_eq!(
+            table(&rows),
+            "\
+phase                    time  allocations        bytes
+parse                 50.00µs            5          500
+optimize              10.00µs            2           64
+  const-fold           8.00µs            2           64
+total                 60.00µs            7          564
+"
+        );
+
+        let (before, bytes_before) = allocations();
+        let boxed = std::hint::black_box(Box::new([0u8; 100]));
+        let (after, bytes_after) = allocations();
+        drop(boxed);
+        // other tests allocate at the same time
+        assert!(after > before);
+        assert!(bytes_after >= bytes_before + 100);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::ast::{ExpressionAST, FunctionAST, Item, PrototypeAST, Step};
+
+// visitor - read only traversal of the ast
+// override the `visit_*` methods of interest and call the matching `walk_*`
+// function from them to keep descending into child nodes
+// expressions are the exception, `walk_expr` visits them without recursion,
+// `visit_expr` is called before and `leave_expr` after the children
+pub trait Visitor {
+    fn visit_item(&mut self, item: &Item) {
+        walk_item(self, item)
+    }
+
+    fn visit_function(&mut self, func: &FunctionAST) {
+        walk_function(self, func)
+    }
+
+    fn visit_prototype(&mut self, _proto: &PrototypeAST) {}
+
+    fn visit_expr(&mut self, _expr: &ExpressionAST) {}
+
+    fn leave_expr(&mut self, _expr: &ExpressionAST) {}
+}
+
+pub fn walk_item<V: Visitor + ?Sized>(v: &mut V, item: &Item) {
+    match item {
+        Item::Function(func) => v.visit_function(func),
+        Item::Extern(proto) => v.visit_prototype(proto),
+        Item::Expr(expr) => walk_expr(v, expr),
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(v: &mut V, func: &FunctionAST) {
+    v.visit_prototype(&func.0);
+    walk_expr(v, &func.1);
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, expr: &ExpressionAST) {
+    for step in expr.walk() {
+        match step {
+            Step::Enter(expr, _) => v.visit_expr(expr),
+            Step::Leave(expr, _) => v.leave_expr(expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Visitor;
+    use crate::ast::{ExpressionAST, Item, PrototypeAST};
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Item {
+        let mut out = Parser::from_str(input).parse_all();
+        out.items.remove(0)
+    }
+
+    // collects names defined, called and referenced in visiting order
+    #[derive(Default)]
+    struct Symbols {
+        defined: Vec<String>,
+        called: Vec<String>,
+        vars: Vec<String>,
+    }
+
+    impl Visitor for Symbols {
+        fn visit_prototype(&mut self, proto: &PrototypeAST) {
+            self.defined.push(proto.0.clone());
+        }
+
+        fn visit_expr(&mut self, expr: &ExpressionAST) {
+            match expr {
+                ExpressionAST::Call(callee, ..) => self.called.push(callee.clone()),
+                ExpressionAST::Variable(name, ..) => self.vars.push(name.clone()),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn visit_symbols() {
+        let mut symbols = Symbols::default();
+        symbols.visit_item(&parse("def foo(a b) bar(a, baz(b) * c) + a"));
+
+        assert_eq!(symbols.defined, vec!["foo"]);
+        assert_eq!(symbols.called, vec!["bar", "baz"]);
+        assert_eq!(symbols.vars, vec!["a", "b", "c", "a"]);
+
+        symbols.visit_item(&parse("extern qux(x)"));
+        assert_eq!(symbols.defined, vec!["foo", "qux"]);
+    }
+
+    #[test]
+    fn visit_defaults_walk_everything() {
+        struct Numbers(f64);
+
+        impl Visitor for Numbers {
+            fn visit_expr(&mut self, expr: &ExpressionAST) {
+                if let ExpressionAST::Number(num, ..) = expr {
+                    self.0 += num;
+                }
+            }
+        }
+
+        let mut sum = Numbers(0.0);
+        sum.visit_item(&parse("f(1, (2 + g(3)) * 4) < 5"));
+        assert_eq!(sum.0, 15.0);
+    }
+}
@@ -0,0 +1,552 @@
+// cli - run klc the way users do and check what it prints
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn klc(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_klc"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// `klc` with `input` piped into stdin
+fn klc_stdin(args: &[&str], input: &str) -> Output {
+    let mut klc = Command::new(env!("CARGO_BIN_EXE_klc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    klc.stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    klc.wait_with_output().unwrap()
+}
+
+// write `source` to a file named `name` in a fresh directory of `test`
+fn source_file(test: &str, name: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("klc-cli-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn cli_source_files() {
+    let a = source_file("sources", "a.ks", "def f(x) x * 2;\nf(3);\n");
+    let b = source_file("sources", "b.ks", "f(4);\n  g(1);\n");
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+    // functions persist from one file to the next, errors name their file
+    let out = klc(&["--interp", a, b]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "=> 6\n=> 8\n");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        format!("error: {}:2:3: unknown function 'g'\n", b)
+    );
+
+    let out = klc(&["--interp", a, "/nonexistent/c.ks"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&out.stderr).starts_with("error: cannot read /nonexistent/c.ks")
+    );
+    std::fs::remove_dir_all(Path::new(a).parent().unwrap()).unwrap();
+}
+
+#[test]
+fn cli_tokens() {
+    let path = source_file("tokens", "t.ks", "def f(x)\n  x * 2; # twice\n");
+    let path = path.to_str().unwrap();
+    let out = klc(&["--tokens", path]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        format!(
+            "{}:
+1:1-1:4      keyword      def
+1:5-1:6      identifier   f
+1:6-1:7      punctuation  (
+1:7-1:8      identifier   x
+1:8-1:9      punctuation  )
+2:3-2:4      identifier   x
+2:5-2:6      operator     *
+2:7-2:8      number       2
+2:8-2:9      punctuation  ;
+2:10-2:17    comment      \" twice\"
+",
+            path
+        )
+    );
+    std::fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+}
+
+#[test]
+fn cli_dump_ast() {
+    let path = source_file("dump", "d.ks", "def f(x) -x;\nf(1)\n");
+    let path = path.to_str().unwrap();
+    let out = klc(&["--dump-ast=sexpr", path]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "(def f (x) (unary - (var x)))\n(call f (num 1))\n"
+    );
+    let out = klc(&["--dump-ast=dot", path]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("[label=\"def f(x)\", shape=note]"));
+    let out = klc(&["--dump-ast", path]);
+    assert!(String::from_utf8_lossy(&out.stdout).starts_with("[\n    Function("));
+
+    let out = klc(&["--dump-ast=yaml", path]);
+    assert_eq!(out.status.code(), Some(2));
+    std::fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+}
+
+#[test]
+fn cli_output() {
+    let path = source_file("output", "p.ks", "def f(x) x + 1;\n");
+    let dir = path.parent().unwrap();
+    let source = path.to_str().unwrap();
+
+    // next to the source by default, wherever -o says otherwise
+    let out = klc(&["--ir", source]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    let ir = std::fs::read_to_string(dir.join("p.ll")).unwrap();
+    assert!(ir.contains("define double @f(double %x)"));
+    let custom = dir.join("custom.mlir");
+    let out = klc(&["--mlir", source, "-o", custom.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert!(custom.exists());
+
+    // artifacts that are not text need a name when reading stdin
+    let out = klc(&["--object"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: reading stdin, name the output with -o <file>\n"
+    );
+    let out = klc(&["--ir", source, "-o", source]);
+    assert_eq!(out.status.code(), Some(1));
+
+    // -o - is stdout for text, not a file named `-`
+    let out = Command::new(env!("CARGO_BIN_EXE_klc"))
+        .args(["--ir", source, "-o", "-"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("define double @f(double %x)"));
+    assert!(!dir.join("-").exists());
+    let out = klc(&["--object", source, "-o", "-"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: the output is not text, name it with -o <file>\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cli_emit() {
+    let path = source_file("emit", "e.ks", "def f(x) x * 3;\n");
+    let dir = path.parent().unwrap();
+    let source = path.to_str().unwrap();
+
+    // each stage like its own command
+    let out = klc(&["--emit=tokens", source]);
+    assert_eq!(out.stdout, klc(&["--tokens", source]).stdout);
+    let out = klc(&["--emit=ast", source]);
+    assert_eq!(out.stdout, klc(&["--dump-ast", source]).stdout);
+    let f = dir.join("f.ll");
+    let out = klc(&[
+        "--emit=ir",
+        "--function",
+        "f",
+        source,
+        "-o",
+        f.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    let ir = std::fs::read_to_string(f).unwrap();
+    assert!(ir.starts_with("define double @f(double %x)"));
+
+    let llc = Command::new("llc").arg("--version").output();
+    if llc.is_ok_and(|out| out.status.success()) {
+        let out = klc(&["--emit=asm", source]);
+        assert!(out.status.success());
+        let asm = std::fs::read_to_string(dir.join("e.s")).unwrap();
+        assert!(asm.contains("f:"));
+    }
+
+    let out = klc(&["--emit=wasm", source]);
+    assert_eq!(out.status.code(), Some(2));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cli_piped_stdin() {
+    // no banner or prompts, just the results
+    let out = klc_stdin(&["--interp"], "def f(x) x + 1;\nf(1);\nf(2)\n");
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "=> 2\n=> 3\n");
+    assert!(out.stderr.is_empty());
+
+    let out = klc_stdin(&["--interp", "--result-prefix== ", "--prompt=> "], "1");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "= 1\n");
+
+    // long operator chains are flat, they don't count against the nesting
+    // limit and nothing walking them recurses
+    let sum = vec!["x"; 100_000].join(" + ");
+    let input = format!("def f(x) {};\nf(1) + {}\n", sum, sum.replace('x', "1"));
+    let out = klc_stdin(&["--interp"], &input);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "=> 200000\n");
+
+    let lli = Command::new("lli").arg("--version").output();
+    if lli.is_ok_and(|out| out.status.success()) {
+        let out = klc_stdin(&[], "1 + 2;\n");
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).starts_with("=> 3\n"));
+    }
+}
+
+#[test]
+fn cli_errors() {
+    let out = klc_stdin(&["--interp"], "x; y; 1; z;\n");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "=> 1\n");
+    assert_eq!(String::from_utf8_lossy(&out.stderr).lines().count(), 3);
+
+    // nothing after the n-th error
+    let out = klc_stdin(&["--interp", "--max-errors=2"], "x; y; 1; z;\n");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: <stdin>:1:1: unknown variable 'x'
+error: <stdin>:1:4: unknown variable 'y'
+error: stopping after 2 errors
+"
+    );
+    assert!(out.stdout.is_empty());
+
+    // syntax errors too, as messages and not as rust data
+    let out = klc_stdin(&["--interp"], "def f(x) x +;\n1 (\n");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: <stdin>:1:13: expected expression, found ';'
+error: <stdin>:3:1: expected expression, found end of input
+"
+    );
+
+    let out = klc(&["--max-errors=0"]);
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn cli_lints() {
+    let source = "def f(x y) x;\nf(1, 2);\n";
+    let out = klc_stdin(&["--interp"], source);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "warning: <stdin>:1:5: unused parameter 'y' of 'f' [unused-parameter]\n"
+    );
+    let out = klc_stdin(&["--interp", "-A", "unused-parameter"], source);
+    assert!(out.stderr.is_empty());
+
+    // a denied lint is an error, the function is not defined
+    let out = klc_stdin(&["--interp", "-Dunused-parameter"], source);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: <stdin>:1:5: unused parameter 'y' of 'f' [unused-parameter]
+error: <stdin>:2:1: unknown function 'f'
+"
+    );
+}
+
+#[test]
+fn cli_verbose() {
+    let out = klc_stdin(&["--ir", "-v"], "def f(x) x + 1;\nf(2);\n");
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let phases: Vec<_> = stderr
+        .lines()
+        .map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            (fields[0], fields[2..].join(" "))
+        })
+        .collect();
+    assert_eq!(
+        phases,
+        [
+            ("lex", "14 tokens in <stdin>".into()),
+            ("parse", "2 items in <stdin>".into()),
+            ("analyze", "2 items in <stdin>".into()),
+            ("optimize", "2 items in <stdin>".into()),
+            ("codegen", "2 items in <stdin>".into()),
+        ]
+    );
+}
+
+#[test]
+fn cli_multiple_files() {
+    let main = source_file("files", "main.ks", "def twice(x) x * 2;\ninc(twice(20));\n");
+    let lib = source_file("files", "lib.ks", "def inc(x) x + 1;\n");
+    let (main, lib) = (main.to_str().unwrap(), lib.to_str().unwrap());
+
+    // calls resolve across files in either order, into one module
+    for args in [["--ir", main, lib], ["--ir", lib, main]] {
+        let out = klc(&args);
+        assert!(out.status.success());
+        let ir = std::fs::read_to_string(Path::new(args[1]).with_extension("ll")).unwrap();
+        assert!(ir.contains("define double @inc(double %x)"));
+        assert!(ir.contains("call double @inc("));
+    }
+
+    let dup = source_file("files", "dup.ks", "def twice(y) y;\n");
+    let out = klc(&["--ir", main, lib, dup.to_str().unwrap(), "-o", "/dev/null"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        format!(
+            "error: {}:1:5: redefinition of 'twice'\nnote: {}:1:5: first defined here\n",
+            dup.display(),
+            main
+        )
+    );
+
+    // errors in a later file are at its own lines
+    let bad = source_file("files", "bad.ks", "def f(x)\n  g(x);\n");
+    let out = klc(&["--ir", main, lib, bad.to_str().unwrap(), "-o", "/dev/null"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        format!("error: {}:2:3: unknown function 'g'\n", bad.display())
+    );
+    std::fs::remove_dir_all(Path::new(main).parent().unwrap()).unwrap();
+}
+
+#[test]
+fn cli_config() {
+    let main = source_file("config", "main.ks", "def f(x) x + (1 + 2);\n");
+    let dir = main.parent().unwrap();
+    std::fs::write(
+        dir.join("kaleidoscope.toml"),
+        "entry = \"main.ks\"\nopt-level = 0\n\n[lints]\nunused-parameter = \"deny\"\n",
+    )
+    .unwrap();
+    let klc_in_dir = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_klc"))
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+    };
+
+    // the entry without files, at the level of the config unless a flag
+    // says otherwise
+    assert!(klc_in_dir(&["--ir"]).status.success());
+    let ir = std::fs::read_to_string(dir.join("main.ll")).unwrap();
+    assert!(ir.contains("fadd double 0x3FF0000000000000, 0x4000000000000000"));
+    assert!(klc_in_dir(&["--ir", "-O1"]).status.success());
+    let ir = std::fs::read_to_string(dir.join("main.ll")).unwrap();
+    assert!(ir.contains("fadd double %x, 0x4008000000000000"));
+
+    std::fs::write(main.as_path(), "def f(x y) x;\n").unwrap();
+    assert_eq!(klc_in_dir(&["--ir"]).status.code(), Some(1));
+    assert!(klc_in_dir(&["--ir", "-W", "unused-parameter"])
+        .status
+        .success());
+
+    std::fs::write(dir.join("kaleidoscope.toml"), "opt-level = 4\n").unwrap();
+    let out = klc_in_dir(&["--ir"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: kaleidoscope.toml:1: expected 0, 1 or 2 for 'opt-level'\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cli_time_passes() {
+    let out = klc_stdin(&["--ir", "--time-passes", "-O2"], "def f(x) x + (1 + 2);\n");
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let names: Vec<_> = stderr
+        .lines()
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "phase",
+            "lex",
+            "parse",
+            "analyze",
+            "optimize",
+            "const-fold",
+            "codegen",
+            "total"
+        ]
+    );
+    assert!(stderr.contains("\n  const-fold "));
+}
+
+#[test]
+fn cli_test() {
+    let source = "\
+extern assert(x);
+def add(a b) a + b;
+def test_add() add(1, 2) - 3;
+def test_off() add(1, 2);
+def test_assert() assert(add(1, 1) - 2) + assert(1);
+def test_args(x) x;
+add(1, 2);
+";
+    let out = klc_stdin(&["test"], source);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "\
+test test_add ... ok
+test test_off ... FAILED: returned 3
+test test_assert ... FAILED: 1 assert(s) failed
+test test_args ... FAILED: 'test_args' takes 1 argument(s), found 0
+
+test result: FAILED. 1 passed, 3 failed
+"
+    );
+
+    let out = klc_stdin(&["test"], "def test_zero() 0;\n");
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).ends_with("test result: ok. 1 passed, 0 failed\n"));
+}
+
+#[test]
+fn cli_pass() {
+    let out = klc_stdin(
+        &["--ir", "-O0", "--pass=const-fold"],
+        "def f(x) x + (1 + 2);\n",
+    );
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("fadd double %x, 0x4008000000000000"));
+
+    let out = klc_stdin(&["--ir", "--pass=inline"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: unknown pass 'inline', expected const-fold\n"
+    );
+}
+
+#[test]
+fn cli_completions() {
+    let usage = String::from_utf8_lossy(&klc(&["--nope"]).stderr).into_owned();
+    let flags = usage
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .filter(|word| word.starts_with("--") && word.len() > 2);
+    for shell in ["bash", "zsh", "fish"] {
+        let out = klc(&["completions", shell]);
+        assert!(out.status.success());
+        let script = String::from_utf8_lossy(&out.stdout);
+        for flag in flags.clone() {
+            let name = if shell == "fish" { &flag[2..] } else { flag };
+            assert!(script.contains(name), "{} completes {}", shell, flag);
+        }
+        for command in ["build", "coverage", "run", "test", "completions"] {
+            assert!(script.contains(command));
+        }
+    }
+    assert_eq!(klc(&["completions", "tcsh"]).status.code(), Some(2));
+}
+
+#[test]
+fn cli_run() {
+    let script = source_file(
+        "run",
+        "sum.ks",
+        "extern argc(); extern argv(i); extern printd(x);\n\
+         printd(argc());\nprintd(argv(0) + argv(2));\nargv(3);\n",
+    );
+    let script = script.to_str().unwrap();
+    // values of top-level expressions are not printed, the arguments
+    // after -- are the program's even if they look like options
+    let out = klc(&["run", script, "--", "1", "-2", "3.5"]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "3.000000\n4.500000\n");
+
+    let out = klc(&["run", script]);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "0.000000\nNaN\n");
+
+    let out = klc(&["run", script, "--", "x"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: argument 'x' is not a number\n"
+    );
+    std::fs::remove_dir_all(Path::new(script).parent().unwrap()).unwrap();
+}
+
+#[test]
+fn cli_cmdline() {
+    let lib = source_file("cmdline", "lib.ks", "def twice(x) x * 2;\n");
+    let lib = lib.to_str().unwrap();
+    // -e is read after the files
+    let out = klc(&["--interp", lib, "-e", "twice(3);", "-e", "y"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "=> 6\n");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: <cmdline>:1:1: unknown variable 'y'\n"
+    );
+
+    let out = klc(&["--stats", "-e", "def f(x) x; f(1)"]);
+    assert!(out.status.success());
+    assert!(!out.stdout.is_empty());
+    std::fs::remove_dir_all(Path::new(lib).parent().unwrap()).unwrap();
+}
+
+#[test]
+fn cli_commands() {
+    let lib = source_file("commands", "lib.ks", "def twice(x) x * 2;\n");
+    let saved = lib.with_file_name("saved.ks");
+    let (lib, saved) = (lib.to_str().unwrap(), saved.to_str().unwrap());
+
+    // command lines of piped stdin run like typed ones
+    let input = format!(
+        ":load {}\nextern sin(x);\ntwice(3);\n:save {}\n",
+        lib, saved
+    );
+    let out = klc_stdin(&["--interp"], &input);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "=> 6\n");
+    assert_eq!(
+        std::fs::read_to_string(saved).unwrap(),
+        "def twice(x) x * 2;\nextern sin(x);\n"
+    );
+
+    let out = klc_stdin(
+        &["--interp"],
+        ":load\n:frobnicate x\n:load /nonexistent.ks\n1;\n",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "=> 1\n");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let errors: Vec<_> = stderr.lines().collect();
+    assert_eq!(errors[0], "error: expected a file after ':load'");
+    assert!(errors[1].starts_with("error: unknown command ':frobnicate'"));
+    assert!(errors[2].starts_with("error: cannot read /nonexistent.ks"));
+    std::fs::remove_dir_all(Path::new(lib).parent().unwrap()).unwrap();
+}
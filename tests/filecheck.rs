@@ -0,0 +1,148 @@
+// filecheck - compile the `.ks` fixtures of tests/codegen with klc and
+// match the output against the patterns in their comments
+//
+//   # RUN: --ir -O2        arguments of klc, `--ir` if there is no RUN
+//   # CHECK: fmul double   a later line contains the pattern
+//   # CHECK-NEXT: ret      the line after the last match contains it
+//   # CHECK-NOT: call      no line up to the next match contains it
+//
+// patterns are plain text, runs of whitespace match any whitespace
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Debug)]
+enum Directive {
+    Check(String),
+    Next(String),
+    Not(String),
+}
+
+// the klc arguments and checks of a fixture
+fn directives(source: &str) -> (Vec<String>, Vec<Directive>) {
+    let mut args = vec!["--ir".to_string()];
+    let mut checks = Vec::new();
+    for line in source.lines() {
+        let Some(comment) = line.trim_start().strip_prefix('#') else {
+            continue;
+        };
+        let comment = comment.trim();
+        if let Some(run) = comment.strip_prefix("RUN:") {
+            args = run.split_whitespace().map(String::from).collect();
+        } else if let Some(pattern) = comment.strip_prefix("CHECK:") {
+            checks.push(Directive::Check(normalize(pattern)));
+        } else if let Some(pattern) = comment.strip_prefix("CHECK-NEXT:") {
+            checks.push(Directive::Next(normalize(pattern)));
+        } else if let Some(pattern) = comment.strip_prefix("CHECK-NOT:") {
+            checks.push(Directive::Not(normalize(pattern)));
+        }
+    }
+    (args, checks)
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// match `output` against `checks`, the failed check if any
+fn file_check(output: &str, checks: &[Directive]) -> Result<(), String> {
+    let lines: Vec<String> = output.lines().map(normalize).collect();
+    // index of the line after the last match
+    let mut next = 0;
+    let mut nots: Vec<&str> = Vec::new();
+    let check_nots = |nots: &mut Vec<&str>, lines: &[String]| {
+        for not in nots.drain(..) {
+            if let Some(line) = lines.iter().find(|line| line.contains(not)) {
+                return Err(format!("CHECK-NOT: {} found in {:?}", not, line));
+            }
+        }
+        Ok(())
+    };
+    for check in checks {
+        match check {
+            Directive::Check(pattern) => {
+                let found = lines[next..].iter().position(|line| line.contains(pattern));
+                let Some(found) = found else {
+                    return Err(format!("CHECK: {} not found", pattern));
+                };
+                check_nots(&mut nots, &lines[next..next + found])?;
+                next += found + 1;
+            }
+            Directive::Next(pattern) => {
+                if !lines.get(next).is_some_and(|line| line.contains(pattern)) {
+                    return Err(format!("CHECK-NEXT: {} not on line {}", pattern, next + 1));
+                }
+                check_nots(&mut nots, &[])?;
+                next += 1;
+            }
+            Directive::Not(pattern) => nots.push(pattern),
+        }
+    }
+    check_nots(&mut nots, &lines[next..])
+}
+
+fn klc(args: &[String], input: &str) -> String {
+    let mut klc = Command::new(env!("CARGO_BIN_EXE_klc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    klc.stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = klc.wait_with_output().unwrap();
+    assert!(
+        out.status.success(),
+        "klc {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn codegen_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/codegen");
+    let mut fixtures: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ks"))
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+
+    let mut failures = Vec::new();
+    for fixture in &fixtures {
+        let source = std::fs::read_to_string(fixture).unwrap();
+        let (args, checks) = directives(&source);
+        assert!(!checks.is_empty(), "{} has no checks", fixture.display());
+        let output = klc(&args, &source);
+        if let Err(err) = file_check(&output, &checks) {
+            let name = fixture.file_name().unwrap().to_string_lossy();
+            failures.push(format!("{}: {}\n{}", name, err, output));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn file_check_directives() {
+    let output = "define double @f() {\nentry:\n  %0 = fadd double 1, 2\n  ret double %0\n}\n";
+    let check = |source: &str| file_check(output, &directives(source).1);
+    assert_eq!(
+        check("# CHECK: define  double @f\n# CHECK-NEXT: entry:"),
+        Ok(())
+    );
+    assert_eq!(
+        check("# CHECK: entry\n# CHECK-NOT: call\n# CHECK: ret"),
+        Ok(())
+    );
+    assert!(check("# CHECK: ret\n# CHECK: fadd").is_err());
+    assert!(check("# CHECK: define\n# CHECK-NEXT: fadd").is_err());
+    assert!(check("# CHECK: entry\n# CHECK-NOT: fadd\n# CHECK: ret").is_err());
+    assert!(check("# CHECK: fadd\n# CHECK-NOT: }").is_err());
+}
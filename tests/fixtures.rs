@@ -0,0 +1,155 @@
+// fixtures - lex, parse and evaluate the `.ks` fixtures of tests/fixtures
+// and compare what each phase gives with the `.expected` file next to it
+//
+//   == tokens        one token per line at its line and column
+//   == ast           the items as s-expressions
+//   == diagnostics   syntax errors as klc prints them, if any
+//   == eval          the results of top-level expressions, what `printd`
+//                    and `putchard` print and evaluation errors
+//
+// to add a fixture, or after changing what a phase gives, write the
+// `.expected` files with
+//
+//   UPDATE_EXPECTED=1 cargo test --test fixtures
+//
+// and review their diff
+use kaleidoscope::ast;
+use kaleidoscope::diagnostic::{Diagnostic, Emitter, TerminalEmitter};
+use kaleidoscope::interp::{Interp, InterpError};
+use kaleidoscope::lexer::{Lexer, Span, Token};
+use kaleidoscope::parser::Parser;
+use kaleidoscope::source::{SourceFile, SourceMap};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// what the phases give for `file`
+fn run(file: &SourceFile) -> String {
+    let mut out = String::from("== tokens\n");
+    let mut lexer = Lexer::new(file.contents.chars());
+    loop {
+        let token = lexer.next_token();
+        if token == Token::Eof {
+            break;
+        }
+        let start = lexer.token_start();
+        writeln!(out, "{}:{} {:?}", start.line, start.column, token).unwrap();
+    }
+
+    let mut sources = SourceMap::new();
+    let start = sources.add(file.clone());
+    let lexer = Lexer::new(file.contents.chars()).starting_at(start);
+    let parsed = Parser::new(lexer).parse_all();
+    out.push_str("== ast\n");
+    out.push_str(&ast::program_to_sexpr(&parsed.items));
+    if !parsed.diagnostics.is_empty() {
+        out.push_str("== diagnostics\n");
+        out.push_str(&render(&parsed.diagnostics, &sources));
+    }
+
+    out.push_str("== eval\n");
+    let printed = Arc::new(Mutex::new(String::new()));
+    let mut interp = Interp::new();
+    let printd = printed.clone();
+    interp.bind("printd", 1, move |args| {
+        writeln!(printd.lock().unwrap(), "{:.6}", args[0]).unwrap();
+        0.0
+    });
+    let putchard = printed.clone();
+    interp.bind("putchard", 1, move |args| {
+        putchard.lock().unwrap().push(args[0] as u8 as char);
+        0.0
+    });
+    for item in &parsed.items {
+        let result = interp.eval_item(item);
+        out.push_str(&std::mem::take(&mut *printed.lock().unwrap()));
+        match result {
+            Ok(Some(value)) => writeln!(out, "=> {}", value).unwrap(),
+            Ok(None) => {}
+            Err(err) => out.push_str(&render(&[interp_diagnostic(err)], &sources)),
+        }
+    }
+    out
+}
+
+fn render(diagnostics: &[Diagnostic], sources: &SourceMap) -> String {
+    let mut out = Vec::new();
+    let mut emitter = TerminalEmitter::new(&mut out);
+    for diagnostic in diagnostics {
+        emitter.emit(diagnostic, sources).unwrap();
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn interp_diagnostic(err: InterpError) -> Diagnostic {
+    match err {
+        InterpError::Codegen(err) => err.into(),
+        InterpError::Unbound { name, pos } => {
+            Diagnostic::error(Span::at(pos), format!("no binding for extern '{}'", name))
+        }
+        InterpError::StackOverflow { name, pos } => Diagnostic::error(
+            Span::at(pos),
+            format!("calls nested too deep calling '{}'", name),
+        ),
+    }
+}
+
+// the first line `output` differs from `expected` in
+fn first_difference(expected: &str, output: &str) -> String {
+    let mut expected = expected.lines();
+    let mut output = output.lines();
+    for line in 1.. {
+        match (expected.next(), output.next()) {
+            (Some(a), Some(b)) if a == b => {}
+            (a, b) => return format!("line {}: expected {:?}, got {:?}", line, a, b),
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn language_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let update = std::env::var_os("UPDATE_EXPECTED").is_some();
+    let mut fixtures: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ks"))
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+
+    let mut failures = Vec::new();
+    for fixture in &fixtures {
+        // diagnostics name the fixture, not where the checkout is
+        let name = fixture.file_name().unwrap().to_string_lossy();
+        let file = SourceFile {
+            name: name.to_string(),
+            contents: std::fs::read_to_string(fixture).unwrap(),
+        };
+        let output = run(&file);
+        let expected_path = fixture.with_extension("expected");
+        if update {
+            std::fs::write(&expected_path, &output).unwrap();
+            continue;
+        }
+        match std::fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == output => {}
+            Ok(expected) => failures.push(format!(
+                "{}: {}",
+                name,
+                first_difference(&expected, &output)
+            )),
+            Err(_) => failures.push(format!(
+                "{}: no {}",
+                name,
+                expected_path.file_name().unwrap().to_string_lossy()
+            )),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nrun with UPDATE_EXPECTED=1 to write the .expected files",
+        failures.join("\n")
+    );
+}